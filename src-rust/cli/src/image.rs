@@ -0,0 +1,63 @@
+use std::{fs::File, io::BufWriter, path::Path};
+
+use crate::CliResult;
+
+/// Write RGBA8 pixels to a PNG file.
+pub fn write_png(path: &Path, width: u32, height: u32, pixels: &[u8]) -> CliResult {
+    let file = BufWriter::new(File::create(path)?);
+    let mut encoder = png::Encoder::new(file, width, height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(pixels)?;
+    writer.finish()?;
+    Ok(())
+}
+
+/// A grid of tiles of the same size, filled row by row, on a black background.
+pub struct Mosaic {
+    tile_width: u32,
+    tile_height: u32,
+    columns: usize,
+    rows: usize,
+    pixels: Vec<u8>,
+}
+
+impl Mosaic {
+    pub fn new(tile_width: u32, tile_height: u32, count: usize, columns: usize) -> Self {
+        let columns = columns.min(count);
+        let rows = count.div_ceil(columns);
+        let pixel_count = columns * rows * tile_width as usize * tile_height as usize;
+        Self {
+            tile_width,
+            tile_height,
+            columns,
+            rows,
+            pixels: [0, 0, 0, 255].repeat(pixel_count),
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.columns as u32 * self.tile_width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.rows as u32 * self.tile_height
+    }
+
+    pub fn pixels(&self) -> &[u8] {
+        &self.pixels
+    }
+
+    /// Draw the RGBA8 pixels of a tile at a given index of the grid.
+    pub fn draw_tile(&mut self, index: usize, tile: &[u8]) {
+        let tile_row_size = 4 * self.tile_width as usize;
+        let row_size = 4 * self.width() as usize;
+        let x = (index % self.columns) * tile_row_size;
+        let y = (index / self.columns) * self.tile_height as usize;
+        for (i, tile_row) in tile.chunks(tile_row_size).enumerate() {
+            let start = (y + i) * row_size + x;
+            self.pixels[start..start + tile_row_size].copy_from_slice(tile_row);
+        }
+    }
+}
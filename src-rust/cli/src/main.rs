@@ -0,0 +1,384 @@
+use std::{error::Error, fs::File, io::BufWriter, path::{Path, PathBuf}, process::ExitCode};
+
+use brain_render_core::{cpu_renderer, display_window::{DisplayPolarity, DisplayWindow}, nifti::{self, Nifti, SliceView}, nifti_extension::NiftiExtension, image_reader::{self, ReaderLimits}, nifti_reader, nifti_writer::{self, NiftiScaling, NiftiWriteOptions}, npy::{self, NpySidecar, NpyWriteOptions}, nrrd_reader::NrrdHeader, stats::VolumeStats, volume::VoxelType};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+
+mod image;
+
+type CliResult = Result<(), Box<dyn Error>>;
+
+/// Inspect brain images and render their slices, with the same rendering rules as the web viewer.
+#[derive(Parser)]
+#[command(name = "brain-render", version)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Print the header and the intensity statistics of an image.
+    Info {
+        input: PathBuf,
+    },
+    /// Render a slice of an image to a PNG file.
+    Slice {
+        input: PathBuf,
+        #[arg(short, long)]
+        output: PathBuf,
+        /// The index of the slice along the axis, which defaults to the middle slice.
+        #[arg(long)]
+        index: Option<usize>,
+        #[command(flatten)]
+        view: ViewArgs,
+    },
+    /// Render a grid of evenly spaced slices of an image to a PNG file.
+    Mosaic {
+        input: PathBuf,
+        #[arg(short, long)]
+        output: PathBuf,
+        /// The number of slices of the mosaic.
+        #[arg(long, default_value_t = 16)]
+        count: usize,
+        /// The number of slices in each row of the mosaic.
+        #[arg(long, default_value_t = 4)]
+        columns: usize,
+        #[command(flatten)]
+        view: ViewArgs,
+    },
+    /// Convert an image to a NIfTI file, which is compressed if its extension is `.nii.gz`, or to a
+    /// NumPy `.npy` array with a `.json` sidecar or a `.npz` archive.
+    Convert {
+        input: PathBuf,
+        #[arg(short, long)]
+        output: PathBuf,
+        /// The voxel datatype of the converted image, which defaults to the datatype of the input.
+        #[arg(long, value_enum)]
+        datatype: Option<Datatype>,
+        /// Scale the voxels to the range of the integer datatype, and store the scaling in the
+        /// header of NIfTI files.
+        #[arg(long, requires = "datatype")]
+        scale_to_fit: bool,
+        /// The description of the converted image, which defaults to the description of the input.
+        #[arg(long)]
+        description: Option<String>,
+    },
+}
+
+/// The parameters of the rendered slices.
+#[derive(Args)]
+struct ViewArgs {
+    #[arg(long, value_enum, default_value_t = Axis::Axial)]
+    axis: Axis,
+    #[arg(long, default_value_t = 0)]
+    timepoint: usize,
+    /// The window level, which defaults to the level of the web viewer.
+    #[arg(long)]
+    level: Option<f32>,
+    /// The window width, which defaults to the width of the web viewer.
+    #[arg(long)]
+    width: Option<f32>,
+    /// Invert the grayscale values.
+    #[arg(long)]
+    negative: bool,
+    /// The clockwise rotation of the slices, in degrees.
+    #[arg(long, value_enum, default_value_t = Rotation::Rotate0)]
+    rotation: Rotation,
+    /// The number of pixels per voxel of the rendered slices.
+    #[arg(long, default_value_t = 1)]
+    scale: u32,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Axis {
+    Axial,
+    Coronal,
+    Sagittal,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Rotation {
+    #[value(name = "0")]
+    Rotate0,
+    #[value(name = "90")]
+    Rotate90,
+    #[value(name = "180")]
+    Rotate180,
+    #[value(name = "270")]
+    Rotate270,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Datatype {
+    U8,
+    I16,
+    U16,
+    I32,
+    F32,
+    F64,
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let result = match cli.command {
+        Command::Info { input } => info(&input),
+        Command::Slice { input, output, index, view } => slice(&input, &output, index, &view),
+        Command::Mosaic { input, output, count, columns, view } => mosaic(&input, &output, count, columns, &view),
+        Command::Convert { input, output, datatype, scale_to_fit, description } => {
+            convert(&input, &output, datatype.map(VoxelType::from), scale_to_fit, description)
+        }
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("error: {}", error);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn info(input: &Path) -> CliResult {
+    let nifti = read_image(input)?;
+    let (rows, columns, slices, timepoints) = nifti.volume.dim();
+    let [x_size, y_size, z_size] = nifti.affine.voxel_sizes();
+    let stats = VolumeStats::compute(&nifti.volume);
+
+    println!("file:               {}", input.display());
+    println!("description:        {}", nifti.description);
+    for extension in &nifti.extensions {
+        let summary = match extension {
+            NiftiExtension::Json { code, value } => format!("json (code {}): {}", code, value),
+            NiftiExtension::Afni { attributes } => format!("afni: {} attributes", attributes.len()),
+            NiftiExtension::Text { code, text } => format!("text (code {}): {}", code, text),
+            NiftiExtension::Binary { code, size } => format!("binary (code {}): {} bytes", code, size),
+        };
+        println!("extension:          {}", summary);
+    }
+    println!("dimensions:         {} x {} x {} x {}", rows, columns, slices, timepoints);
+    println!("datatype:           {:?}", nifti.volume.voxel_type());
+    println!("voxel size:         {:.4} x {:.4} x {:.4} mm", x_size, y_size, z_size);
+    for (i, row) in nifti.affine.0.iter().enumerate() {
+        let label = if i == 0 { "affine:" } else { "" };
+        println!("{:<20}{:>12.4} {:>12.4} {:>12.4} {:>12.4}", label, row[0], row[1], row[2], row[3]);
+    }
+    println!("minimum:            {}", stats.minimum);
+    println!("maximum:            {}", stats.maximum);
+    println!("mean:               {}", stats.mean);
+    println!("standard deviation: {}", stats.standard_deviation);
+    Ok(())
+}
+
+fn slice(input: &Path, output: &Path, index: Option<usize>, args: &ViewArgs) -> CliResult {
+    let nifti = read_image(input)?;
+    let slice_count = get_slice_count(&nifti, args.axis);
+    // The web viewer initially displays the middle slice of the volume.
+    let index = index.unwrap_or(slice_count.div_ceil(2).min(slice_count - 1));
+    let view = create_view(&nifti, index, args);
+
+    let (width, height) = get_slice_size(&nifti, args);
+    let pixels = cpu_renderer::render_slice(&nifti.volume, width, height, &view)?;
+    image::write_png(output, width, height, &pixels)
+}
+
+fn mosaic(input: &Path, output: &Path, count: usize, columns: usize, args: &ViewArgs) -> CliResult {
+    if count == 0 || columns == 0 {
+        return Err("the mosaic must have at least one slice and one column".into());
+    }
+
+    let nifti = read_image(input)?;
+    let slice_count = get_slice_count(&nifti, args.axis);
+    let count = count.min(slice_count);
+    let (tile_width, tile_height) = get_slice_size(&nifti, args);
+    let mut mosaic = image::Mosaic::new(tile_width, tile_height, count, columns);
+
+    for i in 0..count {
+        // Space the slices evenly, from the first slice to the last one.
+        let index = if count == 1 { slice_count / 2 } else { i * (slice_count - 1) / (count - 1) };
+        let view = create_view(&nifti, index, args);
+        let pixels = cpu_renderer::render_slice(&nifti.volume, tile_width, tile_height, &view)?;
+        mosaic.draw_tile(i, &pixels);
+    }
+
+    image::write_png(output, mosaic.width(), mosaic.height(), mosaic.pixels())
+}
+
+fn convert(input: &Path, output: &Path, datatype: Option<VoxelType>, scale_to_fit: bool, description: Option<String>) -> CliResult {
+    let name = output.to_string_lossy();
+    if name.ends_with(".npy") || name.ends_with(".npz") {
+        if scale_to_fit {
+            return Err("NumPy arrays cannot store a scaling, --scale-to-fit is only supported for NIfTI files".into());
+        }
+
+        let mut nifti = read_image(input)?;
+        if let Some(description) = description {
+            nifti.description = description;
+        }
+
+        let archive = name.ends_with(".npz");
+        let options = NpyWriteOptions { datatype, archive, compress: archive, ..NpyWriteOptions::default() };
+        npy::write_npy(&nifti, BufWriter::new(File::create(output)?), &options)?;
+        // The affine of `.npy` arrays is stored in their sidecar.
+        if !archive {
+            std::fs::write(output.with_extension("json"), NpySidecar::from_nifti(&nifti).to_json())?;
+        }
+
+        return Ok(());
+    }
+
+    let compress = if name.ends_with(".nii.gz") {
+        true
+    } else if name.ends_with(".nii") {
+        false
+    } else {
+        return Err(format!("unsupported output format for {}, expected a .nii, .nii.gz, .npy or .npz file", output.display()).into());
+    };
+
+    let options = NiftiWriteOptions {
+        compress,
+        datatype,
+        scaling: scale_to_fit.then_some(NiftiScaling::Fit),
+        description,
+        ..NiftiWriteOptions::default()
+    };
+
+    let nifti = read_image(input)?;
+    let writer = BufWriter::new(File::create(output)?);
+    nifti_writer::write_nifti(&nifti, writer, &options)?;
+    Ok(())
+}
+
+/// Read an image, which is either a single file whose format is found from its name, a two-file
+/// image given by its `.hdr` or `.img` file, or a detached NRRD image given by its `.nhdr` file.
+/// NumPy arrays take their affine and description from the `.json` sidecar of the same name, if
+/// there is one.
+fn read_image(input: &Path) -> Result<Nifti, Box<dyn Error>> {
+    let name = input.to_string_lossy();
+    if matches!(input.extension().and_then(|extension| extension.to_str()), Some("npy" | "npz")) {
+        let mut nifti = image_reader::read_image(File::open(input)?, &name, ReaderLimits::default())?;
+        let sidecar = input.with_extension("json");
+        if sidecar.exists() {
+            NpySidecar::parse(&std::fs::read_to_string(sidecar)?)?.apply(&mut nifti);
+        }
+
+        return Ok(nifti);
+    }
+
+    if name.to_ascii_lowercase().ends_with(".nhdr") {
+        let data_file = find_nrrd_data_file(input)?;
+        let reader = image_reader::open_image_pair(File::open(input)?, File::open(data_file)?, &name, ReaderLimits::default())?;
+        return Ok(image_reader::read_volume(reader)?);
+    }
+
+    let nifti = match get_pair_stem(input) {
+        Some(stem) => {
+            let header = find_pair_file(input, stem, "hdr")?;
+            let image = find_pair_file(input, stem, "img")?;
+            nifti_reader::read_nifti_pair(File::open(header)?, File::open(image)?, &name, ReaderLimits::default())?
+        }
+        None => image_reader::read_image(File::open(input)?, &name, ReaderLimits::default())?,
+    };
+
+    Ok(nifti)
+}
+
+/// Get the file name without extension of a `.hdr` or `.img` file, which may be gzip compressed.
+fn get_pair_stem(input: &Path) -> Option<&str> {
+    let name = input.file_name()?.to_str()?;
+    let name = name.strip_suffix(".gz").unwrap_or(name);
+    name.strip_suffix(".hdr").or_else(|| name.strip_suffix(".img"))
+}
+
+/// Find the file of a two-file image with an extension, which may be gzip compressed.
+fn find_pair_file(input: &Path, stem: &str, extension: &str) -> Result<PathBuf, String> {
+    ["", ".gz"].into_iter()
+        .map(|compression| input.with_file_name(format!("{}.{}{}", stem, extension, compression)))
+        .find(|path| path.exists())
+        .ok_or_else(|| format!("could not find the .{} file of {}", extension, input.display()))
+}
+
+/// Find the data file of a detached NRRD header, whose path is relative to the header directory.
+fn find_nrrd_data_file(input: &Path) -> Result<PathBuf, Box<dyn Error>> {
+    let mut header_source = std::io::BufReader::new(File::open(input)?);
+    let header = NrrdHeader::parse(&mut header_source)?;
+    let data_file = header.data_file()
+        .ok_or_else(|| format!("{} has no detached data file", input.display()))?;
+
+    Ok(input.with_file_name("").join(data_file))
+}
+
+fn create_view(nifti: &Nifti, index: usize, args: &ViewArgs) -> SliceView {
+    let default_window = DisplayWindow::from_range(nifti.get_min_intensity(), nifti.get_max_intensity());
+    SliceView {
+        axis: args.axis.into(),
+        coordinate: index,
+        timepoint: args.timepoint,
+        window: DisplayWindow {
+            level: args.level.unwrap_or(default_window.level),
+            width: args.width.unwrap_or(default_window.width),
+            polarity: if args.negative { DisplayPolarity::Negative } else { DisplayPolarity::Positive },
+        },
+        rotation: args.rotation.into(),
+    }
+}
+
+/// Get the number of slices of an image along an axis.
+fn get_slice_count(nifti: &Nifti, axis: Axis) -> usize {
+    let (rows, columns, slices, _) = nifti.volume.dim();
+    match axis {
+        Axis::Axial    => slices,
+        Axis::Coronal  => columns,
+        Axis::Sagittal => rows,
+    }
+}
+
+/// Get the size of the rendered slices of an image, which has one pixel per voxel at scale one.
+fn get_slice_size(nifti: &Nifti, args: &ViewArgs) -> (u32, u32) {
+    let (rows, columns, slices, _) = nifti.volume.dim();
+    let (width, height) = match args.axis {
+        Axis::Axial    => (rows, columns),
+        Axis::Coronal  => (rows, slices),
+        Axis::Sagittal => (columns, slices),
+    };
+
+    let (width, height) = match args.rotation {
+        Rotation::Rotate0 | Rotation::Rotate180 => (width, height),
+        Rotation::Rotate90 | Rotation::Rotate270 => (height, width),
+    };
+
+    (width as u32 * args.scale, height as u32 * args.scale)
+}
+
+impl From<Axis> for nifti::AnatomicalAxis {
+    fn from(axis: Axis) -> Self {
+        match axis {
+            Axis::Axial    => nifti::AnatomicalAxis::Axial,
+            Axis::Coronal  => nifti::AnatomicalAxis::Coronal,
+            Axis::Sagittal => nifti::AnatomicalAxis::Sagittal,
+        }
+    }
+}
+
+impl From<Rotation> for nifti::Rotation {
+    fn from(rotation: Rotation) -> Self {
+        match rotation {
+            Rotation::Rotate0   => nifti::Rotation::Rotate0,
+            Rotation::Rotate90  => nifti::Rotation::Rotate90,
+            Rotation::Rotate180 => nifti::Rotation::Rotate180,
+            Rotation::Rotate270 => nifti::Rotation::Rotate270,
+        }
+    }
+}
+
+impl From<Datatype> for VoxelType {
+    fn from(datatype: Datatype) -> Self {
+        match datatype {
+            Datatype::U8  => VoxelType::U8,
+            Datatype::I16 => VoxelType::I16,
+            Datatype::U16 => VoxelType::U16,
+            Datatype::I32 => VoxelType::I32,
+            Datatype::F32 => VoxelType::F32,
+            Datatype::F64 => VoxelType::F64,
+        }
+    }
+}
@@ -0,0 +1,152 @@
+use nifti::NiftiHeader;
+
+use crate::{error::Error, geometry::Affine};
+
+/// The size of Analyze 7.5 and NIfTI-1 headers, which is also their first field.
+pub(crate) const HEADER_SIZE: usize = 348;
+
+/// The magic of two-file NIfTI-1 headers.
+const NIFTI_PAIR_MAGIC: &[u8; 4] = b"ni1\0";
+
+/// The magic of single file NIfTI-1 headers.
+const NIFTI_SINGLE_MAGIC: &[u8; 4] = b"n+1\0";
+
+/// The offset of the magic of NIfTI-1 headers, which are the `smin` field of Analyze headers.
+const MAGIC_OFFSET: usize = 344;
+
+/// The offset of the orient field of Analyze headers, which are followed by the SPM origin.
+const ORIENT_OFFSET: usize = 252;
+
+/// The offset of the `qform_code` and `sform_code` fields of NIfTI-1 headers.
+const FORM_CODES_OFFSET: usize = 252;
+
+/// The orientation of the voxel axes of an Analyze image. The first voxel axis goes from the right
+/// to the left of the patient, and flipped images have their second voxel axis reversed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Orient {
+    Transverse,
+    Coronal,
+    Sagittal,
+    TransverseFlipped,
+    CoronalFlipped,
+    SagittalFlipped,
+}
+
+/// Parse the header of a two-file image, which is either an Analyze 7.5 header or a NIfTI-1
+/// header with the `ni1` or `n+1` magic. The affine of Analyze images is returned with the header,
+/// since their header has no NIfTI transform.
+pub(crate) fn parse_pair_header(mut bytes: [u8; HEADER_SIZE]) -> Result<(NiftiHeader, Option<Affine>), Error> {
+    let is_big_endian = is_big_endian(&bytes)?;
+    let magic = &bytes[MAGIC_OFFSET..MAGIC_OFFSET + 4];
+    if magic == NIFTI_PAIR_MAGIC || magic == NIFTI_SINGLE_MAGIC {
+        log::debug!("found nifti header");
+        return Ok((NiftiHeader::from_reader(bytes.as_slice())?, None));
+    }
+
+    log::debug!("found analyze header");
+    let orient = get_orient(bytes[ORIENT_OFFSET]);
+    let origin = get_spm_origin(&bytes, is_big_endian);
+
+    // The Analyze fields that overlap the NIfTI transform codes and magic are cleared, so that the
+    // header is read as a NIfTI-1 header without transform. The other NIfTI fields are either
+    // shared with Analyze or unused by the reader.
+    bytes[FORM_CODES_OFFSET..FORM_CODES_OFFSET + 4].fill(0);
+    bytes[MAGIC_OFFSET..MAGIC_OFFSET + 4].copy_from_slice(NIFTI_PAIR_MAGIC);
+    let header = NiftiHeader::from_reader(bytes.as_slice())?;
+    let affine = get_analyze_affine(&header, orient, origin);
+    Ok((header, Some(affine)))
+}
+
+/// Check the size of an Analyze 7.5 or NIfTI-1 header, which is its first field, and get whether
+/// the header is big endian.
+pub(crate) fn is_big_endian(bytes: &[u8]) -> Result<bool, Error> {
+    match bytes.get(..4).and_then(|size| size.try_into().ok()).map(i32::from_le_bytes) {
+        Some(size) if size == HEADER_SIZE as i32 => Ok(false),
+        Some(size) if size.swap_bytes() == HEADER_SIZE as i32 => Ok(true),
+        _ => Err(Error::InvalidHeader("invalid header size, expected an Analyze 7.5 or NIfTI-1 header".to_string())),
+    }
+}
+
+/// Get the orientation of an Analyze image. Some writers store the ASCII digit of the orientation,
+/// which is read as the orientation itself, and the image is read as transverse for other values.
+fn get_orient(orient: u8) -> Orient {
+    match orient {
+        0 | b'0' => Orient::Transverse,
+        1 | b'1' => Orient::Coronal,
+        2 | b'2' => Orient::Sagittal,
+        3 | b'3' => Orient::TransverseFlipped,
+        4 | b'4' => Orient::CoronalFlipped,
+        5 | b'5' => Orient::SagittalFlipped,
+        orient => {
+            log::warn!("unknown analyze orient {}, reading the image as transverse", orient);
+            Orient::Transverse
+        }
+    }
+}
+
+/// Get the origin set by SPM in the originator field of an Analyze header, which is a one-based
+/// voxel index, if it is set.
+fn get_spm_origin(bytes: &[u8; HEADER_SIZE], is_big_endian: bool) -> Option<[f64; 3]> {
+    let origin = [0, 1, 2].map(|i| {
+        let offset = ORIENT_OFFSET + 1 + 2 * i;
+        let value = [bytes[offset], bytes[offset + 1]];
+        if is_big_endian { i16::from_be_bytes(value) } else { i16::from_le_bytes(value) }
+    });
+
+    if origin.iter().all(|&index| index == 0) {
+        None
+    } else {
+        Some(origin.map(|index| index as f64 - 1.0))
+    }
+}
+
+/// Get the affine of an Analyze image from its orientation and voxel sizes, centered on the SPM
+/// origin if it is set, or on the center of the volume otherwise.
+fn get_analyze_affine(header: &NiftiHeader, orient: Orient, origin: Option<[f64; 3]>) -> Affine {
+    let voxel_sizes = [1, 2, 3].map(|i| (header.pixdim[i] as f64).abs());
+    let origin = origin.unwrap_or_else(|| [1, 2, 3].map(|i| (header.dim[i] as f64 - 1.0) / 2.0));
+
+    // The world axis of each voxel axis, and its direction, with world axes in RAS order.
+    let axes: [(usize, f64); 3] = match orient {
+        Orient::Transverse        => [(0, -1.0), (1, 1.0),  (2, 1.0)],
+        Orient::Coronal           => [(0, -1.0), (2, 1.0),  (1, 1.0)],
+        Orient::Sagittal          => [(1, 1.0),  (2, 1.0),  (0, 1.0)],
+        Orient::TransverseFlipped => [(0, -1.0), (1, -1.0), (2, 1.0)],
+        Orient::CoronalFlipped    => [(0, -1.0), (2, -1.0), (1, 1.0)],
+        Orient::SagittalFlipped   => [(1, 1.0),  (2, -1.0), (0, 1.0)],
+    };
+
+    let mut matrix = Affine::IDENTITY.0;
+    matrix[0][0] = 0.0;
+    matrix[1][1] = 0.0;
+    matrix[2][2] = 0.0;
+    for (voxel_axis, (world_axis, direction)) in axes.into_iter().enumerate() {
+        let scale = direction * voxel_sizes[voxel_axis];
+        matrix[world_axis][voxel_axis] = scale;
+        matrix[world_axis][3] = -scale * origin[voxel_axis];
+    }
+
+    Affine(matrix)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_ascii_and_unknown_orients() {
+        assert_eq!(get_orient(4), Orient::CoronalFlipped);
+        assert_eq!(get_orient(b'0'), Orient::Transverse);
+        assert_eq!(get_orient(b'4'), Orient::CoronalFlipped);
+        assert_eq!(get_orient(b'6'), Orient::Transverse);
+        assert_eq!(get_orient(u8::MAX), Orient::Transverse);
+    }
+
+    #[test]
+    fn finds_the_byte_order_of_headers() {
+        assert!(!is_big_endian(&(HEADER_SIZE as i32).to_le_bytes()).unwrap());
+        assert!(is_big_endian(&(HEADER_SIZE as i32).to_be_bytes()).unwrap());
+        assert!(is_big_endian(&540_i32.to_le_bytes()).is_err());
+        assert!(is_big_endian(&[0x5c, 0x01]).is_err());
+    }
+}
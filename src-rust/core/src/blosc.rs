@@ -0,0 +1,250 @@
+//! A decoder of Blosc1 compressed buffers, as written by the `blosc` codec of zarr arrays, with the
+//! LZ4, zlib and Zstandard compressors and byte shuffling.
+
+use std::io::Read;
+
+use flate2::read::ZlibDecoder;
+
+use crate::error::Error;
+
+const HEADER_SIZE: usize = 16;
+
+const FLAG_SHUFFLE: u8 = 0x01;
+const FLAG_MEMCPYED: u8 = 0x02;
+const FLAG_BITSHUFFLE: u8 = 0x04;
+const FLAG_DONT_SPLIT: u8 = 0x10;
+
+const COMPRESSOR_LZ4: u8 = 1;
+const COMPRESSOR_ZLIB: u8 = 3;
+const COMPRESSOR_ZSTD: u8 = 4;
+
+/// Decompress a Blosc1 buffer, whose decompressed size must be at most a maximum size.
+pub(crate) fn decompress(source: &[u8], max_size: usize) -> Result<Vec<u8>, Error> {
+    let header = source.get(..HEADER_SIZE)
+        .ok_or_else(|| invalid("truncated header"))?;
+    let flags = header[2];
+    let type_size = (header[3] as usize).max(1);
+    let size = read_u32(header, 4) as usize;
+    let block_size = read_u32(header, 8) as usize;
+    if size > max_size {
+        return Err(invalid(&format!("expected at most {} bytes, found {}", max_size, size)));
+    }
+
+    if flags & FLAG_MEMCPYED != 0 {
+        return get_bytes(source, HEADER_SIZE, size)
+            .map(<[u8]>::to_vec)
+            .ok_or_else(|| invalid("truncated data"));
+    }
+
+    if flags & FLAG_BITSHUFFLE != 0 {
+        return Err(Error::UnsupportedDatatype("bit shuffled blosc data is not supported".to_string()));
+    }
+
+    if block_size == 0 {
+        return Err(invalid("invalid block size 0"));
+    }
+
+    let compressor = flags >> 5;
+    let block_count = size.div_ceil(block_size);
+    let mut data = Vec::with_capacity(size);
+    for block in 0..block_count {
+        let block_start = get_bytes(source, HEADER_SIZE + 4 * block, 4)
+            .map(|offset| read_u32(offset, 0) as usize)
+            .ok_or_else(|| invalid("truncated block offsets"))?;
+        let is_leftover = block == block_count - 1 && !size.is_multiple_of(block_size);
+        let size = if is_leftover { size % block_size } else { block_size };
+        // The bytes of each position of the values of a block are compressed as separate streams,
+        // unless the block is not split.
+        let stream_count = if flags & FLAG_DONT_SPLIT == 0 && !is_leftover { type_size } else { 1 };
+        let stream_size = size / stream_count;
+        let mut block_data = Vec::with_capacity(size);
+        let mut offset = block_start;
+        for _ in 0..stream_count {
+            let compressed_size = get_bytes(source, offset, 4)
+                .map(|bytes| read_u32(bytes, 0) as usize)
+                .ok_or_else(|| invalid("truncated block"))?;
+            let compressed = get_bytes(source, offset + 4, compressed_size)
+                .ok_or_else(|| invalid("truncated block"))?;
+            if compressed_size == stream_size {
+                block_data.extend_from_slice(compressed);
+            } else {
+                block_data.extend(decompress_stream(compressor, compressed, stream_size)?);
+            }
+
+            offset += 4 + compressed_size;
+        }
+
+        if block_data.len() != size {
+            return Err(invalid(&format!("expected a block of {} bytes, found {}", size, block_data.len())));
+        }
+
+        if flags & FLAG_SHUFFLE != 0 && type_size > 1 {
+            unshuffle(&block_data, type_size, &mut data);
+        } else {
+            data.extend(block_data);
+        }
+    }
+
+    Ok(data)
+}
+
+/// Decompress a stream of a block with a compressor. The bytes after the size of the stream are
+/// not decompressed, except for one, so that streams that are too large are detected.
+fn decompress_stream(compressor: u8, compressed: &[u8], size: usize) -> Result<Vec<u8>, Error> {
+    let mut data = Vec::with_capacity(size);
+    match compressor {
+        COMPRESSOR_LZ4 => {
+            data = lz4_flex::block::decompress(compressed, size)
+                .map_err(|error| invalid(&format!("invalid lz4 stream: {}", error)))?;
+        }
+        COMPRESSOR_ZLIB => {
+            ZlibDecoder::new(compressed).take(size as u64 + 1).read_to_end(&mut data)?;
+        }
+        COMPRESSOR_ZSTD => {
+            ruzstd::decoding::StreamingDecoder::new(compressed)
+                .map_err(|error| invalid(&format!("invalid zstd stream: {}", error)))?
+                .take(size as u64 + 1)
+                .read_to_end(&mut data)?;
+        }
+        compressor => {
+            return Err(Error::UnsupportedDatatype(format!("blosc compressor {} is not supported, only lz4, zlib and zstd are", compressor)));
+        }
+    }
+
+    Ok(data)
+}
+
+/// Gather the bytes of the values of a shuffled block, which stores the first byte of all its
+/// values, then their second byte, and so on. The bytes after the last whole value are not
+/// shuffled.
+fn unshuffle(block: &[u8], type_size: usize, data: &mut Vec<u8>) {
+    let value_count = block.len() / type_size;
+    for value in 0..value_count {
+        data.extend((0..type_size).map(|byte| block[byte * value_count + value]));
+    }
+
+    data.extend_from_slice(&block[value_count * type_size..]);
+}
+
+fn invalid(message: &str) -> Error {
+    Error::InvalidHeader(format!("invalid blosc data: {}", message))
+}
+
+/// Get the bytes of a source at an offset, if the source has them.
+fn get_bytes(source: &[u8], offset: usize, size: usize) -> Option<&[u8]> {
+    source.get(offset..)?.get(..size)
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use flate2::{Compression, write::ZlibEncoder};
+
+    use super::*;
+
+    /// Shuffle the bytes of the values of a block, which is the inverse of `unshuffle`.
+    fn shuffle(block: &[u8], type_size: usize) -> Vec<u8> {
+        let value_count = block.len() / type_size;
+        let mut shuffled: Vec<u8> = (0..type_size)
+            .flat_map(|byte| (0..value_count).map(move |value| block[value * type_size + byte]))
+            .collect();
+        shuffled.extend_from_slice(&block[value_count * type_size..]);
+        shuffled
+    }
+
+    /// Compress a Blosc1 buffer, whose streams are compressed by a function, or stored if they are
+    /// not smaller.
+    fn compress(data: &[u8], type_size: u8, block_size: usize, flags: u8, compress_stream: impl Fn(&[u8]) -> Vec<u8>) -> Vec<u8> {
+        let blocks: Vec<&[u8]> = data.chunks(block_size).collect();
+        let mut buffer = vec![2, 1, flags, type_size];
+        buffer.extend((data.len() as u32).to_le_bytes());
+        buffer.extend((block_size as u32).to_le_bytes());
+        buffer.extend(0u32.to_le_bytes());
+
+        let mut block_data = Vec::new();
+        for block in &blocks {
+            buffer.extend(((HEADER_SIZE + 4 * blocks.len() + block_data.len()) as u32).to_le_bytes());
+            let block = if flags & FLAG_SHUFFLE != 0 { shuffle(block, type_size as usize) } else { block.to_vec() };
+            let stream_count = if flags & FLAG_DONT_SPLIT == 0 && block.len() == block_size { type_size as usize } else { 1 };
+            for stream in block.chunks(block.len() / stream_count) {
+                let compressed = compress_stream(stream);
+                let compressed = if compressed.len() < stream.len() { compressed } else { stream.to_vec() };
+                block_data.extend((compressed.len() as u32).to_le_bytes());
+                block_data.extend(compressed);
+            }
+        }
+
+        buffer.extend(block_data);
+        let compressed_size = buffer.len() as u32;
+        buffer[12..16].copy_from_slice(&compressed_size.to_le_bytes());
+        buffer
+    }
+
+    fn zlib(stream: &[u8]) -> Vec<u8> {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(stream).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    /// Get 16-bit values with repeated high bytes, so that shuffled streams are compressible.
+    fn get_data() -> Vec<u8> {
+        (0..100u16).flat_map(|value| (value % 7 + 0x1200).to_le_bytes()).collect()
+    }
+
+    #[test]
+    fn decompresses_memcpyed_buffers() {
+        let data = get_data();
+        let mut buffer = compress(&[], 2, 64, FLAG_MEMCPYED, |stream| stream.to_vec());
+        buffer[4..8].copy_from_slice(&(data.len() as u32).to_le_bytes());
+        buffer.extend(&data);
+
+        assert_eq!(decompress(&buffer, data.len()).unwrap(), data);
+        assert!(decompress(&buffer, data.len() - 1).is_err());
+        assert!(decompress(&buffer[..buffer.len() - 1], data.len()).is_err());
+    }
+
+    #[test]
+    fn decompresses_shuffled_and_split_blocks() {
+        let data = get_data();
+        let lz4 = |stream: &[u8]| lz4_flex::block::compress(stream);
+        // The blocks of 64 bytes leave a leftover block of 8 bytes, which is not split.
+        let buffers = [
+            compress(&data, 2, 64, FLAG_SHUFFLE | (COMPRESSOR_LZ4 << 5), lz4),
+            compress(&data, 2, 64, FLAG_SHUFFLE | FLAG_DONT_SPLIT | (COMPRESSOR_LZ4 << 5), lz4),
+            compress(&data, 2, 64, FLAG_SHUFFLE | (COMPRESSOR_ZLIB << 5), zlib),
+            compress(&data, 2, 64, COMPRESSOR_ZLIB << 5, zlib),
+            compress(&data, 2, 64, FLAG_SHUFFLE | (COMPRESSOR_ZLIB << 5), |stream| stream.to_vec()),
+        ];
+
+        for buffer in buffers {
+            assert_eq!(decompress(&buffer, data.len()).unwrap(), data);
+            assert!(decompress(&buffer, data.len() - 1).is_err());
+            for size in 0..buffer.len() {
+                assert!(decompress(&buffer[..size], data.len()).is_err(), "decompressed a buffer from {} bytes", size);
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_streams_larger_than_their_block() {
+        let data = get_data();
+        // The streams decompress to more bytes than their block.
+        let buffer = compress(&data, 2, 256, FLAG_DONT_SPLIT | (COMPRESSOR_ZLIB << 5), |stream| zlib(&[stream, stream].concat()));
+        assert!(decompress(&buffer, data.len()).is_err());
+    }
+
+    #[test]
+    fn rejects_unsupported_buffers() {
+        let data = get_data();
+        assert!(decompress(&compress(&data, 2, 64, FLAG_BITSHUFFLE | (COMPRESSOR_ZLIB << 5), zlib), data.len()).is_err());
+        assert!(decompress(&compress(&data, 2, 64, 2 << 5, zlib), data.len()).is_err());
+        let mut buffer = compress(&data, 2, 64, COMPRESSOR_ZLIB << 5, zlib);
+        buffer[8..12].fill(0);
+        assert!(decompress(&buffer, data.len()).is_err());
+    }
+}
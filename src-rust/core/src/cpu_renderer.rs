@@ -0,0 +1,205 @@
+use ndarray::Array4;
+
+use crate::{display_window::{DisplayPolarity, DisplayWindow}, error::Error, nifti::{AnatomicalAxis, Rotation, SliceView}, volume::{Volume, Voxel, with_volume}};
+
+/// Render a slice of a volume into an image of the given size, and return its RGBA8 pixels, row by
+/// row from the top of the image. This is a CPU implementation of the slice pipeline of
+/// `shader.wgsl`, which produces the same pixels as the GPU renderer.
+pub fn render_slice(volume: &Volume, width: u32, height: u32, view: &SliceView) -> Result<Vec<u8>, Error> {
+    view.validate(volume)?;
+    Ok(with_volume!(volume, array => render_typed_slice(array, width, height, view)))
+}
+
+/// Get the position of a slice between `0.0` for the first slice and `1.0` for the last slice, or
+/// `0.0` if the volume has a single slice along the axis.
+pub fn normalize_slice_index(slice_index: usize, dimension_length: usize) -> f32 {
+    slice_index as f32 / dimension_length.saturating_sub(1).max(1) as f32
+}
+
+fn render_typed_slice<T: Voxel>(volume: &Array4<T>, width: u32, height: u32, view: &SliceView) -> Vec<u8> {
+    let SliceView { axis, coordinate: slice_index, timepoint, window, rotation } = *view;
+    let (x_size, y_size, z_size, _) = volume.dim();
+    let volume_dims = [x_size as f32, y_size as f32, z_size as f32];
+
+    let dimension_length = match axis {
+        AnatomicalAxis::Axial    => z_size,
+        AnatomicalAxis::Coronal  => y_size,
+        AnatomicalAxis::Sagittal => x_size,
+    };
+
+    let normalized_slice_index = normalize_slice_index(slice_index, dimension_length);
+
+    let mut pixels = Vec::with_capacity(4 * width as usize * height as usize);
+    for y in 0..height {
+        for x in 0..width {
+            // Sample the fragment at the center of the pixel, like the rasterizer does.
+            let tex_coords = [
+                (x as f32 + 0.5) / width as f32,
+                (y as f32 + 0.5) / height as f32,
+            ];
+
+            let voxel_coords = get_voxel_coords(tex_coords, axis, rotation, normalized_slice_index, volume_dims);
+
+            // Get the nearest voxel, clamped to the edges of the volume.
+            let voxel = [0, 1, 2].map(|i| {
+                let size = volume.shape()[i];
+                ((voxel_coords[i] * size as f32).floor().max(0.0) as usize).min(size - 1)
+            });
+
+            let raw_value = volume[[voxel[0], voxel[1], voxel[2], timepoint]].to_f32();
+            let value = (get_grayscale_value(raw_value, window) * 255.0).round() as u8;
+            pixels.extend_from_slice(&[value, value, value, 255]);
+        }
+    }
+
+    pixels
+}
+
+/// Get the grayscale value of a voxel intensity between `0.0` and `1.0`, using a display window.
+pub fn get_grayscale_value(raw_value: f32, window: DisplayWindow) -> f32 {
+    // Normalize the value based on window parameters.
+    let normalized_value = (raw_value - window.min()) / (window.max() - window.min());
+
+    // Clamp the normalized value into a grayscale value.
+    let grayscale_value = normalized_value.clamp(0.0, 1.0);
+
+    // Invert the color if specified in the parameters.
+    match window.polarity {
+        DisplayPolarity::Positive => grayscale_value,
+        DisplayPolarity::Negative => 1.0 - grayscale_value,
+    }
+}
+
+fn get_voxel_coords(tex_coords: [f32; 2], axis: AnatomicalAxis, rotation: Rotation, slice_index: f32, volume_dims: [f32; 3]) -> [f32; 3] {
+    let [x, y, z] = volume_dims;
+    match axis {
+        AnatomicalAxis::Axial => { // XY plane at Z
+            let [u, v] = rotate_slice_coords(tex_coords, rotation, [x, y]);
+            [u, v, slice_index]
+        }
+        AnatomicalAxis::Coronal => { // XZ plane at Y
+            let [u, v] = rotate_slice_coords(tex_coords, rotation, [x, z]);
+            [u, slice_index, v]
+        }
+        AnatomicalAxis::Sagittal => { // YZ plane at X
+            let [u, v] = rotate_slice_coords(tex_coords, rotation, [y, z]);
+            [slice_index, u, v]
+        }
+    }
+}
+
+fn rotate_slice_coords(tex_coords: [f32; 2], rotation: Rotation, plane_dims: [f32; 2]) -> [f32; 2] {
+    let voxel_pos = [tex_coords[0] * plane_dims[0], tex_coords[1] * plane_dims[1]];
+
+    match rotation {
+        Rotation::Rotate0 => tex_coords,
+        Rotation::Rotate90 => { // 90 degrees clockwise
+            [voxel_pos[1] / plane_dims[1], (plane_dims[0] - voxel_pos[0]) / plane_dims[0]]
+        }
+        Rotation::Rotate180 => {
+            [(plane_dims[0] - voxel_pos[0]) / plane_dims[0], (plane_dims[1] - voxel_pos[1]) / plane_dims[1]]
+        }
+        Rotation::Rotate270 => { // 270 degrees clockwise
+            [(plane_dims[1] - voxel_pos[1]) / plane_dims[1], voxel_pos[0] / plane_dims[0]]
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ndarray::Array4;
+
+    use super::*;
+
+    /// A window that displays the intensities from 0 to 255 as the same gray levels.
+    const IDENTITY_WINDOW: DisplayWindow = DisplayWindow { level: 127.5, width: 255.0, polarity: DisplayPolarity::Positive };
+
+    /// Create a volume whose voxel at `(x, y, z, t)` is `x + 3y + 6z + 12t`, so that every voxel
+    /// of a 3x2x2x2 volume has a distinct intensity.
+    fn create_volume(dimensions: (usize, usize, usize, usize)) -> Volume {
+        Volume::U8(Array4::from_shape_fn(dimensions, |(x, y, z, t)| (x + 3 * y + 6 * z + 12 * t) as u8))
+    }
+
+    fn create_view(axis: AnatomicalAxis, coordinate: usize, timepoint: usize, rotation: Rotation) -> SliceView {
+        SliceView { axis, coordinate, timepoint, window: IDENTITY_WINDOW, rotation }
+    }
+
+    /// Render a slice and get the gray level of its pixels, row by row.
+    fn render_gray_slice(volume: &Volume, width: u32, height: u32, view: &SliceView) -> Vec<u8> {
+        let pixels = render_slice(volume, width, height, view).unwrap();
+        assert_eq!(pixels.len(), 4 * width as usize * height as usize);
+        pixels.chunks_exact(4)
+            .map(|pixel| {
+                assert!(pixel[0] == pixel[1] && pixel[1] == pixel[2] && pixel[3] == 255);
+                pixel[0]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn renders_the_slices_of_each_axis() {
+        let volume = create_volume((3, 2, 2, 2));
+
+        let axial = create_view(AnatomicalAxis::Axial, 1, 0, Rotation::Rotate0);
+        assert_eq!(render_gray_slice(&volume, 3, 2, &axial), [6, 7, 8, 9, 10, 11]);
+
+        let coronal = create_view(AnatomicalAxis::Coronal, 1, 1, Rotation::Rotate0);
+        assert_eq!(render_gray_slice(&volume, 3, 2, &coronal), [15, 16, 17, 21, 22, 23]);
+
+        let sagittal = create_view(AnatomicalAxis::Sagittal, 2, 0, Rotation::Rotate0);
+        assert_eq!(render_gray_slice(&volume, 2, 2, &sagittal), [2, 5, 8, 11]);
+    }
+
+    #[test]
+    fn renders_rotated_slices() {
+        let volume = create_volume((3, 2, 2, 2));
+
+        let rotate_90 = create_view(AnatomicalAxis::Axial, 1, 0, Rotation::Rotate90);
+        assert_eq!(render_gray_slice(&volume, 2, 3, &rotate_90), [9, 6, 10, 7, 11, 8]);
+
+        let rotate_180 = create_view(AnatomicalAxis::Axial, 1, 0, Rotation::Rotate180);
+        assert_eq!(render_gray_slice(&volume, 3, 2, &rotate_180), [11, 10, 9, 8, 7, 6]);
+
+        let rotate_270 = create_view(AnatomicalAxis::Axial, 1, 0, Rotation::Rotate270);
+        assert_eq!(render_gray_slice(&volume, 2, 3, &rotate_270), [8, 11, 7, 10, 6, 9]);
+    }
+
+    #[test]
+    fn scales_slices_to_the_image_size() {
+        let volume = create_volume((3, 2, 2, 2));
+        let view = create_view(AnatomicalAxis::Sagittal, 0, 0, Rotation::Rotate0);
+        assert_eq!(render_gray_slice(&volume, 4, 2, &view), [0, 0, 3, 3, 6, 6, 9, 9]);
+    }
+
+    #[test]
+    fn renders_volumes_with_a_single_slice() {
+        let volume = create_volume((3, 2, 1, 1));
+        let axial = create_view(AnatomicalAxis::Axial, 0, 0, Rotation::Rotate0);
+        assert_eq!(render_gray_slice(&volume, 3, 2, &axial), [0, 1, 2, 3, 4, 5]);
+
+        let volume = create_volume((3, 1, 2, 1));
+        let coronal = create_view(AnatomicalAxis::Coronal, 0, 0, Rotation::Rotate0);
+        assert_eq!(render_gray_slice(&volume, 3, 2, &coronal), [0, 1, 2, 6, 7, 8]);
+    }
+
+    #[test]
+    fn applies_the_display_window() {
+        let volume = create_volume((3, 2, 2, 2));
+        let mut view = create_view(AnatomicalAxis::Axial, 0, 0, Rotation::Rotate0);
+        view.window = DisplayWindow { level: 3.0, width: 2.0, polarity: DisplayPolarity::Positive };
+        assert_eq!(render_gray_slice(&volume, 3, 2, &view), [0, 0, 0, 128, 255, 255]);
+
+        view.window.polarity = DisplayPolarity::Negative;
+        assert_eq!(render_gray_slice(&volume, 3, 2, &view), [255, 255, 255, 128, 0, 0]);
+    }
+
+    #[test]
+    fn rejects_views_outside_the_volume() {
+        let volume = create_volume((3, 2, 2, 2));
+        let slice = create_view(AnatomicalAxis::Axial, 2, 0, Rotation::Rotate0);
+        assert!(matches!(render_slice(&volume, 3, 2, &slice), Err(Error::BadArgument(_))));
+
+        let timepoint = create_view(AnatomicalAxis::Coronal, 0, 2, Rotation::Rotate0);
+        assert!(matches!(render_slice(&volume, 3, 2, &timepoint), Err(Error::BadArgument(_))));
+    }
+}
@@ -0,0 +1,958 @@
+use std::collections::BTreeMap;
+
+use crate::{error::Error, geometry::Affine, image_reader::{ReaderLimits, get_voxel_count}, nifti::Nifti, volume::{Volume, Voxel, VoxelType, with_volume}};
+
+/// The size of the preamble of DICOM files, which is followed by the `DICM` magic.
+const PREAMBLE_SIZE: usize = 128;
+
+const DICM_MAGIC: &[u8; 4] = b"DICM";
+
+const IMPLICIT_VR_LITTLE_ENDIAN: &str = "1.2.840.10008.1.2";
+const EXPLICIT_VR_LITTLE_ENDIAN: &str = "1.2.840.10008.1.2.1";
+const RLE_LOSSLESS: &str = "1.2.840.10008.1.2.5";
+
+/// The length of the elements and items whose end is marked by a delimitation item.
+const UNDEFINED_LENGTH: u32 = 0xFFFF_FFFF;
+
+/// The maximum nesting depth of sequences, so that hostile files cannot overflow the stack.
+const MAX_SEQUENCE_DEPTH: usize = 16;
+
+/// The maximum distance between the positions of two frames of the same slice, in millimeters.
+const POSITION_TOLERANCE: f64 = 0.01;
+
+/// The maximum difference between the direction cosines of two frames of the same series.
+const ORIENTATION_TOLERANCE: f64 = 1e-4;
+
+/// The explicit value representations whose length is stored on four bytes.
+const LONG_VRS: [&[u8; 2]; 13] = [b"OB", b"OD", b"OF", b"OL", b"OV", b"OW", b"SQ", b"SV", b"UC", b"UN", b"UR", b"UT", b"UV"];
+
+/// A DICOM tag, as its group and element numbers.
+type Tag = (u16, u16);
+
+const TRANSFER_SYNTAX_UID: Tag         = (0x0002, 0x0010);
+const SERIES_DESCRIPTION: Tag          = (0x0008, 0x103E);
+const SLICE_THICKNESS: Tag             = (0x0018, 0x0050);
+const SERIES_INSTANCE_UID: Tag         = (0x0020, 0x000E);
+const SERIES_NUMBER: Tag               = (0x0020, 0x0011);
+const INSTANCE_NUMBER: Tag             = (0x0020, 0x0013);
+const IMAGE_POSITION_PATIENT: Tag      = (0x0020, 0x0032);
+const IMAGE_ORIENTATION_PATIENT: Tag   = (0x0020, 0x0037);
+const PLANE_POSITION_SEQUENCE: Tag     = (0x0020, 0x9113);
+const PLANE_ORIENTATION_SEQUENCE: Tag  = (0x0020, 0x9116);
+const SAMPLES_PER_PIXEL: Tag           = (0x0028, 0x0002);
+const NUMBER_OF_FRAMES: Tag            = (0x0028, 0x0008);
+const ROWS: Tag                        = (0x0028, 0x0010);
+const COLUMNS: Tag                     = (0x0028, 0x0011);
+const PIXEL_SPACING: Tag               = (0x0028, 0x0030);
+const BITS_ALLOCATED: Tag              = (0x0028, 0x0100);
+const BITS_STORED: Tag                 = (0x0028, 0x0101);
+const PIXEL_REPRESENTATION: Tag        = (0x0028, 0x0103);
+const RESCALE_INTERCEPT: Tag           = (0x0028, 0x1052);
+const RESCALE_SLOPE: Tag               = (0x0028, 0x1053);
+const PIXEL_MEASURES_SEQUENCE: Tag     = (0x0028, 0x9110);
+const PIXEL_VALUE_TRANSFORMATION: Tag  = (0x0028, 0x9145);
+const SHARED_FUNCTIONAL_GROUPS: Tag    = (0x5200, 0x9229);
+const PER_FRAME_FUNCTIONAL_GROUPS: Tag = (0x5200, 0x9230);
+const PIXEL_DATA: Tag                  = (0x7FE0, 0x0010);
+const ITEM: Tag                        = (0xFFFE, 0xE000);
+const ITEM_DELIMITATION: Tag           = (0xFFFE, 0xE00D);
+const SEQUENCE_DELIMITATION: Tag       = (0xFFFE, 0xE0DD);
+
+/// The sequences read by the reader, whose value representation is not stored in implicit VR
+/// files.
+const SEQUENCE_TAGS: [Tag; 6] = [
+    PLANE_POSITION_SEQUENCE,
+    PLANE_ORIENTATION_SEQUENCE,
+    PIXEL_MEASURES_SEQUENCE,
+    PIXEL_VALUE_TRANSFORMATION,
+    SHARED_FUNCTIONAL_GROUPS,
+    PER_FRAME_FUNCTIONAL_GROUPS,
+];
+
+/// An image of a DICOM series, read from a single DICOM file, which contains one or more frames.
+pub struct DicomInstance {
+    series_uid: String,
+    series_number: Option<i64>,
+    series_description: String,
+    instance_number: Option<i64>,
+    rows: usize,
+    columns: usize,
+    format: PixelFormat,
+    frames: Vec<DicomFrame>,
+}
+
+/// A 2D frame of a DICOM image, whose pixels are the little-endian stored values.
+struct DicomFrame {
+    position: Option<[f64; 3]>,
+    orientation: Option<[f64; 6]>,
+    /// The spacing between the rows and between the columns, in millimeters.
+    spacing: Option<[f64; 2]>,
+    slice_thickness: Option<f64>,
+    slope: f64,
+    intercept: f64,
+    pixels: Vec<u8>,
+}
+
+/// The encoding of the stored values of the pixels of a DICOM image.
+#[derive(Clone, Copy, PartialEq)]
+struct PixelFormat {
+    bits_allocated: u16,
+    bits_stored: u16,
+    is_signed: bool,
+}
+
+/// A volume read from a DICOM series.
+pub struct DicomSeries {
+    pub uid: String,
+    pub number: Option<i64>,
+    pub nifti: Nifti,
+}
+
+impl DicomInstance {
+    /// Parse a DICOM file, with an implicit VR little endian, explicit VR little endian or RLE
+    /// lossless transfer syntax, and decode its frames, whose voxels must be within the limits.
+    pub fn parse(bytes: &[u8], limits: ReaderLimits) -> Result<Self, Error> {
+        let data_set = parse_file(bytes)?;
+        let rows = data_set.u16(ROWS).ok_or_else(|| missing_attribute("rows"))? as usize;
+        let columns = data_set.u16(COLUMNS).ok_or_else(|| missing_attribute("columns"))? as usize;
+        if rows == 0 || columns == 0 {
+            return Err(Error::InvalidHeader(format!("invalid image size {} x {}", columns, rows)));
+        }
+
+        let samples = data_set.u16(SAMPLES_PER_PIXEL).unwrap_or(1);
+        if samples != 1 {
+            return Err(Error::UnsupportedDatatype(format!("images with {} samples per pixel are not supported", samples)));
+        }
+
+        let format = PixelFormat {
+            bits_allocated: data_set.u16(BITS_ALLOCATED).ok_or_else(|| missing_attribute("bits allocated"))?,
+            bits_stored: data_set.u16(BITS_STORED).ok_or_else(|| missing_attribute("bits stored"))?,
+            is_signed: data_set.u16(PIXEL_REPRESENTATION) == Some(1),
+        };
+
+        if ![8, 16, 32].contains(&format.bits_allocated) || format.bits_stored == 0 || format.bits_stored > format.bits_allocated {
+            return Err(Error::UnsupportedDatatype(format!("{} bits stored in {} bits are not supported", format.bits_stored, format.bits_allocated)));
+        }
+
+        let frame_count = data_set.number(NUMBER_OF_FRAMES).map_or(1, |count| count as usize).max(1);
+        // The frames are checked against the limits before they are decompressed.
+        get_voxel_count(&[columns, rows, frame_count], limits)?;
+        let frame_size = rows * columns * (format.bits_allocated / 8) as usize;
+        let pixels = decode_pixel_data(&data_set, frame_count, frame_size, format)?;
+
+        let shared = data_set.items(SHARED_FUNCTIONAL_GROUPS).first();
+        let per_frame = data_set.items(PER_FRAME_FUNCTIONAL_GROUPS);
+        let frames = pixels.into_iter().enumerate().map(|(i, pixels)| {
+            // The attributes of enhanced multi-frame images are in their functional groups, and
+            // the attributes of the other images apply to all their frames.
+            let group = |sequence| {
+                per_frame.get(i)
+                    .and_then(|groups| groups.items(sequence).first())
+                    .or_else(|| shared.and_then(|groups| groups.items(sequence).first()))
+                    .unwrap_or(&data_set)
+            };
+
+            DicomFrame {
+                position: group(PLANE_POSITION_SEQUENCE).numbers(IMAGE_POSITION_PATIENT).try_into().ok(),
+                orientation: group(PLANE_ORIENTATION_SEQUENCE).numbers(IMAGE_ORIENTATION_PATIENT).try_into().ok(),
+                spacing: group(PIXEL_MEASURES_SEQUENCE).numbers(PIXEL_SPACING).try_into().ok(),
+                slice_thickness: group(PIXEL_MEASURES_SEQUENCE).number(SLICE_THICKNESS),
+                slope: group(PIXEL_VALUE_TRANSFORMATION).number(RESCALE_SLOPE).filter(|&slope| slope != 0.0).unwrap_or(1.0),
+                intercept: group(PIXEL_VALUE_TRANSFORMATION).number(RESCALE_INTERCEPT).unwrap_or(0.0),
+                pixels,
+            }
+        }).collect();
+
+        Ok(Self {
+            series_uid: data_set.string(SERIES_INSTANCE_UID).ok_or_else(|| missing_attribute("series instance uid"))?,
+            series_number: data_set.number(SERIES_NUMBER).map(|number| number as i64),
+            series_description: data_set.string(SERIES_DESCRIPTION).unwrap_or_default(),
+            instance_number: data_set.number(INSTANCE_NUMBER).map(|number| number as i64),
+            rows,
+            columns,
+            format,
+            frames,
+        })
+    }
+
+    /// Get the unique identifier of the series of this image.
+    pub fn series_uid(&self) -> &str {
+        &self.series_uid
+    }
+}
+
+/// Group DICOM images by series, and read the volume of each series, sorted by series number.
+pub fn read_dicom_series(instances: Vec<DicomInstance>, limits: ReaderLimits) -> Result<Vec<DicomSeries>, Error> {
+    let mut series_instances = BTreeMap::<String, Vec<DicomInstance>>::new();
+    for instance in instances {
+        series_instances.entry(instance.series_uid.clone()).or_default().push(instance);
+    }
+
+    let mut series = series_instances.into_iter()
+        .map(|(uid, instances)| read_series(uid, instances, limits))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    series.sort_by_key(|series| series.number.unwrap_or(i64::MAX));
+    Ok(series)
+}
+
+/// Read the volume of a series, whose frames are sorted by position along the slice normal.
+/// Frames at the same position are the timepoints of their slice, in acquisition order.
+fn read_series(uid: String, mut instances: Vec<DicomInstance>, limits: ReaderLimits) -> Result<DicomSeries, Error> {
+    log::debug!("reading dicom series {} with {} images", uid, instances.len());
+    instances.sort_by_key(|instance| instance.instance_number.unwrap_or(i64::MAX));
+    let first = &instances[0];
+    let (rows, columns, format) = (first.rows, first.columns, first.format);
+    if instances.iter().any(|instance| instance.rows != rows || instance.columns != columns || instance.format != format) {
+        return Err(Error::InvalidHeader(format!("series {} has images of different sizes or datatypes", uid)));
+    }
+
+    let frames: Vec<&DicomFrame> = instances.iter().flat_map(|instance| &instance.frames).collect();
+    let orientation = frames[0].orientation.unwrap_or([1.0, 0.0, 0.0, 0.0, 1.0, 0.0]);
+    let is_same_orientation = |frame: &&DicomFrame| frame.orientation.is_none_or(|other| {
+        other.iter().zip(orientation).all(|(a, b)| (a - b).abs() < ORIENTATION_TOLERANCE)
+    });
+
+    if !frames.iter().all(is_same_orientation) {
+        return Err(Error::InvalidHeader(format!("series {} has images with different orientations", uid)));
+    }
+
+    // Series without positions are assumed to be consecutive slices, and their indices cannot be
+    // compared with the distances of positioned frames.
+    let normal = cross([orientation[0], orientation[1], orientation[2]], [orientation[3], orientation[4], orientation[5]]);
+    let positioned_count = frames.iter().filter(|frame| frame.position.is_some()).count();
+    if positioned_count != 0 && positioned_count != frames.len() {
+        return Err(Error::InvalidHeader(format!("series {} has images with and without positions", uid)));
+    }
+
+    let distances: Vec<f64> = frames.iter().enumerate()
+        .map(|(i, frame)| frame.position.map_or(i as f64, |position| dot(position, normal)))
+        .collect();
+
+    let mut order: Vec<usize> = (0..frames.len()).collect();
+    order.sort_by(|&a, &b| distances[a].total_cmp(&distances[b]));
+    let mut slices: Vec<Vec<usize>> = Vec::new();
+    for index in order {
+        match slices.last_mut() {
+            Some(slice) if (distances[index] - distances[slice[0]]).abs() < POSITION_TOLERANCE => slice.push(index),
+            _ => slices.push(vec![index]),
+        }
+    }
+
+    let timepoints = slices[0].len();
+    if slices.iter().any(|slice| slice.len() != timepoints) {
+        return Err(Error::InvalidHeader(format!("series {} has slices with different numbers of timepoints", uid)));
+    }
+
+    let dimensions = (columns, rows, slices.len(), timepoints);
+    let voxel_count = get_voxel_count(&[columns, rows, slices.len(), timepoints], limits)?;
+
+    log::debug!("found {} voxels in {} slices of {} timepoints", voxel_count, slices.len(), timepoints);
+    let mut volume = Volume::zeros(get_voxel_type(&frames, format), dimensions);
+    with_volume!(&mut volume, array => {
+        for (slice_index, slice) in slices.iter().enumerate() {
+            for (timepoint, &frame_index) in slice.iter().enumerate() {
+                let frame = frames[frame_index];
+                for (pixel, value) in stored_values(&frame.pixels, format).enumerate() {
+                    let voxel = &mut array[[pixel % columns, pixel / columns, slice_index, timepoint]];
+                    *voxel = Voxel::from_f64(value as f64 * frame.slope + frame.intercept);
+                }
+            }
+        }
+    });
+
+    let first_frame = frames[slices[0][0]];
+    let last_frame = frames[slices[slices.len() - 1][0]];
+    let affine = get_affine(first_frame, last_frame, orientation, normal, slices.len());
+    Ok(DicomSeries {
+        uid,
+        number: first.series_number,
+        nifti: Nifti { volume, affine, description: first.series_description.clone(), extensions: Vec::new() },
+    })
+}
+
+/// Get the datatype in which the rescaled values of the frames of a series can be stored without
+/// loss. Rescaled values that are not integers are stored as `f32`.
+fn get_voxel_type(frames: &[&DicomFrame], format: PixelFormat) -> VoxelType {
+    let (stored_minimum, stored_maximum) = if format.is_signed {
+        (-(1i64 << (format.bits_stored - 1)) as f64, ((1i64 << (format.bits_stored - 1)) - 1) as f64)
+    } else {
+        (0.0, ((1i64 << format.bits_stored) - 1) as f64)
+    };
+
+    let mut minimum = f64::INFINITY;
+    let mut maximum = f64::NEG_INFINITY;
+    for frame in frames {
+        if frame.slope.fract() != 0.0 || frame.intercept.fract() != 0.0 {
+            return VoxelType::F32;
+        }
+
+        let bounds = [stored_minimum, stored_maximum].map(|value| value * frame.slope + frame.intercept);
+        minimum = minimum.min(bounds[0].min(bounds[1]));
+        maximum = maximum.max(bounds[0].max(bounds[1]));
+    }
+
+    let fits = |type_minimum: f64, type_maximum: f64| minimum >= type_minimum && maximum <= type_maximum;
+    if fits(u8::MIN as f64, u8::MAX as f64) {
+        VoxelType::U8
+    } else if fits(i16::MIN as f64, i16::MAX as f64) {
+        VoxelType::I16
+    } else if fits(u16::MIN as f64, u16::MAX as f64) {
+        VoxelType::U16
+    } else if fits(i32::MIN as f64, i32::MAX as f64) {
+        VoxelType::I32
+    } else {
+        VoxelType::F32
+    }
+}
+
+/// Get the affine of a series from its first and last slices. DICOM coordinates are LPS, and are
+/// converted to the RAS coordinates of NIfTI.
+fn get_affine(first_frame: &DicomFrame, last_frame: &DicomFrame, orientation: [f64; 6], normal: [f64; 3], slice_count: usize) -> Affine {
+    let [row_spacing, column_spacing] = first_frame.spacing.unwrap_or([1.0, 1.0]);
+    let origin = first_frame.position.unwrap_or([0.0, 0.0, 0.0]);
+    let slice_step = match (first_frame.position, last_frame.position) {
+        (Some(first), Some(last)) if slice_count > 1 => [0, 1, 2].map(|i| (last[i] - first[i]) / (slice_count - 1) as f64),
+        _ => normal.map(|value| value * first_frame.slice_thickness.unwrap_or(1.0)),
+    };
+
+    let mut matrix = Affine::IDENTITY.0;
+    for row in 0..3 {
+        // The columns of an image are along its row direction, and the other way around.
+        let sign = if row < 2 { -1.0 } else { 1.0 };
+        matrix[row][0] = sign * orientation[row] * column_spacing;
+        matrix[row][1] = sign * orientation[row + 3] * row_spacing;
+        matrix[row][2] = sign * slice_step[row];
+        matrix[row][3] = sign * origin[row];
+    }
+
+    Affine(matrix)
+}
+
+/// Decode the stored values of little-endian pixels.
+fn stored_values(pixels: &[u8], format: PixelFormat) -> impl Iterator<Item = i64> + '_ {
+    let size = (format.bits_allocated / 8) as usize;
+    let mask = (1u64 << format.bits_stored) - 1;
+    let sign_bit = 1u64 << (format.bits_stored - 1);
+    pixels.chunks_exact(size).map(move |bytes| {
+        let value = bytes.iter().rev().fold(0u64, |value, &byte| (value << 8) | byte as u64) & mask;
+        if format.is_signed && value & sign_bit != 0 {
+            value as i64 - (mask as i64 + 1)
+        } else {
+            value as i64
+        }
+    })
+}
+
+/// Get the little-endian pixels of each frame of a DICOM image, decompressing them if needed.
+fn decode_pixel_data(data_set: &DataSet, frame_count: usize, frame_size: usize, format: PixelFormat) -> Result<Vec<Vec<u8>>, Error> {
+    match data_set.elements.get(&PIXEL_DATA) {
+        Some(Value::Bytes(bytes)) => {
+            let size = frame_count.checked_mul(frame_size).filter(|&size| size <= bytes.len())
+                .ok_or_else(|| Error::InvalidHeader(format!("pixel data is truncated, expected {} frames of {} bytes", frame_count, frame_size)))?;
+            Ok(bytes[..size].chunks_exact(frame_size).map(<[u8]>::to_vec).collect())
+        }
+        Some(Value::Fragments(fragments)) => {
+            // The first fragment is the offset table of the frames, and each RLE frame is a single
+            // fragment.
+            let fragments = fragments.get(1..).unwrap_or_default();
+            if fragments.len() != frame_count {
+                return Err(Error::InvalidHeader(format!("expected {} compressed frames, found {}", frame_count, fragments.len())));
+            }
+
+            fragments.iter().map(|fragment| decode_rle_frame(fragment, frame_size, format)).collect()
+        }
+        _ => Err(missing_attribute("pixel data")),
+    }
+}
+
+/// Decode an RLE lossless frame into little-endian pixels. Each byte of the pixels is stored in
+/// its own segment, from the most significant byte to the least significant one.
+fn decode_rle_frame(fragment: &[u8], frame_size: usize, format: PixelFormat) -> Result<Vec<u8>, Error> {
+    let invalid = || Error::InvalidHeader("invalid rle frame".to_string());
+    let header: Vec<usize> = fragment.get(..64).ok_or_else(invalid)?
+        .chunks_exact(4)
+        .map(|bytes| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize)
+        .collect();
+
+    let pixel_size = (format.bits_allocated / 8) as usize;
+    let pixel_count = frame_size / pixel_size;
+    if header[0] != pixel_size {
+        return Err(Error::UnsupportedDatatype(format!("rle frames with {} segments are not supported", header[0])));
+    }
+
+    let mut pixels = vec![0; frame_size];
+    for segment_index in 0..pixel_size {
+        let start = header[1 + segment_index];
+        let end = if segment_index + 1 < pixel_size { header[2 + segment_index] } else { fragment.len() };
+        let segment = fragment.get(start..end).ok_or_else(invalid)?;
+        let byte_index = pixel_size - 1 - segment_index;
+        let bytes = decode_packbits(segment, pixel_count)?;
+        for (pixel, byte) in bytes.into_iter().enumerate() {
+            pixels[pixel * pixel_size + byte_index] = byte;
+        }
+    }
+
+    Ok(pixels)
+}
+
+/// Decode a PackBits segment of an RLE frame, which must contain a given number of bytes. The
+/// bytes after the given number are ignored, so that the output never grows past it.
+fn decode_packbits(segment: &[u8], size: usize) -> Result<Vec<u8>, Error> {
+    let mut bytes = Vec::new();
+    let mut position = 0;
+    while position < segment.len() && bytes.len() < size {
+        let header = segment[position] as i8;
+        position += 1;
+        match header {
+            0..=127 => {
+                let length = header as usize + 1;
+                let literal = segment.get(position..position + length).ok_or_else(|| Error::InvalidHeader("invalid rle segment".to_string()))?;
+                bytes.extend_from_slice(&literal[..literal.len().min(size - bytes.len())]);
+                position += length;
+            }
+            -127..=-1 => {
+                let byte = *segment.get(position).ok_or_else(|| Error::InvalidHeader("invalid rle segment".to_string()))?;
+                bytes.resize((bytes.len() + (1 - header as isize) as usize).min(size), byte);
+                position += 1;
+            }
+            -128 => {}
+        }
+    }
+
+    if bytes.len() < size {
+        return Err(Error::InvalidHeader(format!("rle segment is truncated, expected {} bytes, found {}", size, bytes.len())));
+    }
+
+    Ok(bytes)
+}
+
+/// The value of a data element, which borrows the bytes of its file.
+enum Value<'a> {
+    Bytes(&'a [u8]),
+    Sequence(Vec<DataSet<'a>>),
+    /// The fragments of encapsulated pixel data.
+    Fragments(Vec<&'a [u8]>),
+}
+
+/// The data elements of a DICOM file or of a sequence item.
+#[derive(Default)]
+struct DataSet<'a> {
+    elements: BTreeMap<Tag, Value<'a>>,
+}
+
+impl<'a> DataSet<'a> {
+    fn bytes(&self, tag: Tag) -> Option<&'a [u8]> {
+        match self.elements.get(&tag) {
+            Some(Value::Bytes(bytes)) => Some(bytes),
+            _ => None,
+        }
+    }
+
+    /// Get a text value, without its padding.
+    fn string(&self, tag: Tag) -> Option<String> {
+        let bytes = self.bytes(tag)?;
+        let text = String::from_utf8_lossy(bytes);
+        Some(text.trim_end_matches(['\0', ' ']).trim_start().to_string())
+    }
+
+    /// Get the decimal or integer strings of a multi-valued text value.
+    fn numbers(&self, tag: Tag) -> Vec<f64> {
+        self.string(tag)
+            .map(|text| text.split('\\').filter_map(|value| value.trim().parse().ok()).collect())
+            .unwrap_or_default()
+    }
+
+    fn number(&self, tag: Tag) -> Option<f64> {
+        self.numbers(tag).first().copied()
+    }
+
+    /// Get an unsigned short binary value.
+    fn u16(&self, tag: Tag) -> Option<u16> {
+        self.bytes(tag).and_then(|bytes| Some(u16::from_le_bytes(bytes.get(..2)?.try_into().ok()?)))
+    }
+
+    fn items(&self, tag: Tag) -> &[DataSet<'a>] {
+        match self.elements.get(&tag) {
+            Some(Value::Sequence(items)) => items,
+            _ => &[],
+        }
+    }
+}
+
+/// Parse the data set of a DICOM file, which starts with a preamble and a file meta group.
+fn parse_file(bytes: &[u8]) -> Result<DataSet<'_>, Error> {
+    if bytes.get(PREAMBLE_SIZE..PREAMBLE_SIZE + 4) != Some(DICM_MAGIC) {
+        return Err(Error::InvalidHeader("not a dicom file".to_string()));
+    }
+
+    // The file meta group is always explicit VR little endian.
+    let mut parser = Parser { bytes, position: PREAMBLE_SIZE + 4, explicit_vr: true };
+    let mut data_set = DataSet::default();
+    while parser.peek_tag().is_some_and(|(group, _)| group == 0x0002) {
+        let (tag, value) = parser.parse_element(0)?;
+        data_set.elements.insert(tag, value);
+    }
+
+    let transfer_syntax = data_set.string(TRANSFER_SYNTAX_UID).unwrap_or_else(|| IMPLICIT_VR_LITTLE_ENDIAN.to_string());
+    parser.explicit_vr = match transfer_syntax.as_str() {
+        IMPLICIT_VR_LITTLE_ENDIAN => false,
+        EXPLICIT_VR_LITTLE_ENDIAN | RLE_LOSSLESS => true,
+        transfer_syntax => return Err(Error::UnsupportedDatatype(format!("transfer syntax {} is not supported", transfer_syntax))),
+    };
+
+    let (elements, _) = parser.parse_data_set(bytes.len(), 0)?;
+    data_set.elements.extend(elements.elements);
+    Ok(data_set)
+}
+
+/// A parser of little-endian DICOM data elements.
+struct Parser<'a> {
+    bytes: &'a [u8],
+    position: usize,
+    explicit_vr: bool,
+}
+
+impl<'a> Parser<'a> {
+    fn read_bytes(&mut self, length: usize) -> Result<&'a [u8], Error> {
+        let end = self.position.checked_add(length)
+            .filter(|&end| end <= self.bytes.len())
+            .ok_or_else(|| Error::InvalidHeader("dicom file is truncated".to_string()))?;
+        let bytes = &self.bytes[self.position..end];
+        self.position = end;
+        Ok(bytes)
+    }
+
+    fn read_u16(&mut self) -> Result<u16, Error> {
+        let bytes = self.read_bytes(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn read_u32(&mut self) -> Result<u32, Error> {
+        let bytes = self.read_bytes(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn read_tag(&mut self) -> Result<Tag, Error> {
+        Ok((self.read_u16()?, self.read_u16()?))
+    }
+
+    fn peek_tag(&self) -> Option<Tag> {
+        let bytes = self.bytes.get(self.position..self.position + 4)?;
+        Some((u16::from_le_bytes([bytes[0], bytes[1]]), u16::from_le_bytes([bytes[2], bytes[3]])))
+    }
+
+    /// Parse data elements until a position, or until an item delimitation. Returns whether the
+    /// data set ended with an item delimitation.
+    fn parse_data_set(&mut self, end: usize, depth: usize) -> Result<(DataSet<'a>, bool), Error> {
+        let mut data_set = DataSet::default();
+        while self.position < end {
+            if self.peek_tag() == Some(ITEM_DELIMITATION) {
+                self.read_tag()?;
+                self.read_u32()?;
+                return Ok((data_set, true));
+            }
+
+            let (tag, value) = self.parse_element(depth)?;
+            data_set.elements.insert(tag, value);
+        }
+
+        Ok((data_set, false))
+    }
+
+    fn parse_element(&mut self, depth: usize) -> Result<(Tag, Value<'a>), Error> {
+        let tag = self.read_tag()?;
+        let (vr, length) = if self.explicit_vr {
+            let vr = self.read_bytes(2)?;
+            if LONG_VRS.iter().any(|long_vr| long_vr.as_slice() == vr) {
+                self.read_u16()?;
+                (Some(vr), self.read_u32()?)
+            } else {
+                (Some(vr), self.read_u16()? as u32)
+            }
+        } else {
+            (None, self.read_u32()?)
+        };
+
+        let is_sequence = match vr {
+            Some(vr) => vr == b"SQ",
+            None => SEQUENCE_TAGS.contains(&tag),
+        };
+
+        let value = if tag == PIXEL_DATA && length == UNDEFINED_LENGTH {
+            Value::Fragments(self.parse_fragments()?)
+        } else if vr == Some(b"UN") && length == UNDEFINED_LENGTH {
+            // Sequences of unknown value representation are always implicit VR.
+            self.explicit_vr = false;
+            let items = self.parse_sequence(length, depth + 1);
+            self.explicit_vr = true;
+            Value::Sequence(items?)
+        } else if is_sequence || length == UNDEFINED_LENGTH {
+            Value::Sequence(self.parse_sequence(length, depth + 1)?)
+        } else {
+            Value::Bytes(self.read_bytes(length as usize)?)
+        };
+
+        Ok((tag, value))
+    }
+
+    fn parse_sequence(&mut self, length: u32, depth: usize) -> Result<Vec<DataSet<'a>>, Error> {
+        if depth > MAX_SEQUENCE_DEPTH {
+            return Err(Error::InvalidHeader("dicom sequences are nested too deeply".to_string()));
+        }
+
+        let end = if length == UNDEFINED_LENGTH { self.bytes.len() } else { self.position.saturating_add(length as usize) };
+        let mut items = Vec::new();
+        while self.position < end {
+            let tag = self.read_tag()?;
+            let item_length = self.read_u32()?;
+            if tag == SEQUENCE_DELIMITATION {
+                return Ok(items);
+            }
+
+            if tag != ITEM {
+                return Err(Error::InvalidHeader(format!("expected a sequence item, found tag ({:04X},{:04X})", tag.0, tag.1)));
+            }
+
+            let item_end = if item_length == UNDEFINED_LENGTH { self.bytes.len() } else { self.position.saturating_add(item_length as usize) };
+            let (item, is_delimited) = self.parse_data_set(item_end, depth)?;
+            if item_length == UNDEFINED_LENGTH && !is_delimited {
+                return Err(Error::InvalidHeader("dicom file is truncated".to_string()));
+            }
+
+            items.push(item);
+        }
+
+        if length == UNDEFINED_LENGTH {
+            return Err(Error::InvalidHeader("dicom file is truncated".to_string()));
+        }
+
+        Ok(items)
+    }
+
+    fn parse_fragments(&mut self) -> Result<Vec<&'a [u8]>, Error> {
+        let mut fragments = Vec::new();
+        loop {
+            let tag = self.read_tag()?;
+            let length = self.read_u32()?;
+            match tag {
+                SEQUENCE_DELIMITATION => return Ok(fragments),
+                ITEM => fragments.push(self.read_bytes(length as usize)?),
+                tag => return Err(Error::InvalidHeader(format!("expected a pixel data fragment, found tag ({:04X},{:04X})", tag.0, tag.1))),
+            }
+        }
+    }
+}
+
+fn missing_attribute(name: &str) -> Error {
+    Error::InvalidHeader(format!("missing dicom attribute {}", name))
+}
+
+fn cross(a: [f64; 3], b: [f64; 3]) -> [f64; 3] {
+    [a[1] * b[2] - a[2] * b[1], a[2] * b[0] - a[0] * b[2], a[0] * b[1] - a[1] * b[0]]
+}
+
+fn dot(a: [f64; 3], b: [f64; 3]) -> f64 {
+    a.iter().zip(b).map(|(a, b)| a * b).sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A data element of a test file, with its explicit value representation.
+    type Element = (Tag, &'static [u8; 2], Vec<u8>);
+
+    /// The orientation of axial images, whose rows go to the left and columns to the posterior.
+    const AXIAL: &str = "1\\0\\0\\0\\1\\0";
+
+    const LIMITS: ReaderLimits = ReaderLimits { max_voxels: 1024 };
+
+    /// Encode a data element, whose length is stored after its value representation in explicit
+    /// VR files.
+    fn encode_element((tag, vr, value): &Element, explicit_vr: bool) -> Vec<u8> {
+        let mut bytes = [tag.0.to_le_bytes(), tag.1.to_le_bytes()].concat();
+        if !explicit_vr {
+            bytes.extend((value.len() as u32).to_le_bytes());
+        } else if LONG_VRS.contains(vr) {
+            bytes.extend(vr.as_slice());
+            bytes.extend([0, 0]);
+            bytes.extend((value.len() as u32).to_le_bytes());
+        } else {
+            bytes.extend(vr.as_slice());
+            bytes.extend((value.len() as u16).to_le_bytes());
+        }
+
+        bytes.extend(value);
+        bytes
+    }
+
+    /// Encode the value of a sequence, whose items have a defined length in explicit VR files, and
+    /// are delimited in implicit VR files.
+    fn encode_sequence(items: &[Vec<Element>], explicit_vr: bool) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for item in items {
+            let value: Vec<u8> = item.iter().flat_map(|element| encode_element(element, explicit_vr)).collect();
+            bytes.extend([ITEM.0.to_le_bytes(), ITEM.1.to_le_bytes()].concat());
+            if explicit_vr {
+                bytes.extend((value.len() as u32).to_le_bytes());
+                bytes.extend(value);
+            } else {
+                bytes.extend(UNDEFINED_LENGTH.to_le_bytes());
+                bytes.extend(value);
+                bytes.extend([ITEM_DELIMITATION.0.to_le_bytes(), ITEM_DELIMITATION.1.to_le_bytes()].concat());
+                bytes.extend(0u32.to_le_bytes());
+            }
+        }
+
+        bytes
+    }
+
+    /// Encode a DICOM file with a transfer syntax, followed by encoded data elements.
+    fn encode_file(transfer_syntax: &str, elements: &[u8]) -> Vec<u8> {
+        let mut bytes = vec![0; PREAMBLE_SIZE];
+        bytes.extend(DICM_MAGIC);
+        bytes.extend(encode_element(&(TRANSFER_SYNTAX_UID, b"UI", text(transfer_syntax)), true));
+        bytes.extend(elements);
+        bytes
+    }
+
+    /// Encode a text value, padded to an even length.
+    fn text(value: &str) -> Vec<u8> {
+        let mut bytes = value.as_bytes().to_vec();
+        if !bytes.len().is_multiple_of(2) {
+            bytes.push(b' ');
+        }
+
+        bytes
+    }
+
+    fn unsigned_short(value: u16) -> Vec<u8> {
+        value.to_le_bytes().to_vec()
+    }
+
+    /// Get the attributes of a 3x2 image of unsigned 16-bit pixels in a series.
+    fn image_attributes(series_uid: &str, instance_number: i64, position: [f64; 3], orientation: &str) -> Vec<Element> {
+        vec![
+            (SERIES_INSTANCE_UID, b"UI", text(series_uid)),
+            (INSTANCE_NUMBER, b"IS", text(&instance_number.to_string())),
+            (IMAGE_POSITION_PATIENT, b"DS", text(&position.map(|value| value.to_string()).join("\\"))),
+            (IMAGE_ORIENTATION_PATIENT, b"DS", text(orientation)),
+            (PIXEL_SPACING, b"DS", text("2\\3")),
+            (ROWS, b"US", unsigned_short(2)),
+            (COLUMNS, b"US", unsigned_short(3)),
+            (BITS_ALLOCATED, b"US", unsigned_short(16)),
+            (BITS_STORED, b"US", unsigned_short(16)),
+            (PIXEL_REPRESENTATION, b"US", unsigned_short(0)),
+        ]
+    }
+
+    /// Encode a file of uncompressed pixels after attributes, in explicit VR.
+    fn encode_image(attributes: Vec<Element>, pixels: &[u16]) -> Vec<u8> {
+        let elements: Vec<u8> = attributes.into_iter()
+            .chain([(PIXEL_DATA, b"OW", bytemuck::cast_slice(pixels).to_vec())])
+            .flat_map(|element| encode_element(&element, true))
+            .collect();
+        encode_file(EXPLICIT_VR_LITTLE_ENDIAN, &elements)
+    }
+
+    /// Get the voxels of a series in Fortran order, in which the frames are consecutive.
+    fn get_u16_voxels(series: &DicomSeries) -> Vec<u16> {
+        let Volume::U16(voxels) = &series.nifti.volume else {
+            panic!("expected u16 voxels, found {:?}", series.nifti.volume.voxel_type());
+        };
+
+        voxels.t().iter().copied().collect()
+    }
+
+    fn frame(position: Option<[f64; 3]>, slope: f64, intercept: f64) -> DicomFrame {
+        DicomFrame {
+            position,
+            orientation: None,
+            spacing: Some([2.0, 3.0]),
+            slice_thickness: Some(4.0),
+            slope,
+            intercept,
+            pixels: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn parses_explicit_and_implicit_vr_files() {
+        let pixels: [u16; 6] = [1, 2, 300, 400, 5000, 60000];
+        for explicit_vr in [true, false] {
+            // The pixel spacing of the shared functional groups overrides the top-level one.
+            let pixel_measures = encode_sequence(&[vec![(PIXEL_SPACING, b"DS", text("0.5\\0.75"))]], explicit_vr);
+            let shared_groups = encode_sequence(&[vec![(PIXEL_MEASURES_SEQUENCE, b"SQ", pixel_measures)]], explicit_vr);
+            let elements: Vec<u8> = image_attributes("1.2.3", 7, [1.0, -2.5, 3.0], AXIAL).into_iter()
+                .chain([
+                    (SERIES_NUMBER, b"IS", text("4")),
+                    (RESCALE_SLOPE, b"DS", text("1")),
+                    (RESCALE_INTERCEPT, b"DS", text("0")),
+                    (SHARED_FUNCTIONAL_GROUPS, b"SQ", shared_groups),
+                    (PIXEL_DATA, b"OW", bytemuck::cast_slice(&pixels).to_vec()),
+                ])
+                .flat_map(|element| encode_element(&element, explicit_vr))
+                .collect();
+            let transfer_syntax = if explicit_vr { EXPLICIT_VR_LITTLE_ENDIAN } else { IMPLICIT_VR_LITTLE_ENDIAN };
+            let instance = DicomInstance::parse(&encode_file(transfer_syntax, &elements), LIMITS).unwrap();
+
+            assert_eq!(instance.series_uid(), "1.2.3");
+            assert_eq!((instance.series_number, instance.instance_number), (Some(4), Some(7)));
+            assert_eq!((instance.columns, instance.rows), (3, 2));
+            assert_eq!(instance.frames.len(), 1);
+            let frame = &instance.frames[0];
+            assert_eq!(frame.position, Some([1.0, -2.5, 3.0]));
+            assert_eq!(frame.orientation, Some([1.0, 0.0, 0.0, 0.0, 1.0, 0.0]));
+            assert_eq!(frame.spacing, Some([0.5, 0.75]));
+            assert_eq!(frame.pixels, bytemuck::cast_slice::<u16, u8>(&pixels));
+        }
+    }
+
+    #[test]
+    fn decodes_packbits_segments() {
+        // A literal of 3 bytes, a run of 4 bytes, a no-op and a literal of 1 byte.
+        let segment = [2, 1, 2, 3, (-3i8) as u8, 9, 0x80, 0, 7];
+        assert_eq!(decode_packbits(&segment, 8).unwrap(), [1, 2, 3, 9, 9, 9, 9, 7]);
+        assert_eq!(decode_packbits(&segment, 5).unwrap(), [1, 2, 3, 9, 9]);
+        assert!(decode_packbits(&segment, 9).is_err());
+        assert!(decode_packbits(&segment[..3], 3).is_err());
+        assert!(decode_packbits(&segment[..5], 4).is_err());
+    }
+
+    #[test]
+    fn parses_rle_lossless_files() {
+        let pixels: [u16; 6] = [1, 2, 0x0300, 0x0304, 5, 0xFFFF];
+        // The most significant bytes have runs, and the least significant ones are a literal.
+        let high_bytes = [0xFF, 0, 0xFF, 3, 1, 0, 0xFF];
+        let low_bytes = [5, 1, 2, 0, 4, 5, 0xFF];
+        let mut fragment = vec![0; 64];
+        for (i, value) in [2, 64, 64 + high_bytes.len() as u32].into_iter().enumerate() {
+            fragment[4 * i..4 * i + 4].copy_from_slice(&value.to_le_bytes());
+        }
+
+        fragment.extend(high_bytes);
+        fragment.extend(low_bytes);
+
+        let mut elements: Vec<u8> = image_attributes("1.2.3", 1, [0.0, 0.0, 0.0], AXIAL).iter()
+            .flat_map(|element| encode_element(element, true))
+            .collect();
+        elements.extend([PIXEL_DATA.0.to_le_bytes(), PIXEL_DATA.1.to_le_bytes()].concat());
+        elements.extend(b"OB\0\0");
+        elements.extend(UNDEFINED_LENGTH.to_le_bytes());
+        // The offset table is empty, and the frame is a single fragment.
+        for fragment in [&[][..], &fragment] {
+            elements.extend([ITEM.0.to_le_bytes(), ITEM.1.to_le_bytes()].concat());
+            elements.extend((fragment.len() as u32).to_le_bytes());
+            elements.extend(fragment);
+        }
+
+        elements.extend([SEQUENCE_DELIMITATION.0.to_le_bytes(), SEQUENCE_DELIMITATION.1.to_le_bytes()].concat());
+        elements.extend(0u32.to_le_bytes());
+
+        let bytes = encode_file(RLE_LOSSLESS, &elements);
+        let instance = DicomInstance::parse(&bytes, LIMITS).unwrap();
+        assert_eq!(instance.frames[0].pixels, bytemuck::cast_slice::<u16, u8>(&pixels));
+
+        for size in 0..bytes.len() {
+            assert!(DicomInstance::parse(&bytes[..size], LIMITS).is_err(), "parsed a dicom file from {} bytes", size);
+        }
+    }
+
+    #[test]
+    fn sorts_slices_along_the_normal() {
+        // The normal of coronal images goes to the posterior, and the instance numbers do not
+        // follow the positions.
+        let coronal = "1\\0\\0\\0\\0\\-1";
+        let positions = [[0.0, 20.0, 0.0], [0.0, 0.0, 0.0], [0.0, 10.0, 0.0]];
+        let instances = positions.iter().enumerate().map(|(i, &position)| {
+            let pixels: Vec<u16> = (0..6).map(|pixel| 10 * i as u16 + pixel).collect();
+            let bytes = encode_image(image_attributes("1.2.3", i as i64 + 1, position, coronal), &pixels);
+            DicomInstance::parse(&bytes, LIMITS).unwrap()
+        }).collect();
+
+        let series = read_dicom_series(instances, LIMITS).unwrap();
+        assert_eq!(series.len(), 1);
+        assert_eq!(series[0].nifti.volume.dim(), (3, 2, 3, 1));
+        let voxels = get_u16_voxels(&series[0]);
+        // The slices are the instances 2, 3 and 1.
+        let expected: Vec<u16> = [1, 2, 0].into_iter().flat_map(|i| (0..6).map(move |pixel| 10 * i + pixel)).collect();
+        assert_eq!(voxels, expected);
+        assert_eq!(series[0].nifti.affine.0[1], [0.0, 0.0, -10.0, 0.0]);
+    }
+
+    #[test]
+    fn reads_frames_at_the_same_position_as_timepoints() {
+        // A multi-frame image with two slices of two timepoints, acquired slice after slice.
+        let per_frame_groups: Vec<Vec<Element>> = [0.0, 1.0, 0.0, 1.0].into_iter().map(|z: f64| {
+            let position = encode_sequence(&[vec![(IMAGE_POSITION_PATIENT, b"DS", text(&format!("0\\0\\{}", z)))]], true);
+            vec![(PLANE_POSITION_SEQUENCE, b"SQ", position)]
+        }).collect();
+        let attributes: Vec<Element> = image_attributes("1.2.3", 1, [0.0, 0.0, 0.0], AXIAL).into_iter()
+            .chain([
+                (NUMBER_OF_FRAMES, b"IS", text("4")),
+                (PER_FRAME_FUNCTIONAL_GROUPS, b"SQ", encode_sequence(&per_frame_groups, true)),
+            ])
+            .collect();
+        let pixels: Vec<u16> = (0..4).flat_map(|frame| (0..6).map(move |pixel| 10 * frame + pixel)).collect();
+        let instance = DicomInstance::parse(&encode_image(attributes, &pixels), LIMITS).unwrap();
+        assert_eq!(instance.frames.len(), 4);
+
+        // The slices of each timepoint are consecutive, so the frames keep their order.
+        let series = read_dicom_series(vec![instance], LIMITS).unwrap();
+        assert_eq!(series[0].nifti.volume.dim(), (3, 2, 2, 2));
+        assert_eq!(get_u16_voxels(&series[0]), pixels);
+    }
+
+    #[test]
+    fn converts_lps_positions_to_ras() {
+        let orientation = [1.0, 0.0, 0.0, 0.0, 1.0, 0.0];
+        let normal = [0.0, 0.0, 1.0];
+        let first = frame(Some([10.0, 20.0, 30.0]), 1.0, 0.0);
+        let last = frame(Some([10.0, 20.0, 36.0]), 1.0, 0.0);
+        let expected = [
+            [-3.0, 0.0, 0.0, -10.0],
+            [0.0, -2.0, 0.0, -20.0],
+            [0.0, 0.0, 3.0, 30.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ];
+        assert_eq!(get_affine(&first, &last, orientation, normal, 3).0, expected);
+
+        // A single slice is as thick as its slice thickness.
+        let mut expected = expected;
+        expected[2][2] = 4.0;
+        assert_eq!(get_affine(&first, &first, orientation, normal, 1).0, expected);
+    }
+
+    #[test]
+    fn picks_the_voxel_type_of_rescaled_values() {
+        let format = |bits_stored, is_signed| PixelFormat { bits_allocated: if bits_stored > 16 { 32 } else { 16 }, bits_stored, is_signed };
+        let voxel_type = |format, slope, intercept| get_voxel_type(&[&frame(None, slope, intercept)], format);
+
+        assert_eq!(voxel_type(format(8, false), 1.0, 0.0), VoxelType::U8);
+        assert_eq!(voxel_type(format(12, false), 1.0, -1024.0), VoxelType::I16);
+        assert_eq!(voxel_type(format(16, true), 1.0, 0.0), VoxelType::I16);
+        assert_eq!(voxel_type(format(16, false), 1.0, 0.0), VoxelType::U16);
+        assert_eq!(voxel_type(format(16, true), 2.0, 0.0), VoxelType::I32);
+        assert_eq!(voxel_type(format(12, false), 0.5, 0.0), VoxelType::F32);
+        assert_eq!(voxel_type(format(12, false), 1.0, 0.25), VoxelType::F32);
+        assert_eq!(voxel_type(format(32, false), 1.0, 0.0), VoxelType::F32);
+
+        // The range of all the frames of a series is used.
+        let frames = [frame(None, 1.0, 0.0), frame(None, 1.0, -10.0)];
+        assert_eq!(get_voxel_type(&[&frames[0], &frames[1]], format(8, false)), VoxelType::I16);
+    }
+
+    #[test]
+    fn rejects_truncated_and_garbage_files() {
+        let bytes = encode_image(image_attributes("1.2.3", 1, [0.0, 0.0, 0.0], AXIAL), &[0; 6]);
+        assert!(DicomInstance::parse(&bytes, LIMITS).is_ok());
+        for size in 0..bytes.len() {
+            assert!(DicomInstance::parse(&bytes[..size], LIMITS).is_err(), "parsed a dicom file from {} bytes", size);
+        }
+
+        assert!(DicomInstance::parse(b"not a dicom file", LIMITS).is_err());
+        let garbage: Vec<u8> = (0..=255).cycle().take(2048).collect();
+        assert!(DicomInstance::parse(&encode_file(EXPLICIT_VR_LITTLE_ENDIAN, &garbage), LIMITS).is_err());
+    }
+}
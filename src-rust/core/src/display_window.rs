@@ -14,6 +14,19 @@ pub enum DisplayPolarity {
 }
 
 impl DisplayWindow {
+    /// Get the default display window of a volume with given minimum and maximum intensities,
+    /// which is the window initially used by the viewer. The window of volumes without negative
+    /// intensities starts from zero.
+    pub fn from_range(minimum: f32, maximum: f32) -> Self {
+        let minimum = minimum.min(0.0);
+        let range = maximum - minimum;
+        Self {
+            level: (minimum + range * 0.25).round(),
+            width: (range * 0.5).round(),
+            polarity: DisplayPolarity::Positive,
+        }
+    }
+
     /// Get the minimum value of this display window.
     pub fn min(&self) -> f32 {
         self.level - self.width / 2.0
@@ -0,0 +1,74 @@
+use std::fmt;
+
+/// An error of the brain renderer.
+#[derive(Debug)]
+pub enum Error {
+    /// The file could not be read.
+    Io(std::io::Error),
+    /// The datatype of the file is not supported.
+    UnsupportedDatatype(String),
+    /// The header of the file is invalid or inconsistent with its data.
+    InvalidHeader(String),
+    /// The renderer could not be created or could not draw.
+    Gpu(String),
+    /// An argument passed to the API is invalid.
+    BadArgument(String),
+    /// A file could not be fetched from a server.
+    Network(String),
+    /// The operation was aborted by the user.
+    Aborted,
+}
+
+impl Error {
+    /// Get the machine-readable code of this error, which is exposed to JavaScript.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::Io(_)                  => "io",
+            Error::UnsupportedDatatype(_) => "unsupported-datatype",
+            Error::InvalidHeader(_)       => "invalid-header",
+            Error::Gpu(_)                 => "gpu",
+            Error::BadArgument(_)         => "bad-argument",
+            Error::Network(_)             => "network",
+            Error::Aborted                => "aborted",
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(error)                  => write!(f, "could not read file: {}", error),
+            Error::UnsupportedDatatype(error) => write!(f, "unsupported datatype: {}", error),
+            Error::InvalidHeader(error)       => write!(f, "invalid header: {}", error),
+            Error::Gpu(error)                 => write!(f, "renderer error: {}", error),
+            Error::BadArgument(error)         => write!(f, "bad argument: {}", error),
+            Error::Network(error)             => write!(f, "network error: {}", error),
+            Error::Aborted                    => write!(f, "operation aborted"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(error: std::io::Error) -> Self {
+        Error::Io(error)
+    }
+}
+
+impl From<nifti::NiftiError> for Error {
+    fn from(error: nifti::NiftiError) -> Self {
+        match error {
+            nifti::NiftiError::Io(error) => Error::Io(error),
+            nifti::NiftiError::UnsupportedDataType(datatype) => Error::UnsupportedDatatype(format!("{:?}", datatype)),
+            error => Error::InvalidHeader(error.to_string()),
+        }
+    }
+}
@@ -0,0 +1,55 @@
+use serde::{Deserialize, Serialize};
+
+/// The affine transform from the voxel indices of a volume to its world coordinates in
+/// millimeters, stored as the rows of a 4x4 matrix.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Affine(pub [[f64; 4]; 4]);
+
+impl Affine {
+    pub const IDENTITY: Affine = Affine::from_scaling([1.0, 1.0, 1.0]);
+
+    /// Create an affine that only scales the voxel indices by the voxel sizes.
+    pub const fn from_scaling(voxel_sizes: [f64; 3]) -> Self {
+        let [x, y, z] = voxel_sizes;
+        Affine([
+            [x,   0.0, 0.0, 0.0],
+            [0.0, y,   0.0, 0.0],
+            [0.0, 0.0, z,   0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+
+    /// Create an affine from a NIfTI quaternion rotation, voxel sizes and offset. The quaternion
+    /// factor is `-1.0` for left-handed voxel axes, and `1.0` otherwise.
+    pub fn from_quaternion(quaternion: [f64; 3], qfac: f64, voxel_sizes: [f64; 3], offset: [f64; 3]) -> Self {
+        let [b, c, d] = quaternion;
+        let a = (1.0 - b * b - c * c - d * d).max(0.0).sqrt();
+        let rotation = [
+            [a * a + b * b - c * c - d * d, 2.0 * (b * c - a * d),         2.0 * (b * d + a * c)],
+            [2.0 * (b * c + a * d),         a * a + c * c - b * b - d * d, 2.0 * (c * d - a * b)],
+            [2.0 * (b * d - a * c),         2.0 * (c * d + a * b),         a * a + d * d - b * b - c * c],
+        ];
+
+        let scales = [voxel_sizes[0], voxel_sizes[1], qfac * voxel_sizes[2]];
+        let mut matrix = Self::IDENTITY.0;
+        for row in 0..3 {
+            for column in 0..3 {
+                matrix[row][column] = rotation[row][column] * scales[column];
+            }
+            matrix[row][3] = offset[row];
+        }
+
+        Affine(matrix)
+    }
+
+    /// Get the sizes of the voxels along each voxel axis, in millimeters.
+    pub fn voxel_sizes(&self) -> [f64; 3] {
+        [0, 1, 2].map(|column| (0..3).map(|row| self.0[row][column].powi(2)).sum::<f64>().sqrt())
+    }
+}
+
+impl Default for Affine {
+    fn default() -> Self {
+        Self::IDENTITY
+    }
+}
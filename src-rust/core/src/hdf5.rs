@@ -0,0 +1,1277 @@
+//! A reader for the subset of HDF5 used by MINC2 files: old-style and compact groups, attributes,
+//! and contiguous, compact and chunked datasets, which may be deflate compressed.
+
+use std::io::{Read, Seek, SeekFrom};
+
+use flate2::read::ZlibDecoder;
+
+use crate::{error::Error, image_reader::{ReaderLimits, get_voxel_count}};
+
+/// The signature at the start of the superblock of HDF5 files.
+const SIGNATURE: [u8; 8] = [0x89, b'H', b'D', b'F', b'\r', b'\n', 0x1a, b'\n'];
+
+/// The maximum depth of the B-trees of a file, so that cyclic trees are rejected.
+const MAX_TREE_DEPTH: usize = 32;
+
+/// The maximum number of continuation blocks of an object header.
+const MAX_HEADER_BLOCKS: usize = 1024;
+
+/// The maximum size of the elements of datasets, which is well above the size of numeric types,
+/// so that the chunks of corrupt datatypes are not allocated.
+const MAX_ELEMENT_SIZE: usize = 64;
+
+const MESSAGE_DATASPACE: u16 = 0x01;
+const MESSAGE_LINK_INFO: u16 = 0x02;
+const MESSAGE_DATATYPE: u16 = 0x03;
+const MESSAGE_LINK: u16 = 0x06;
+const MESSAGE_LAYOUT: u16 = 0x08;
+const MESSAGE_FILTERS: u16 = 0x0b;
+const MESSAGE_ATTRIBUTE: u16 = 0x0c;
+const MESSAGE_CONTINUATION: u16 = 0x10;
+const MESSAGE_SYMBOL_TABLE: u16 = 0x11;
+
+const FILTER_DEFLATE: u16 = 1;
+const FILTER_SHUFFLE: u16 = 2;
+const FILTER_FLETCHER32: u16 = 3;
+
+/// An HDF5 file, whose objects are read on demand from a source.
+pub(crate) struct Hdf5File<R> {
+    source: R,
+    source_size: u64,
+    base_address: u64,
+    offset_size: usize,
+    length_size: usize,
+    root_address: u64,
+}
+
+/// The datatype of the elements of a dataset or an attribute.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Datatype {
+    Integer { size: usize, is_signed: bool, is_big_endian: bool },
+    Float { size: usize, is_big_endian: bool },
+    String { size: usize },
+    Other { class: u8, size: usize },
+}
+
+/// An attribute of an object.
+pub(crate) struct Attribute {
+    pub name: String,
+    pub datatype: Datatype,
+    data: Vec<u8>,
+}
+
+/// An object of a file, which is a group or a dataset.
+pub(crate) struct Object {
+    pub attributes: Vec<Attribute>,
+    pub dataset: Option<Dataset>,
+    links: Vec<(String, u64)>,
+    symbol_table: Option<(u64, u64)>,
+}
+
+/// A dataset, whose elements are stored in C order.
+pub(crate) struct Dataset {
+    pub datatype: Datatype,
+    pub shape: Vec<usize>,
+    layout: Layout,
+    filters: Vec<Filter>,
+}
+
+/// A chunk of the elements of a dataset, at an offset in the dataset.
+pub(crate) struct Chunk {
+    pub offset: Vec<usize>,
+    address: u64,
+    size: u64,
+    filter_mask: u32,
+}
+
+enum Layout {
+    Compact(Vec<u8>),
+    Contiguous { address: u64 },
+    /// Chunks indexed by a version 1 B-tree.
+    Chunked { tree_address: u64, chunk_shape: Vec<usize> },
+    SingleChunk { address: u64, size: Option<u64>, filter_mask: u32, chunk_shape: Vec<usize> },
+    /// Unfiltered chunks stored one after the other, in C order.
+    Implicit { address: u64, chunk_shape: Vec<usize> },
+}
+
+/// A node of a version 1 B-tree, with the key before each child and the address of the child.
+struct TreeNode {
+    level: u8,
+    children: Vec<(Vec<u8>, u64)>,
+}
+
+struct Filter {
+    id: u16,
+    values: Vec<u32>,
+}
+
+/// A cursor over the bytes of a structure of a file.
+struct Bytes<'a> {
+    bytes: &'a [u8],
+    position: usize,
+    offset_size: usize,
+    length_size: usize,
+}
+
+impl<R: Read + Seek> Hdf5File<R> {
+    /// Open an HDF5 file, whose superblock is at the start of the file or after a user block.
+    pub fn open(mut source: R) -> Result<Self, Error> {
+        let source_size = source.seek(SeekFrom::End(0))?;
+        let mut superblock_address = 0;
+        loop {
+            if superblock_address + SIGNATURE.len() as u64 > source_size {
+                return Err(Error::InvalidHeader("not an hdf5 file".to_string()));
+            }
+
+            let mut signature = [0; SIGNATURE.len()];
+            source.seek(SeekFrom::Start(superblock_address))?;
+            source.read_exact(&mut signature)?;
+            if signature == SIGNATURE {
+                break;
+            }
+
+            superblock_address = if superblock_address == 0 { 512 } else { superblock_address * 2 };
+        }
+
+        let mut file = Self { source, source_size, base_address: 0, offset_size: 8, length_size: 8, root_address: 0 };
+        let superblock = file.read_at(superblock_address, 128.min(source_size - superblock_address))?;
+        let field = |index: usize| superblock.get(index).copied()
+            .ok_or_else(|| Error::InvalidHeader("truncated hdf5 superblock".to_string()));
+        let version = field(8)?;
+        match version {
+            0 | 1 => {
+                file.offset_size = field(13)? as usize;
+                file.length_size = field(14)? as usize;
+                let mut bytes = file.bytes(&superblock);
+                bytes.skip(if version == 0 { 24 } else { 28 })?;
+                file.base_address = bytes.offset()?;
+                bytes.skip(3 * file.offset_size)?;
+                // The root group symbol table entry starts with the offset of its name.
+                bytes.offset()?;
+                file.root_address = file.absolute_address(bytes.offset()?)?;
+            }
+            2 | 3 => {
+                file.offset_size = field(9)? as usize;
+                file.length_size = field(10)? as usize;
+                let mut bytes = file.bytes(&superblock);
+                bytes.skip(12)?;
+                file.base_address = bytes.offset()?;
+                bytes.skip(2 * file.offset_size)?;
+                file.root_address = file.absolute_address(bytes.offset()?)?;
+            }
+            version => return Err(Error::UnsupportedDatatype(format!("hdf5 superblock version {} is not supported", version))),
+        }
+
+        if ![2, 4, 8].contains(&file.offset_size) || ![2, 4, 8].contains(&file.length_size) {
+            return Err(Error::InvalidHeader(format!("invalid hdf5 offset size {} or length size {}", file.offset_size, file.length_size)));
+        }
+
+        Ok(file)
+    }
+
+    /// Get the object at a path of the file, such as `/group/dataset`.
+    pub fn object(&mut self, path: &str) -> Result<Object, Error> {
+        let mut object = self.read_object(self.root_address)?;
+        for name in path.split('/').filter(|name| !name.is_empty()) {
+            let address = self.find_link(&object, name)?
+                .ok_or_else(|| Error::InvalidHeader(format!("missing hdf5 object {}", path)))?;
+            object = self.read_object(address)?;
+        }
+
+        Ok(object)
+    }
+
+    /// Check whether an object exists at a path of the file.
+    pub fn has_object(&mut self, path: &str) -> Result<bool, Error> {
+        let (parent, name) = path.rsplit_once('/').unwrap_or(("", path));
+        let parent = self.object(parent)?;
+        Ok(self.find_link(&parent, name)?.is_some())
+    }
+
+    /// Get the chunks of a dataset, which are the slices along its first axis if it is not chunked.
+    pub fn chunks(&mut self, dataset: &Dataset) -> Result<Vec<Chunk>, Error> {
+        let zero_offset = vec![0; dataset.shape.len()];
+        match &dataset.layout {
+            Layout::Compact(data) => Ok(vec![Chunk { offset: zero_offset, address: u64::MAX, size: data.len() as u64, filter_mask: 0 }]),
+            Layout::Contiguous { address } if *address == u64::MAX => Ok(Vec::new()),
+            Layout::Contiguous { address } => {
+                let slice_count = dataset.shape.first().copied().unwrap_or(1);
+                let slice_size = dataset.chunk_byte_size() as u64;
+                (0..slice_count).map(|slice| {
+                    let mut offset = zero_offset.clone();
+                    if let Some(first) = offset.first_mut() {
+                        *first = slice;
+                    }
+
+                    Ok(Chunk { offset, address: get_chunk_address(*address, slice, slice_size)?, size: slice_size, filter_mask: 0 })
+                }).collect()
+            }
+            Layout::Chunked { tree_address, .. } if *tree_address == u64::MAX => Ok(Vec::new()),
+            Layout::Chunked { tree_address, .. } => {
+                let mut chunks = Vec::new();
+                self.read_chunk_tree(*tree_address, dataset.shape.len(), 0, &mut chunks)?;
+                Ok(chunks)
+            }
+            Layout::SingleChunk { address, size, filter_mask, .. } => {
+                let size = size.unwrap_or(dataset.chunk_byte_size() as u64);
+                Ok(vec![Chunk { offset: zero_offset, address: *address, size, filter_mask: *filter_mask }])
+            }
+            Layout::Implicit { address, chunk_shape } => {
+                let grid: Vec<usize> = dataset.shape.iter().zip(chunk_shape).map(|(&size, &chunk)| size.div_ceil(chunk)).collect();
+                let chunk_size = dataset.chunk_byte_size() as u64;
+                let chunk_count: usize = grid.iter().product();
+                (0..chunk_count).map(|index| {
+                    let mut remainder = index;
+                    let mut offset = zero_offset.clone();
+                    for axis in (0..grid.len()).rev() {
+                        offset[axis] = remainder % grid[axis] * chunk_shape[axis];
+                        remainder /= grid[axis];
+                    }
+
+                    Ok(Chunk { offset, address: get_chunk_address(*address, index, chunk_size)?, size: chunk_size, filter_mask: 0 })
+                }).collect()
+            }
+        }
+    }
+
+    /// Read the elements of a chunk of a dataset, in C order over the chunk shape, after undoing
+    /// its filters.
+    pub fn read_chunk(&mut self, dataset: &Dataset, chunk: &Chunk) -> Result<Vec<u8>, Error> {
+        let expected_size = dataset.chunk_byte_size();
+        let mut data = match &dataset.layout {
+            Layout::Compact(data) => data.clone(),
+            _ => self.read_at(chunk.address, chunk.size)?,
+        };
+
+        for (index, filter) in dataset.filters.iter().enumerate().rev() {
+            // Filters beyond the 32 bits of the mask cannot be skipped.
+            if chunk.filter_mask.checked_shr(index as u32).is_some_and(|mask| mask & 1 != 0) {
+                continue;
+            }
+
+            data = match filter.id {
+                FILTER_DEFLATE => {
+                    let mut decoded = Vec::with_capacity(expected_size);
+                    ZlibDecoder::new(data.as_slice()).take(expected_size as u64).read_to_end(&mut decoded)?;
+                    decoded
+                }
+                FILTER_SHUFFLE => {
+                    let element_size = filter.values.first().map_or(dataset.datatype.size(), |&size| size as usize);
+                    unshuffle(&data, element_size)
+                }
+                FILTER_FLETCHER32 => {
+                    data.truncate(data.len().saturating_sub(4));
+                    data
+                }
+                id => return Err(Error::UnsupportedDatatype(format!("hdf5 filter {} is not supported", id))),
+            };
+        }
+
+        if data.len() < expected_size {
+            return Err(Error::InvalidHeader(format!("expected an hdf5 chunk of {} bytes, found {}", expected_size, data.len())));
+        }
+
+        data.truncate(expected_size);
+        Ok(data)
+    }
+
+    /// Read all the elements of a dataset, in C order. The dataset and its chunks must be within
+    /// the limits.
+    pub fn read_dataset(&mut self, dataset: &Dataset, limits: ReaderLimits) -> Result<Vec<u8>, Error> {
+        let element_size = dataset.datatype.size();
+        let chunk_shape = dataset.chunk_shape();
+        get_voxel_count(&dataset.shape, limits)?;
+        get_voxel_count(&chunk_shape, limits)?;
+        let mut data = vec![0; dataset.shape.iter().product::<usize>() * element_size];
+        for chunk in self.chunks(dataset)? {
+            let chunk_data = self.read_chunk(dataset, &chunk)?;
+            for_each_element(&dataset.shape, &chunk_shape, &chunk.offset, |chunk_index, index| {
+                data[index * element_size..(index + 1) * element_size]
+                    .copy_from_slice(&chunk_data[chunk_index * element_size..(chunk_index + 1) * element_size]);
+            });
+        }
+
+        Ok(data)
+    }
+
+    fn read_at(&mut self, address: u64, size: u64) -> Result<Vec<u8>, Error> {
+        if address.checked_add(size).is_none_or(|end| end > self.source_size) {
+            return Err(Error::InvalidHeader(format!("hdf5 structure of {} bytes at {} is outside of the file", size, address)));
+        }
+
+        let mut bytes = vec![0; size as usize];
+        self.source.seek(SeekFrom::Start(address))?;
+        self.source.read_exact(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    /// Read a structure with a signature and a size, which must be within the file.
+    fn read_structure(&mut self, address: u64, signature: &[u8; 4], size: u64) -> Result<Vec<u8>, Error> {
+        let bytes = self.read_at(address, size)?;
+        if !bytes.starts_with(signature) {
+            return Err(Error::InvalidHeader(format!("expected an hdf5 {} structure at {}", String::from_utf8_lossy(signature), address)));
+        }
+
+        Ok(bytes)
+    }
+
+    fn bytes<'a>(&self, bytes: &'a [u8]) -> Bytes<'a> {
+        Bytes { bytes, position: 0, offset_size: self.offset_size, length_size: self.length_size }
+    }
+
+    /// Read an object from its header, and its messages.
+    fn read_object(&mut self, address: u64) -> Result<Object, Error> {
+        let mut object = Object { attributes: Vec::new(), dataset: None, links: Vec::new(), symbol_table: None };
+        let (mut datatype, mut shape, mut layout, mut filters) = (None, None, None, Vec::new());
+        for (message_type, data) in self.read_messages(address)? {
+            let mut bytes = self.bytes(&data);
+            match message_type {
+                MESSAGE_DATASPACE => shape = parse_dataspace(&mut bytes)?,
+                MESSAGE_DATATYPE => datatype = Some(parse_datatype(&mut bytes)?),
+                MESSAGE_LAYOUT => layout = Some(self.parse_layout(&mut bytes)?),
+                MESSAGE_FILTERS => filters = parse_filters(&mut bytes)?,
+                MESSAGE_ATTRIBUTE => object.attributes.extend(parse_attribute(&mut bytes)?),
+                MESSAGE_LINK => object.links.extend(self.parse_link(&mut bytes)?),
+                MESSAGE_SYMBOL_TABLE => {
+                    let tree_address = self.absolute_address(bytes.offset()?)?;
+                    let heap_address = self.absolute_address(bytes.offset()?)?;
+                    object.symbol_table = Some((tree_address, heap_address));
+                }
+                MESSAGE_LINK_INFO => {
+                    bytes.skip(1)?;
+                    let flags = bytes.u8()?;
+                    bytes.skip(if flags & 0x01 != 0 { 8 } else { 0 })?;
+                    if bytes.offset()? != self.undefined_offset() {
+                        log::warn!("hdf5 groups with dense link storage are not supported");
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        if let (Some(datatype), Some(layout)) = (datatype, layout) {
+            let dataset = Dataset { datatype, shape: shape.unwrap_or_default(), layout, filters };
+            dataset.validate()?;
+            object.dataset = Some(dataset);
+        }
+
+        Ok(object)
+    }
+
+    /// Read the messages of an object header and of its continuation blocks.
+    fn read_messages(&mut self, address: u64) -> Result<Vec<(u16, Vec<u8>)>, Error> {
+        let prefix = self.read_at(address, 16.min(self.source_size.saturating_sub(address)))?;
+        let is_version_2 = prefix.starts_with(b"OHDR");
+        let mut blocks = Vec::new();
+        if is_version_2 {
+            let flags = *prefix.get(5).ok_or_else(|| Error::InvalidHeader("truncated hdf5 object header".to_string()))?;
+            let mut prefix_size = 6;
+            if flags & 0x20 != 0 {
+                prefix_size += 16;
+            }
+
+            if flags & 0x10 != 0 {
+                prefix_size += 4;
+            }
+
+            let size_length = 1 << (flags & 0x03);
+            let header = self.read_at(address, (prefix_size + size_length) as u64)?;
+            let block_size = read_uint(&header[prefix_size..], size_length);
+            let block_address = address + (prefix_size + size_length) as u64;
+            blocks.push((block_address, block_size, flags & 0x04 != 0));
+        } else {
+            if prefix.first() != Some(&1) || prefix.len() < 12 {
+                return Err(Error::InvalidHeader(format!("invalid hdf5 object header at {}", address)));
+            }
+
+            let block_size = read_uint(&prefix[8..], 4);
+            blocks.push((address + 16, block_size, false));
+        }
+
+        let mut messages = Vec::new();
+        let mut block_index = 0;
+        while let Some(&(block_address, block_size, has_creation_order)) = blocks.get(block_index) {
+            if block_index >= MAX_HEADER_BLOCKS {
+                return Err(Error::InvalidHeader("too many hdf5 object header blocks".to_string()));
+            }
+
+            block_index += 1;
+            let block = self.read_at(block_address, block_size)?;
+            // Version 2 continuation blocks start with a signature and end with a checksum, which
+            // is not counted in the size of the first block.
+            let is_continuation = is_version_2 && block_index > 1;
+            let mut position = if is_continuation { 4 } else { 0 };
+            let end = if is_continuation { block.len().saturating_sub(4) } else { block.len() };
+            let message_header_size = match (is_version_2, has_creation_order) {
+                (false, _) => 8,
+                (true, false) => 4,
+                (true, true) => 6,
+            };
+
+            while position + message_header_size <= end {
+                let (message_type, size) = if is_version_2 {
+                    (block[position] as u16, read_uint(&block[position + 1..], 2) as usize)
+                } else {
+                    (read_uint(&block[position..], 2) as u16, read_uint(&block[position + 2..], 2) as usize)
+                };
+
+                let flags = block[position + if is_version_2 { 3 } else { 4 }];
+                position += message_header_size;
+                let data = block.get(position..position + size)
+                    .ok_or_else(|| Error::InvalidHeader(format!("truncated hdf5 message {}", message_type)))?;
+                position += size;
+                if message_type == MESSAGE_CONTINUATION {
+                    let mut bytes = self.bytes(data);
+                    let continuation_address = self.absolute_address(bytes.offset()?)?;
+                    blocks.push((continuation_address, bytes.length()?, has_creation_order));
+                } else if flags & 0x02 != 0 {
+                    log::warn!("shared hdf5 message {} is not supported", message_type);
+                } else {
+                    messages.push((message_type, data.to_vec()));
+                }
+            }
+        }
+
+        Ok(messages)
+    }
+
+    /// Parse a data layout message.
+    fn parse_layout(&self, bytes: &mut Bytes) -> Result<Layout, Error> {
+        let version = bytes.u8()?;
+        match version {
+            1 | 2 => {
+                let dimensionality = bytes.u8()? as usize;
+                let class = bytes.u8()?;
+                bytes.skip(5)?;
+                let address = if class != 0 { self.address(bytes.offset()?)? } else { u64::MAX };
+                let dimensions: Vec<usize> = (0..dimensionality).map(|_| bytes.u32().map(|size| size as usize)).collect::<Result<_, _>>()?;
+                match class {
+                    0 => {
+                        let size = bytes.u32()? as usize;
+                        Ok(Layout::Compact(bytes.take(size)?.to_vec()))
+                    }
+                    1 => Ok(Layout::Contiguous { address }),
+                    _ => Ok(Layout::Chunked { tree_address: address, chunk_shape: dimensions[..dimensionality.saturating_sub(1)].to_vec() }),
+                }
+            }
+            3 | 4 => match bytes.u8()? {
+                0 => {
+                    let size = bytes.u16()? as usize;
+                    Ok(Layout::Compact(bytes.take(size)?.to_vec()))
+                }
+                1 => Ok(Layout::Contiguous { address: self.address(bytes.offset()?)? }),
+                2 if version == 3 => {
+                    let dimensionality = bytes.u8()? as usize;
+                    let tree_address = self.address(bytes.offset()?)?;
+                    let dimensions: Vec<usize> = (0..dimensionality).map(|_| bytes.u32().map(|size| size as usize)).collect::<Result<_, _>>()?;
+                    Ok(Layout::Chunked { tree_address, chunk_shape: dimensions[..dimensionality.saturating_sub(1)].to_vec() })
+                }
+                2 => {
+                    let flags = bytes.u8()?;
+                    let dimensionality = bytes.u8()? as usize;
+                    let size_length = bytes.u8()? as usize;
+                    let dimensions: Vec<usize> = (0..dimensionality).map(|_| bytes.uint(size_length).map(|size| size as usize)).collect::<Result<_, _>>()?;
+                    let chunk_shape = dimensions[..dimensionality.saturating_sub(1)].to_vec();
+                    match bytes.u8()? {
+                        1 => {
+                            let (size, filter_mask) = if flags & 0x02 != 0 {
+                                (Some(bytes.length()?), bytes.u32()?)
+                            } else {
+                                (None, 0)
+                            };
+
+                            Ok(Layout::SingleChunk { address: self.address(bytes.offset()?)?, size, filter_mask, chunk_shape })
+                        }
+                        2 => Ok(Layout::Implicit { address: self.address(bytes.offset()?)?, chunk_shape }),
+                        index => Err(Error::UnsupportedDatatype(format!("hdf5 chunk index type {} is not supported", index))),
+                    }
+                }
+                class => Err(Error::UnsupportedDatatype(format!("hdf5 layout class {} is not supported", class))),
+            },
+            version => Err(Error::UnsupportedDatatype(format!("hdf5 layout version {} is not supported", version))),
+        }
+    }
+
+    /// Parse a link message, and get the name and the object header address of hard links.
+    fn parse_link(&self, bytes: &mut Bytes) -> Result<Option<(String, u64)>, Error> {
+        bytes.skip(1)?;
+        let flags = bytes.u8()?;
+        let link_type = if flags & 0x08 != 0 { bytes.u8()? } else { 0 };
+        bytes.skip(if flags & 0x04 != 0 { 8 } else { 0 })?;
+        bytes.skip(if flags & 0x10 != 0 { 1 } else { 0 })?;
+        let name_length = bytes.uint(1 << (flags & 0x03))? as usize;
+        let name = String::from_utf8_lossy(bytes.take(name_length)?).into_owned();
+        if link_type != 0 {
+            return Ok(None);
+        }
+
+        Ok(Some((name, self.absolute_address(bytes.offset()?)?)))
+    }
+
+    /// Find the object header address of a child of a group.
+    fn find_link(&mut self, group: &Object, name: &str) -> Result<Option<u64>, Error> {
+        if let Some((_, address)) = group.links.iter().find(|(link, _)| link == name) {
+            return Ok(Some(*address));
+        }
+
+        let Some((tree_address, heap_address)) = group.symbol_table else {
+            return Ok(None);
+        };
+
+        let heap = self.read_structure(heap_address, b"HEAP", 8 + 2 * self.length_size as u64 + self.offset_size as u64)?;
+        let mut bytes = self.bytes(&heap);
+        bytes.skip(8)?;
+        let heap_size = bytes.length()?;
+        bytes.length()?;
+        let heap_data_address = self.absolute_address(bytes.offset()?)?;
+        let heap_data = self.read_at(heap_data_address, heap_size)?;
+
+        let mut entries = Vec::new();
+        self.read_group_tree(tree_address, 0, &mut entries)?;
+        for (name_offset, address) in entries {
+            let link_name = heap_data.get(name_offset as usize..).unwrap_or_default();
+            let length = link_name.iter().position(|&byte| byte == 0).unwrap_or(link_name.len());
+            if &link_name[..length] == name.as_bytes() {
+                return Ok(Some(address));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Read the symbol table entries of a group B-tree, as the heap offsets of their names and the
+    /// addresses of their object headers.
+    fn read_group_tree(&mut self, address: u64, depth: usize, entries: &mut Vec<(u64, u64)>) -> Result<(), Error> {
+        if depth > MAX_TREE_DEPTH {
+            return Err(Error::InvalidHeader("hdf5 group tree is too deep".to_string()));
+        }
+
+        let node = self.read_tree_node(address, 0, self.length_size)?;
+        for child in node.children {
+            if node.level > 0 {
+                self.read_group_tree(child.1, depth + 1, entries)?;
+                continue;
+            }
+
+            let node = self.read_structure(child.1, b"SNOD", 8)?;
+            let symbol_count = read_uint(&node[6..], 2) as usize;
+            let entry_size = 2 * self.offset_size + 24;
+            let node = self.read_structure(child.1, b"SNOD", (8 + symbol_count * entry_size) as u64)?;
+            for entry in node[8..].chunks_exact(entry_size) {
+                let mut bytes = self.bytes(entry);
+                let name_offset = bytes.offset()?;
+                let header_address = self.absolute_address(bytes.offset()?)?;
+                entries.push((name_offset, header_address));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Read the chunks of a chunk B-tree of a dataset with a rank.
+    fn read_chunk_tree(&mut self, address: u64, rank: usize, depth: usize, chunks: &mut Vec<Chunk>) -> Result<(), Error> {
+        if depth > MAX_TREE_DEPTH {
+            return Err(Error::InvalidHeader("hdf5 chunk tree is too deep".to_string()));
+        }
+
+        let key_size = 8 + 8 * (rank + 1);
+        let node = self.read_tree_node(address, 1, key_size)?;
+        for (key, child_address) in node.children {
+            if node.level > 0 {
+                self.read_chunk_tree(child_address, rank, depth + 1, chunks)?;
+            } else {
+                let size = read_uint(&key, 4);
+                let filter_mask = read_uint(&key[4..], 4) as u32;
+                let offset = (0..rank).map(|axis| read_uint(&key[8 + 8 * axis..], 8) as usize).collect();
+                chunks.push(Chunk { offset, address: child_address, size, filter_mask });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Read a version 1 B-tree node of a type, whose keys have a size.
+    fn read_tree_node(&mut self, address: u64, node_type: u8, key_size: usize) -> Result<TreeNode, Error> {
+        let header_size = 8 + 2 * self.offset_size;
+        let header = self.read_structure(address, b"TREE", header_size as u64)?;
+        if header[4] != node_type {
+            return Err(Error::InvalidHeader(format!("expected an hdf5 tree node of type {}, found {}", node_type, header[4])));
+        }
+
+        let level = header[5];
+        let entry_count = read_uint(&header[6..], 2) as usize;
+        let node_size = header_size + entry_count * (key_size + self.offset_size) + key_size;
+        let node = self.read_at(address, node_size as u64)?;
+        let mut bytes = self.bytes(&node);
+        bytes.skip(header_size)?;
+        let mut children = Vec::with_capacity(entry_count);
+        for _ in 0..entry_count {
+            let key = bytes.take(key_size)?.to_vec();
+            children.push((key, self.absolute_address(bytes.offset()?)?));
+        }
+
+        Ok(TreeNode { level, children })
+    }
+
+    fn undefined_offset(&self) -> u64 {
+        u64::MAX >> (64 - 8 * self.offset_size)
+    }
+
+    /// Get the absolute address of an address of the file, which is undefined if it is all ones.
+    fn address(&self, address: u64) -> Result<u64, Error> {
+        if address == self.undefined_offset() { Ok(u64::MAX) } else { self.absolute_address(address) }
+    }
+
+    /// Get the absolute address of an address relative to the base address of the file.
+    fn absolute_address(&self, address: u64) -> Result<u64, Error> {
+        self.base_address.checked_add(address)
+            .ok_or_else(|| Error::InvalidHeader(format!("invalid hdf5 address {}", address)))
+    }
+}
+
+impl Datatype {
+    /// Get the size of an element of this type, in bytes.
+    pub fn size(self) -> usize {
+        match self {
+            Datatype::Integer { size, .. } | Datatype::Float { size, .. } | Datatype::String { size } | Datatype::Other { size, .. } => size,
+        }
+    }
+
+    /// Decode numeric elements of this type.
+    pub fn decode(self, bytes: &[u8]) -> Result<Vec<f64>, Error> {
+        macro_rules! decode {
+            ($scalar:ty, $is_big_endian:expr) => {
+                bytes.chunks_exact(size_of::<$scalar>())
+                    .map(|value| {
+                        let value = value.try_into().unwrap();
+                        let value = if $is_big_endian { <$scalar>::from_be_bytes(value) } else { <$scalar>::from_le_bytes(value) };
+                        value as f64
+                    })
+                    .collect()
+            };
+        }
+
+        match self {
+            Datatype::Integer { size: 1, is_signed: true, .. } => Ok(bytes.iter().map(|&value| value as i8 as f64).collect()),
+            Datatype::Integer { size: 1, is_signed: false, .. } => Ok(bytes.iter().map(|&value| value as f64).collect()),
+            Datatype::Integer { size: 2, is_signed: true, is_big_endian } => Ok(decode!(i16, is_big_endian)),
+            Datatype::Integer { size: 2, is_signed: false, is_big_endian } => Ok(decode!(u16, is_big_endian)),
+            Datatype::Integer { size: 4, is_signed: true, is_big_endian } => Ok(decode!(i32, is_big_endian)),
+            Datatype::Integer { size: 4, is_signed: false, is_big_endian } => Ok(decode!(u32, is_big_endian)),
+            Datatype::Integer { size: 8, is_signed: true, is_big_endian } => Ok(decode!(i64, is_big_endian)),
+            Datatype::Integer { size: 8, is_signed: false, is_big_endian } => Ok(decode!(u64, is_big_endian)),
+            Datatype::Float { size: 4, is_big_endian } => Ok(decode!(f32, is_big_endian)),
+            Datatype::Float { size: 8, is_big_endian } => Ok(decode!(f64, is_big_endian)),
+            datatype => Err(Error::UnsupportedDatatype(format!("hdf5 datatype {:?} is not numeric", datatype))),
+        }
+    }
+}
+
+impl Attribute {
+    /// Get the value of a string attribute, without its padding.
+    pub fn string(&self) -> Option<String> {
+        match self.datatype {
+            Datatype::String { .. } => Some(String::from_utf8_lossy(&self.data).trim_end_matches(['\0', ' ']).to_string()),
+            _ => None,
+        }
+    }
+
+    /// Get the values of a numeric attribute.
+    pub fn values(&self) -> Option<Vec<f64>> {
+        self.datatype.decode(&self.data).ok()
+    }
+}
+
+impl Object {
+    pub fn attribute(&self, name: &str) -> Option<&Attribute> {
+        self.attributes.iter().find(|attribute| attribute.name == name)
+    }
+}
+
+impl Dataset {
+    /// Get the shape of the chunks of this dataset, which are the slices along its first axis if
+    /// it is not chunked.
+    pub fn chunk_shape(&self) -> Vec<usize> {
+        match &self.layout {
+            Layout::Compact(_) => self.shape.clone(),
+            Layout::Contiguous { .. } => self.shape.iter().enumerate().map(|(axis, &size)| if axis == 0 { 1 } else { size }).collect(),
+            Layout::Chunked { chunk_shape, .. } | Layout::SingleChunk { chunk_shape, .. } | Layout::Implicit { chunk_shape, .. } => chunk_shape.clone(),
+        }
+    }
+
+    /// Check that the elements of this dataset are not too large, that its chunk shape has its
+    /// rank and no empty dimension, and that the size of its chunks does not overflow.
+    fn validate(&self) -> Result<(), Error> {
+        if self.datatype.size() > MAX_ELEMENT_SIZE {
+            return Err(Error::UnsupportedDatatype(format!("hdf5 elements of {} bytes are not supported", self.datatype.size())));
+        }
+
+        let chunk_shape = self.chunk_shape();
+        if chunk_shape.len() != self.shape.len() {
+            return Err(Error::InvalidHeader(format!("hdf5 chunks of rank {} in a dataset of rank {}", chunk_shape.len(), self.shape.len())));
+        }
+
+        chunk_shape.iter()
+            .try_fold(self.datatype.size(), |size, &dimension| size.checked_mul(dimension))
+            .ok_or_else(|| Error::InvalidHeader(format!("hdf5 chunk shape {:?} is too large", chunk_shape)))?;
+        let is_chunked = !matches!(self.layout, Layout::Compact(_) | Layout::Contiguous { .. });
+        if is_chunked && chunk_shape.contains(&0) {
+            return Err(Error::InvalidHeader(format!("invalid hdf5 chunk shape {:?}", chunk_shape)));
+        }
+
+        Ok(())
+    }
+
+    fn chunk_byte_size(&self) -> usize {
+        self.chunk_shape().iter().product::<usize>() * self.datatype.size()
+    }
+}
+
+impl<'a> Bytes<'a> {
+    fn take(&mut self, size: usize) -> Result<&'a [u8], Error> {
+        let bytes = self.position.checked_add(size)
+            .and_then(|end| self.bytes.get(self.position..end))
+            .ok_or_else(|| Error::InvalidHeader("truncated hdf5 structure".to_string()))?;
+        self.position += size;
+        Ok(bytes)
+    }
+
+    fn skip(&mut self, size: usize) -> Result<(), Error> {
+        self.take(size).map(|_| ())
+    }
+
+    /// Skip to the next multiple of 8 bytes.
+    fn align(&mut self) -> Result<(), Error> {
+        self.skip(self.position.next_multiple_of(8) - self.position)
+    }
+
+    fn uint(&mut self, size: usize) -> Result<u64, Error> {
+        self.take(size).map(|bytes| read_uint(bytes, size))
+    }
+
+    fn u8(&mut self) -> Result<u8, Error> {
+        self.uint(1).map(|value| value as u8)
+    }
+
+    fn u16(&mut self) -> Result<u16, Error> {
+        self.uint(2).map(|value| value as u16)
+    }
+
+    fn u32(&mut self) -> Result<u32, Error> {
+        self.uint(4).map(|value| value as u32)
+    }
+
+    fn offset(&mut self) -> Result<u64, Error> {
+        self.uint(self.offset_size)
+    }
+
+    fn length(&mut self) -> Result<u64, Error> {
+        self.uint(self.length_size)
+    }
+}
+
+/// Read a little-endian unsigned integer of a size.
+fn read_uint(bytes: &[u8], size: usize) -> u64 {
+    bytes[..size].iter().rev().fold(0, |value, &byte| value << 8 | byte as u64)
+}
+
+/// Parse a dataspace message, and get the shape of the dataspace, which is empty for scalars and
+/// missing for null dataspaces.
+fn parse_dataspace(bytes: &mut Bytes) -> Result<Option<Vec<usize>>, Error> {
+    let version = bytes.u8()?;
+    let dimensionality = bytes.u8()? as usize;
+    bytes.u8()?;
+    match version {
+        1 => bytes.skip(5)?,
+        2 => {
+            if bytes.u8()? == 2 {
+                return Ok(None);
+            }
+        }
+        version => return Err(Error::UnsupportedDatatype(format!("hdf5 dataspace version {} is not supported", version))),
+    }
+
+    let shape = (0..dimensionality).map(|_| bytes.length().map(|size| size as usize)).collect::<Result<_, _>>()?;
+    Ok(Some(shape))
+}
+
+/// Parse a datatype message.
+fn parse_datatype(bytes: &mut Bytes) -> Result<Datatype, Error> {
+    let class = bytes.u8()? & 0x0f;
+    let flags = bytes.u8()?;
+    bytes.skip(2)?;
+    let size = bytes.u32()? as usize;
+    let is_big_endian = flags & 0x01 != 0;
+    Ok(match class {
+        0 => Datatype::Integer { size, is_signed: flags & 0x08 != 0, is_big_endian },
+        1 if flags & 0x40 == 0 => Datatype::Float { size, is_big_endian },
+        3 => Datatype::String { size },
+        class => Datatype::Other { class, size },
+    })
+}
+
+/// Parse a filter pipeline message.
+fn parse_filters(bytes: &mut Bytes) -> Result<Vec<Filter>, Error> {
+    let version = bytes.u8()?;
+    let filter_count = bytes.u8()?;
+    if version == 1 {
+        bytes.skip(6)?;
+    }
+
+    (0..filter_count).map(|_| {
+        let id = bytes.u16()?;
+        let name_length = if version == 1 || id >= 256 { bytes.u16()? as usize } else { 0 };
+        bytes.u16()?;
+        let value_count = bytes.u16()? as usize;
+        bytes.skip(name_length)?;
+        let values = (0..value_count).map(|_| bytes.u32()).collect::<Result<_, _>>()?;
+        if version == 1 && value_count % 2 == 1 {
+            bytes.skip(4)?;
+        }
+
+        Ok(Filter { id, values })
+    }).collect()
+}
+
+/// Parse an attribute message, and get the attribute if its datatype and dataspace are supported.
+fn parse_attribute(bytes: &mut Bytes) -> Result<Option<Attribute>, Error> {
+    let version = bytes.u8()?;
+    let flags = bytes.u8()?;
+    let name_size = bytes.u16()? as usize;
+    let datatype_size = bytes.u16()? as usize;
+    let dataspace_size = bytes.u16()? as usize;
+    if version == 3 {
+        bytes.u8()?;
+    }
+
+    if flags & 0x03 != 0 {
+        return Ok(None);
+    }
+
+    let name = bytes.take(name_size)?;
+    let name = String::from_utf8_lossy(name).trim_end_matches('\0').to_string();
+    if version == 1 {
+        bytes.align()?;
+    }
+
+    let datatype = parse_datatype(&mut Bytes { bytes: bytes.take(datatype_size)?, position: 0, ..*bytes })?;
+    if version == 1 {
+        bytes.align()?;
+    }
+
+    let shape = parse_dataspace(&mut Bytes { bytes: bytes.take(dataspace_size)?, position: 0, ..*bytes })?;
+    if version == 1 {
+        bytes.align()?;
+    }
+
+    let Some(shape) = shape else {
+        return Ok(None);
+    };
+
+    let data_size = shape.iter()
+        .try_fold(datatype.size(), |size, &dimension| size.checked_mul(dimension))
+        .ok_or_else(|| Error::InvalidHeader(format!("hdf5 attribute {} is too large", name)))?;
+    let data = bytes.take(data_size)?.to_vec();
+    Ok(Some(Attribute { name, datatype, data }))
+}
+
+/// Get the address of a chunk of a dataset whose chunks are stored one after the other.
+fn get_chunk_address(address: u64, index: usize, chunk_size: u64) -> Result<u64, Error> {
+    chunk_size.checked_mul(index as u64)
+        .and_then(|offset| address.checked_add(offset))
+        .ok_or_else(|| Error::InvalidHeader(format!("invalid hdf5 chunk {} at {}", index, address)))
+}
+
+/// Undo the shuffle filter, which stores the bytes of each element position together. The bytes
+/// after the last whole element are not shuffled.
+fn unshuffle(data: &[u8], element_size: usize) -> Vec<u8> {
+    if element_size <= 1 || element_size > data.len() {
+        return data.to_vec();
+    }
+
+    let element_count = data.len() / element_size;
+    let mut unshuffled = data.to_vec();
+    for byte in 0..element_size {
+        for element in 0..element_count {
+            unshuffled[element * element_size + byte] = data[byte * element_count + element];
+        }
+    }
+
+    unshuffled
+}
+
+/// Call a function with the index in a chunk and the index in a dataset of each element of the
+/// chunk that is in the dataset, in C order.
+pub(crate) fn for_each_element(shape: &[usize], chunk_shape: &[usize], chunk_offset: &[usize], mut f: impl FnMut(usize, usize)) {
+    let rank = shape.len();
+    if chunk_offset.iter().zip(shape).any(|(&offset, &size)| offset >= size) {
+        return;
+    }
+
+    let mut position = vec![0; rank];
+    let chunk_count: usize = chunk_shape.iter().product();
+    for chunk_index in 0..chunk_count {
+        if position.iter().zip(chunk_offset).zip(shape).all(|((&position, &offset), &size)| position + offset < size) {
+            let index = (0..rank).fold(0, |index, axis| index * shape[axis] + chunk_offset[axis] + position[axis]);
+            f(chunk_index, index);
+        }
+
+        for axis in (0..rank).rev() {
+            position[axis] += 1;
+            if position[axis] < chunk_shape[axis] {
+                break;
+            }
+
+            position[axis] = 0;
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::io::{Cursor, Write};
+
+    use flate2::{Compression, write::ZlibEncoder};
+
+    use super::*;
+
+    /// A message of an object header, as its type and its data.
+    pub(crate) type Message = (u16, Vec<u8>);
+
+    /// A writer of small HDF5 files, with a version 0 superblock, offsets and lengths of 8 bytes
+    /// and version 1 object headers. The structures are written before the structures that refer
+    /// to them, and the superblock is written last at the start of the file.
+    pub(crate) struct Hdf5Writer {
+        bytes: Vec<u8>,
+    }
+
+    impl Hdf5Writer {
+        const SUPERBLOCK_SIZE: usize = 96;
+
+        pub fn new() -> Self {
+            Self { bytes: vec![0; Self::SUPERBLOCK_SIZE] }
+        }
+
+        /// Write bytes at the end of the file, and get their address.
+        pub fn write(&mut self, bytes: &[u8]) -> u64 {
+            let address = self.bytes.len() as u64;
+            self.bytes.extend_from_slice(bytes);
+            address
+        }
+
+        /// Write an object header with messages, and get its address.
+        pub fn object(&mut self, messages: &[Message]) -> u64 {
+            let mut block = Vec::new();
+            for (message_type, data) in messages {
+                block.extend(message_type.to_le_bytes());
+                block.extend((data.len() as u16).to_le_bytes());
+                block.extend([0; 4]);
+                block.extend(data);
+            }
+
+            let mut header = vec![1, 0];
+            header.extend((messages.len() as u16).to_le_bytes());
+            header.extend(1u32.to_le_bytes());
+            header.extend((block.len() as u32).to_le_bytes());
+            header.extend([0; 4]);
+            header.extend(block);
+            self.write(&header)
+        }
+
+        /// Write an old-style group, whose children are listed by a symbol table, and get its
+        /// address.
+        pub fn old_style_group(&mut self, children: &[(&str, u64)]) -> u64 {
+            // The names of the children are stored in a local heap, after an empty name.
+            let mut heap_data = vec![0];
+            let mut node = b"SNOD".to_vec();
+            node.extend([1, 0]);
+            node.extend((children.len() as u16).to_le_bytes());
+            for (name, address) in children {
+                node.extend((heap_data.len() as u64).to_le_bytes());
+                node.extend(address.to_le_bytes());
+                node.extend([0; 24]);
+                heap_data.extend(name.as_bytes());
+                heap_data.push(0);
+            }
+
+            let heap_data_address = self.write(&heap_data);
+            let mut heap = b"HEAP".to_vec();
+            heap.extend([0; 4]);
+            heap.extend((heap_data.len() as u64).to_le_bytes());
+            heap.extend(u64::MAX.to_le_bytes());
+            heap.extend(heap_data_address.to_le_bytes());
+            let heap_address = self.write(&heap);
+            let node_address = self.write(&node);
+            let tree_address = self.tree(0, &[(vec![0; 8], node_address)], vec![0; 8]);
+
+            let mut symbol_table = tree_address.to_le_bytes().to_vec();
+            symbol_table.extend(heap_address.to_le_bytes());
+            self.object(&[(MESSAGE_SYMBOL_TABLE, symbol_table)])
+        }
+
+        /// Write a compact group, whose children are listed by link messages, and get its address.
+        pub fn compact_group(&mut self, children: &[(&str, u64)]) -> u64 {
+            let links: Vec<Message> = children.iter()
+                .map(|(name, address)| {
+                    let mut link = vec![1, 0, name.len() as u8];
+                    link.extend(name.as_bytes());
+                    link.extend(address.to_le_bytes());
+                    (MESSAGE_LINK, link)
+                })
+                .collect();
+            self.object(&links)
+        }
+
+        /// Write the chunks of a dataset of a shape and their chunk B-tree, and get the address of
+        /// the tree. The chunks are given by their offset in the dataset and their stored bytes.
+        pub fn chunk_tree(&mut self, shape: &[usize], chunks: &[(Vec<usize>, Vec<u8>)]) -> u64 {
+            let key = |size: usize, offset: &[usize]| {
+                let mut key = (size as u32).to_le_bytes().to_vec();
+                key.extend([0; 4]);
+                for &offset in offset.iter().chain([&0]) {
+                    key.extend((offset as u64).to_le_bytes());
+                }
+                key
+            };
+
+            let children: Vec<_> = chunks.iter()
+                .map(|(offset, bytes)| (key(bytes.len(), offset), self.write(bytes)))
+                .collect();
+            self.tree(1, &children, key(0, shape))
+        }
+
+        /// Write a version 1 B-tree leaf node of a type, with the key before each child and the
+        /// last key, and get its address.
+        fn tree(&mut self, node_type: u8, children: &[(Vec<u8>, u64)], last_key: Vec<u8>) -> u64 {
+            let mut node = b"TREE".to_vec();
+            node.extend([node_type, 0]);
+            node.extend((children.len() as u16).to_le_bytes());
+            node.extend(u64::MAX.to_le_bytes());
+            node.extend(u64::MAX.to_le_bytes());
+            for (key, address) in children {
+                node.extend(key);
+                node.extend(address.to_le_bytes());
+            }
+
+            node.extend(last_key);
+            self.write(&node)
+        }
+
+        /// Write the superblock, whose root group is at an address, and get the bytes of the file.
+        pub fn finish(mut self, root_address: u64) -> Vec<u8> {
+            let mut superblock = SIGNATURE.to_vec();
+            superblock.extend([0, 0, 0, 0, 0, 8, 8, 0]);
+            superblock.extend(4u16.to_le_bytes());
+            superblock.extend(16u16.to_le_bytes());
+            superblock.extend([0; 4]);
+            superblock.extend(0u64.to_le_bytes());
+            superblock.extend(u64::MAX.to_le_bytes());
+            superblock.extend((self.bytes.len() as u64).to_le_bytes());
+            superblock.extend(u64::MAX.to_le_bytes());
+            superblock.extend(0u64.to_le_bytes());
+            superblock.extend(root_address.to_le_bytes());
+            superblock.extend([0; 24]);
+            self.bytes[..Self::SUPERBLOCK_SIZE].copy_from_slice(&superblock);
+            self.bytes
+        }
+    }
+
+    pub(crate) fn dataspace(shape: &[usize]) -> Message {
+        let mut dataspace = vec![1, shape.len() as u8, 0, 0, 0, 0, 0, 0];
+        for &size in shape {
+            dataspace.extend((size as u64).to_le_bytes());
+        }
+        (MESSAGE_DATASPACE, dataspace)
+    }
+
+    pub(crate) fn integer_type(size: u32, is_signed: bool) -> Message {
+        let mut datatype = vec![0x10, if is_signed { 0x08 } else { 0 }, 0, 0];
+        datatype.extend(size.to_le_bytes());
+        (MESSAGE_DATATYPE, datatype)
+    }
+
+    pub(crate) fn float_type(size: u32) -> Message {
+        let mut datatype = vec![0x11, 0x20, 8 * size as u8 - 1, 0];
+        datatype.extend(size.to_le_bytes());
+        (MESSAGE_DATATYPE, datatype)
+    }
+
+    pub(crate) fn string_type(size: u32) -> Message {
+        let mut datatype = vec![0x13, 0, 0, 0];
+        datatype.extend(size.to_le_bytes());
+        (MESSAGE_DATATYPE, datatype)
+    }
+
+    pub(crate) fn contiguous_layout(address: u64, size: usize) -> Message {
+        let mut layout = vec![3, 1];
+        layout.extend(address.to_le_bytes());
+        layout.extend((size as u64).to_le_bytes());
+        (MESSAGE_LAYOUT, layout)
+    }
+
+    pub(crate) fn chunked_layout(tree_address: u64, chunk_shape: &[usize], element_size: usize) -> Message {
+        let mut layout = vec![3, 2, chunk_shape.len() as u8 + 1];
+        layout.extend(tree_address.to_le_bytes());
+        for &size in chunk_shape.iter().chain([&element_size]) {
+            layout.extend((size as u32).to_le_bytes());
+        }
+        (MESSAGE_LAYOUT, layout)
+    }
+
+    /// Get a filter pipeline message of filters, given by their identifier and their values.
+    pub(crate) fn filters(filters: &[(u16, &[u32])]) -> Message {
+        let mut pipeline = vec![2, filters.len() as u8];
+        for (id, values) in filters {
+            pipeline.extend(id.to_le_bytes());
+            pipeline.extend(0u16.to_le_bytes());
+            pipeline.extend((values.len() as u16).to_le_bytes());
+            for value in *values {
+                pipeline.extend(value.to_le_bytes());
+            }
+        }
+        (MESSAGE_FILTERS, pipeline)
+    }
+
+    pub(crate) fn deflate_filter() -> Message {
+        filters(&[(FILTER_DEFLATE, &[6])])
+    }
+
+    /// Get a version 1 attribute message, whose datatype is a datatype message.
+    pub(crate) fn attribute(name: &str, datatype: Message, shape: &[usize], data: &[u8]) -> Message {
+        let padded = |bytes: &[u8]| {
+            let mut bytes = bytes.to_vec();
+            bytes.resize(bytes.len().next_multiple_of(8), 0);
+            bytes
+        };
+
+        let name = [name.as_bytes(), &[0]].concat();
+        let (_, dataspace) = dataspace(shape);
+        let mut attribute = vec![1, 0];
+        attribute.extend((name.len() as u16).to_le_bytes());
+        attribute.extend((datatype.1.len() as u16).to_le_bytes());
+        attribute.extend((dataspace.len() as u16).to_le_bytes());
+        attribute.extend(padded(&name));
+        attribute.extend(padded(&datatype.1));
+        attribute.extend(padded(&dataspace));
+        attribute.extend(data);
+        (MESSAGE_ATTRIBUTE, attribute)
+    }
+
+    pub(crate) fn string_attribute(name: &str, value: &str) -> Message {
+        attribute(name, string_type(value.len() as u32), &[], value.as_bytes())
+    }
+
+    pub(crate) fn f64_attribute(name: &str, values: &[f64]) -> Message {
+        attribute(name, float_type(8), &[values.len()], bytemuck::cast_slice(values))
+    }
+
+    pub(crate) fn deflate(bytes: &[u8]) -> Vec<u8> {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(bytes).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    /// Shuffle the bytes of elements of a size, the reverse of `unshuffle`.
+    fn shuffle(data: &[u8], element_size: usize) -> Vec<u8> {
+        (0..element_size)
+            .flat_map(|byte| data.chunks_exact(element_size).map(move |element| element[byte]))
+            .collect()
+    }
+
+    /// The values of the 2x3 datasets of the test file.
+    const VALUES: [i16; 6] = [-3, -2, -1, 0, 1, 300];
+
+    /// Write a file with an old-style root group, whose `group` compact group holds a contiguous
+    /// `contiguous` dataset and a `chunked` dataset in 1x2 chunks that are shuffled and deflated.
+    fn write_file() -> Vec<u8> {
+        let mut writer = Hdf5Writer::new();
+        let data: &[u8] = bytemuck::cast_slice(&VALUES);
+        let data_address = writer.write(data);
+        let contiguous = writer.object(&[
+            dataspace(&[2, 3]),
+            integer_type(2, true),
+            contiguous_layout(data_address, data.len()),
+            string_attribute("units", "mm"),
+            f64_attribute("range", &[-3.0, 300.0]),
+        ]);
+
+        let chunks: Vec<_> = [(0, 0), (0, 2), (1, 0), (1, 2)].into_iter()
+            .map(|(row, column)| {
+                let mut chunk: Vec<i16> = VALUES[3 * row + column..3 * row + 3].iter().copied().take(2).collect();
+                // The chunks at the end of the dataset are padded.
+                chunk.resize(2, 0);
+                (vec![row, column], deflate(&shuffle(bytemuck::cast_slice(&chunk), 2)))
+            })
+            .collect();
+        let tree_address = writer.chunk_tree(&[2, 3], &chunks);
+        let chunked = writer.object(&[
+            dataspace(&[2, 3]),
+            integer_type(2, true),
+            chunked_layout(tree_address, &[1, 2], 2),
+            filters(&[(FILTER_SHUFFLE, &[2]), (FILTER_DEFLATE, &[6])]),
+        ]);
+
+        let group = writer.compact_group(&[("contiguous", contiguous), ("chunked", chunked)]);
+        let root = writer.old_style_group(&[("group", group)]);
+        writer.finish(root)
+    }
+
+    /// Read the attributes and the values of a dataset of a file, within limits that keep the
+    /// datasets of corrupt files small.
+    fn read_file(bytes: Vec<u8>, path: &str) -> Result<(Object, Vec<f64>), Error> {
+        let mut file = Hdf5File::open(Cursor::new(bytes))?;
+        let object = file.object(path)?;
+        let dataset = object.dataset.as_ref()
+            .ok_or_else(|| Error::InvalidHeader(format!("{} is not a dataset", path)))?;
+        let values = dataset.datatype.decode(&file.read_dataset(dataset, ReaderLimits { max_voxels: 64 })?)?;
+        Ok((object, values))
+    }
+
+    #[test]
+    fn reads_contiguous_datasets_and_their_attributes() {
+        let (object, values) = read_file(write_file(), "/group/contiguous").unwrap();
+        assert_eq!(values, VALUES.map(f64::from));
+        assert_eq!(object.dataset.as_ref().unwrap().shape, [2, 3]);
+        assert_eq!(object.attribute("units").and_then(Attribute::string).as_deref(), Some("mm"));
+        assert_eq!(object.attribute("range").and_then(Attribute::values), Some(vec![-3.0, 300.0]));
+    }
+
+    #[test]
+    fn reads_shuffled_and_deflated_chunked_datasets() {
+        let (_, values) = read_file(write_file(), "/group/chunked").unwrap();
+        assert_eq!(values, VALUES.map(f64::from));
+    }
+
+    #[test]
+    fn finds_the_objects_of_groups() {
+        let mut file = Hdf5File::open(Cursor::new(write_file())).unwrap();
+        assert!(file.has_object("/group").unwrap());
+        assert!(file.has_object("/group/chunked").unwrap());
+        assert!(!file.has_object("/group/missing").unwrap());
+        assert!(file.object("/missing/contiguous").is_err());
+    }
+
+    #[test]
+    fn rejects_truncated_files() {
+        let bytes = write_file();
+        for size in 0..bytes.len() {
+            for path in ["/group/contiguous", "/group/chunked"] {
+                assert!(read_file(bytes[..size].to_vec(), path).is_err(), "read {} from {} bytes", path, size);
+            }
+        }
+    }
+
+    #[test]
+    fn does_not_panic_on_corrupt_files() {
+        let bytes = write_file();
+        for position in Hdf5Writer::SUPERBLOCK_SIZE..bytes.len() {
+            for value in [0x00, 0x7f, 0xff] {
+                let mut corrupt = bytes.clone();
+                corrupt[position] = value;
+                for path in ["/group/contiguous", "/group/chunked"] {
+                    let _ = read_file(corrupt.clone(), path);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,245 @@
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom};
+
+use flate2::bufread::GzDecoder;
+
+use crate::{error::Error, mgh_reader::MghReader, minc_reader::MincReader, nifti::Nifti, nifti_reader::NiftiReader, npy::NpyReader, nrrd_reader::NrrdReader};
+
+/// The default maximum number of voxels of a volume, which is 1 GiB of `f32` voxels.
+pub const DEFAULT_MAX_VOXELS: usize = 1 << 28;
+
+/// The magic number at the start of gzip compressed files.
+pub(crate) const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// The limits enforced when reading a file, so that corrupt or hostile headers are rejected before
+/// the volume is allocated.
+#[derive(Clone, Copy)]
+pub struct ReaderLimits {
+    pub max_voxels: usize,
+}
+
+impl Default for ReaderLimits {
+    fn default() -> Self {
+        Self { max_voxels: DEFAULT_MAX_VOXELS }
+    }
+}
+
+/// The progress of the read of a volume, in slices.
+#[derive(Clone, Copy, Debug)]
+pub struct ReadProgress {
+    pub slices_read: usize,
+    pub slice_count: usize,
+}
+
+/// A reader that reads a volume slice by slice, so that the read can be reported and interrupted.
+pub trait VolumeReader {
+    fn progress(&self) -> ReadProgress;
+
+    /// Check whether all the slices of the volume are read.
+    fn is_done(&self) -> bool {
+        let progress = self.progress();
+        progress.slices_read == progress.slice_count
+    }
+
+    /// Read the next slice of the volume.
+    fn read_slice(&mut self) -> Result<(), Error>;
+
+    /// Get the image once all its slices are read.
+    fn finish(self: Box<Self>) -> Result<Nifti, Error>;
+}
+
+/// The file formats of the images that can be read from a single file.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ImageFormat {
+    Nifti,
+    Mgh,
+    Minc,
+    Nrrd,
+    Npy,
+    Npz,
+}
+
+impl ImageFormat {
+    /// Get the format of an image from its file name, which is NIfTI if the name is not known.
+    pub fn from_name(name: &str) -> Self {
+        let name = name.to_ascii_lowercase();
+        if name.ends_with(".mgh") || name.ends_with(".mgz") || name.ends_with(".mgh.gz") {
+            ImageFormat::Mgh
+        } else if name.ends_with(".mnc") {
+            ImageFormat::Minc
+        } else if name.ends_with(".nrrd") || name.ends_with(".nhdr") {
+            ImageFormat::Nrrd
+        } else if name.ends_with(".npy") {
+            ImageFormat::Npy
+        } else if name.ends_with(".npz") {
+            ImageFormat::Npz
+        } else {
+            ImageFormat::Nifti
+        }
+    }
+}
+
+/// Open an image from a source, whose format is found from its file name, and allocate its volume.
+/// The source may be gzip compressed.
+pub fn open_image<'a, R: Read + Seek + 'a>(source: R, name_hint: &str, limits: ReaderLimits) -> Result<Box<dyn VolumeReader + 'a>, Error> {
+    match ImageFormat::from_name(name_hint) {
+        ImageFormat::Nifti => Ok(Box::new(NiftiReader::open(source, name_hint, limits)?)),
+        ImageFormat::Mgh   => Ok(Box::new(MghReader::open(source, name_hint, limits)?)),
+        ImageFormat::Minc  => Ok(Box::new(MincReader::open(source, name_hint, limits)?)),
+        ImageFormat::Nrrd  => Ok(Box::new(NrrdReader::open(source, name_hint, limits)?)),
+        ImageFormat::Npy   => Ok(Box::new(NpyReader::open(source, name_hint, limits)?)),
+        ImageFormat::Npz   => Ok(Box::new(NpyReader::open_npz(source, name_hint, limits)?)),
+    }
+}
+
+/// Open a two-file image from the source of its header file and the source of its data file, and
+/// allocate its volume. The image is a detached NRRD image if its header is a `.nhdr` file, and an
+/// Analyze 7.5 or NIfTI-1 `.hdr`/`.img` pair otherwise.
+pub fn open_image_pair<'a, H: Read + Seek + 'a, R: Read + Seek + 'a>(header_source: H, data_source: R, header_name: &str, limits: ReaderLimits) -> Result<Box<dyn VolumeReader + 'a>, Error> {
+    if header_name.to_ascii_lowercase().ends_with(".nhdr") {
+        Ok(Box::new(NrrdReader::open_detached(header_source, data_source, header_name, limits)?))
+    } else {
+        Ok(Box::new(NiftiReader::open_pair(header_source, data_source, header_name, limits)?))
+    }
+}
+
+/// Read an image from a source, whose format is found from its file name. The source may be gzip
+/// compressed.
+pub fn read_image<R: Read + Seek>(source: R, name_hint: &str, limits: ReaderLimits) -> Result<Nifti, Error> {
+    read_volume(open_image(source, name_hint, limits)?)
+}
+
+/// Read all the slices of a volume.
+pub fn read_volume(mut reader: Box<dyn VolumeReader + '_>) -> Result<Nifti, Error> {
+    while !reader.is_done() {
+        reader.read_slice()?;
+    }
+
+    reader.finish()
+}
+
+/// Open a source, which may be gzip compressed, and get its size if it is not compressed.
+pub(crate) fn open_source<'a, R: Read + Seek + 'a>(mut source: R) -> Result<(Box<dyn Read + 'a>, Option<u64>), Error> {
+    let source_size = source.seek(SeekFrom::End(0))?;
+    source.rewind()?;
+
+    let mut source = BufReader::new(source);
+    if source.fill_buf()?.starts_with(&GZIP_MAGIC) {
+        log::debug!("found gzip compressed file");
+        // The size of compressed files says nothing about the size of their voxel data,
+        // truncated compressed files are detected while reading the slices instead.
+        Ok((Box::new(GzDecoder::new(source)), None))
+    } else {
+        Ok((Box::new(source), Some(source_size)))
+    }
+}
+
+/// Get the number of voxels of a volume, and check that it is within the limits.
+pub(crate) fn get_voxel_count(dimensions: &[usize], limits: ReaderLimits) -> Result<usize, Error> {
+    if dimensions.contains(&0) {
+        return Err(Error::InvalidHeader(format!("invalid image dimensions {:?}", dimensions)));
+    }
+
+    dimensions.iter()
+        .try_fold(1usize, |count, &dimension| count.checked_mul(dimension))
+        .filter(|&count| count <= limits.max_voxels)
+        .ok_or_else(|| Error::InvalidHeader(format!("image dimensions {:?} exceed the maximum of {} voxels", dimensions, limits.max_voxels)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use ndarray::Array4;
+
+    use super::*;
+    use crate::{geometry::Affine, nifti_writer::{NiftiWriteOptions, encode_nifti}, volume::Volume};
+
+    fn create_voxels() -> Array4<i16> {
+        Array4::from_shape_fn((3, 2, 2, 2), |(x, y, z, t)| (x + 3 * y + 6 * z + 12 * t) as i16 - 10)
+    }
+
+    fn encode_image(compress: bool) -> Vec<u8> {
+        let nifti = Nifti {
+            volume: Volume::I16(create_voxels()),
+            affine: Affine::default(),
+            description: "test image".to_string(),
+            extensions: Vec::new(),
+        };
+
+        encode_nifti(&nifti, &NiftiWriteOptions { compress, ..Default::default() }).unwrap()
+    }
+
+    fn check_image(nifti: &Nifti) {
+        let Volume::I16(voxels) = &nifti.volume else {
+            panic!("expected i16 voxels, found {:?}", nifti.volume.voxel_type());
+        };
+
+        assert_eq!(voxels, &create_voxels());
+        assert_eq!(nifti.description, "test image");
+    }
+
+    #[test]
+    fn reads_nifti_images() {
+        let nifti = read_image(Cursor::new(encode_image(false)), "image.nii", ReaderLimits::default()).unwrap();
+        check_image(&nifti);
+    }
+
+    #[test]
+    fn reads_gzip_compressed_nifti_images() {
+        let bytes = encode_image(true);
+        assert!(bytes.starts_with(&GZIP_MAGIC));
+
+        let nifti = read_image(Cursor::new(bytes), "image.nii.gz", ReaderLimits::default()).unwrap();
+        check_image(&nifti);
+    }
+
+    #[test]
+    fn rejects_truncated_images() {
+        let mut bytes = encode_image(false);
+        bytes.truncate(bytes.len() - 2);
+        let result = read_image(Cursor::new(bytes), "image.nii", ReaderLimits::default());
+        assert!(matches!(result, Err(Error::InvalidHeader(_))));
+
+        let mut bytes = encode_image(true);
+        bytes.truncate(bytes.len() / 2);
+        assert!(read_image(Cursor::new(bytes), "image.nii.gz", ReaderLimits::default()).is_err());
+
+        let bytes = encode_image(false)[..100].to_vec();
+        assert!(read_image(Cursor::new(bytes), "image.nii", ReaderLimits::default()).is_err());
+    }
+
+    #[test]
+    fn rejects_images_above_the_limits() {
+        let limits = ReaderLimits { max_voxels: 23 };
+        for compress in [false, true] {
+            let result = open_image(Cursor::new(encode_image(compress)), "image.nii", limits);
+            assert!(matches!(result, Err(Error::InvalidHeader(_))));
+        }
+
+        let limits = ReaderLimits { max_voxels: 24 };
+        assert!(read_image(Cursor::new(encode_image(false)), "image.nii", limits).is_ok());
+    }
+
+    #[test]
+    fn opens_plain_and_compressed_sources() {
+        let mut bytes = Vec::new();
+        let (mut source, size) = open_source(Cursor::new(encode_image(false))).unwrap();
+        source.read_to_end(&mut bytes).unwrap();
+        assert_eq!(size, Some(bytes.len() as u64));
+
+        let mut decompressed = Vec::new();
+        let (mut source, size) = open_source(Cursor::new(encode_image(true))).unwrap();
+        source.read_to_end(&mut decompressed).unwrap();
+        assert_eq!(size, None);
+        assert_eq!(decompressed, bytes);
+    }
+
+    #[test]
+    fn counts_voxels_within_the_limits() {
+        let limits = ReaderLimits { max_voxels: 24 };
+        assert_eq!(get_voxel_count(&[3, 2, 2, 2], limits).unwrap(), 24);
+        assert!(get_voxel_count(&[3, 2, 5], limits).is_err());
+        assert!(get_voxel_count(&[3, 0, 2], limits).is_err());
+        assert!(get_voxel_count(&[usize::MAX, 2], ReaderLimits::default()).is_err());
+    }
+}
@@ -0,0 +1,23 @@
+mod analyze;
+mod blosc;
+pub mod cpu_renderer;
+pub mod dicom_reader;
+pub mod display_window;
+pub mod error;
+pub mod geometry;
+mod hdf5;
+pub mod image_reader;
+pub mod mgh_reader;
+pub mod minc_reader;
+pub mod nifti;
+pub mod nifti_extension;
+pub mod nifti_reader;
+pub mod nifti_writer;
+pub mod npy;
+pub mod nrrd_reader;
+pub mod ome_zarr;
+pub mod stats;
+pub mod volume;
+mod zip;
+
+pub use error::Error;
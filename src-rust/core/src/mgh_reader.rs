@@ -0,0 +1,280 @@
+use std::io::{Read, Seek};
+
+use ndarray::ShapeBuilder;
+
+use crate::{error::Error, geometry::Affine, image_reader::{ReadProgress, ReaderLimits, VolumeReader, get_voxel_count, open_source}, nifti::Nifti, volume::{Volume, Voxel, VoxelType, decode_voxels, with_volume}};
+
+/// The offset of the voxels of MGH files, after their header.
+const HEADER_SIZE: usize = 284;
+
+const MGH_VERSION: i32 = 1;
+
+/// The direction cosines of the voxel axes of MGH files without RAS information, which are coronal
+/// slices of a FreeSurfer conformed volume.
+const DEFAULT_DIRECTIONS: [[f64; 3]; 3] = [
+    [-1.0, 0.0, 0.0],
+    [0.0, 0.0, -1.0],
+    [0.0, 1.0, 0.0],
+];
+
+/// A reader that reads a FreeSurfer MGH volume slice by slice, from a `.mgh` file or a gzip
+/// compressed `.mgz` file. The frames of the volume are its timepoints.
+pub struct MghReader<'a> {
+    source: Box<dyn Read + 'a>,
+    volume: Volume,
+    affine: Affine,
+    slice_count: usize,
+    slices_read: usize,
+}
+
+impl<'a> MghReader<'a> {
+    /// Open an MGH image from a source, which may be gzip compressed, and allocate its volume.
+    pub fn open<R: Read + Seek + 'a>(source: R, name_hint: &str, limits: ReaderLimits) -> Result<Self, Error> {
+        log::debug!("reading the mgh file {}", name_hint);
+        let (mut source, source_size) = open_source(source)?;
+        let mut header = [0; HEADER_SIZE];
+        source.read_exact(&mut header)?;
+
+        let version = read_i32(&header, 0);
+        if version != MGH_VERSION {
+            return Err(Error::InvalidHeader(format!("unsupported mgh version {}", version)));
+        }
+
+        let dimensions = [4, 8, 12, 16].map(|offset| read_i32(&header, offset));
+        if let Some(&dimension) = dimensions.iter().find(|&&dimension| dimension <= 0) {
+            return Err(Error::InvalidHeader(format!("invalid dimension {}", dimension)));
+        }
+
+        let [width, height, depth, frames] = dimensions.map(|dimension| dimension as usize);
+        let voxel_count = get_voxel_count(&[width, height, depth, frames], limits)?;
+        let voxel_type = match read_i32(&header, 20) {
+            0 => VoxelType::U8,
+            1 => VoxelType::I32,
+            3 => VoxelType::F32,
+            4 => VoxelType::I16,
+            code => return Err(Error::UnsupportedDatatype(format!("unknown mgh datatype code {}", code))),
+        };
+
+        if let Some(source_size) = source_size {
+            let expected_size = HEADER_SIZE as u64 + voxel_count as u64 * voxel_type.size() as u64;
+            if source_size < expected_size {
+                return Err(Error::InvalidHeader(format!("file is truncated, expected {} bytes, found {}", expected_size, source_size)));
+            }
+        }
+
+        Ok(Self {
+            source,
+            volume: Volume::zeros(voxel_type, (width, height, depth, frames)),
+            affine: get_affine(&header, [width, height, depth]),
+            slice_count: depth * frames,
+            slices_read: 0,
+        })
+    }
+}
+
+impl VolumeReader for MghReader<'_> {
+    fn progress(&self) -> ReadProgress {
+        ReadProgress {
+            slices_read: self.slices_read,
+            slice_count: self.slice_count,
+        }
+    }
+
+    fn read_slice(&mut self) -> Result<(), Error> {
+        if self.slices_read == self.slice_count {
+            return Err(Error::InvalidHeader(format!("expected {} slices, found more", self.slice_count)));
+        }
+
+        let (width, height, depth, _) = self.volume.dim();
+        let mut bytes = vec![0; width * height * self.volume.voxel_type().size()];
+        self.source.read_exact(&mut bytes)?;
+        let (slice, frame) = (self.slices_read % depth, self.slices_read / depth);
+        with_volume!(&mut self.volume, array => assign_slice(array, bytes, slice, frame));
+        self.slices_read += 1;
+        Ok(())
+    }
+
+    fn finish(self: Box<Self>) -> Result<Nifti, Error> {
+        if self.slices_read != self.slice_count {
+            return Err(Error::InvalidHeader(format!("expected {} slices, found {}", self.slice_count, self.slices_read)));
+        }
+
+        log::debug!("read {} mgh slices", self.slices_read);
+        Ok(Nifti {
+            volume: self.volume,
+            affine: self.affine,
+            description: String::new(),
+            extensions: Vec::new(),
+        })
+    }
+}
+
+/// Get the vox2ras affine of an MGH file, from the direction cosines and voxel sizes of its voxel
+/// axes, and the world coordinates of its center voxel.
+fn get_affine(header: &[u8; HEADER_SIZE], dimensions: [usize; 3]) -> Affine {
+    let has_ras = read_i16(header, 28) > 0;
+    let voxel_sizes = [30, 34, 38].map(|offset| read_f32(header, offset) as f64)
+        .map(|size| if size > 0.0 { size } else { 1.0 });
+
+    let (directions, center) = if has_ras {
+        let directions = [0, 1, 2].map(|axis| [0, 1, 2].map(|i| read_f32(header, 42 + 12 * axis + 4 * i) as f64));
+        let center = [0, 1, 2].map(|i| read_f32(header, 78 + 4 * i) as f64);
+        (directions, center)
+    } else {
+        (DEFAULT_DIRECTIONS, [0.0; 3])
+    };
+
+    let mut matrix = Affine::IDENTITY.0;
+    for row in 0..3 {
+        for axis in 0..3 {
+            matrix[row][axis] = directions[axis][row] * voxel_sizes[axis];
+        }
+
+        let center_offset: f64 = (0..3).map(|axis| matrix[row][axis] * dimensions[axis] as f64 / 2.0).sum();
+        matrix[row][3] = center[row] - center_offset;
+    }
+
+    Affine(matrix)
+}
+
+/// Copy a slice of big-endian voxels into a frame of a volume.
+fn assign_slice<T: Voxel>(array: &mut ndarray::Array4<T>, bytes: Vec<u8>, slice: usize, frame: usize) {
+    let (width, height, _, _) = array.dim();
+    let voxels = decode_voxels::<T>(bytes, true);
+    // The slice has the size of the volume slices, since it was read from it.
+    let voxels = ndarray::ArrayView2::from_shape((width, height).f(), &voxels).unwrap();
+    array.slice_mut(ndarray::s![.., .., slice, frame]).assign(&voxels);
+}
+
+fn read_i32(header: &[u8; HEADER_SIZE], offset: usize) -> i32 {
+    i32::from_be_bytes([header[offset], header[offset + 1], header[offset + 2], header[offset + 3]])
+}
+
+fn read_i16(header: &[u8; HEADER_SIZE], offset: usize) -> i16 {
+    i16::from_be_bytes([header[offset], header[offset + 1]])
+}
+
+fn read_f32(header: &[u8; HEADER_SIZE], offset: usize) -> f32 {
+    f32::from_be_bytes([header[offset], header[offset + 1], header[offset + 2], header[offset + 3]])
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Write};
+
+    use flate2::{Compression, write::GzEncoder};
+    use ndarray::Array4;
+
+    use super::*;
+    use crate::image_reader::read_volume;
+
+    const LIMITS: ReaderLimits = ReaderLimits { max_voxels: 64 };
+
+    /// The dimensions of the test images, which have two frames.
+    const DIMENSIONS: (usize, usize, usize, usize) = (3, 2, 2, 2);
+
+    fn create_voxels() -> Array4<i16> {
+        Array4::from_shape_fn(DIMENSIONS, |(x, y, z, t)| (x + 3 * y + 6 * z + 12 * t) as i16 * 300 - 1000)
+    }
+
+    /// Encode an MGH file of 16-bit voxels, with RAS information if it has the direction cosines
+    /// of its voxel axes.
+    fn encode_mgh(directions: Option<[[f32; 3]; 3]>) -> Vec<u8> {
+        let (width, height, depth, frames) = DIMENSIONS;
+        let mut header = vec![0; HEADER_SIZE];
+        let mut write = |offset: usize, bytes: &[u8]| header[offset..offset + bytes.len()].copy_from_slice(bytes);
+        write(0, &MGH_VERSION.to_be_bytes());
+        for (i, dimension) in [width, height, depth, frames].into_iter().enumerate() {
+            write(4 + 4 * i, &(dimension as i32).to_be_bytes());
+        }
+
+        write(20, &4i32.to_be_bytes());
+        for (i, size) in [1.0f32, 2.0, 3.0].into_iter().enumerate() {
+            write(30 + 4 * i, &size.to_be_bytes());
+        }
+
+        if let Some(directions) = directions {
+            write(28, &1i16.to_be_bytes());
+            for (i, value) in directions.into_iter().flatten().chain([10.0, 20.0, 30.0]).enumerate() {
+                write(42 + 4 * i, &value.to_be_bytes());
+            }
+        }
+
+        // The voxels are big endian, in Fortran order.
+        let voxels = create_voxels();
+        header.extend(voxels.t().iter().flat_map(|value| value.to_be_bytes()));
+        header
+    }
+
+    fn gzip(bytes: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(bytes).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn read_mgh(bytes: Vec<u8>) -> Result<Nifti, Error> {
+        read_volume(Box::new(MghReader::open(Cursor::new(bytes), "image.mgh", LIMITS)?))
+    }
+
+    fn check_voxels(nifti: &Nifti) {
+        let Volume::I16(voxels) = &nifti.volume else {
+            panic!("expected i16 voxels, found {:?}", nifti.volume.voxel_type());
+        };
+
+        assert_eq!(voxels, &create_voxels());
+    }
+
+    #[test]
+    fn reads_big_endian_slices_of_all_frames() {
+        let bytes = encode_mgh(None);
+        check_voxels(&read_mgh(bytes.clone()).unwrap());
+        check_voxels(&read_mgh(gzip(&bytes)).unwrap());
+    }
+
+    #[test]
+    fn gets_the_affine_from_the_vox2ras_information() {
+        // The voxel axes go to the left, inferior and anterior, centered on (10, 20, 30).
+        let directions = [[-1.0, 0.0, 0.0], [0.0, 0.0, -1.0], [0.0, 1.0, 0.0]];
+        let nifti = read_mgh(encode_mgh(Some(directions))).unwrap();
+        let expected = [
+            [-1.0, 0.0, 0.0, 11.5],
+            [0.0, 0.0, 3.0, 17.0],
+            [0.0, -2.0, 0.0, 32.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ];
+        assert_eq!(nifti.affine.0, expected);
+
+        // The images without RAS information are conformed coronal slices centered on the origin.
+        let nifti = read_mgh(encode_mgh(None)).unwrap();
+        let expected = [
+            [-1.0, 0.0, 0.0, 1.5],
+            [0.0, 0.0, 3.0, -3.0],
+            [0.0, -2.0, 0.0, 2.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ];
+        assert_eq!(nifti.affine.0, expected);
+    }
+
+    #[test]
+    fn rejects_truncated_and_invalid_files() {
+        let bytes = encode_mgh(None);
+        let compressed = gzip(&bytes);
+        for size in [0, 100, HEADER_SIZE, bytes.len() - 1] {
+            assert!(read_mgh(bytes[..size].to_vec()).is_err(), "read an mgh file from {} bytes", size);
+        }
+
+        for size in [20, compressed.len() / 2, compressed.len() - 10] {
+            assert!(read_mgh(compressed[..size].to_vec()).is_err(), "read an mgz file from {} bytes", size);
+        }
+
+        // The version, a dimension and the datatype are invalid.
+        for (offset, value) in [(0, 2), (12, 0), (20, 2)] {
+            let mut bytes = bytes.clone();
+            bytes[offset..offset + 4].copy_from_slice(&i32::to_be_bytes(value));
+            assert!(read_mgh(bytes).is_err());
+        }
+
+        let limits = ReaderLimits { max_voxels: 23 };
+        assert!(MghReader::open(Cursor::new(bytes), "image.mgh", limits).is_err());
+    }
+}
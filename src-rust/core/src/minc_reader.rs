@@ -0,0 +1,457 @@
+use std::io::{Read, Seek};
+
+use crate::{error::Error, geometry::Affine, hdf5::{Chunk, Dataset, Datatype, Hdf5File, for_each_element}, image_reader::{ReadProgress, ReaderLimits, VolumeReader, get_voxel_count}, nifti::Nifti, volume::{Volume, Voxel, VoxelType, with_volume}};
+
+const IMAGE_PATH: &str = "/minc-2.0/image/0";
+const DIMENSIONS_PATH: &str = "/minc-2.0/dimensions";
+
+/// The magic number at the start of MINC1 files, which are netCDF files.
+const NETCDF_MAGIC: [u8; 3] = *b"CDF";
+
+/// A reader that reads a MINC2 volume chunk by chunk, from a `.mnc` file. The voxels of integer
+/// volumes are converted to real values with the `image-min` and `image-max` of their slices.
+pub struct MincReader<R> {
+    file: Hdf5File<R>,
+    dataset: Dataset,
+    chunks: Vec<Chunk>,
+    volume: Volume,
+    affine: Affine,
+    /// The volume axis of each dataset axis.
+    volume_axes: Vec<usize>,
+    scaling: Option<SliceScaling>,
+    chunks_read: usize,
+}
+
+/// The scaling of the voxels of a MINC2 volume from their valid range to the real range of their
+/// slice.
+struct SliceScaling {
+    /// The dataset axes of the real ranges, whose shape is the shape of these axes.
+    axes: Vec<usize>,
+    minimums: Vec<f64>,
+    maximums: Vec<f64>,
+    valid_range: [f64; 2],
+}
+
+impl<R: Read + Seek> MincReader<R> {
+    /// Open a MINC2 file, and allocate its volume.
+    pub fn open(mut source: R, name_hint: &str, limits: ReaderLimits) -> Result<Self, Error> {
+        log::debug!("reading the minc file {}", name_hint);
+        let mut magic = [0; NETCDF_MAGIC.len()];
+        source.read_exact(&mut magic)?;
+        if magic == NETCDF_MAGIC {
+            return Err(Error::UnsupportedDatatype("minc1 files are not supported, convert them to minc2 with mincconvert -2".to_string()));
+        }
+
+        let mut file = Hdf5File::open(source)?;
+        let image = file.object(&format!("{}/image", IMAGE_PATH))?;
+        let dimension_names = get_dimension_order(image.attribute("dimorder").and_then(|order| order.string()));
+        let valid_range = image.attribute("valid_range").and_then(|range| range.values());
+        let dataset = image.dataset
+            .ok_or_else(|| Error::InvalidHeader("the minc image is not a dataset".to_string()))?;
+
+        if dimension_names.len() != dataset.shape.len() {
+            return Err(Error::InvalidHeader(format!("expected {} minc dimensions, found {:?}", dataset.shape.len(), dimension_names)));
+        }
+
+        if !(3..=4).contains(&dataset.shape.len()) {
+            return Err(Error::InvalidHeader(format!("unsupported {}d image, only 3d and 4d images are supported", dataset.shape.len())));
+        }
+
+        get_voxel_count(&dataset.shape, limits)?;
+        get_voxel_count(&dataset.chunk_shape(), limits)?;
+
+        // The voxel axes of the volume are the spatial dimensions from the fastest varying one,
+        // and the time dimension.
+        let mut volume_axes = vec![0; dataset.shape.len()];
+        let mut spatial_dimensions = Vec::new();
+        for (axis, name) in dimension_names.iter().enumerate().rev() {
+            volume_axes[axis] = match name.as_str() {
+                "xspace" | "yspace" | "zspace" => {
+                    spatial_dimensions.push(name.as_str());
+                    spatial_dimensions.len() - 1
+                }
+                "time" => 3,
+                name => return Err(Error::UnsupportedDatatype(format!("minc dimension {} is not supported", name))),
+            };
+        }
+
+        if spatial_dimensions.len() != 3 {
+            return Err(Error::InvalidHeader(format!("expected 3 spatial minc dimensions, found {:?}", dimension_names)));
+        }
+
+        let mut dimensions = [1; 4];
+        for (axis, &size) in dataset.shape.iter().enumerate() {
+            dimensions[volume_axes[axis]] = size;
+        }
+
+        let affine = get_affine(&mut file, &spatial_dimensions)?;
+        let scaling = match dataset.datatype {
+            Datatype::Integer { size: 1 | 2 | 4 | 8, .. } => get_scaling(&mut file, &dimension_names, &dataset, valid_range, limits)?,
+            Datatype::Float { size: 4 | 8, .. } => None,
+            datatype => return Err(Error::UnsupportedDatatype(format!("minc datatype {:?} is not supported", datatype))),
+        };
+
+        let voxel_type = if scaling.is_some() { VoxelType::F32 } else { get_native_type(dataset.datatype) };
+        let chunks = file.chunks(&dataset)?;
+        Ok(Self {
+            file,
+            dataset,
+            chunks,
+            volume: Volume::zeros(voxel_type, (dimensions[0], dimensions[1], dimensions[2], dimensions[3])),
+            affine,
+            volume_axes,
+            scaling,
+            chunks_read: 0,
+        })
+    }
+}
+
+impl<R: Read + Seek> VolumeReader for MincReader<R> {
+    /// Get the progress of the read, in chunks of the dataset, which are its slices if it is not
+    /// chunked.
+    fn progress(&self) -> ReadProgress {
+        ReadProgress {
+            slices_read: self.chunks_read,
+            slice_count: self.chunks.len(),
+        }
+    }
+
+    fn read_slice(&mut self) -> Result<(), Error> {
+        let chunk = self.chunks.get(self.chunks_read)
+            .ok_or_else(|| Error::InvalidHeader(format!("expected {} chunks, found more", self.chunks.len())))?;
+        let data = self.file.read_chunk(&self.dataset, chunk)?;
+        let values = self.dataset.datatype.decode(&data)?;
+        let layout = ChunkLayout {
+            shape: &self.dataset.shape,
+            chunk_shape: &self.dataset.chunk_shape(),
+            chunk_offset: &chunk.offset,
+            volume_axes: &self.volume_axes,
+        };
+
+        let scaling = self.scaling.as_ref();
+        with_volume!(&mut self.volume, array => assign_chunk(array, &values, &layout, scaling));
+        self.chunks_read += 1;
+        Ok(())
+    }
+
+    fn finish(self: Box<Self>) -> Result<Nifti, Error> {
+        if self.chunks_read != self.chunks.len() {
+            return Err(Error::InvalidHeader(format!("expected {} chunks, found {}", self.chunks.len(), self.chunks_read)));
+        }
+
+        log::debug!("read {} minc chunks", self.chunks_read);
+        Ok(Nifti {
+            volume: self.volume,
+            affine: self.affine,
+            description: String::new(),
+            extensions: Vec::new(),
+        })
+    }
+}
+
+/// The position of a chunk in a dataset, and the volume axes of the dataset axes.
+struct ChunkLayout<'a> {
+    shape: &'a [usize],
+    chunk_shape: &'a [usize],
+    chunk_offset: &'a [usize],
+    volume_axes: &'a [usize],
+}
+
+impl SliceScaling {
+    /// Convert a voxel value at a position of the dataset to its real value.
+    fn apply(&self, value: f64, position: &[usize; 4], shape: &[usize]) -> f64 {
+        let index = self.axes.iter().fold(0, |index, &axis| index * shape[axis] + position[axis]);
+        let (minimum, maximum) = (self.minimums[index], self.maximums[index]);
+        let [valid_minimum, valid_maximum] = self.valid_range;
+        if valid_maximum == valid_minimum {
+            return minimum;
+        }
+
+        (value - valid_minimum) / (valid_maximum - valid_minimum) * (maximum - minimum) + minimum
+    }
+}
+
+/// Get the dimension names of a MINC2 image from slowest to fastest varying, from its `dimorder`
+/// attribute.
+fn get_dimension_order(order: Option<String>) -> Vec<String> {
+    order.unwrap_or_default()
+        .split(',')
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .collect()
+}
+
+/// Get the affine of a MINC2 file from the start, step and direction cosines of its spatial
+/// dimensions, in the order of the voxel axes. MINC world coordinates are RAS coordinates.
+fn get_affine<R: Read + Seek>(file: &mut Hdf5File<R>, spatial_dimensions: &[&str]) -> Result<Affine, Error> {
+    let mut matrix = Affine::IDENTITY.0;
+    for (axis, &name) in spatial_dimensions.iter().enumerate() {
+        let dimension = file.object(&format!("{}/{}", DIMENSIONS_PATH, name))?;
+        let value = |name: &str| dimension.attribute(name).and_then(|attribute| attribute.values());
+        let step = value("step").and_then(|step| step.first().copied()).unwrap_or(1.0);
+        let start = value("start").and_then(|start| start.first().copied()).unwrap_or(0.0);
+        let mut direction = match name {
+            "xspace" => [1.0, 0.0, 0.0],
+            "yspace" => [0.0, 1.0, 0.0],
+            _ => [0.0, 0.0, 1.0],
+        };
+
+        if let Some(cosines) = value("direction_cosines").filter(|cosines| cosines.len() == 3) {
+            let norm = cosines.iter().map(|cosine| cosine * cosine).sum::<f64>().sqrt();
+            if norm > 0.0 {
+                direction = [0, 1, 2].map(|i| cosines[i] / norm);
+            }
+        }
+
+        for row in 0..3 {
+            matrix[row][axis] = step * direction[row];
+            matrix[row][3] += start * direction[row];
+        }
+    }
+
+    Ok(Affine(matrix))
+}
+
+/// Get the slice scaling of the dataset of an integer MINC2 image from its `image-min` and
+/// `image-max` datasets, whose dimensions are the slowest varying dimensions of the image, and
+/// whose shapes must match the image shape along them.
+fn get_scaling<R: Read + Seek>(file: &mut Hdf5File<R>, dimension_names: &[String], image: &Dataset, valid_range: Option<Vec<f64>>, limits: ReaderLimits) -> Result<Option<SliceScaling>, Error> {
+    let Datatype::Integer { size, is_signed, .. } = image.datatype else {
+        return Ok(None);
+    };
+
+    let (minimum_path, maximum_path) = (format!("{}/image-min", IMAGE_PATH), format!("{}/image-max", IMAGE_PATH));
+    if !file.has_object(&minimum_path)? || !file.has_object(&maximum_path)? {
+        log::debug!("found minc image without slice scaling");
+        return Ok(None);
+    }
+
+    let mut ranges = Vec::new();
+    let mut axes = Vec::new();
+    for path in [minimum_path, maximum_path] {
+        let object = file.object(&path)?;
+        let order = get_dimension_order(object.attribute("dimorder").and_then(|order| order.string()));
+        let dataset = object.dataset
+            .ok_or_else(|| Error::InvalidHeader(format!("the minc {} is not a dataset", path)))?;
+        let dataset_axes: Vec<usize> = if order.is_empty() {
+            (0..dataset.shape.len()).collect()
+        } else {
+            order.iter()
+                .map(|name| dimension_names.iter().position(|dimension| dimension == name))
+                .collect::<Option<Vec<_>>>()
+                .ok_or_else(|| Error::InvalidHeader(format!("invalid minc {} dimensions {:?}", path, order)))?
+        };
+
+        let expected_count: usize = dataset.shape.iter().product();
+        let values = dataset.datatype.decode(&file.read_dataset(&dataset, limits)?)?;
+        let is_image_shape = dataset_axes.len() == dataset.shape.len()
+            && dataset_axes.iter().zip(&dataset.shape).all(|(&axis, dimension)| image.shape.get(axis) == Some(dimension));
+        if !is_image_shape || values.len() != expected_count || (!ranges.is_empty() && dataset_axes != axes) {
+            return Err(Error::InvalidHeader(format!("invalid minc {} shape {:?}", path, dataset.shape)));
+        }
+
+        axes = dataset_axes;
+        ranges.push(values);
+    }
+
+    let valid_range = match valid_range.as_deref() {
+        Some(&[minimum, maximum]) if minimum.is_finite() && maximum.is_finite() => [minimum.min(maximum), minimum.max(maximum)],
+        _ => {
+            let bits = 8 * size as i32;
+            if is_signed {
+                [-(2f64.powi(bits - 1)), 2f64.powi(bits - 1) - 1.0]
+            } else {
+                [0.0, 2f64.powi(bits) - 1.0]
+            }
+        }
+    };
+
+    let maximums = ranges.pop().unwrap_or_default();
+    let minimums = ranges.pop().unwrap_or_default();
+    Ok(Some(SliceScaling { axes, minimums, maximums, valid_range }))
+}
+
+/// Get the datatype in which the voxels of an unscaled MINC2 image can be stored without loss,
+/// except for 64-bit integers.
+fn get_native_type(datatype: Datatype) -> VoxelType {
+    match datatype {
+        Datatype::Integer { size: 1, is_signed: false, .. } => VoxelType::U8,
+        Datatype::Integer { size: 1 | 2, is_signed: true, .. } => VoxelType::I16,
+        Datatype::Integer { size: 2, is_signed: false, .. } => VoxelType::U16,
+        Datatype::Integer { size: 4, is_signed: true, .. } => VoxelType::I32,
+        Datatype::Float { size: 4, .. } => VoxelType::F32,
+        _ => VoxelType::F64,
+    }
+}
+
+/// Copy the values of a chunk into a volume, converting them to real values if the volume is
+/// scaled.
+fn assign_chunk<T: Voxel>(array: &mut ndarray::Array4<T>, values: &[f64], layout: &ChunkLayout, scaling: Option<&SliceScaling>) {
+    let shape = layout.shape;
+    for_each_element(shape, layout.chunk_shape, layout.chunk_offset, |chunk_index, index| {
+        let mut position = [0; 4];
+        let mut volume_position = [0; 4];
+        let mut remainder = index;
+        for axis in (0..shape.len()).rev() {
+            position[axis] = remainder % shape[axis];
+            remainder /= shape[axis];
+            volume_position[layout.volume_axes[axis]] = position[axis];
+        }
+
+        let value = values[chunk_index];
+        let value = scaling.map_or(value, |scaling| scaling.apply(value, &position, shape));
+        array[volume_position] = T::from_f64(value);
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::{hdf5::tests::*, image_reader::read_volume};
+
+    /// The shape of the test images, in (z, y, x) order.
+    const SHAPE: [usize; 3] = [2, 2, 3];
+
+    /// The minimum and maximum real values of the z slices of the test images.
+    const SLICE_RANGES: [[f64; 2]; 2] = [[0.0, 100.0], [-10.0, 10.0]];
+
+    /// Get the stored value of a voxel of the test images, within the valid range from 0 to 100.
+    fn get_stored_value(x: usize, y: usize, z: usize) -> u16 {
+        (10 * (x + 3 * y + 6 * z) % 101) as u16
+    }
+
+    /// Write a dataset of f64 values along the z dimension, such as `image-min`.
+    fn write_slice_values(writer: &mut Hdf5Writer, values: [f64; 2]) -> u64 {
+        let address = writer.write(bytemuck::cast_slice(&values));
+        writer.object(&[
+            dataspace(&[2]),
+            float_type(8),
+            contiguous_layout(address, 16),
+            string_attribute("dimorder", "zspace"),
+        ])
+    }
+
+    /// Write a MINC2 file of an unsigned 16-bit image scaled by the ranges of its z slices, which
+    /// is either contiguous or chunked in 1x2x2 chunks that are deflated.
+    fn write_minc(is_chunked: bool) -> Vec<u8> {
+        let [z_size, y_size, x_size] = SHAPE;
+        let mut writer = Hdf5Writer::new();
+        let layout = if is_chunked {
+            let chunks: Vec<_> = (0..z_size)
+                .flat_map(|z| [0, 2].map(|x_start| (z, x_start)))
+                .map(|(z, x_start)| {
+                    // The chunks at the end of the x dimension are padded.
+                    let chunk: Vec<u16> = (0..2)
+                        .flat_map(|y| (x_start..x_start + 2).map(move |x| if x < x_size { get_stored_value(x, y, z) } else { 0 }))
+                        .collect();
+                    (vec![z, 0, x_start], deflate(bytemuck::cast_slice(&chunk)))
+                })
+                .collect();
+            let tree_address = writer.chunk_tree(&SHAPE, &chunks);
+            vec![chunked_layout(tree_address, &[1, 2, 2], 2), deflate_filter()]
+        } else {
+            let voxels: Vec<u16> = (0..z_size)
+                .flat_map(|z| (0..y_size).flat_map(move |y| (0..x_size).map(move |x| get_stored_value(x, y, z))))
+                .collect();
+            let address = writer.write(bytemuck::cast_slice(&voxels));
+            vec![contiguous_layout(address, 2 * voxels.len())]
+        };
+
+        let mut messages = vec![
+            dataspace(&SHAPE),
+            integer_type(2, false),
+            string_attribute("dimorder", "zspace,yspace,xspace"),
+            f64_attribute("valid_range", &[0.0, 100.0]),
+        ];
+        messages.extend(layout);
+        let image = writer.object(&messages);
+        let image_min = write_slice_values(&mut writer, SLICE_RANGES.map(|range| range[0]));
+        let image_max = write_slice_values(&mut writer, SLICE_RANGES.map(|range| range[1]));
+
+        let dimensions = [
+            ("xspace", 2.0, -10.0, [2.0, 0.0, 0.0]),
+            ("yspace", 3.0, 5.0, [0.0, 0.6, 0.8]),
+            ("zspace", 1.5, 1.0, [0.0, -0.8, 0.6]),
+        ]
+        .map(|(name, step, start, cosines)| {
+            let address = writer.object(&[
+                f64_attribute("step", &[step]),
+                f64_attribute("start", &[start]),
+                f64_attribute("direction_cosines", &cosines),
+            ]);
+            (name, address)
+        });
+
+        let image_group = writer.compact_group(&[("image", image), ("image-min", image_min), ("image-max", image_max)]);
+        let images = writer.old_style_group(&[("0", image_group)]);
+        let dimensions = writer.compact_group(&dimensions);
+        let minc = writer.old_style_group(&[("image", images), ("dimensions", dimensions)]);
+        let root = writer.old_style_group(&[("minc-2.0", minc)]);
+        writer.finish(root)
+    }
+
+    /// Read a MINC2 file within limits that keep the volumes of corrupt files small.
+    fn read_minc(bytes: Vec<u8>) -> Result<Nifti, Error> {
+        read_volume(Box::new(MincReader::open(Cursor::new(bytes), "image.mnc", ReaderLimits { max_voxels: 64 })?))
+    }
+
+    fn check_image(nifti: &Nifti) {
+        let Volume::F32(voxels) = &nifti.volume else {
+            panic!("expected f32 voxels, found {:?}", nifti.volume.voxel_type());
+        };
+
+        assert_eq!(voxels.dim(), (3, 2, 2, 1));
+        for ((x, y, z, _), &value) in voxels.indexed_iter() {
+            let [minimum, maximum] = SLICE_RANGES[z];
+            let expected = get_stored_value(x, y, z) as f64 / 100.0 * (maximum - minimum) + minimum;
+            assert!((value as f64 - expected).abs() < 1e-4, "voxel ({}, {}, {}) is {}, expected {}", x, y, z, value, expected);
+        }
+
+        let expected_affine = [
+            [2.0, 0.0, 0.0, -10.0],
+            [0.0, 1.8, -1.2, 2.2],
+            [0.0, 2.4, 0.9, 4.6],
+            [0.0, 0.0, 0.0, 1.0],
+        ];
+        let is_expected_affine = nifti.affine.0.iter().flatten().zip(expected_affine.iter().flatten())
+            .all(|(value, expected)| (value - expected).abs() < 1e-9);
+        assert!(is_expected_affine, "affine {:?}, expected {:?}", nifti.affine.0, expected_affine);
+    }
+
+    #[test]
+    fn reads_contiguous_images() {
+        check_image(&read_minc(write_minc(false)).unwrap());
+    }
+
+    #[test]
+    fn reads_chunked_and_deflated_images() {
+        check_image(&read_minc(write_minc(true)).unwrap());
+    }
+
+    #[test]
+    fn rejects_truncated_and_garbage_files() {
+        for is_chunked in [false, true] {
+            let bytes = write_minc(is_chunked);
+            for size in 0..bytes.len() {
+                assert!(read_minc(bytes[..size].to_vec()).is_err(), "read a minc file from {} bytes", size);
+            }
+        }
+
+        assert!(read_minc(b"CDF\x01 minc1 file".to_vec()).is_err());
+        assert!(read_minc((0..=255).cycle().take(2048).collect()).is_err());
+    }
+
+    #[test]
+    fn does_not_panic_on_corrupt_files() {
+        for is_chunked in [false, true] {
+            let bytes = write_minc(is_chunked);
+            for position in 0..bytes.len() {
+                let mut corrupt = bytes.clone();
+                corrupt[position] ^= 0xff;
+                let _ = read_minc(corrupt);
+            }
+        }
+    }
+}
@@ -0,0 +1,115 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{display_window::DisplayWindow, error::Error, geometry::Affine, nifti_extension::NiftiExtension, volume::{Volume, VoxelType}};
+
+pub struct Nifti {
+    pub volume: Volume,
+    /// The transform from the voxel indices of the volume to its world coordinates.
+    pub affine: Affine,
+    /// The free text description of the image.
+    pub description: String,
+    /// The decoded header extensions of NIfTI images, which the other formats do not have.
+    pub extensions: Vec<NiftiExtension>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct NiftiProperies {
+    pub dimensions: ImageDimensions,
+    pub datatype: VoxelType,
+    pub minimum: f32,
+    pub maximum: f32,
+    pub extensions: Vec<NiftiExtension>,
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub struct ImageDimensions {
+    pub rows:       usize,
+    pub columns:    usize,
+    pub slices:     usize,
+    pub timepoints: usize,
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub enum AnatomicalAxis {
+    Axial    = 0, // XY plane (constant Z)
+    Coronal  = 1, // XZ plane (constant Y)
+    Sagittal = 2, // YZ plane (constant X)
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub enum Rotation {
+    Rotate0   = 0,
+    Rotate90  = 1,
+    Rotate180 = 2,
+    Rotate270 = 3,
+}
+
+/// The parameters of a displayed slice.
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub struct SliceView {
+    pub axis:       AnatomicalAxis,
+    pub coordinate: usize,
+    pub timepoint:  usize,
+    pub window:     DisplayWindow,
+    pub rotation:   Rotation,
+}
+
+impl SliceView {
+    /// Check that this view is within the bounds of a volume.
+    pub fn validate(&self, volume: &Volume) -> Result<(), Error> {
+        self.validate_dimensions(volume.dim())
+    }
+
+    /// Check that this view is within the bounds of a volume of the given dimensions, such as a
+    /// streamed volume whose timepoints are not all allocated.
+    pub fn validate_dimensions(&self, dimensions: (usize, usize, usize, usize)) -> Result<(), Error> {
+        let slice_count = self.slice_count(dimensions);
+        let (_, _, _, timepoints) = dimensions;
+        if self.coordinate >= slice_count {
+            return Err(Error::BadArgument(format!("slice {} is out of bounds, the volume has {} slices along this axis", self.coordinate, slice_count)));
+        }
+        if self.timepoint >= timepoints {
+            return Err(Error::BadArgument(format!("timepoint {} is out of bounds, the volume has {} timepoints", self.timepoint, timepoints)));
+        }
+        Ok(())
+    }
+
+    /// Get the number of slices along the axis of this view in a volume of the given dimensions.
+    pub fn slice_count(&self, dimensions: (usize, usize, usize, usize)) -> usize {
+        let (rows, columns, slices, _) = dimensions;
+        match self.axis {
+            AnatomicalAxis::Axial    => slices,
+            AnatomicalAxis::Coronal  => columns,
+            AnatomicalAxis::Sagittal => rows,
+        }
+    }
+}
+
+impl Nifti {
+    pub fn get_properties(&self) -> NiftiProperies {
+        let minimum = self.get_min_intensity();
+        let maximum = self.get_max_intensity();
+        let dimensions = self.volume.dim();
+
+        NiftiProperies {
+            dimensions: ImageDimensions {
+                rows:       dimensions.0,
+                columns:    dimensions.1,
+                slices:     dimensions.2,
+                timepoints: dimensions.3,
+            },
+            datatype: self.volume.voxel_type(),
+            minimum,
+            maximum,
+            extensions: self.extensions.clone(),
+        }
+    }
+
+    pub fn get_min_intensity(&self) -> f32 {
+        self.volume.min()
+    }
+
+    pub fn get_max_intensity(&self) -> f32 {
+        self.volume.max()
+    }
+}
@@ -0,0 +1,265 @@
+//! The header extensions of NIfTI-1 images, which carry metadata such as the JSON sidecar written
+//! by dcm2niix or the attributes of AFNI datasets, and the intents that mark files that are not
+//! volumes, such as CIFTI-2 files.
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::error::Error;
+
+/// The size of the extension flags that follow the header, whose first byte is set if the header
+/// has extensions.
+const EXTENDER_SIZE: usize = 4;
+
+/// The size of the size and code fields at the start of each extension.
+const EXTENSION_HEADER_SIZE: usize = 8;
+
+/// The code of the extensions that store the attributes of AFNI datasets as XML.
+const ECODE_AFNI: i32 = 4;
+
+/// The code of the extensions that store the CIFTI-2 XML of connectivity files.
+const ECODE_CIFTI: i32 = 32;
+
+/// A decoded header extension of a NIfTI image.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+pub enum NiftiExtension {
+    /// JSON metadata, such as the sidecar written by dcm2niix or the header of NIfTI-MRS files.
+    Json { code: i32, value: Value },
+    /// The attributes of an AFNI dataset, by name. String attributes are unquoted, and the other
+    /// attributes are their whitespace separated values.
+    Afni { attributes: BTreeMap<String, String> },
+    /// Free text, such as a comment or the CIFTI-2 XML of connectivity files.
+    Text { code: i32, text: String },
+    /// An extension whose content is not decoded, with its size in bytes.
+    Binary { code: i32, size: usize },
+}
+
+/// Parse the extensions of a header, from the bytes that follow the header up to the voxels or up
+/// to the end of the header file, starting with the extension flags. The extensions after an
+/// invalid extension size are skipped, since they cannot be located.
+pub(crate) fn parse_extensions(bytes: &[u8], is_big_endian: bool) -> Vec<NiftiExtension> {
+    if bytes.len() < EXTENDER_SIZE || bytes[0] == 0 {
+        return Vec::new();
+    }
+
+    let read_i32 = |offset: usize| {
+        let value = bytes[offset..offset + 4].try_into().unwrap();
+        if is_big_endian { i32::from_be_bytes(value) } else { i32::from_le_bytes(value) }
+    };
+
+    let mut extensions = Vec::new();
+    let mut offset = EXTENDER_SIZE;
+    while offset + EXTENSION_HEADER_SIZE <= bytes.len() {
+        let (size, code) = (read_i32(offset), read_i32(offset + 4));
+        // The space between the last extension and the voxels is usually zero padded.
+        if size == 0 {
+            break;
+        }
+
+        let end = usize::try_from(size).ok()
+            .filter(|&size| size >= EXTENSION_HEADER_SIZE)
+            .and_then(|size| offset.checked_add(size))
+            .filter(|&end| end <= bytes.len());
+        let Some(end) = end else {
+            log::warn!("skipping the header extensions after an invalid extension size {}", size);
+            break;
+        };
+
+        extensions.push(decode_extension(code, &bytes[offset + EXTENSION_HEADER_SIZE..end]));
+        offset = end;
+    }
+
+    log::debug!("found {} header extensions", extensions.len());
+    extensions
+}
+
+/// Decode the content of an extension from its code. JSON and text contents are recognized from
+/// their bytes, since their codes vary between writers.
+pub(crate) fn decode_extension(code: i32, data: &[u8]) -> NiftiExtension {
+    // The contents are zero padded to the size of their extension.
+    let end = data.iter().rposition(|&byte| byte != 0).map_or(0, |position| position + 1);
+    let Ok(text) = std::str::from_utf8(&data[..end]) else {
+        return NiftiExtension::Binary { code, size: data.len() };
+    };
+
+    let text = text.trim();
+    if code == ECODE_AFNI && let Some(attributes) = parse_afni_attributes(text) {
+        return NiftiExtension::Afni { attributes };
+    }
+
+    if text.starts_with('{') && let Ok(value) = serde_json::from_str(text) {
+        return NiftiExtension::Json { code, value };
+    }
+
+    // The CIFTI-2 XML is kept as text, the intent code of its image tells whether it is a volume.
+    if code == ECODE_CIFTI || text.chars().all(|c| !c.is_control() || c.is_whitespace()) {
+        NiftiExtension::Text { code, text: text.to_string() }
+    } else {
+        NiftiExtension::Binary { code, size: data.len() }
+    }
+}
+
+/// Check that the intent code of a header does not mark its image as data that is not a volume,
+/// such as a CIFTI-2 file, a surface or a matrix.
+pub(crate) fn check_intent(intent_code: i16) -> Result<(), Error> {
+    let kind = match intent_code {
+        1004 => "general matrix",
+        1005 => "symmetric matrix",
+        1008 => "point set",
+        1009 => "triangle",
+        2002 => "node index",
+        2005 => "surface shape",
+        3000..=3099 => "CIFTI-2",
+        _ => return Ok(()),
+    };
+
+    Err(Error::UnsupportedDatatype(format!("the image holds {} data (nifti intent code {}), which is unsupported as volume", kind, intent_code)))
+}
+
+/// Parse the `AFNI_atr` elements of the XML of an AFNI extension, or get `None` if it has none.
+fn parse_afni_attributes(xml: &str) -> Option<BTreeMap<String, String>> {
+    let mut attributes = BTreeMap::new();
+    let mut rest = xml;
+    while let Some(start) = rest.find("<AFNI_atr") {
+        let element = &rest[start..];
+        let tag_end = element.find('>')?;
+        let end = element[tag_end..].find("</AFNI_atr>")? + tag_end;
+        let name = get_xml_attribute(&element[..tag_end], "atr_name")?;
+        let value = unescape_xml(element[tag_end + 1..end].trim());
+        let value = match value.strip_prefix('"').and_then(|value| value.strip_suffix('"')) {
+            Some(string) => string.to_string(),
+            None => value.split_whitespace().collect::<Vec<_>>().join(" "),
+        };
+
+        attributes.insert(name, value);
+        rest = &element[end..];
+    }
+
+    (!attributes.is_empty()).then_some(attributes)
+}
+
+/// Get the value of an attribute of an XML start tag.
+fn get_xml_attribute(tag: &str, name: &str) -> Option<String> {
+    let start = tag.match_indices(name)
+        .map(|(position, _)| position + name.len())
+        .find(|&position| tag[position..].starts_with('=') && tag[..position - name.len()].ends_with(char::is_whitespace))?;
+    let value = &tag[start + 1..];
+    let quote = value.chars().next().filter(|&quote| quote == '"' || quote == '\'')?;
+    let end = value[1..].find(quote)? + 1;
+    Some(unescape_xml(&value[1..end]))
+}
+
+fn unescape_xml(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const AFNI_XML: &str = r#"<?xml version='1.0' ?>
+<AFNI_attributes self_idcode="XYZ_1" ni_form="ni_group">
+  <AFNI_atr atr_name="HISTORY_NOTE" ni_type="String" ni_dimen="1">
+    "3dcalc -a &quot;anat&quot; &lt; 1"
+  </AFNI_atr>
+  <AFNI_atr ni_type="float" ni_dimen="3" atr_name="DELTA">
+    -1.5
+    2
+    3
+  </AFNI_atr>
+</AFNI_attributes>"#;
+
+    /// Encode the extensions of a header after its extension flags, with each content zero padded
+    /// to a multiple of 16 bytes.
+    fn encode_extensions(extensions: &[(i32, &[u8])], is_big_endian: bool) -> Vec<u8> {
+        let encode_i32 = |value: i32| if is_big_endian { value.to_be_bytes() } else { value.to_le_bytes() };
+        let mut bytes = vec![1, 0, 0, 0];
+        for &(code, data) in extensions {
+            let size = (EXTENSION_HEADER_SIZE + data.len()).next_multiple_of(16);
+            bytes.extend(encode_i32(size as i32));
+            bytes.extend(encode_i32(code));
+            bytes.extend(data);
+            bytes.resize(bytes.len() + size - EXTENSION_HEADER_SIZE - data.len(), 0);
+        }
+
+        bytes
+    }
+
+    #[test]
+    fn decodes_extensions_of_both_byte_orders() {
+        let extensions: [(i32, &[u8]); 5] = [
+            (44, br#"{"EchoTime": 0.03, "Manufacturer": "Siemens"}"#),
+            (ECODE_AFNI, AFNI_XML.as_bytes()),
+            (6, b"a comment\n"),
+            (ECODE_CIFTI, b"<CIFTI Version=\"2\"></CIFTI>"),
+            (0, &[0xFF, 0xFE, 1, 2]),
+        ];
+
+        for is_big_endian in [false, true] {
+            let mut bytes = encode_extensions(&extensions, is_big_endian);
+            // The voxels follow zero padding.
+            bytes.extend([0; 16]);
+            let decoded = parse_extensions(&bytes, is_big_endian);
+            assert_eq!(decoded.len(), 5);
+
+            let NiftiExtension::Json { code: 44, value } = &decoded[0] else {
+                panic!("expected a json extension, found {:?}", decoded[0]);
+            };
+            assert_eq!(value["Manufacturer"], "Siemens");
+
+            assert!(matches!(&decoded[1], NiftiExtension::Afni { attributes } if attributes.len() == 2));
+            assert!(matches!(&decoded[2], NiftiExtension::Text { code: 6, text } if text == "a comment"));
+            assert!(matches!(&decoded[3], NiftiExtension::Text { code: ECODE_CIFTI, text } if text.starts_with("<CIFTI")));
+            assert!(matches!(decoded[4], NiftiExtension::Binary { code: 0, size: 8 }));
+        }
+    }
+
+    #[test]
+    fn parses_afni_attributes() {
+        let attributes = parse_afni_attributes(AFNI_XML).unwrap();
+        assert_eq!(attributes["HISTORY_NOTE"], r#"3dcalc -a "anat" < 1"#);
+        assert_eq!(attributes["DELTA"], "-1.5 2 3");
+
+        // XML without attributes is kept as text.
+        assert!(parse_afni_attributes("<AFNI_attributes></AFNI_attributes>").is_none());
+        assert!(matches!(decode_extension(ECODE_AFNI, b"<AFNI_attributes/>"), NiftiExtension::Text { .. }));
+        assert!(parse_afni_attributes(r#"<AFNI_atr atr_name="TRUNCATED">1 2"#).is_none());
+    }
+
+    #[test]
+    fn skips_extensions_after_an_invalid_size() {
+        let mut bytes = encode_extensions(&[(6, b"first"), (6, b"second")], false);
+        assert_eq!(parse_extensions(&bytes, false).len(), 2);
+
+        // The size of the second extension is past the end of the bytes.
+        bytes[20..24].copy_from_slice(&1000i32.to_le_bytes());
+        assert_eq!(parse_extensions(&bytes, false).len(), 1);
+        bytes[20..24].copy_from_slice(&4i32.to_le_bytes());
+        assert_eq!(parse_extensions(&bytes, false).len(), 1);
+        bytes[20..24].copy_from_slice(&(-16i32).to_le_bytes());
+        assert_eq!(parse_extensions(&bytes, false).len(), 1);
+
+        // The extensions are ignored without the extension flag.
+        bytes[0] = 0;
+        assert!(parse_extensions(&bytes, false).is_empty());
+        assert!(parse_extensions(&[1, 0], false).is_empty());
+    }
+
+    #[test]
+    fn rejects_intents_that_are_not_volumes() {
+        for intent_code in [0, 2, 1006, 1007, 2003] {
+            assert!(check_intent(intent_code).is_ok(), "rejected intent code {}", intent_code);
+        }
+
+        for intent_code in [1004, 1005, 1008, 1009, 2002, 2005, 3000, 3006, 3099] {
+            assert!(matches!(check_intent(intent_code), Err(Error::UnsupportedDatatype(_))), "accepted intent code {}", intent_code);
+        }
+    }
+}
@@ -0,0 +1,435 @@
+use std::{io::{self, Read, Seek}, ops::Range};
+
+use ndarray::ShapeBuilder;
+use nifti::{DataElement, InMemNiftiVolume, NiftiHeader, NiftiObject, NiftiType, StreamedNiftiObject, StreamedNiftiVolume};
+use nifti::volume::ndarray::IntoNdArray;
+
+use crate::{analyze::{self, HEADER_SIZE}, error::Error, image_reader::{self, ReadProgress, ReaderLimits, VolumeReader, open_source}, geometry::Affine, nifti::Nifti, nifti_extension::{self, NiftiExtension}, volume::{ScalarType, Volume, Voxel, VoxelType, with_volume}};
+
+/// The offset of the voxels in a single file NIfTI-1 image, after the header and its extension
+/// flags.
+const MIN_VOX_OFFSET: f32 = 352.0;
+
+/// Read a NIfTI image from a source, which may be gzip compressed. The name hint is the file name
+/// of the image, if any, and is only used for logging.
+pub fn read_nifti<R: Read + Seek>(source: R, name_hint: &str, limits: ReaderLimits) -> Result<Nifti, Error> {
+    image_reader::read_volume(Box::new(NiftiReader::open(source, name_hint, limits)?))
+}
+
+/// Read a two-file image, from the source of its `.hdr` header and the source of its `.img` voxels,
+/// which may both be gzip compressed. The header is either an Analyze 7.5 header or a NIfTI-1
+/// header.
+pub fn read_nifti_pair<H: Read + Seek, R: Read + Seek>(header_source: H, image_source: R, name_hint: &str, limits: ReaderLimits) -> Result<Nifti, Error> {
+    image_reader::read_volume(Box::new(NiftiReader::open_pair(header_source, image_source, name_hint, limits)?))
+}
+
+/// A reader that reads a NIfTI volume slice by slice. The volume is allocated when the reader is
+/// opened, and freed if the reader is dropped before being finished.
+pub struct NiftiReader<'a> {
+    volume_reader: StreamedNiftiVolume<Box<dyn Read + 'a>>,
+    volume: Volume,
+    affine: Affine,
+    description: String,
+    extensions: Vec<NiftiExtension>,
+    is_4d: bool,
+    slice_count: usize,
+    slices_read: usize,
+}
+
+impl<'a> NiftiReader<'a> {
+    /// Open a NIfTI image from a source, which may be gzip compressed, and allocate its volume.
+    pub fn open<R: Read + Seek + 'a>(source: R, name_hint: &str, limits: ReaderLimits) -> Result<Self, Error> {
+        log::debug!("reading the nifti file {}", name_hint);
+        let (source, source_size) = open_source(source)?;
+        let nifti = StreamedNiftiObject::from_reader(source)?;
+        validate_header(nifti.header(), source_size, MIN_VOX_OFFSET, limits)?;
+        let extensions = nifti.extensions().iter()
+            .map(|extension| nifti_extension::decode_extension(extension.code(), extension.data()))
+            .collect();
+        let header = nifti.header().clone();
+        Self::from_volume_reader(&header, nifti.into_volume(), get_affine(&header), extensions)
+    }
+
+    /// Open a two-file image from the source of its `.hdr` header and the source of its `.img`
+    /// voxels, which may both be gzip compressed, and allocate its volume.
+    pub fn open_pair<H: Read + Seek + 'a, R: Read + Seek + 'a>(header_source: H, image_source: R, name_hint: &str, limits: ReaderLimits) -> Result<Self, Error> {
+        log::debug!("reading the two-file image {}", name_hint);
+        let mut bytes = [0; HEADER_SIZE];
+        let (mut header_source, _) = open_source(header_source)?;
+        header_source.read_exact(&mut bytes)?;
+        let (header, analyze_affine) = analyze::parse_pair_header(bytes)?;
+        // The extensions of NIfTI-1 pairs follow the header in the header file, and Analyze 7.5
+        // headers have none.
+        let extensions = if analyze_affine.is_none() {
+            let mut extension_bytes = Vec::new();
+            header_source.read_to_end(&mut extension_bytes)?;
+            nifti_extension::parse_extensions(&extension_bytes, analyze::is_big_endian(&bytes)?)
+        } else {
+            Vec::new()
+        };
+
+        // The voxels of two-file images start at the voxel offset of their image file, which is
+        // usually zero.
+        let (mut image_source, image_size) = open_source(image_source)?;
+        validate_header(&header, image_size, 0.0, limits)?;
+        io::copy(&mut image_source.by_ref().take(header.vox_offset as u64), &mut io::sink())?;
+        let volume_reader = StreamedNiftiVolume::from_reader(image_source, &header)?;
+        Self::from_volume_reader(&header, volume_reader, analyze_affine.unwrap_or_else(|| get_affine(&header)), extensions)
+    }
+
+    fn from_volume_reader(header: &NiftiHeader, volume_reader: StreamedNiftiVolume<Box<dyn Read + 'a>>, affine: Affine, extensions: Vec<NiftiExtension>) -> Result<Self, Error> {
+        let voxel_type = get_native_type(header);
+        let description = get_description(header);
+        let dimensions = volume_reader.dim().to_owned();
+        let is_4d = match dimensions.len() {
+            3 => {
+                log::debug!("found 3d nifti file");
+                false
+            }
+            4 => {
+                log::debug!("found 4d nifti file");
+                true
+            }
+            dimensionality => {
+                return Err(Error::InvalidHeader(format!("unsupported {}d image, only 3d and 4d images are supported", dimensionality)));
+            }
+        };
+
+        let timepoints = if is_4d { dimensions[3] as usize } else { 1 };
+
+        let dimensions = (
+            dimensions[0] as usize,
+            dimensions[1] as usize,
+            dimensions[2] as usize,
+            timepoints,
+        );
+
+        Ok(Self {
+            volume_reader,
+            volume: Volume::zeros(get_voxel_type(voxel_type), dimensions),
+            affine,
+            description,
+            extensions,
+            is_4d,
+            slice_count: if is_4d { dimensions.3 } else { dimensions.2 },
+            slices_read: 0,
+        })
+    }
+}
+
+impl VolumeReader for NiftiReader<'_> {
+    fn progress(&self) -> ReadProgress {
+        ReadProgress {
+            slices_read: self.slices_read,
+            slice_count: self.slice_count,
+        }
+    }
+
+    /// Check whether the source has no slices left to read.
+    fn is_done(&self) -> bool {
+        self.volume_reader.slices_left() == 0
+    }
+
+    fn read_slice(&mut self) -> Result<(), Error> {
+        if self.slices_read == self.slice_count {
+            return Err(Error::InvalidHeader(format!("expected {} slices, found more", self.slice_count)));
+        }
+
+        let slice = self.volume_reader.read_slice()?;
+        let (index, is_4d) = (self.slices_read, self.is_4d);
+        with_volume!(&mut self.volume, array => assign_slice(array, slice, index, is_4d))?;
+        self.slices_read += 1;
+        Ok(())
+    }
+
+    fn finish(self: Box<Self>) -> Result<Nifti, Error> {
+        if self.slices_read != self.slice_count {
+            return Err(Error::InvalidHeader(format!("expected {} slices, found {}", self.slice_count, self.slices_read)));
+        }
+
+        log::debug!("read {} nifti slices", self.slices_read);
+        Ok(Nifti {
+            volume: self.volume,
+            affine: self.affine,
+            description: self.description,
+            extensions: self.extensions,
+        })
+    }
+}
+
+/// A reader of the voxels of an uncompressed single file NIfTI-1 image from byte ranges of its
+/// file, such as ranges of a file served over HTTP. The reader gives the ranges to read and decodes
+/// them, and the caller fetches them, so that the timepoints of 4D images are only read when they
+/// are needed. The slices of each timepoint are read in order.
+pub struct NiftiRangeReader {
+    value_type: ScalarType,
+    is_big_endian: bool,
+    /// The slope and intercept of the voxels, if they are scaled.
+    scaling: Option<(f64, f64)>,
+    vox_offset: u64,
+    /// The dimensions of the volume of each timepoint.
+    dimensions: (usize, usize, usize, usize),
+    /// The size of the slices, in bytes.
+    slice_size: u64,
+    slice_count: usize,
+    /// The number of slices read of each timepoint.
+    slices_read: Vec<usize>,
+}
+
+impl NiftiRangeReader {
+    /// The size of the header, which is the first range to read.
+    pub const HEADER_SIZE: u64 = HEADER_SIZE as u64;
+
+    /// Open an image from the bytes of its header and the size of its file, if it is known. Only
+    /// the first timepoint of its volume is allocated, whose voxels are zero until their slices are
+    /// read, and the volumes of the other timepoints are allocated by the caller when they are
+    /// read.
+    pub fn open(header_bytes: &[u8], file_size: Option<u64>, name_hint: &str, limits: ReaderLimits) -> Result<(Self, Nifti), Error> {
+        log::debug!("reading the nifti file {} by ranges", name_hint);
+        if header_bytes.starts_with(&image_reader::GZIP_MAGIC) {
+            return Err(Error::UnsupportedDatatype("compressed nifti files cannot be read by ranges".to_string()));
+        }
+
+        let header_bytes = header_bytes.get(..HEADER_SIZE)
+            .ok_or_else(|| Error::InvalidHeader(format!("expected a header of {} bytes, found {}", HEADER_SIZE, header_bytes.len())))?;
+        let is_big_endian = analyze::is_big_endian(header_bytes)?;
+
+        let header = NiftiHeader::from_reader(header_bytes)?;
+        validate_header(&header, file_size, MIN_VOX_OFFSET, limits)?;
+        let dimensionality = header.dim[0] as usize;
+        if !(3..=4).contains(&dimensionality) {
+            return Err(Error::InvalidHeader(format!("unsupported {}d image, only 3d and 4d images are supported", dimensionality)));
+        }
+
+        let datatype = header.data_type()?;
+        let value_type = get_scalar_type(datatype)
+            .ok_or_else(|| Error::UnsupportedDatatype(format!("{:?} voxels cannot be read by ranges", datatype)))?;
+        let [x_size, y_size, slice_count] = [1, 2, 3].map(|i| header.dim[i] as usize);
+        let timepoints = if dimensionality == 4 { header.dim[4] as usize } else { 1 };
+        let reader = Self {
+            value_type,
+            is_big_endian,
+            scaling: get_scaling(&header),
+            vox_offset: header.vox_offset as u64,
+            dimensions: (x_size, y_size, slice_count, 1),
+            slice_size: (x_size * y_size * value_type.size()) as u64,
+            slice_count,
+            slices_read: vec![0; timepoints],
+        };
+
+        let nifti = Nifti {
+            volume: Volume::zeros(get_voxel_type(get_native_type(&header)), reader.dimensions),
+            affine: get_affine(&header),
+            description: get_description(&header),
+            extensions: Vec::new(),
+        };
+
+        Ok((reader, nifti))
+    }
+
+    /// Get the byte range of the extensions of the header, which follow the header and its
+    /// extension flags, or `None` if the voxels follow the extension flags.
+    pub fn extension_range(&self) -> Option<Range<u64>> {
+        (self.vox_offset > MIN_VOX_OFFSET as u64).then_some(Self::HEADER_SIZE..self.vox_offset)
+    }
+
+    /// Decode the extensions of the header from the bytes of their range into the image.
+    pub fn read_extensions(&self, nifti: &mut Nifti, bytes: &[u8]) {
+        nifti.extensions = nifti_extension::parse_extensions(bytes, self.is_big_endian);
+    }
+
+    /// Get the number of timepoints of the image.
+    pub fn timepoints(&self) -> usize {
+        self.slices_read.len()
+    }
+
+    /// Get the progress of the read of a timepoint, in slices.
+    pub fn progress(&self, timepoint: usize) -> ReadProgress {
+        ReadProgress {
+            slices_read: self.slices_read.get(timepoint).copied().unwrap_or(self.slice_count),
+            slice_count: self.slice_count,
+        }
+    }
+
+    /// Check whether all the slices of a timepoint are read.
+    pub fn is_loaded(&self, timepoint: usize) -> bool {
+        let progress = self.progress(timepoint);
+        progress.slices_read == progress.slice_count
+    }
+
+    /// Get the byte range of the next slices of a timepoint to read, which is at most the maximum
+    /// size but has at least one slice, or `None` if the timepoint is read.
+    pub fn next_range(&self, timepoint: usize, max_size: u64) -> Option<Range<u64>> {
+        let first_slice = *self.slices_read.get(timepoint)?;
+        if first_slice == self.slice_count {
+            return None;
+        }
+
+        let slice_count = (max_size / self.slice_size).clamp(1, (self.slice_count - first_slice) as u64);
+        let start = self.vox_offset + (timepoint * self.slice_count + first_slice) as u64 * self.slice_size;
+        Some(start..start + slice_count * self.slice_size)
+    }
+
+    /// Decode the bytes of the next range of a timepoint into the volume of the timepoint, which
+    /// has a single timepoint, and get the indices of the slices read.
+    pub fn read_range(&mut self, volume: &mut Volume, timepoint: usize, bytes: Vec<u8>) -> Result<Range<usize>, Error> {
+        let first_slice = *self.slices_read.get(timepoint)
+            .ok_or_else(|| Error::BadArgument(format!("invalid timepoint {}", timepoint)))?;
+        if volume.dim() != self.dimensions {
+            return Err(Error::BadArgument(format!("expected the volume of a timepoint of dimensions {:?}, found {:?}", self.dimensions, volume.dim())));
+        }
+        let slice_count = bytes.len() / self.slice_size as usize;
+        if !bytes.len().is_multiple_of(self.slice_size as usize) || first_slice + slice_count > self.slice_count {
+            return Err(Error::InvalidHeader(format!("expected at most {} slices of {} bytes, found {} bytes", self.slice_count - first_slice, self.slice_size, bytes.len())));
+        }
+
+        let slices = first_slice..first_slice + slice_count;
+        with_volume!(volume, array => assign_slices(array, self.decode(bytes), 0, slices.clone()));
+        self.slices_read[timepoint] = slices.end;
+        Ok(slices)
+    }
+
+    /// Decode stored voxels, and scale them if they are scaled.
+    fn decode<T: Voxel>(&self, bytes: Vec<u8>) -> Vec<T> {
+        match self.scaling {
+            Some((slope, intercept)) => self.value_type.decode::<f64>(bytes, self.is_big_endian).into_iter()
+                .map(|value| T::from_f64(value * slope + intercept))
+                .collect(),
+            None => self.value_type.decode(bytes, self.is_big_endian),
+        }
+    }
+}
+
+/// Check that the header of a NIfTI file is consistent and holds a volume, and that the volume is
+/// within the limits, before allocating it. The voxels of the file must start after the minimum
+/// voxel offset.
+fn validate_header(header: &NiftiHeader, source_size: Option<u64>, min_vox_offset: f32, limits: ReaderLimits) -> Result<(), Error> {
+    nifti_extension::check_intent(header.intent_code)?;
+    let dimensionality = header.dim[0] as usize;
+    if !(1..=7).contains(&dimensionality) {
+        return Err(Error::InvalidHeader(format!("invalid number of dimensions {}", header.dim[0] as i16)));
+    }
+
+    // The dimensions are signed on disk, negative dimensions are read as very large ones.
+    let dimensions = &header.dim[1..=dimensionality];
+    if let Some(&dimension) = dimensions.iter().find(|&&dimension| dimension == 0 || dimension > i16::MAX as u16) {
+        return Err(Error::InvalidHeader(format!("invalid dimension {}", dimension as i16)));
+    }
+
+    let dimensions: Vec<usize> = dimensions.iter().map(|&dimension| dimension as usize).collect();
+    let voxel_count = image_reader::get_voxel_count(&dimensions, limits)?;
+
+    let datatype = header.data_type()
+        .map_err(|_| Error::UnsupportedDatatype(format!("unknown datatype code {}", header.datatype)))?;
+    let voxel_size = datatype.size_of();
+    if header.bitpix as usize != 8 * voxel_size {
+        return Err(Error::InvalidHeader(format!("bitpix {} does not match datatype {:?}", header.bitpix, datatype)));
+    }
+
+    if !header.vox_offset.is_finite() || header.vox_offset < min_vox_offset {
+        return Err(Error::InvalidHeader(format!("invalid voxel offset {}", header.vox_offset)));
+    }
+
+    if let Some(source_size) = source_size {
+        let expected_size = header.vox_offset as u64 + (voxel_count as u64) * (voxel_size as u64);
+        if source_size < expected_size {
+            return Err(Error::InvalidHeader(format!("file is truncated, expected {} bytes, found {}", expected_size, source_size)));
+        }
+    }
+
+    Ok(())
+}
+
+/// Get the affine of a NIfTI file, from its sform if it is set, from its qform otherwise, or from
+/// its voxel sizes if neither is set.
+fn get_affine(header: &NiftiHeader) -> Affine {
+    let voxel_sizes = [1, 2, 3].map(|i| header.pixdim[i] as f64);
+    if header.sform_code > 0 {
+        let rows = [header.srow_x, header.srow_y, header.srow_z].map(|row| row.map(|value| value as f64));
+        Affine([rows[0], rows[1], rows[2], [0.0, 0.0, 0.0, 1.0]])
+    } else if header.qform_code > 0 {
+        let quaternion = [header.quatern_b, header.quatern_c, header.quatern_d].map(|value| value as f64);
+        let offset = [header.quatern_x, header.quatern_y, header.quatern_z].map(|value| value as f64);
+        let qfac = if header.pixdim[0] < 0.0 { -1.0 } else { 1.0 };
+        Affine::from_quaternion(quaternion, qfac, voxel_sizes, offset)
+    } else {
+        Affine::from_scaling(voxel_sizes)
+    }
+}
+
+/// Get the description of a NIfTI file, which is a null terminated string.
+fn get_description(header: &NiftiHeader) -> String {
+    let length = header.descrip.iter().position(|&byte| byte == 0).unwrap_or(header.descrip.len());
+    String::from_utf8_lossy(&header.descrip[..length]).into_owned()
+}
+
+/// Get the datatype in which the voxels of a NIfTI file can be stored without loss. Files whose
+/// datatype is not natively supported, or whose values are scaled, are stored as `f32`.
+fn get_native_type(header: &NiftiHeader) -> NiftiType {
+    match header.data_type() {
+        Ok(voxel_type) if get_scaling(header).is_none() => voxel_type,
+        _ => NiftiType::Float32,
+    }
+}
+
+/// Get the slope and intercept with which the values of a NIfTI file are scaled, or `None` if the
+/// values are not scaled, which a zero slope also means.
+fn get_scaling(header: &NiftiHeader) -> Option<(f64, f64)> {
+    let is_scaled = header.scl_slope != 0.0 && (header.scl_slope != 1.0 || header.scl_inter != 0.0);
+    is_scaled.then_some((header.scl_slope as f64, header.scl_inter as f64))
+}
+
+/// Get the voxel datatype of the volume of a NIfTI datatype, which is `f32` for the datatypes that
+/// are not natively supported.
+fn get_voxel_type(voxel_type: NiftiType) -> VoxelType {
+    match voxel_type {
+        NiftiType::Uint8   => VoxelType::U8,
+        NiftiType::Int16   => VoxelType::I16,
+        NiftiType::Uint16  => VoxelType::U16,
+        NiftiType::Int32   => VoxelType::I32,
+        NiftiType::Float64 => VoxelType::F64,
+        _                  => VoxelType::F32,
+    }
+}
+
+/// Get the type of the stored values of a NIfTI datatype, if it is a scalar datatype.
+fn get_scalar_type(datatype: NiftiType) -> Option<ScalarType> {
+    match datatype {
+        NiftiType::Int8    => Some(ScalarType::I8),
+        NiftiType::Uint8   => Some(ScalarType::U8),
+        NiftiType::Int16   => Some(ScalarType::I16),
+        NiftiType::Uint16  => Some(ScalarType::U16),
+        NiftiType::Int32   => Some(ScalarType::I32),
+        NiftiType::Uint32  => Some(ScalarType::U32),
+        NiftiType::Int64   => Some(ScalarType::I64),
+        NiftiType::Uint64  => Some(ScalarType::U64),
+        NiftiType::Float32 => Some(ScalarType::F32),
+        NiftiType::Float64 => Some(ScalarType::F64),
+        _                  => None,
+    }
+}
+
+/// Copy slices of a timepoint read from byte ranges into the array of a volume.
+fn assign_slices<T: Voxel>(volume: &mut ndarray::Array4<T>, voxels: Vec<T>, timepoint: usize, slices: Range<usize>) {
+    let mut volume_slices = volume.slice_mut(ndarray::s![.., .., slices, timepoint]);
+    // The voxels have the size of the slices, since they were read from them.
+    let voxels = ndarray::ArrayView3::from_shape(volume_slices.raw_dim().f(), &voxels).unwrap();
+    volume_slices.assign(&voxels);
+}
+
+/// Copy a slice read from a NIfTI file into the array of its volume.
+fn assign_slice<T: Voxel + DataElement>(volume: &mut ndarray::Array4<T>, slice: InMemNiftiVolume, index: usize, is_4d: bool) -> Result<(), Error> {
+    let slice_array = slice.into_ndarray::<T>()?;
+    let mut volume_slice = if is_4d {
+        volume.slice_mut(ndarray::s![.., .., .., index]).into_dyn()
+    } else {
+        volume.slice_mut(ndarray::s![.., .., index, 0]).into_dyn()
+    };
+
+    if volume_slice.shape() != slice_array.shape() {
+        return Err(Error::InvalidHeader(format!("expected slices of shape {:?}, found {:?}", volume_slice.shape(), slice_array.shape())));
+    }
+
+    volume_slice.assign(&slice_array);
+    Ok(())
+}
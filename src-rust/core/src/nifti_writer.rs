@@ -0,0 +1,238 @@
+use std::io::Write;
+
+use flate2::{write::GzEncoder, Compression};
+use ndarray::Array4;
+use serde::{Deserialize, Serialize};
+
+use crate::{error::Error, geometry::Affine, nifti::Nifti, volume::{Voxel, VoxelType, voxel_to_le_bytes, with_volume}};
+
+/// The size of a NIfTI-1 header.
+const HEADER_SIZE: usize = 348;
+
+/// The offset of the voxels in a single file NIfTI-1 image, after the header and its extension
+/// flags.
+const VOX_OFFSET: usize = 352;
+
+/// The NIfTI code of the scanner-based world coordinates.
+const XFORM_SCANNER_ANAT: i16 = 1;
+
+/// The NIfTI units code of millimeters.
+const UNITS_MM: u8 = 2;
+
+/// The options of a written NIfTI-1 file. The fields that are not set are taken from the image.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NiftiWriteOptions {
+    /// Whether the file is gzip compressed, as a `.nii.gz` file.
+    pub compress: bool,
+    pub affine: Option<Affine>,
+    /// The datatype of the stored voxels.
+    pub datatype: Option<VoxelType>,
+    /// The scaling from the stored voxels to the voxels of the image, which are not scaled by
+    /// default.
+    pub scaling: Option<NiftiScaling>,
+    /// The description of the file, which is truncated to 79 bytes.
+    pub description: Option<String>,
+}
+
+/// The scaling of the voxels of a NIfTI-1 file, whose real values are `slope * stored + intercept`.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum NiftiScaling {
+    Linear { slope: f64, intercept: f64 },
+    /// Fit the range of the image to the range of the integer datatype of the stored voxels, such
+    /// as for floating point maps stored as `I16`.
+    Fit,
+}
+
+/// Write an image as a single file NIfTI-1 image.
+pub fn write_nifti<W: Write>(nifti: &Nifti, writer: W, options: &NiftiWriteOptions) -> Result<(), Error> {
+    let (x_size, y_size, z_size, timepoints) = nifti.volume.dim();
+    if [x_size, y_size, z_size, timepoints].into_iter().any(|size| size > i16::MAX as usize) {
+        return Err(Error::BadArgument(format!("image dimensions {:?} are too large for NIfTI-1", nifti.volume.dim())));
+    }
+
+    if let Some(affine) = options.affine && affine.0.iter().flatten().any(|value| !value.is_finite()) {
+        return Err(Error::BadArgument(format!("invalid affine {:?}", affine.0)));
+    }
+
+    let datatype = options.datatype.unwrap_or(nifti.volume.voxel_type());
+    let (slope, intercept) = match options.scaling {
+        None => (1.0, 0.0),
+        Some(NiftiScaling::Linear { slope, intercept }) => {
+            if slope == 0.0 || !slope.is_finite() || !intercept.is_finite() {
+                return Err(Error::BadArgument(format!("invalid scaling slope {} and intercept {}", slope, intercept)));
+            }
+
+            (slope, intercept)
+        }
+        Some(NiftiScaling::Fit) => get_fit_scaling(nifti, datatype),
+    };
+
+    let file = NiftiFile { nifti, options, datatype, slope, intercept };
+    if options.compress {
+        let mut encoder = GzEncoder::new(writer, Compression::default());
+        file.write(&mut encoder)?;
+        encoder.finish()?;
+        Ok(())
+    } else {
+        file.write(writer)
+    }
+}
+
+/// Write an image as a single file NIfTI-1 image in memory.
+pub fn encode_nifti(nifti: &Nifti, options: &NiftiWriteOptions) -> Result<Vec<u8>, Error> {
+    let mut bytes = Vec::new();
+    write_nifti(nifti, &mut bytes, options)?;
+    Ok(bytes)
+}
+
+/// An image with the datatype and scaling of its written voxels.
+struct NiftiFile<'a> {
+    nifti: &'a Nifti,
+    options: &'a NiftiWriteOptions,
+    datatype: VoxelType,
+    slope: f64,
+    intercept: f64,
+}
+
+impl NiftiFile<'_> {
+    fn write<W: Write>(&self, mut writer: W) -> Result<(), Error> {
+        writer.write_all(&self.encode_header())?;
+        // The extension flags, which mark that the header has no extensions.
+        writer.write_all(&[0; VOX_OFFSET - HEADER_SIZE])?;
+        with_volume!(&self.nifti.volume, array => match self.datatype {
+            VoxelType::U8  => self.write_voxels::<_, u8, _>(array, &mut writer),
+            VoxelType::I16 => self.write_voxels::<_, i16, _>(array, &mut writer),
+            VoxelType::U16 => self.write_voxels::<_, u16, _>(array, &mut writer),
+            VoxelType::I32 => self.write_voxels::<_, i32, _>(array, &mut writer),
+            VoxelType::F32 => self.write_voxels::<_, f32, _>(array, &mut writer),
+            VoxelType::F64 => self.write_voxels::<_, f64, _>(array, &mut writer),
+        })?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Encode the NIfTI-1 header of the image, in little-endian byte order.
+    fn encode_header(&self) -> [u8; HEADER_SIZE] {
+        let mut header = HeaderEncoder([0; HEADER_SIZE]);
+        let (x_size, y_size, z_size, timepoints) = self.nifti.volume.dim();
+        let dimensionality = if timepoints > 1 { 4 } else { 3 };
+        let (datatype, bitpix) = get_datatype_code(self.datatype);
+        let affine = self.options.affine.unwrap_or(self.nifti.affine);
+        let [x_voxel_size, y_voxel_size, z_voxel_size] = affine.voxel_sizes();
+        let description = self.options.description.as_ref().unwrap_or(&self.nifti.description);
+
+        header.i32(0, HEADER_SIZE as i32);
+        header.bytes(38, b"r");
+        header.i16s(40, &[dimensionality, x_size as i16, y_size as i16, z_size as i16, timepoints as i16, 1, 1, 1]);
+        header.i16(70, datatype);
+        header.i16(72, bitpix);
+        header.f32s(76, &[1.0, x_voxel_size as f32, y_voxel_size as f32, z_voxel_size as f32, 1.0, 1.0, 1.0, 1.0]);
+        header.f32(108, VOX_OFFSET as f32);
+        header.f32s(112, &[self.slope as f32, self.intercept as f32]);
+        header.bytes(123, &[UNITS_MM]);
+        header.bytes(148, &description.as_bytes()[..description.len().min(79)]);
+        header.i16(254, XFORM_SCANNER_ANAT);
+        for (i, row) in affine.0[..3].iter().enumerate() {
+            header.f32s(280 + 16 * i, &row.map(|value| value as f32));
+        }
+        header.bytes(344, b"n+1\0");
+        header.0
+    }
+
+    /// Write the voxels of a volume with the first voxel axis varying the fastest, in the stored
+    /// datatype. Scaled voxels are rounded to the nearest stored value.
+    fn write_voxels<T: Voxel, U: Voxel, W: Write>(&self, array: &Array4<T>, writer: &mut W) -> Result<(), Error> {
+        let is_scaled = self.slope != 1.0 || self.intercept != 0.0;
+        let is_integer = !matches!(self.datatype, VoxelType::F32 | VoxelType::F64);
+        let encode = |voxel: T| -> U {
+            let value = voxel.to_f64();
+            if is_scaled {
+                let value = (value - self.intercept) / self.slope;
+                U::from_f64(if is_integer { value.round() } else { value })
+            } else {
+                U::from_f64(value)
+            }
+        };
+
+        for timepoint in array.axis_iter(ndarray::Axis(3)) {
+            for slice in timepoint.axis_iter(ndarray::Axis(2)) {
+                let mut row = Vec::with_capacity(slice.len() * size_of::<U>());
+                for voxel in slice.t().iter() {
+                    row.extend_from_slice(&voxel_to_le_bytes(encode(*voxel)));
+                }
+                writer.write_all(&row)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Get the scaling that fits the range of an image to the range of an integer datatype, or no
+/// scaling for floating point datatypes.
+fn get_fit_scaling(nifti: &Nifti, datatype: VoxelType) -> (f64, f64) {
+    let (stored_minimum, stored_maximum) = match datatype {
+        VoxelType::U8  => (u8::MIN as f64, u8::MAX as f64),
+        VoxelType::I16 => (i16::MIN as f64, i16::MAX as f64),
+        VoxelType::U16 => (u16::MIN as f64, u16::MAX as f64),
+        VoxelType::I32 => (i32::MIN as f64, i32::MAX as f64),
+        VoxelType::F32 | VoxelType::F64 => return (1.0, 0.0),
+    };
+
+    let (minimum, maximum) = with_volume!(&nifti.volume, array => array.fold((f64::INFINITY, f64::NEG_INFINITY), |(minimum, maximum), voxel| {
+        (minimum.min(voxel.to_f64()), maximum.max(voxel.to_f64()))
+    }));
+
+    if !(minimum.is_finite() && maximum.is_finite()) || minimum == maximum {
+        return (1.0, if minimum.is_finite() { minimum } else { 0.0 });
+    }
+
+    let slope = (maximum - minimum) / (stored_maximum - stored_minimum);
+    (slope, minimum - stored_minimum * slope)
+}
+
+/// Get the NIfTI datatype code and number of bits per voxel of a voxel datatype.
+fn get_datatype_code(voxel_type: VoxelType) -> (i16, i16) {
+    match voxel_type {
+        VoxelType::U8  => (2, 8),
+        VoxelType::I16 => (4, 16),
+        VoxelType::U16 => (512, 16),
+        VoxelType::I32 => (8, 32),
+        VoxelType::F32 => (16, 32),
+        VoxelType::F64 => (64, 64),
+    }
+}
+
+/// A writer of the fields of a header at their byte offsets.
+struct HeaderEncoder([u8; HEADER_SIZE]);
+
+impl HeaderEncoder {
+    fn bytes(&mut self, offset: usize, bytes: &[u8]) {
+        self.0[offset..offset + bytes.len()].copy_from_slice(bytes);
+    }
+
+    fn i16(&mut self, offset: usize, value: i16) {
+        self.bytes(offset, &value.to_le_bytes());
+    }
+
+    fn i16s(&mut self, offset: usize, values: &[i16]) {
+        for (i, value) in values.iter().enumerate() {
+            self.i16(offset + 2 * i, *value);
+        }
+    }
+
+    fn i32(&mut self, offset: usize, value: i32) {
+        self.bytes(offset, &value.to_le_bytes());
+    }
+
+    fn f32(&mut self, offset: usize, value: f32) {
+        self.bytes(offset, &value.to_le_bytes());
+    }
+
+    fn f32s(&mut self, offset: usize, values: &[f32]) {
+        for (i, value) in values.iter().enumerate() {
+            self.f32(offset + 4 * i, *value);
+        }
+    }
+}
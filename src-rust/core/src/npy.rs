@@ -0,0 +1,549 @@
+use std::io::{BufReader, Read, Seek, SeekFrom, Write};
+
+use ndarray::{Array4, ShapeBuilder};
+use serde::{Deserialize, Serialize};
+
+use crate::{error::Error, geometry::Affine, image_reader::{ReadProgress, ReaderLimits, VolumeReader, get_voxel_count}, nifti::Nifti, volume::{ScalarType, Volume, Voxel, VoxelType, voxel_to_le_bytes, with_volume}, zip};
+
+/// The magic string at the start of NumPy `.npy` files.
+const NPY_MAGIC: &[u8; 6] = b"\x93NUMPY";
+
+/// The alignment of the data of `.npy` files, which is also the alignment of the header end.
+const NPY_ALIGNMENT: usize = 64;
+
+/// The name of the entry of `.npz` archives that stores the affine of the volume.
+const AFFINE_ENTRY: &str = "affine.npy";
+
+/// The name of the entry of written `.npz` archives that stores the volume.
+const VOLUME_ENTRY: &str = "volume.npy";
+
+/// A reader that reads a NumPy array slice by slice, from a `.npy` file or from an array of a
+/// `.npz` archive. The array axes are the voxel axes of the volume, followed by its timepoint axis
+/// for 4D arrays. NumPy arrays do not have an affine, which is set by a sidecar or by the affine
+/// array of `.npz` archives.
+pub struct NpyReader<'a> {
+    source: Box<dyn Read + 'a>,
+    volume: Volume,
+    affine: Affine,
+    value_type: ScalarType,
+    is_big_endian: bool,
+    fortran_order: bool,
+    /// The outermost array axis, along which the slices are read.
+    slice_axis: usize,
+    slice_count: usize,
+    slices_read: usize,
+}
+
+/// The header of a `.npy` array.
+struct NpyHeader {
+    value_type: ScalarType,
+    is_big_endian: bool,
+    fortran_order: bool,
+    shape: Vec<usize>,
+}
+
+/// The options of written NumPy arrays.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NpyWriteOptions {
+    /// Whether the voxels are stored with the first axis varying the fastest, instead of the last.
+    pub fortran_order: bool,
+    /// The datatype of the stored voxels, which is the datatype of the volume by default.
+    pub datatype: Option<VoxelType>,
+    /// Whether the volume is written to a `.npz` archive, along with its affine.
+    pub archive: bool,
+    /// Whether the arrays of the `.npz` archive are deflate compressed.
+    pub compress: bool,
+}
+
+/// The JSON sidecar of a NumPy array, which carries the geometry that the array does not store.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NpySidecar {
+    pub affine: Option<Affine>,
+    pub description: Option<String>,
+}
+
+impl<'a> NpyReader<'a> {
+    /// Open a `.npy` file, and allocate its volume.
+    pub fn open<R: Read + Seek + 'a>(mut source: R, name_hint: &str, limits: ReaderLimits) -> Result<Self, Error> {
+        log::debug!("reading the npy file {}", name_hint);
+        let source_size = source.seek(SeekFrom::End(0))?;
+        source.rewind()?;
+
+        let mut source = BufReader::new(source);
+        let header = NpyHeader::parse(&mut source)?;
+        let data_size = header.voxel_count(limits)? as u64 * header.value_type.size() as u64;
+        let header_size = source.stream_position()?;
+        if header_size + data_size > source_size {
+            return Err(Error::InvalidHeader(format!("file is truncated, expected {} bytes of data, found {}", data_size, source_size - header_size)));
+        }
+
+        Self::from_header(header, Box::new(source), Affine::IDENTITY, limits)
+    }
+
+    /// Open the first 3D or 4D array of a `.npz` archive, and allocate its volume. The affine of
+    /// the volume is read from the `affine.npy` array of the archive if it has one.
+    pub fn open_npz<R: Read + Seek + 'a>(mut source: R, name_hint: &str, limits: ReaderLimits) -> Result<Self, Error> {
+        log::debug!("reading the npz archive {}", name_hint);
+        let entries = zip::read_entries(&mut source)?;
+        let affine = match entries.iter().find(|entry| entry.name == AFFINE_ENTRY) {
+            Some(entry) => read_affine(zip::open_entry(&mut source, entry)?)?,
+            None => Affine::IDENTITY,
+        };
+
+        for entry in entries.iter().filter(|entry| entry.name.ends_with(".npy") && entry.name != AFFINE_ENTRY) {
+            let header = NpyHeader::parse(&mut zip::open_entry(&mut source, entry)?)?;
+            if (3..=4).contains(&header.shape.len()) {
+                log::debug!("reading the npz array {}", entry.name);
+                let mut entry_source = zip::open_entry(source, entry)?;
+                let header = NpyHeader::parse(&mut entry_source)?;
+                return Self::from_header(header, entry_source, affine, limits);
+            }
+        }
+
+        Err(Error::InvalidHeader("the npz archive has no 3d or 4d array".to_string()))
+    }
+
+    fn from_header(header: NpyHeader, source: Box<dyn Read + 'a>, affine: Affine, limits: ReaderLimits) -> Result<Self, Error> {
+        header.voxel_count(limits)?;
+        if !(3..=4).contains(&header.shape.len()) {
+            return Err(Error::InvalidHeader(format!("unsupported {}d array, only 3d and 4d arrays are supported", header.shape.len())));
+        }
+
+        let shape = &header.shape;
+        let timepoints = shape.get(3).copied().unwrap_or(1);
+        let slice_axis = if header.fortran_order { shape.len() - 1 } else { 0 };
+        Ok(Self {
+            source,
+            volume: Volume::zeros(header.value_type.voxel_type(), (shape[0], shape[1], shape[2], timepoints)),
+            affine,
+            value_type: header.value_type,
+            is_big_endian: header.is_big_endian,
+            fortran_order: header.fortran_order,
+            slice_axis,
+            slice_count: shape[slice_axis],
+            slices_read: 0,
+        })
+    }
+}
+
+impl VolumeReader for NpyReader<'_> {
+    fn progress(&self) -> ReadProgress {
+        ReadProgress {
+            slices_read: self.slices_read,
+            slice_count: self.slice_count,
+        }
+    }
+
+    fn read_slice(&mut self) -> Result<(), Error> {
+        if self.slices_read == self.slice_count {
+            return Err(Error::InvalidHeader(format!("expected {} slices, found more", self.slice_count)));
+        }
+
+        let (rows, columns, slices, timepoints) = self.volume.dim();
+        let slice_size = rows * columns * slices * timepoints / self.slice_count;
+        let mut bytes = vec![0; slice_size * self.value_type.size()];
+        self.source.read_exact(&mut bytes)?;
+        let (value_type, is_big_endian, fortran_order) = (self.value_type, self.is_big_endian, self.fortran_order);
+        let (slice_axis, index) = (self.slice_axis, self.slices_read);
+        with_volume!(&mut self.volume, array => assign_slice(array, value_type.decode(bytes, is_big_endian), fortran_order, slice_axis, index));
+        self.slices_read += 1;
+        Ok(())
+    }
+
+    fn finish(self: Box<Self>) -> Result<Nifti, Error> {
+        if self.slices_read != self.slice_count {
+            return Err(Error::InvalidHeader(format!("expected {} slices, found {}", self.slice_count, self.slices_read)));
+        }
+
+        log::debug!("read {} npy slices", self.slices_read);
+        Ok(Nifti {
+            volume: self.volume,
+            affine: self.affine,
+            description: String::new(),
+            extensions: Vec::new(),
+        })
+    }
+}
+
+impl NpyHeader {
+    /// Parse the header of a `.npy` array, which is a Python dictionary literal.
+    fn parse<R: Read>(source: &mut R) -> Result<Self, Error> {
+        let mut preamble = [0; 8];
+        source.read_exact(&mut preamble)?;
+        if &preamble[..6] != NPY_MAGIC {
+            return Err(Error::InvalidHeader("not a npy file".to_string()));
+        }
+
+        let header_size = match preamble[6] {
+            1 => {
+                let mut size = [0; 2];
+                source.read_exact(&mut size)?;
+                u16::from_le_bytes(size) as usize
+            }
+            2 | 3 => {
+                let mut size = [0; 4];
+                source.read_exact(&mut size)?;
+                u32::from_le_bytes(size) as usize
+            }
+            version => return Err(Error::UnsupportedDatatype(format!("npy version {} is not supported", version))),
+        };
+
+        let mut header = Vec::new();
+        source.take(header_size as u64).read_to_end(&mut header)?;
+        if header.len() != header_size {
+            return Err(Error::InvalidHeader("the npy header is truncated".to_string()));
+        }
+
+        let header = String::from_utf8_lossy(&header);
+        let field = |name: &str| get_field(&header, name).ok_or_else(|| Error::InvalidHeader(format!("missing npy header field {}", name)));
+        let descr = field("descr")?;
+        let (value_type, is_big_endian) = parse_descr(descr.trim_matches(['\'', '"']))?;
+        let fortran_order = match field("fortran_order")? {
+            "True" => true,
+            "False" => false,
+            value => return Err(Error::InvalidHeader(format!("invalid npy fortran_order {}", value))),
+        };
+
+        let shape = field("shape")?;
+        let shape = shape.strip_prefix('(').and_then(|shape| shape.strip_suffix(')'))
+            .ok_or_else(|| Error::InvalidHeader(format!("invalid npy shape {}", shape)))?
+            .split(',')
+            .map(str::trim)
+            .filter(|size| !size.is_empty())
+            .map(|size| size.parse::<usize>().map_err(|_| Error::InvalidHeader(format!("invalid npy shape {}", shape))))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Self { value_type, is_big_endian, fortran_order, shape })
+    }
+
+    fn voxel_count(&self, limits: ReaderLimits) -> Result<usize, Error> {
+        get_voxel_count(&self.shape, limits)
+    }
+}
+
+/// Get the value of a field of a Python dictionary literal, which is a string, a tuple or a name.
+fn get_field<'h>(header: &'h str, name: &str) -> Option<&'h str> {
+    let key_end = ["'", "\""].into_iter()
+        .find_map(|quote| header.find(&format!("{quote}{name}{quote}")))
+        .map(|start| start + name.len() + 2)?;
+
+    let value = header[key_end..].trim_start().strip_prefix(':')?.trim_start();
+    let end = match value.chars().next()? {
+        '(' => value.find(')')? + 1,
+        quote @ ('\'' | '"') => value[1..].find(quote)? + 2,
+        _ => value.find([',', '}']).unwrap_or(value.len()),
+    };
+
+    Some(value[..end].trim())
+}
+
+/// Parse a NumPy array-protocol type string, such as `<f4`, into a scalar type and byte order.
+pub(crate) fn parse_descr(descr: &str) -> Result<(ScalarType, bool), Error> {
+    let unsupported = || Error::UnsupportedDatatype(format!("numpy dtype {} is not supported", descr));
+    let mut chars = descr.chars();
+    let is_big_endian = match chars.next() {
+        Some('<' | '|') => false,
+        Some('>') => true,
+        Some('=') => cfg!(target_endian = "big"),
+        _ => return Err(unsupported()),
+    };
+
+    let value_type = match chars.as_str() {
+        "b1" | "u1" => ScalarType::U8,
+        "i1" => ScalarType::I8,
+        "i2" => ScalarType::I16,
+        "u2" => ScalarType::U16,
+        "i4" => ScalarType::I32,
+        "u4" => ScalarType::U32,
+        "i8" => ScalarType::I64,
+        "u8" => ScalarType::U64,
+        "f4" => ScalarType::F32,
+        "f8" => ScalarType::F64,
+        _ => return Err(unsupported()),
+    };
+
+    Ok((value_type, is_big_endian))
+}
+
+/// Read a 4x4 affine array of a `.npz` archive.
+fn read_affine<R: Read>(mut source: R) -> Result<Affine, Error> {
+    let header = NpyHeader::parse(&mut source)?;
+    if header.shape != [4, 4] {
+        return Err(Error::InvalidHeader(format!("expected a 4x4 npz affine, found shape {:?}", header.shape)));
+    }
+
+    let mut bytes = vec![0; 16 * header.value_type.size()];
+    source.read_exact(&mut bytes)?;
+    let values: Vec<f64> = header.value_type.decode(bytes, header.is_big_endian);
+    let mut affine = Affine::IDENTITY;
+    for (i, value) in values.into_iter().enumerate() {
+        // The values of Fortran order arrays are stored column by column.
+        let (row, column) = if header.fortran_order { (i % 4, i / 4) } else { (i / 4, i % 4) };
+        affine.0[row][column] = value;
+    }
+
+    Ok(affine)
+}
+
+/// Copy a slice of voxels along the outermost array axis into a volume.
+fn assign_slice<T: Voxel>(array: &mut Array4<T>, voxels: Vec<T>, fortran_order: bool, slice_axis: usize, index: usize) {
+    let mut slice_view = array.index_axis_mut(ndarray::Axis(slice_axis), index);
+    // The voxels have the size of the slice, since they were read from it.
+    let voxels = ndarray::ArrayView3::from_shape(slice_view.raw_dim().set_f(fortran_order), &voxels).unwrap();
+    slice_view.assign(&voxels);
+}
+
+/// Write the volume of an image as a `.npy` array, or as a `.npz` archive with its affine. The
+/// array is 3D if the volume has a single timepoint.
+pub fn write_npy<W: Write>(nifti: &Nifti, mut writer: W, options: &NpyWriteOptions) -> Result<(), Error> {
+    if !options.archive {
+        return write_array(&nifti.volume, writer, options);
+    }
+
+    let mut volume = Vec::new();
+    write_array(&nifti.volume, &mut volume, options)?;
+    let mut affine = encode_header("<f8", false, &[4, 4]);
+    for value in nifti.affine.0.iter().flatten() {
+        affine.extend_from_slice(&value.to_le_bytes());
+    }
+
+    let mut archive = zip::ZipWriter::new(&mut writer, options.compress);
+    archive.write_entry(VOLUME_ENTRY, &volume)?;
+    archive.write_entry(AFFINE_ENTRY, &affine)?;
+    archive.finish()?;
+    Ok(())
+}
+
+/// Write the volume of an image as a `.npy` array or a `.npz` archive in memory.
+pub fn encode_npy(nifti: &Nifti, options: &NpyWriteOptions) -> Result<Vec<u8>, Error> {
+    let mut bytes = Vec::new();
+    write_npy(nifti, &mut bytes, options)?;
+    Ok(bytes)
+}
+
+fn write_array<W: Write>(volume: &Volume, mut writer: W, options: &NpyWriteOptions) -> Result<(), Error> {
+    let (x_size, y_size, z_size, timepoints) = volume.dim();
+    let shape: &[usize] = if timepoints > 1 { &[x_size, y_size, z_size, timepoints] } else { &[x_size, y_size, z_size] };
+    let datatype = options.datatype.unwrap_or(volume.voxel_type());
+    let descr = match datatype {
+        VoxelType::U8  => "|u1",
+        VoxelType::I16 => "<i2",
+        VoxelType::U16 => "<u2",
+        VoxelType::I32 => "<i4",
+        VoxelType::F32 => "<f4",
+        VoxelType::F64 => "<f8",
+    };
+
+    writer.write_all(&encode_header(descr, options.fortran_order, shape))?;
+    with_volume!(volume, array => match datatype {
+        VoxelType::U8  => write_voxels::<_, u8, _>(array, options.fortran_order, &mut writer),
+        VoxelType::I16 => write_voxels::<_, i16, _>(array, options.fortran_order, &mut writer),
+        VoxelType::U16 => write_voxels::<_, u16, _>(array, options.fortran_order, &mut writer),
+        VoxelType::I32 => write_voxels::<_, i32, _>(array, options.fortran_order, &mut writer),
+        VoxelType::F32 => write_voxels::<_, f32, _>(array, options.fortran_order, &mut writer),
+        VoxelType::F64 => write_voxels::<_, f64, _>(array, options.fortran_order, &mut writer),
+    })?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Encode the header of a `.npy` array, which is padded so that the data is aligned.
+fn encode_header(descr: &str, fortran_order: bool, shape: &[usize]) -> Vec<u8> {
+    let shape = match shape {
+        [size] => format!("({},)", size),
+        shape => format!("({})", shape.iter().map(usize::to_string).collect::<Vec<_>>().join(", ")),
+    };
+
+    let mut dictionary = format!("{{'descr': '{}', 'fortran_order': {}, 'shape': {}, }}", descr, if fortran_order { "True" } else { "False" }, shape);
+    // The dictionary is followed by a newline, after the magic string, the version and the size.
+    let unpadded_size = NPY_MAGIC.len() + 4 + dictionary.len() + 1;
+    dictionary.push_str(&" ".repeat(unpadded_size.next_multiple_of(NPY_ALIGNMENT) - unpadded_size));
+    dictionary.push('\n');
+
+    let mut header = NPY_MAGIC.to_vec();
+    header.extend_from_slice(&[1, 0]);
+    header.extend_from_slice(&(dictionary.len() as u16).to_le_bytes());
+    header.extend_from_slice(dictionary.as_bytes());
+    header
+}
+
+/// Write the voxels of a volume in the stored datatype, with the first axis varying the fastest in
+/// Fortran order and the last axis varying the fastest otherwise.
+fn write_voxels<T: Voxel, U: Voxel, W: Write>(array: &Array4<T>, fortran_order: bool, writer: &mut W) -> Result<(), Error> {
+    let view = if fortran_order { array.t() } else { array.view() };
+    for slice in view.axis_iter(ndarray::Axis(0)) {
+        let mut bytes = Vec::with_capacity(slice.len() * size_of::<U>());
+        for voxel in slice.iter() {
+            bytes.extend_from_slice(&voxel_to_le_bytes(U::from_f64(voxel.to_f64())));
+        }
+        writer.write_all(&bytes)?;
+    }
+
+    Ok(())
+}
+
+impl NpySidecar {
+    /// Parse a JSON sidecar.
+    pub fn parse(json: &str) -> Result<Self, Error> {
+        serde_json::from_str(json).map_err(|error| Error::InvalidHeader(format!("invalid npy sidecar: {}", error)))
+    }
+
+    /// Get the sidecar that carries the affine and description of an image.
+    pub fn from_nifti(nifti: &Nifti) -> Self {
+        Self {
+            affine: Some(nifti.affine),
+            description: Some(nifti.description.clone()).filter(|description| !description.is_empty()),
+        }
+    }
+
+    /// Serialize this sidecar to JSON.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap()
+    }
+
+    /// Set the affine and description of an image to the ones of this sidecar, if it has them.
+    pub fn apply(self, nifti: &mut Nifti) {
+        if let Some(affine) = self.affine {
+            nifti.affine = affine;
+        }
+
+        if let Some(description) = self.description {
+            nifti.description = description;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::image_reader::read_volume;
+
+    const LIMITS: ReaderLimits = ReaderLimits { max_voxels: 64 };
+
+    fn create_image(timepoints: usize) -> Nifti {
+        let voxels = Array4::from_shape_fn((3, 2, 2, timepoints), |(x, y, z, t)| (x + 3 * y + 6 * z + 12 * t) as i16 * 300 - 1000);
+        let mut affine = Affine::from_scaling([1.0, 2.0, 3.0]);
+        affine.0[0][3] = -10.0;
+        affine.0[1][0] = 0.5;
+        Nifti { volume: Volume::I16(voxels), affine, description: String::new(), extensions: Vec::new() }
+    }
+
+    fn read_npy(bytes: Vec<u8>) -> Result<Nifti, Error> {
+        read_volume(Box::new(NpyReader::open(Cursor::new(bytes), "volume.npy", LIMITS)?))
+    }
+
+    fn read_npz(bytes: Vec<u8>) -> Result<Nifti, Error> {
+        read_volume(Box::new(NpyReader::open_npz(Cursor::new(bytes), "volume.npz", LIMITS)?))
+    }
+
+    fn get_i16_voxels(nifti: &Nifti) -> &Array4<i16> {
+        let Volume::I16(voxels) = &nifti.volume else {
+            panic!("expected i16 voxels, found {:?}", nifti.volume.voxel_type());
+        };
+
+        voxels
+    }
+
+    #[test]
+    fn round_trips_c_and_fortran_order_arrays() {
+        for timepoints in [1, 2] {
+            let image = create_image(timepoints);
+            for fortran_order in [false, true] {
+                let bytes = encode_npy(&image, &NpyWriteOptions { fortran_order, ..Default::default() }).unwrap();
+                assert_eq!((bytes.len() - 2 * 12 * timepoints) % NPY_ALIGNMENT, 0);
+                let nifti = read_npy(bytes).unwrap();
+                assert_eq!(get_i16_voxels(&nifti), get_i16_voxels(&image));
+                assert_eq!(nifti.affine.0, Affine::IDENTITY.0);
+            }
+        }
+    }
+
+    #[test]
+    fn writes_voxels_in_the_requested_datatype() {
+        let image = create_image(1);
+        let bytes = encode_npy(&image, &NpyWriteOptions { datatype: Some(VoxelType::F32), ..Default::default() }).unwrap();
+        let nifti = read_npy(bytes).unwrap();
+        let Volume::F32(voxels) = &nifti.volume else {
+            panic!("expected f32 voxels, found {:?}", nifti.volume.voxel_type());
+        };
+
+        assert_eq!(voxels, &get_i16_voxels(&image).mapv(f32::from));
+    }
+
+    #[test]
+    fn round_trips_archives_with_their_affine() {
+        let image = create_image(2);
+        for (fortran_order, compress) in [(false, false), (true, true)] {
+            let options = NpyWriteOptions { fortran_order, archive: true, compress, ..Default::default() };
+            let nifti = read_npz(encode_npy(&image, &options).unwrap()).unwrap();
+            assert_eq!(get_i16_voxels(&nifti), get_i16_voxels(&image));
+            assert_eq!(nifti.affine.0, image.affine.0);
+        }
+    }
+
+    #[test]
+    fn reads_big_endian_arrays_and_fortran_order_affines() {
+        // The values are stored with the first axis varying the fastest.
+        let mut bytes = encode_header(">i2", true, &[2, 2, 1]);
+        bytes.extend([1i16, 2, 3, 4].iter().flat_map(|value| value.to_be_bytes()));
+        let nifti = read_npy(bytes).unwrap();
+        assert_eq!(get_i16_voxels(&nifti).iter().copied().collect::<Vec<_>>(), [1, 3, 2, 4]);
+
+        let mut bytes = encode_header("<f4", true, &[4, 4]);
+        bytes.extend((0..16).flat_map(|value| (value as f32).to_le_bytes()));
+        let affine = read_affine(bytes.as_slice()).unwrap();
+        assert_eq!(affine.0[0], [0.0, 4.0, 8.0, 12.0]);
+        assert_eq!(affine.0[3], [3.0, 7.0, 11.0, 15.0]);
+    }
+
+    #[test]
+    fn parses_header_fields_and_dtypes() {
+        let header = r#"{"descr": "<u2", 'fortran_order': False, 'shape': (3, 2, 2), }"#;
+        assert_eq!(get_field(header, "descr"), Some("\"<u2\""));
+        assert_eq!(get_field(header, "fortran_order"), Some("False"));
+        assert_eq!(get_field(header, "shape"), Some("(3, 2, 2)"));
+        assert_eq!(get_field(header, "version"), None);
+
+        assert!(matches!(parse_descr("|u1"), Ok((ScalarType::U8, false))));
+        assert!(matches!(parse_descr(">f8"), Ok((ScalarType::F64, true))));
+        assert!(matches!(parse_descr("<i8"), Ok((ScalarType::I64, false))));
+        for descr in ["<c8", "<U4", "f4", ""] {
+            assert!(parse_descr(descr).is_err(), "parsed dtype {}", descr);
+        }
+    }
+
+    #[test]
+    fn rejects_truncated_and_invalid_arrays() {
+        let bytes = encode_npy(&create_image(1), &NpyWriteOptions::default()).unwrap();
+        for size in [0, 8, 10, NPY_ALIGNMENT, bytes.len() - 1] {
+            assert!(read_npy(bytes[..size].to_vec()).is_err(), "read a npy file from {} bytes", size);
+        }
+
+        let mut bytes = encode_header("<i2", false, &[3, 2]);
+        bytes.extend([0; 12]);
+        assert!(read_npy(bytes).is_err());
+        assert!(read_npy(encode_header("<i2", false, &[30, 20, 20])).is_err());
+
+        // The archive has no 3D or 4D array.
+        let mut writer = zip::ZipWriter::new(Vec::new(), false);
+        writer.write_entry("labels.npy", &encode_header("<i2", false, &[3])).unwrap();
+        assert!(read_npz(writer.finish().unwrap()).is_err());
+    }
+
+    #[test]
+    fn applies_sidecars() {
+        let mut image = create_image(1);
+        image.description = "test image".to_string();
+        let sidecar = NpySidecar::parse(&NpySidecar::from_nifti(&image).to_json()).unwrap();
+
+        let mut nifti = read_npy(encode_npy(&image, &NpyWriteOptions::default()).unwrap()).unwrap();
+        sidecar.apply(&mut nifti);
+        assert_eq!(nifti.affine.0, image.affine.0);
+        assert_eq!(nifti.description, "test image");
+        assert!(NpySidecar::parse("[1, 2]").is_err());
+    }
+}
@@ -0,0 +1,549 @@
+use std::{collections::HashMap, io::{self, BufRead, BufReader, Read, Seek, SeekFrom}};
+
+use flate2::bufread::GzDecoder;
+use ndarray::ShapeBuilder;
+
+use crate::{error::Error, geometry::Affine, image_reader::{ReadProgress, ReaderLimits, VolumeReader, get_voxel_count}, nifti::Nifti, volume::{ScalarType, Volume, Voxel, with_volume}};
+
+/// The maximum size of NRRD headers, so that binary files are not read as a header.
+const MAX_HEADER_SIZE: u64 = 1 << 20;
+
+/// The header of a NRRD file, whose fields are stored by lowercase name.
+pub struct NrrdHeader {
+    fields: HashMap<String, String>,
+}
+
+/// The encoding of the data of a NRRD file.
+#[derive(Clone, Copy, PartialEq)]
+enum Encoding {
+    Raw,
+    Gzip,
+}
+
+/// A reader that reads a NRRD volume slice by slice, from a `.nrrd` file with an attached header,
+/// or from a `.nhdr` header and its detached data file. 4D volumes have one non-spatial axis, such
+/// as a time or gradient axis, which is read as their timepoints.
+pub struct NrrdReader<'a> {
+    source: Box<dyn Read + 'a>,
+    volume: Volume,
+    affine: Affine,
+    description: String,
+    value_type: ScalarType,
+    is_big_endian: bool,
+    /// The volume axis of each NRRD axis, followed by the volume axes that are not in the file.
+    axis_order: [usize; 4],
+    /// The outermost NRRD axis, along which the slices are read.
+    slice_axis: usize,
+    slice_count: usize,
+    slices_read: usize,
+}
+
+impl NrrdHeader {
+    /// Parse a NRRD header, which ends with an empty line or with the end of the source.
+    pub fn parse<R: BufRead>(source: &mut R) -> Result<Self, Error> {
+        let mut source = source.take(MAX_HEADER_SIZE);
+        let mut line = String::new();
+        source.read_line(&mut line)?;
+        if !line.starts_with("NRRD") {
+            return Err(Error::InvalidHeader("not a nrrd file".to_string()));
+        }
+
+        let mut fields = HashMap::new();
+        loop {
+            line.clear();
+            if source.read_line(&mut line)? == 0 {
+                break;
+            }
+
+            let line = line.trim_end_matches(['\n', '\r']);
+            if line.is_empty() {
+                break;
+            }
+
+            // Comments and key/value pairs are not used by the reader.
+            if line.starts_with('#') || line.contains(":=") {
+                continue;
+            }
+
+            let (field, value) = line.split_once(": ")
+                .ok_or_else(|| Error::InvalidHeader(format!("invalid nrrd header line {}", line)))?;
+            fields.insert(field.trim().to_ascii_lowercase(), value.trim().to_string());
+        }
+
+        Ok(Self { fields })
+    }
+
+    /// Get the name of the detached data file of this header, relative to the header file, if the
+    /// data is not attached to the header.
+    pub fn data_file(&self) -> Option<&str> {
+        self.field("data file").or_else(|| self.field("datafile"))
+    }
+
+    fn field(&self, name: &str) -> Option<&str> {
+        self.fields.get(name).map(String::as_str)
+    }
+
+    fn required_field(&self, name: &str) -> Result<&str, Error> {
+        self.field(name).ok_or_else(|| Error::InvalidHeader(format!("missing nrrd field {}", name)))
+    }
+
+    /// Get the values of a field, which are separated by whitespace.
+    fn values(&self, name: &str) -> Vec<&str> {
+        self.field(name).map(|value| value.split_whitespace().collect()).unwrap_or_default()
+    }
+
+    fn integer(&self, name: &str) -> Result<Option<i64>, Error> {
+        self.field(name)
+            .map(|value| value.parse().map_err(|_| Error::InvalidHeader(format!("invalid nrrd {} {}", name, value))))
+            .transpose()
+    }
+}
+
+impl<'a> NrrdReader<'a> {
+    /// Open a NRRD file with an attached header, and allocate its volume.
+    pub fn open<R: Read + Seek + 'a>(source: R, name_hint: &str, limits: ReaderLimits) -> Result<Self, Error> {
+        log::debug!("reading the nrrd file {}", name_hint);
+        let mut source = BufReader::new(source);
+        let header = NrrdHeader::parse(&mut source)?;
+        if let Some(data_file) = header.data_file() {
+            return Err(Error::BadArgument(format!("the nrrd header has a detached data file {}, which must be read with it", data_file)));
+        }
+
+        Self::from_header(header, source, limits)
+    }
+
+    /// Open a NRRD file from a detached header and the data file it refers to, and allocate its
+    /// volume.
+    pub fn open_detached<H: Read + 'a, R: Read + Seek + 'a>(header_source: H, data_source: R, name_hint: &str, limits: ReaderLimits) -> Result<Self, Error> {
+        log::debug!("reading the nrrd header {}", name_hint);
+        let header = NrrdHeader::parse(&mut BufReader::new(header_source))?;
+        match header.data_file() {
+            Some(data_file) if data_file.starts_with("LIST") || data_file.contains('%') => {
+                Err(Error::UnsupportedDatatype(format!("nrrd data split across files {} is not supported", data_file)))
+            }
+            Some(_) => Self::from_header(header, BufReader::new(data_source), limits),
+            None => Err(Error::BadArgument("the nrrd header has no detached data file".to_string())),
+        }
+    }
+
+    fn from_header<S: BufRead + Seek + 'a>(header: NrrdHeader, source: S, limits: ReaderLimits) -> Result<Self, Error> {
+        let value_type = parse_type(header.required_field("type")?)?;
+        let sizes = header.values("sizes").iter()
+            .map(|size| size.parse::<usize>().map_err(|_| Error::InvalidHeader(format!("invalid nrrd size {}", size))))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let dimension = header.integer("dimension")?.ok_or_else(|| Error::InvalidHeader("missing nrrd field dimension".to_string()))?;
+        if sizes.len() as i64 != dimension {
+            return Err(Error::InvalidHeader(format!("expected {} nrrd sizes, found {}", dimension, sizes.len())));
+        }
+
+        if !(3..=4).contains(&sizes.len()) {
+            return Err(Error::InvalidHeader(format!("unsupported {}d image, only 3d and 4d images are supported", sizes.len())));
+        }
+
+        let voxel_count = get_voxel_count(&sizes, limits)?;
+        let is_big_endian = match header.field("endian") {
+            Some("big") => true,
+            Some("little") | None => false,
+            Some(endian) => return Err(Error::InvalidHeader(format!("invalid nrrd endian {}", endian))),
+        };
+
+        let encoding = match header.required_field("encoding")? {
+            "raw" => Encoding::Raw,
+            "gzip" | "gz" => Encoding::Gzip,
+            encoding => return Err(Error::UnsupportedDatatype(format!("nrrd encoding {} is not supported", encoding))),
+        };
+
+        let spatial_axes = get_spatial_axes(&header, sizes.len())?;
+        let mut axis_order = [0; 4];
+        let mut dimensions = [1; 4];
+        let mut next_spatial_axis = 0;
+        for (axis, &size) in sizes.iter().enumerate() {
+            let volume_axis = if spatial_axes.contains(&axis) {
+                next_spatial_axis += 1;
+                next_spatial_axis - 1
+            } else {
+                3
+            };
+
+            axis_order[axis] = volume_axis;
+            dimensions[volume_axis] = size;
+        }
+
+        // The timepoint axis of 3D volumes is not in the file.
+        if sizes.len() == 3 {
+            axis_order[3] = 3;
+        }
+
+        let data_size = voxel_count as u64 * value_type.size() as u64;
+        let source = open_data(&header, source, encoding, data_size)?;
+        let slice_axis = sizes.len() - 1;
+        Ok(Self {
+            source,
+            volume: Volume::zeros(value_type.voxel_type(), (dimensions[0], dimensions[1], dimensions[2], dimensions[3])),
+            affine: get_affine(&header, &spatial_axes)?,
+            description: header.field("content").unwrap_or_default().to_string(),
+            value_type,
+            is_big_endian,
+            axis_order,
+            slice_axis,
+            slice_count: sizes[slice_axis],
+            slices_read: 0,
+        })
+    }
+}
+
+impl VolumeReader for NrrdReader<'_> {
+    fn progress(&self) -> ReadProgress {
+        ReadProgress {
+            slices_read: self.slices_read,
+            slice_count: self.slice_count,
+        }
+    }
+
+    fn read_slice(&mut self) -> Result<(), Error> {
+        if self.slices_read == self.slice_count {
+            return Err(Error::InvalidHeader(format!("expected {} slices, found more", self.slice_count)));
+        }
+
+        let (rows, columns, slices, timepoints) = self.volume.dim();
+        let slice_size = rows * columns * slices * timepoints / self.slice_count;
+        let mut bytes = vec![0; slice_size * self.value_type.size()];
+        self.source.read_exact(&mut bytes)?;
+        let (value_type, is_big_endian) = (self.value_type, self.is_big_endian);
+        let (axis_order, slice_axis, index) = (self.axis_order, self.slice_axis, self.slices_read);
+        with_volume!(&mut self.volume, array => assign_slice(array, value_type.decode(bytes, is_big_endian), axis_order, slice_axis, index));
+        self.slices_read += 1;
+        Ok(())
+    }
+
+    fn finish(self: Box<Self>) -> Result<Nifti, Error> {
+        if self.slices_read != self.slice_count {
+            return Err(Error::InvalidHeader(format!("expected {} slices, found {}", self.slice_count, self.slices_read)));
+        }
+
+        log::debug!("read {} nrrd slices", self.slices_read);
+        Ok(Nifti {
+            volume: self.volume,
+            affine: self.affine,
+            description: self.description,
+            extensions: Vec::new(),
+        })
+    }
+}
+
+fn parse_type(name: &str) -> Result<ScalarType, Error> {
+    match name {
+        "signed char" | "int8" | "int8_t" => Ok(ScalarType::I8),
+        "uchar" | "unsigned char" | "uint8" | "uint8_t" => Ok(ScalarType::U8),
+        "short" | "short int" | "signed short" | "signed short int" | "int16" | "int16_t" => Ok(ScalarType::I16),
+        "ushort" | "unsigned short" | "unsigned short int" | "uint16" | "uint16_t" => Ok(ScalarType::U16),
+        "int" | "signed int" | "int32" | "int32_t" => Ok(ScalarType::I32),
+        "uint" | "unsigned int" | "uint32" | "uint32_t" => Ok(ScalarType::U32),
+        "longlong" | "long long" | "long long int" | "signed long long" | "signed long long int" | "int64" | "int64_t" => Ok(ScalarType::I64),
+        "ulonglong" | "unsigned long long" | "unsigned long long int" | "uint64" | "uint64_t" => Ok(ScalarType::U64),
+        "float" => Ok(ScalarType::F32),
+        "double" => Ok(ScalarType::F64),
+        name => Err(Error::UnsupportedDatatype(format!("nrrd type {} is not supported", name))),
+    }
+}
+
+/// Get the spatial axes of a NRRD file, which are the axes that have a space direction, or the
+/// axes whose kind is spatial. The other axis of 4D files is their timepoint axis.
+fn get_spatial_axes(header: &NrrdHeader, dimension: usize) -> Result<Vec<usize>, Error> {
+    let directions = header.values("space directions");
+    let kinds = header.values("kinds");
+    let spatial_axes: Vec<usize> = if !directions.is_empty() {
+        (0..dimension).filter(|&axis| directions.get(axis).is_some_and(|&direction| direction != "none")).collect()
+    } else if kinds.len() == dimension {
+        (0..dimension).filter(|&axis| ["domain", "space"].contains(&kinds[axis])).collect()
+    } else {
+        (0..3).collect()
+    };
+
+    if spatial_axes.len() != 3 {
+        return Err(Error::InvalidHeader(format!("expected 3 spatial nrrd axes, found {}", spatial_axes.len())));
+    }
+
+    Ok(spatial_axes)
+}
+
+/// Get the affine of a NRRD file from its space directions and origin, or from its spacings if it
+/// has no space. The coordinates of the space are converted to RAS coordinates.
+fn get_affine(header: &NrrdHeader, spatial_axes: &[usize]) -> Result<Affine, Error> {
+    let directions = header.values("space directions");
+    if directions.is_empty() {
+        let spacings = header.values("spacings");
+        let voxel_sizes = [0, 1, 2].map(|i| {
+            spatial_axes.get(i)
+                .and_then(|&axis| spacings.get(axis))
+                .and_then(|spacing| spacing.parse::<f64>().ok())
+                .filter(|spacing| spacing.is_finite())
+                .unwrap_or(1.0)
+        });
+
+        return Ok(Affine::from_scaling(voxel_sizes));
+    }
+
+    // The sign of each coordinate that converts the space to RAS coordinates. Spaces that are not
+    // anatomical are assumed to be RAS.
+    let signs = match header.field("space") {
+        Some("left-posterior-superior" | "LPS") => [-1.0, -1.0, 1.0],
+        Some("left-anterior-superior" | "LAS") => [-1.0, 1.0, 1.0],
+        _ => [1.0, 1.0, 1.0],
+    };
+
+    let origin = header.field("space origin").map_or(Ok([0.0; 3]), parse_vector)?;
+    let mut matrix = Affine::IDENTITY.0;
+    for (volume_axis, &axis) in spatial_axes.iter().enumerate() {
+        let direction = parse_vector(directions[axis])?;
+        for row in 0..3 {
+            matrix[row][volume_axis] = signs[row] * direction[row];
+        }
+    }
+
+    for row in 0..3 {
+        matrix[row][3] = signs[row] * origin[row];
+    }
+
+    Ok(Affine(matrix))
+}
+
+/// Parse a NRRD vector of the form `(x,y,z)`.
+fn parse_vector(vector: &str) -> Result<[f64; 3], Error> {
+    let invalid = || Error::InvalidHeader(format!("invalid nrrd vector {}", vector));
+    let values = vector.trim().strip_prefix('(').and_then(|vector| vector.strip_suffix(')')).ok_or_else(invalid)?
+        .split(',')
+        .map(|value| value.trim().parse::<f64>().map_err(|_| invalid()))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    values.try_into().map_err(|_| invalid())
+}
+
+/// Get the reader of the data of a NRRD file after its skipped lines and bytes. The data of raw
+/// files is checked to fit in the file.
+fn open_data<'a, S: BufRead + Seek + 'a>(header: &NrrdHeader, mut source: S, encoding: Encoding, data_size: u64) -> Result<Box<dyn Read + 'a>, Error> {
+    for _ in 0..header.integer("line skip")?.unwrap_or(0).max(0) {
+        source.skip_until(b'\n')?;
+    }
+
+    let byte_skip = header.integer("byte skip")?.unwrap_or(0);
+    match encoding {
+        Encoding::Raw => {
+            let position = source.stream_position()?;
+            let source_size = source.seek(SeekFrom::End(0))?;
+            // A byte skip of -1 means that the data is at the end of the file.
+            let data_offset = match byte_skip {
+                -1 => source_size.checked_sub(data_size).filter(|&offset| offset >= position),
+                skip if skip >= 0 => Some(position + skip as u64),
+                skip => return Err(Error::InvalidHeader(format!("invalid nrrd byte skip {}", skip))),
+            };
+
+            match data_offset {
+                Some(data_offset) if data_offset + data_size <= source_size => {
+                    source.seek(SeekFrom::Start(data_offset))?;
+                    Ok(Box::new(source))
+                }
+                _ => Err(Error::InvalidHeader(format!("file is truncated, expected {} bytes of data, found {}", data_size, source_size.saturating_sub(position)))),
+            }
+        }
+        Encoding::Gzip => {
+            if byte_skip < 0 {
+                return Err(Error::InvalidHeader(format!("invalid nrrd byte skip {} for gzip data", byte_skip)));
+            }
+
+            let mut source = GzDecoder::new(source);
+            io::copy(&mut source.by_ref().take(byte_skip as u64), &mut io::sink())?;
+            Ok(Box::new(source))
+        }
+    }
+}
+
+/// Copy a slice of voxels along the outermost NRRD axis into a volume, whose axes are permuted to
+/// the order of the NRRD axes.
+fn assign_slice<T: Voxel>(array: &mut ndarray::Array4<T>, voxels: Vec<T>, axis_order: [usize; 4], slice_axis: usize, index: usize) {
+    let mut file_view = array.view_mut().permuted_axes(axis_order);
+    let mut slice_view = file_view.index_axis_mut(ndarray::Axis(slice_axis), index);
+    // The voxels have the size of the slice, since they were read from it.
+    let voxels = ndarray::ArrayView3::from_shape(slice_view.raw_dim().f(), &voxels).unwrap();
+    slice_view.assign(&voxels);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Write};
+
+    use flate2::{Compression, write::GzEncoder};
+    use ndarray::Array4;
+
+    use super::*;
+    use crate::image_reader::read_volume;
+
+    const LIMITS: ReaderLimits = ReaderLimits { max_voxels: 64 };
+
+    fn create_voxels(timepoints: usize) -> Array4<i16> {
+        Array4::from_shape_fn((3, 2, 2, timepoints), |(x, y, z, t)| (x + 3 * y + 6 * z + 12 * t) as i16 * 300 - 1000)
+    }
+
+    /// Encode voxels in the order of the NRRD axes, whose first axis is the fastest, for volume
+    /// axes in the order of the NRRD axes.
+    fn encode_voxels(voxels: &Array4<i16>, axis_order: [usize; 4], is_big_endian: bool) -> Vec<u8> {
+        voxels.view().permuted_axes(axis_order).t().iter()
+            .flat_map(|value| if is_big_endian { value.to_be_bytes() } else { value.to_le_bytes() })
+            .collect()
+    }
+
+    fn gzip(bytes: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(bytes).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    /// Encode a header of an image of shorts of sizes with fields, which ends with an empty line.
+    fn encode_header(sizes: &[usize], fields: &[&str]) -> Vec<u8> {
+        let sizes: Vec<String> = sizes.iter().map(ToString::to_string).collect();
+        let mut header = format!("NRRD0004\n# a comment\ntype: short\ndimension: {}\nsizes: {}\nkey:=value\n", sizes.len(), sizes.join(" "));
+        for field in fields {
+            header.push_str(field);
+            header.push('\n');
+        }
+
+        header.push('\n');
+        header.into_bytes()
+    }
+
+    fn read_nrrd(bytes: Vec<u8>) -> Result<Nifti, Error> {
+        read_volume(Box::new(NrrdReader::open(Cursor::new(bytes), "image.nrrd", LIMITS)?))
+    }
+
+    fn read_detached_nrrd(header: Vec<u8>, data: Vec<u8>) -> Result<Nifti, Error> {
+        read_volume(Box::new(NrrdReader::open_detached(Cursor::new(header), Cursor::new(data), "image.nhdr", LIMITS)?))
+    }
+
+    fn check_voxels(nifti: &Nifti, timepoints: usize) {
+        let Volume::I16(voxels) = &nifti.volume else {
+            panic!("expected i16 voxels, found {:?}", nifti.volume.voxel_type());
+        };
+
+        assert_eq!(voxels, &create_voxels(timepoints));
+    }
+
+    #[test]
+    fn reads_raw_and_gzip_attached_data() {
+        let data = encode_voxels(&create_voxels(1), [0, 1, 2, 3], false);
+        let mut bytes = encode_header(&[3, 2, 2], &["encoding: raw", "endian: little", "content: test image"]);
+        bytes.extend(&data);
+        let nifti = read_nrrd(bytes).unwrap();
+        check_voxels(&nifti, 1);
+        assert_eq!(nifti.description, "test image");
+
+        let mut bytes = encode_header(&[3, 2, 2], &["encoding: gzip", "endian: big"]);
+        bytes.extend(gzip(&encode_voxels(&create_voxels(1), [0, 1, 2, 3], true)));
+        check_voxels(&read_nrrd(bytes).unwrap(), 1);
+    }
+
+    #[test]
+    fn reads_detached_data() {
+        let header = encode_header(&[3, 2, 2], &["encoding: raw", "data file: image.raw"]);
+        let data = encode_voxels(&create_voxels(1), [0, 1, 2, 3], false);
+        assert_eq!(NrrdHeader::parse(&mut header.as_slice()).unwrap().data_file(), Some("image.raw"));
+        check_voxels(&read_detached_nrrd(header.clone(), data.clone()).unwrap(), 1);
+
+        // Detached headers cannot be read alone, and attached ones have no data file.
+        assert!(matches!(read_nrrd(header), Err(Error::BadArgument(_))));
+        assert!(read_detached_nrrd(encode_header(&[3, 2, 2], &["encoding: raw"]), data.clone()).is_err());
+        assert!(read_detached_nrrd(encode_header(&[3, 2, 2], &["encoding: raw", "data file: LIST"]), data.clone()).is_err());
+        assert!(read_detached_nrrd(encode_header(&[3, 2, 2], &["encoding: raw", "datafile: image.%03d.raw 1 2 1"]), data).is_err());
+    }
+
+    #[test]
+    fn reads_data_at_the_end_of_the_file_with_a_byte_skip_of_minus_one() {
+        let header = encode_header(&[3, 2, 2], &["encoding: raw", "byte skip: -1", "data file: image.raw"]);
+        let mut data = b"a leading header of the data file".to_vec();
+        data.extend(encode_voxels(&create_voxels(1), [0, 1, 2, 3], false));
+        check_voxels(&read_detached_nrrd(header, data).unwrap(), 1);
+
+        // The data cannot start before the end of the header.
+        let mut bytes = encode_header(&[3, 2, 2], &["encoding: raw", "byte skip: -1"]);
+        bytes.extend(&encode_voxels(&create_voxels(1), [0, 1, 2, 3], false)[1..]);
+        assert!(read_nrrd(bytes).is_err());
+
+        let mut bytes = encode_header(&[3, 2, 2], &["encoding: gzip", "byte skip: -1"]);
+        bytes.extend(gzip(&encode_voxels(&create_voxels(1), [0, 1, 2, 3], false)));
+        assert!(read_nrrd(bytes).is_err());
+    }
+
+    #[test]
+    fn converts_lps_space_directions_to_ras() {
+        let mut bytes = encode_header(&[3, 2, 2], &[
+            "encoding: raw",
+            "space: left-posterior-superior",
+            "space directions: (1,0,0) (0,2,0) (0,0.5,3)",
+            "space origin: (10,20,30)",
+        ]);
+        bytes.extend(encode_voxels(&create_voxels(1), [0, 1, 2, 3], false));
+        let expected = [
+            [-1.0, 0.0, 0.0, -10.0],
+            [0.0, -2.0, -0.5, -20.0],
+            [0.0, 0.0, 3.0, 30.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ];
+        assert_eq!(read_nrrd(bytes).unwrap().affine.0, expected);
+
+        // The images without space directions are scaled by their spacings.
+        let mut bytes = encode_header(&[3, 2, 2], &["encoding: raw", "spacings: 1.5 2 nan"]);
+        bytes.extend(encode_voxels(&create_voxels(1), [0, 1, 2, 3], false));
+        assert_eq!(read_nrrd(bytes).unwrap().affine.0, Affine::from_scaling([1.5, 2.0, 1.0]).0);
+    }
+
+    #[test]
+    fn reads_the_non_spatial_axis_of_4d_images_as_timepoints() {
+        // The first axis has no space direction, such as the gradients of diffusion images.
+        let voxels = create_voxels(4);
+        let mut bytes = encode_header(&[4, 3, 2, 2], &["encoding: raw", "space: RAS", "space directions: none (1,0,0) (0,1,0) (0,0,1)"]);
+        bytes.extend(encode_voxels(&voxels, [3, 0, 1, 2], false));
+        let nifti = read_nrrd(bytes).unwrap();
+        check_voxels(&nifti, 4);
+        assert_eq!(nifti.affine.0, Affine::IDENTITY.0);
+
+        // The axes of images without space directions are found by kind.
+        let mut bytes = encode_header(&[3, 2, 2, 4], &["encoding: raw", "kinds: domain domain domain list"]);
+        bytes.extend(encode_voxels(&voxels, [0, 1, 2, 3], false));
+        check_voxels(&read_nrrd(bytes).unwrap(), 4);
+    }
+
+    #[test]
+    fn rejects_truncated_and_invalid_files() {
+        let data = encode_voxels(&create_voxels(1), [0, 1, 2, 3], false);
+        let mut bytes = encode_header(&[3, 2, 2], &["encoding: raw"]);
+        bytes.extend(&data);
+        for size in [0, 10, bytes.len() - data.len(), bytes.len() - 1] {
+            assert!(read_nrrd(bytes[..size].to_vec()).is_err(), "read a nrrd file from {} bytes", size);
+        }
+
+        let mut bytes = encode_header(&[3, 2, 2], &["encoding: gzip"]);
+        let compressed = gzip(&data);
+        bytes.extend(&compressed[..compressed.len() / 2]);
+        assert!(read_nrrd(bytes).is_err());
+
+        let invalid_fields = [
+            "encoding: bzip2",
+            "encoding: raw\nendian: middle",
+            "encoding: raw\ntype: complex",
+            "encoding: raw\nspace directions: (1,0,0) (0,1,0) none",
+            "encoding: raw\nspace directions: (1,0,0) (0,1) (0,0,1)",
+            "encoding: raw\nbyte skip: -2",
+            "encoding: raw\nsizes: 3 2",
+            "encoding: raw\nsizes: 30 20 20",
+            "encoding: raw\ndimension: 5",
+            "no field separator",
+        ];
+        for fields in invalid_fields {
+            let mut bytes = encode_header(&[3, 2, 2], &[fields]);
+            bytes.extend(&data);
+            assert!(read_nrrd(bytes).is_err(), "read a nrrd file with {}", fields);
+        }
+
+        assert!(read_nrrd(b"P5 not a nrrd file".to_vec()).is_err());
+    }
+}
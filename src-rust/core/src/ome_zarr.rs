@@ -0,0 +1,1104 @@
+//! A reader of OME-NGFF multiscale images, whose levels are zarr v2 or v3 arrays. The keys of the
+//! metadata and chunks to read are given to the caller, which fetches them from the store of the
+//! image, such as a directory or an HTTP server.
+
+use std::{io::Read, ops::Range};
+
+use flate2::{bufread::GzDecoder, read::ZlibDecoder};
+use ndarray::{Array4, ArrayViewD, Axis, Ix4, IxDyn, ShapeBuilder, Slice};
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::{blosc, error::Error, geometry::Affine, image_reader::{ReadProgress, ReaderLimits, get_voxel_count}, nifti::Nifti, npy::parse_descr, volume::{ScalarType, Volume, Voxel, with_volume}};
+
+/// The version of the zarr format of an image.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ZarrFormat {
+    V2,
+    V3,
+}
+
+impl ZarrFormat {
+    /// The formats in the order in which the metadata of an image is looked up.
+    pub const ALL: [ZarrFormat; 2] = [ZarrFormat::V3, ZarrFormat::V2];
+
+    /// Get the key of the metadata of the group of an image, which carries its multiscale
+    /// attributes.
+    pub fn group_metadata_key(self) -> &'static str {
+        match self {
+            ZarrFormat::V2 => ".zattrs",
+            ZarrFormat::V3 => "zarr.json",
+        }
+    }
+
+    fn array_metadata_name(self) -> &'static str {
+        match self {
+            ZarrFormat::V2 => ".zarray",
+            ZarrFormat::V3 => "zarr.json",
+        }
+    }
+}
+
+/// An axis of the arrays of a multiscale image.
+#[derive(Clone, Debug, Deserialize)]
+pub struct ImageAxis {
+    pub name: String,
+    /// The kind of the axis, which is `space`, `time` or `channel`.
+    #[serde(rename = "type", default)]
+    pub kind: Option<String>,
+    #[serde(default)]
+    pub unit: Option<String>,
+}
+
+/// A level of a multiscale image, whose array indices are scaled then translated to its physical
+/// coordinates, along each axis.
+#[derive(Clone, Debug)]
+pub struct ScaleLevel {
+    pub path: String,
+    pub scale: Vec<f64>,
+    pub translation: Vec<f64>,
+}
+
+/// The metadata of an OME-NGFF multiscale image, whose levels are ordered from the finest to the
+/// coarsest resolution.
+#[derive(Clone, Debug)]
+pub struct Multiscale {
+    pub format: ZarrFormat,
+    pub name: Option<String>,
+    pub axes: Vec<ImageAxis>,
+    pub levels: Vec<ScaleLevel>,
+    volume_axes: VolumeAxes,
+}
+
+/// The attributes of the group of an image, which are in the `ome` attribute since version 0.5.
+#[derive(Default, Deserialize)]
+struct GroupAttributes {
+    #[serde(default)]
+    multiscales: Option<Vec<MultiscaleMetadata>>,
+    #[serde(default)]
+    ome: Option<OmeAttributes>,
+}
+
+#[derive(Deserialize)]
+struct OmeAttributes {
+    #[serde(default)]
+    multiscales: Option<Vec<MultiscaleMetadata>>,
+}
+
+#[derive(Deserialize)]
+struct GroupMetadataV3 {
+    #[serde(default)]
+    attributes: GroupAttributes,
+}
+
+#[derive(Deserialize)]
+struct MultiscaleMetadata {
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    axes: Option<Vec<AxisMetadata>>,
+    datasets: Vec<DatasetMetadata>,
+    #[serde(rename = "coordinateTransformations", default)]
+    coordinate_transformations: Vec<CoordinateTransformation>,
+}
+
+/// An axis, which is only named before version 0.4.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum AxisMetadata {
+    Name(String),
+    Axis(ImageAxis),
+}
+
+#[derive(Deserialize)]
+struct DatasetMetadata {
+    path: String,
+    #[serde(rename = "coordinateTransformations", default)]
+    coordinate_transformations: Vec<CoordinateTransformation>,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum CoordinateTransformation {
+    Identity,
+    Scale { scale: Vec<f64> },
+    Translation { translation: Vec<f64> },
+}
+
+impl Multiscale {
+    /// Parse the metadata of the group of an image, which is its `.zattrs` attributes for zarr v2
+    /// and its `zarr.json` metadata for zarr v3.
+    pub fn parse(format: ZarrFormat, json: &[u8]) -> Result<Self, Error> {
+        let invalid = |error: serde_json::Error| Error::InvalidHeader(format!("invalid ome-zarr metadata: {}", error));
+        let attributes = match format {
+            ZarrFormat::V2 => serde_json::from_slice::<GroupAttributes>(json).map_err(invalid)?,
+            ZarrFormat::V3 => serde_json::from_slice::<GroupMetadataV3>(json).map_err(invalid)?.attributes,
+        };
+
+        let multiscale = attributes.ome.and_then(|ome| ome.multiscales)
+            .or(attributes.multiscales)
+            .and_then(|multiscales| multiscales.into_iter().next())
+            .ok_or_else(|| Error::InvalidHeader("the zarr group is not an ome-ngff multiscale image".to_string()))?;
+
+        // The arrays of the versions without axes have 5 dimensions.
+        let axes: Vec<ImageAxis> = match multiscale.axes {
+            Some(axes) => axes.into_iter()
+                .map(|axis| match axis {
+                    AxisMetadata::Name(name) => ImageAxis { name, kind: None, unit: None },
+                    AxisMetadata::Axis(axis) => axis,
+                })
+                .collect(),
+            None => ["t", "c", "z", "y", "x"].map(|name| ImageAxis { name: name.to_string(), kind: None, unit: None }).into(),
+        };
+
+        if multiscale.datasets.is_empty() {
+            return Err(Error::InvalidHeader("the ome-zarr image has no levels".to_string()));
+        }
+
+        let levels = multiscale.datasets.into_iter()
+            .map(|dataset| {
+                let mut level = ScaleLevel {
+                    path: dataset.path.trim_matches('/').to_string(),
+                    scale: vec![1.0; axes.len()],
+                    translation: vec![0.0; axes.len()],
+                };
+
+                // The transformations of the image apply after the ones of its levels.
+                for transformation in dataset.coordinate_transformations.iter().chain(&multiscale.coordinate_transformations) {
+                    level.apply_transformation(transformation)?;
+                }
+                Ok(level)
+            })
+            .collect::<Result<_, Error>>()?;
+
+        Ok(Self {
+            format,
+            name: multiscale.name.filter(|name| !name.is_empty()),
+            volume_axes: VolumeAxes::find(&axes)?,
+            axes,
+            levels,
+        })
+    }
+
+    /// Get the key of the metadata of the array of a level.
+    pub fn array_metadata_key(&self, level: usize) -> Result<String, Error> {
+        Ok(format!("{}/{}", self.level(level)?.path, self.format.array_metadata_name()))
+    }
+
+    /// Pick the level to display at a size in pixels, which is the coarsest level whose largest
+    /// spatial dimension has at least as many voxels, among the levels within the limits. The
+    /// shapes of the levels are estimated from the shape of the finest level and their scales.
+    pub fn pick_level(&self, shape: &[usize], display_size: usize, limits: ReaderLimits) -> Result<usize, Error> {
+        self.check_shape(0, shape)?;
+        let dimensions: Vec<_> = self.levels.iter()
+            .map(|level| {
+                let shape: Vec<usize> = shape.iter().enumerate()
+                    .map(|(axis, &size)| ((size as f64 * self.levels[0].scale[axis] / level.scale[axis]).round() as usize).max(1))
+                    .collect();
+                self.volume_axes.volume_dimensions(&shape)
+            })
+            .collect();
+
+        let finest = dimensions.iter()
+            .position(|&(x, y, z, t)| get_voxel_count(&[x, y, z, t], limits).is_ok())
+            .ok_or_else(|| Error::InvalidHeader(format!("all the levels of the image exceed the maximum of {} voxels", limits.max_voxels)))?;
+
+        Ok((finest..self.levels.len()).rev()
+            .find(|&level| {
+                let (x, y, z, _) = dimensions[level];
+                x.max(y).max(z) >= display_size
+            })
+            .unwrap_or(finest))
+    }
+
+    /// Check that the shape of the array of a level has a dimension for each axis of the image.
+    fn check_shape(&self, level: usize, shape: &[usize]) -> Result<(), Error> {
+        if shape.len() != self.axes.len() {
+            return Err(Error::InvalidHeader(format!("the array of level {} has {} dimensions, expected {}", level, shape.len(), self.axes.len())));
+        }
+
+        Ok(())
+    }
+
+    fn level(&self, level: usize) -> Result<&ScaleLevel, Error> {
+        self.levels.get(level)
+            .ok_or_else(|| Error::BadArgument(format!("invalid level {}, the image has {} levels", level, self.levels.len())))
+    }
+}
+
+impl ScaleLevel {
+    fn apply_transformation(&mut self, transformation: &CoordinateTransformation) -> Result<(), Error> {
+        let check_length = |values: &[f64]| {
+            if values.len() == self.scale.len() {
+                Ok(())
+            } else {
+                Err(Error::InvalidHeader(format!("expected a transformation of {} axes, found {}", self.scale.len(), values.len())))
+            }
+        };
+
+        match transformation {
+            CoordinateTransformation::Identity => {}
+            CoordinateTransformation::Scale { scale } => {
+                check_length(scale)?;
+                for (axis, factor) in scale.iter().enumerate() {
+                    self.scale[axis] *= factor;
+                    self.translation[axis] *= factor;
+                }
+            }
+            CoordinateTransformation::Translation { translation } => {
+                check_length(translation)?;
+                for (axis, offset) in translation.iter().enumerate() {
+                    self.translation[axis] += offset;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// The indices of the array axes of an image that are the axes of its volume, and of its channel
+/// axis if it has one.
+#[derive(Clone, Copy, Debug)]
+struct VolumeAxes {
+    x: usize,
+    y: usize,
+    z: Option<usize>,
+    t: Option<usize>,
+    channel: Option<usize>,
+}
+
+impl VolumeAxes {
+    fn find(axes: &[ImageAxis]) -> Result<Self, Error> {
+        let [mut x, mut y, mut z, mut t, mut channel] = [None; 5];
+        for (index, axis) in axes.iter().enumerate() {
+            let volume_axis = match (axis.name.as_str(), axis.kind.as_deref()) {
+                ("x", _) => &mut x,
+                ("y", _) => &mut y,
+                ("z", _) => &mut z,
+                (_, Some("time")) | ("t", None) => &mut t,
+                (_, Some("channel")) | ("c", None) => &mut channel,
+                (name, _) => return Err(Error::UnsupportedDatatype(format!("ome-zarr axis {} is not supported", name))),
+            };
+
+            if volume_axis.replace(index).is_some() {
+                return Err(Error::InvalidHeader(format!("duplicate ome-zarr axis {}", axis.name)));
+            }
+        }
+
+        let (Some(x), Some(y)) = (x, y) else {
+            return Err(Error::InvalidHeader("the ome-zarr image has no x and y axes".to_string()));
+        };
+
+        Ok(Self { x, y, z, t, channel })
+    }
+
+    /// Get the array axes of the x, y, z and t axes of the volume, if the array has them.
+    fn volume_axes(&self) -> [Option<usize>; 4] {
+        [Some(self.x), Some(self.y), self.z, self.t]
+    }
+
+    /// Get the dimensions of the volume of an array of a shape.
+    fn volume_dimensions(&self, shape: &[usize]) -> (usize, usize, usize, usize) {
+        let [x, y, z, t] = self.volume_axes().map(|axis| axis.map_or(1, |axis| shape[axis]));
+        (x, y, z, t)
+    }
+}
+
+/// A codec that encodes the bytes of the chunks of a zarr array.
+#[derive(Clone, Copy, Debug)]
+enum BytesCodec {
+    Gzip,
+    Zlib,
+    Zstd,
+    Blosc,
+    /// A CRC-32C checksum appended to the chunks, which is not checked.
+    Crc32c,
+}
+
+/// The metadata of the array of a level of a multiscale image.
+#[derive(Clone, Debug)]
+pub struct ZarrArray {
+    pub shape: Vec<usize>,
+    chunk_shape: Vec<usize>,
+    value_type: ScalarType,
+    is_big_endian: bool,
+    fortran_order: bool,
+    /// The codecs of the bytes of the chunks, in the order in which they were applied.
+    codecs: Vec<BytesCodec>,
+    fill_value: f64,
+    /// The prefix of the keys of the chunks, which is followed by their indices.
+    key_prefix: String,
+    key_separator: String,
+}
+
+#[derive(Deserialize)]
+struct ArrayMetadataV2 {
+    shape: Vec<usize>,
+    chunks: Vec<usize>,
+    dtype: String,
+    #[serde(default)]
+    compressor: Option<NamedCodecV2>,
+    #[serde(default)]
+    filters: Option<Vec<NamedCodecV2>>,
+    #[serde(default)]
+    fill_value: Value,
+    #[serde(default)]
+    order: Option<String>,
+    #[serde(default)]
+    dimension_separator: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct NamedCodecV2 {
+    id: String,
+}
+
+#[derive(Deserialize)]
+struct ArrayMetadataV3 {
+    shape: Vec<usize>,
+    data_type: String,
+    chunk_grid: NamedConfiguration,
+    #[serde(default)]
+    chunk_key_encoding: Option<NamedConfiguration>,
+    #[serde(default)]
+    fill_value: Value,
+    codecs: Vec<NamedConfiguration>,
+}
+
+/// An extension of zarr v3 arrays, such as a codec, with its configuration.
+#[derive(Deserialize)]
+struct NamedConfiguration {
+    name: String,
+    #[serde(default)]
+    configuration: Value,
+}
+
+impl ZarrArray {
+    /// Parse the metadata of a zarr array, which is its `.zarray` metadata for zarr v2 and its
+    /// `zarr.json` metadata for zarr v3.
+    pub fn parse(format: ZarrFormat, json: &[u8]) -> Result<Self, Error> {
+        let array = match format {
+            ZarrFormat::V2 => Self::parse_v2(serde_json::from_slice(json).map_err(invalid_metadata)?)?,
+            ZarrFormat::V3 => Self::parse_v3(serde_json::from_slice(json).map_err(invalid_metadata)?)?,
+        };
+
+        if array.shape.len() != array.chunk_shape.len() || array.chunk_shape.contains(&0) {
+            return Err(Error::InvalidHeader(format!("invalid chunk shape {:?} of a zarr array of shape {:?}", array.chunk_shape, array.shape)));
+        }
+
+        Ok(array)
+    }
+
+    fn parse_v2(metadata: ArrayMetadataV2) -> Result<Self, Error> {
+        if metadata.filters.is_some_and(|filters| !filters.is_empty()) {
+            return Err(Error::UnsupportedDatatype("zarr filters are not supported".to_string()));
+        }
+
+        let (value_type, is_big_endian) = parse_descr(&metadata.dtype)?;
+        let codecs = match metadata.compressor.map(|compressor| compressor.id) {
+            None => vec![],
+            Some(id) => vec![get_bytes_codec(&id)?],
+        };
+
+        Ok(Self {
+            shape: metadata.shape,
+            chunk_shape: metadata.chunks,
+            value_type,
+            is_big_endian,
+            fortran_order: metadata.order.as_deref() == Some("F"),
+            codecs,
+            fill_value: get_fill_value(&metadata.fill_value),
+            key_prefix: String::new(),
+            key_separator: metadata.dimension_separator.unwrap_or_else(|| ".".to_string()),
+        })
+    }
+
+    fn parse_v3(metadata: ArrayMetadataV3) -> Result<Self, Error> {
+        let value_type = get_scalar_type(&metadata.data_type)?;
+        if metadata.chunk_grid.name != "regular" {
+            return Err(Error::UnsupportedDatatype(format!("zarr chunk grid {} is not supported", metadata.chunk_grid.name)));
+        }
+
+        let chunk_shape = metadata.chunk_grid.configuration.get("chunk_shape")
+            .and_then(|chunk_shape| serde_json::from_value(chunk_shape.clone()).ok())
+            .ok_or_else(|| Error::InvalidHeader("the zarr array has no chunk shape".to_string()))?;
+
+        let (key_prefix, key_separator) = match &metadata.chunk_key_encoding {
+            None => ("c/".to_string(), "/".to_string()),
+            Some(encoding) => {
+                let separator = encoding.configuration.get("separator").and_then(Value::as_str);
+                match encoding.name.as_str() {
+                    "default" => {
+                        let separator = separator.unwrap_or("/");
+                        (format!("c{}", separator), separator.to_string())
+                    }
+                    "v2" => (String::new(), separator.unwrap_or(".").to_string()),
+                    name => return Err(Error::UnsupportedDatatype(format!("zarr chunk key encoding {} is not supported", name))),
+                }
+            }
+        };
+
+        let mut array = Self {
+            shape: metadata.shape,
+            chunk_shape,
+            value_type,
+            is_big_endian: false,
+            fortran_order: false,
+            codecs: Vec::new(),
+            fill_value: get_fill_value(&metadata.fill_value),
+            key_prefix,
+            key_separator,
+        };
+
+        for codec in &metadata.codecs {
+            match codec.name.as_str() {
+                "transpose" => {
+                    let order: Vec<usize> = codec.configuration.get("order")
+                        .and_then(|order| serde_json::from_value(order.clone()).ok())
+                        .unwrap_or_default();
+                    // Only the transposition of all the axes is supported, which stores the chunks
+                    // in Fortran order.
+                    let dimensions = array.shape.len();
+                    if order.iter().copied().eq((0..dimensions).rev()) {
+                        array.fortran_order = true;
+                    } else if !order.iter().copied().eq(0..dimensions) {
+                        return Err(Error::UnsupportedDatatype(format!("zarr transpose order {:?} is not supported", order)));
+                    }
+                }
+                "bytes" => {
+                    array.is_big_endian = codec.configuration.get("endian").and_then(Value::as_str) == Some("big");
+                }
+                "sharding_indexed" => return Err(Error::UnsupportedDatatype("sharded zarr arrays are not supported".to_string())),
+                name => array.codecs.push(get_bytes_codec(name)?),
+            }
+        }
+
+        Ok(array)
+    }
+
+    /// Get the key of a chunk, relative to the array.
+    fn chunk_key(&self, position: &[usize]) -> String {
+        let indices: Vec<String> = position.iter().map(ToString::to_string).collect();
+        format!("{}{}", self.key_prefix, indices.join(&self.key_separator))
+    }
+
+    /// Decode the stored bytes of a chunk into its voxels, or get the voxels of a missing chunk,
+    /// which are all the fill value. The chunk shape must be within the limits of the reader.
+    fn decode_chunk<T: Voxel>(&self, bytes: Option<Vec<u8>>) -> Result<Vec<T>, Error> {
+        let voxel_count = self.chunk_shape.iter().product::<usize>();
+        let Some(mut bytes) = bytes else {
+            return Ok(vec![T::from_f64(self.fill_value); voxel_count]);
+        };
+
+        let size = voxel_count * self.value_type.size();
+        for codec in self.codecs.iter().rev() {
+            // The bytes decoded by a codec are not larger than both the chunk and the bytes they
+            // are decoded from, so that hostile chunks cannot exhaust the memory.
+            let max_size = size.max(bytes.len());
+            bytes = match codec {
+                BytesCodec::Gzip => read_all(GzDecoder::new(bytes.as_slice()), max_size)?,
+                BytesCodec::Zlib => read_all(ZlibDecoder::new(bytes.as_slice()), max_size)?,
+                BytesCodec::Zstd => {
+                    let decoder = ruzstd::decoding::StreamingDecoder::new(bytes.as_slice())
+                        .map_err(|error| Error::InvalidHeader(format!("invalid zstd chunk: {}", error)))?;
+                    read_all(decoder, max_size)?
+                }
+                BytesCodec::Blosc => blosc::decompress(&bytes, max_size)?,
+                BytesCodec::Crc32c => {
+                    bytes.truncate(bytes.len().saturating_sub(4));
+                    bytes
+                }
+            };
+        }
+
+        if bytes.len() != size {
+            return Err(Error::InvalidHeader(format!("expected a chunk of {} bytes, found {}", size, bytes.len())));
+        }
+
+        Ok(self.value_type.decode(bytes, self.is_big_endian))
+    }
+}
+
+/// A chunk of the array of an image.
+#[derive(Clone, Debug)]
+pub struct ZarrChunk {
+    /// The position of the chunk in the chunk grid of the array.
+    position: Vec<usize>,
+    /// The key of the chunk in the store of the image.
+    pub key: String,
+}
+
+/// A reader of the voxels of a level of an OME-NGFF multiscale image from the chunks of its array,
+/// which are fetched by the caller, so that the timepoints of the image are only read when they
+/// are needed. Only the first channel of multichannel images is read.
+pub struct OmeZarrReader {
+    array: ZarrArray,
+    path: String,
+    axes: VolumeAxes,
+    /// The dimensions of the volume of each timepoint.
+    dimensions: (usize, usize, usize, usize),
+    /// The number of chunks along each array axis.
+    chunk_counts: Vec<usize>,
+    /// The number of chunks of each timepoint read, which are read in C order.
+    next_chunks: Vec<usize>,
+}
+
+impl OmeZarrReader {
+    /// Open a level of an image from the metadata of its array. Only the first timepoint of its
+    /// volume is allocated, whose voxels are zero until their chunks are read, and the volumes of
+    /// the other timepoints are allocated by the caller when they are read.
+    pub fn open(multiscale: &Multiscale, level: usize, array: ZarrArray, limits: ReaderLimits) -> Result<(Self, Nifti), Error> {
+        let scale_level = multiscale.level(level)?;
+        multiscale.check_shape(level, &array.shape)?;
+        get_voxel_count(&array.chunk_shape, limits)?;
+
+        let axes = multiscale.volume_axes;
+        let dimensions = axes.volume_dimensions(&array.shape);
+        get_voxel_count(&[dimensions.0, dimensions.1, dimensions.2, dimensions.3], limits)?;
+        if let Some(channel) = axes.channel && array.shape[channel] > 1 {
+            log::info!("only reading the first of the {} channels of the image", array.shape[channel]);
+        }
+
+        log::debug!("reading level {} of the ome-zarr image, of shape {:?} in chunks of {:?}", level, array.shape, array.chunk_shape);
+        let chunk_counts: Vec<usize> = array.shape.iter().zip(&array.chunk_shape)
+            .map(|(&size, &chunk_size)| size.div_ceil(chunk_size))
+            .collect();
+
+        let nifti = Nifti {
+            volume: Volume::zeros(array.value_type.voxel_type(), (dimensions.0, dimensions.1, dimensions.2, 1)),
+            affine: get_affine(&multiscale.axes, scale_level, axes),
+            description: multiscale.name.clone().unwrap_or_default(),
+            extensions: Vec::new(),
+        };
+
+        let reader = Self {
+            path: scale_level.path.clone(),
+            axes,
+            dimensions: (dimensions.0, dimensions.1, dimensions.2, 1),
+            chunk_counts,
+            next_chunks: vec![0; dimensions.3],
+            array,
+        };
+
+        Ok((reader, nifti))
+    }
+
+    /// Get the number of timepoints of the image.
+    pub fn timepoints(&self) -> usize {
+        self.next_chunks.len()
+    }
+
+    /// Get the progress of the read of a timepoint, in chunks.
+    pub fn progress(&self, timepoint: usize) -> ReadProgress {
+        let chunk_count = self.timepoint_chunk_ranges(0).iter().map(ExactSizeIterator::len).product();
+        ReadProgress {
+            slices_read: self.next_chunks.get(timepoint).copied().unwrap_or(chunk_count),
+            slice_count: chunk_count,
+        }
+    }
+
+    /// Check whether all the chunks of a timepoint are read.
+    pub fn is_loaded(&self, timepoint: usize) -> bool {
+        let progress = self.progress(timepoint);
+        progress.slices_read == progress.slice_count
+    }
+
+    /// Get the next chunk of a timepoint to read, or `None` if the timepoint is read.
+    pub fn next_chunk(&self, timepoint: usize) -> Option<ZarrChunk> {
+        let first_chunk = *self.next_chunks.get(timepoint)?;
+        let position = self.timepoint_chunks(timepoint).nth(first_chunk)?;
+        let key = format!("{}/{}", self.path, self.array.chunk_key(&position));
+        Some(ZarrChunk { position, key })
+    }
+
+    /// Decode the stored bytes of the next chunk of a timepoint into the volume of the timepoint,
+    /// which has a single timepoint, or fill the voxels of the chunk with the fill value of the
+    /// array if it is missing from the store. Get the region of the volume read, as the ranges of
+    /// its x, y, z and t indices.
+    pub fn read_chunk(&mut self, volume: &mut Volume, timepoint: usize, chunk: &ZarrChunk, bytes: Option<Vec<u8>>) -> Result<[Range<usize>; 4], Error> {
+        if self.next_chunk(timepoint).is_none_or(|next| next.key != chunk.key) {
+            return Err(Error::BadArgument(format!("chunk {} is not the next chunk of timepoint {}", chunk.key, timepoint)));
+        }
+        if volume.dim() != self.dimensions {
+            return Err(Error::BadArgument(format!("expected the volume of a timepoint of dimensions {:?}, found {:?}", self.dimensions, volume.dim())));
+        }
+
+        // The chunks at the end of the array are padded to the chunk shape.
+        let region: Vec<Range<usize>> = chunk.position.iter().enumerate()
+            .map(|(axis, &position)| {
+                let start = position * self.array.chunk_shape[axis];
+                start..(start + self.array.chunk_shape[axis]).min(self.array.shape[axis])
+            })
+            .collect();
+
+        let [x, y, z, t] = self.axes.volume_axes().map(|axis| axis.map_or(0..1, |axis| region[axis].clone()));
+        // Chunks may hold several timepoints, of which only the read timepoint is copied.
+        let volume_region = [x, y, z, 0..1];
+        with_volume!(volume, array => assign_chunk(array, self.array.decode_chunk(bytes)?, &self.array, self.axes, &region, timepoint - t.start, &volume_region));
+
+        self.next_chunks[timepoint] += 1;
+        Ok(volume_region)
+    }
+
+    /// Get the ranges of the positions of the chunks of a timepoint along each array axis, which
+    /// are all the chunks of the first channel that contain the timepoint.
+    fn timepoint_chunk_ranges(&self, timepoint: usize) -> Vec<Range<usize>> {
+        (0..self.chunk_counts.len())
+            .map(|axis| {
+                if Some(axis) == self.axes.t {
+                    let position = timepoint / self.array.chunk_shape[axis];
+                    position..position + 1
+                } else if Some(axis) == self.axes.channel {
+                    0..1
+                } else {
+                    0..self.chunk_counts[axis]
+                }
+            })
+            .collect()
+    }
+
+    /// Get the positions of the chunks of a timepoint, in C order.
+    fn timepoint_chunks(&self, timepoint: usize) -> impl Iterator<Item = Vec<usize>> {
+        let ranges = self.timepoint_chunk_ranges(timepoint);
+        let chunk_count = ranges.iter().map(ExactSizeIterator::len).product();
+        (0..chunk_count).map(move |mut index| {
+            let mut position = vec![0; ranges.len()];
+            for axis in (0..ranges.len()).rev() {
+                position[axis] = ranges[axis].start + index % ranges[axis].len();
+                index /= ranges[axis].len();
+            }
+            position
+        })
+    }
+}
+
+/// Copy the voxels of a timepoint of a chunk, given by its index in the chunk, into a region of
+/// the array of the volume of the timepoint, without the padding of the chunk and only for the
+/// first channel.
+fn assign_chunk<T: Voxel>(volume: &mut Array4<T>, voxels: Vec<T>, array: &ZarrArray, axes: VolumeAxes, region: &[Range<usize>], timepoint: usize, volume_region: &[Range<usize>; 4]) {
+    let shape = IxDyn(&array.chunk_shape);
+    // The voxels have the size of the chunk, since they were decoded from it.
+    let chunk = if array.fortran_order {
+        ArrayViewD::from_shape(shape.f(), &voxels)
+    } else {
+        ArrayViewD::from_shape(shape, &voxels)
+    }
+    .unwrap();
+
+    let mut chunk = chunk.slice_each_axis(|axis| Slice::from(0..region[axis.axis.index()].len()));
+    let mut volume_axes = axes.volume_axes();
+    if let Some(channel) = axes.channel {
+        chunk = chunk.index_axis_move(Axis(channel), 0);
+        for axis in volume_axes.iter_mut().flatten() {
+            if *axis > channel {
+                *axis -= 1;
+            }
+        }
+    }
+
+    // The volume axes that the array does not have are added after its axes.
+    for volume_axis in &mut volume_axes {
+        if volume_axis.is_none() {
+            let axis = chunk.ndim();
+            *volume_axis = Some(axis);
+            chunk = chunk.insert_axis(Axis(axis));
+        }
+    }
+
+    let chunk = chunk.permuted_axes(volume_axes.map(Option::unwrap).to_vec())
+        .into_dimensionality::<Ix4>()
+        .unwrap();
+    let [x, y, z, t] = volume_region.clone();
+    volume.slice_mut(ndarray::s![x, y, z, t]).assign(&chunk.slice(ndarray::s![.., .., .., timepoint..timepoint + 1]));
+}
+
+/// Get the affine of a level of an image, whose spatial axes are scaled to millimeters.
+fn get_affine(image_axes: &[ImageAxis], level: &ScaleLevel, axes: VolumeAxes) -> Affine {
+    let mut affine = Affine::IDENTITY;
+    for (row, axis) in [Some(axes.x), Some(axes.y), axes.z].into_iter().enumerate() {
+        let Some(axis) = axis else {
+            continue;
+        };
+
+        let unit_scale = get_unit_scale(image_axes[axis].unit.as_deref());
+        affine.0[row][row] = level.scale[axis] * unit_scale;
+        affine.0[row][3] = level.translation[axis] * unit_scale;
+    }
+
+    affine
+}
+
+/// Get the size of a spatial unit in millimeters, which is the unit of the axes without one.
+fn get_unit_scale(unit: Option<&str>) -> f64 {
+    match unit {
+        Some("angstrom") => 1e-7,
+        Some("nanometer") => 1e-6,
+        Some("micrometer") => 1e-3,
+        Some("millimeter") | None => 1.0,
+        Some("centimeter") => 10.0,
+        Some("meter") => 1e3,
+        Some(unit) => {
+            log::warn!("unknown unit {}, assuming millimeters", unit);
+            1.0
+        }
+    }
+}
+
+/// Get the type of the values of a zarr v3 data type.
+fn get_scalar_type(data_type: &str) -> Result<ScalarType, Error> {
+    match data_type {
+        "bool" | "uint8" => Ok(ScalarType::U8),
+        "int8" => Ok(ScalarType::I8),
+        "int16" => Ok(ScalarType::I16),
+        "uint16" => Ok(ScalarType::U16),
+        "int32" => Ok(ScalarType::I32),
+        "uint32" => Ok(ScalarType::U32),
+        "int64" => Ok(ScalarType::I64),
+        "uint64" => Ok(ScalarType::U64),
+        "float32" => Ok(ScalarType::F32),
+        "float64" => Ok(ScalarType::F64),
+        data_type => Err(Error::UnsupportedDatatype(format!("zarr data type {} is not supported", data_type))),
+    }
+}
+
+/// Get the codec of the bytes of chunks from its name, which is also its identifier for zarr v2.
+fn get_bytes_codec(name: &str) -> Result<BytesCodec, Error> {
+    match name {
+        "gzip" => Ok(BytesCodec::Gzip),
+        "zlib" => Ok(BytesCodec::Zlib),
+        "zstd" => Ok(BytesCodec::Zstd),
+        "blosc" => Ok(BytesCodec::Blosc),
+        "crc32c" => Ok(BytesCodec::Crc32c),
+        name => Err(Error::UnsupportedDatatype(format!("zarr codec {} is not supported", name))),
+    }
+}
+
+/// Get the fill value of the missing chunks of an array, which is a number or the name of a
+/// special floating point value.
+fn get_fill_value(value: &Value) -> f64 {
+    match value {
+        Value::Number(number) => number.as_f64().unwrap_or(0.0),
+        Value::Bool(value) => *value as u8 as f64,
+        Value::String(name) => match name.as_str() {
+            "NaN" => f64::NAN,
+            "Infinity" => f64::INFINITY,
+            "-Infinity" => f64::NEG_INFINITY,
+            _ => 0.0,
+        },
+        _ => 0.0,
+    }
+}
+
+fn invalid_metadata(error: serde_json::Error) -> Error {
+    Error::InvalidHeader(format!("invalid zarr array metadata: {}", error))
+}
+
+/// Read the bytes of a source up to a maximum size, and one more byte, so that sources that are too
+/// large are detected.
+fn read_all<R: Read>(source: R, max_size: usize) -> Result<Vec<u8>, Error> {
+    let mut bytes = Vec::new();
+    source.take(max_size as u64 + 1).read_to_end(&mut bytes)?;
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, io::Write};
+
+    use flate2::{Compression, write::GzEncoder};
+    use ndarray::{ArrayD, Dimension};
+
+    use super::*;
+    use crate::volume::VoxelType;
+
+    const LIMITS: ReaderLimits = ReaderLimits { max_voxels: 1 << 20 };
+
+    /// The multiscale metadata of version 0.4 of a 5D image with two levels, whose image has a
+    /// scale of its own.
+    const MULTISCALE: &str = r#"{
+        "version": "0.4",
+        "name": "image",
+        "axes": [
+            {"name": "t", "type": "time", "unit": "second"},
+            {"name": "c", "type": "channel"},
+            {"name": "z", "type": "space", "unit": "micrometer"},
+            {"name": "y", "type": "space", "unit": "micrometer"},
+            {"name": "x", "type": "space", "unit": "micrometer"}
+        ],
+        "datasets": [
+            {"path": "0", "coordinateTransformations": [{"type": "scale", "scale": [1, 1, 2, 0.5, 0.5]}]},
+            {"path": "1/", "coordinateTransformations": [
+                {"type": "scale", "scale": [1, 1, 4, 1, 1]},
+                {"type": "translation", "translation": [0, 0, 1, 0.25, 0.25]}
+            ]}
+        ],
+        "coordinateTransformations": [{"type": "scale", "scale": [2, 1, 1, 1, 1]}]
+    }"#;
+
+    /// The value of a voxel of the test images at an index of their array.
+    fn get_value(axes: &[ImageAxis], index: &[usize]) -> u16 {
+        axes.iter().zip(index)
+            .map(|(axis, &index)| index as u16 * match axis.name.as_str() {
+                "c" => 1000,
+                "t" => 100,
+                "z" => 25,
+                "y" => 5,
+                _ => 1,
+            })
+            .sum()
+    }
+
+    /// Encode all the chunks of an array of the test values, whose padding is not zero, except for
+    /// a missing chunk. Get the encoded chunks by key.
+    fn encode_chunks(multiscale: &Multiscale, array: &ZarrArray, missing_chunk: &[usize], encode: impl Fn(Vec<u8>) -> Vec<u8>) -> HashMap<String, Vec<u8>> {
+        let chunk_counts: Vec<usize> = array.shape.iter().zip(&array.chunk_shape).map(|(&size, &chunk_size)| size.div_ceil(chunk_size)).collect();
+        ndarray::indices(IxDyn(&chunk_counts)).into_iter()
+            .map(|position| position.slice().to_vec())
+            .filter(|position| position != missing_chunk)
+            .map(|position| {
+                let chunk = ArrayD::from_shape_fn(IxDyn(&array.chunk_shape), |index| {
+                    let index: Vec<usize> = (0..index.ndim()).map(|axis| position[axis] * array.chunk_shape[axis] + index[axis]).collect();
+                    if index.iter().zip(&array.shape).all(|(index, size)| index < size) { get_value(&multiscale.axes, &index) } else { 9999 }
+                });
+                let values: Vec<u16> = if array.fortran_order { chunk.t().iter().copied().collect() } else { chunk.iter().copied().collect() };
+                let bytes = values.iter().flat_map(|value| if array.is_big_endian { value.to_be_bytes() } else { value.to_le_bytes() }).collect();
+                (format!("0/{}", array.chunk_key(&position)), encode(bytes))
+            })
+            .collect()
+    }
+
+    /// Read all the timepoints of the finest level of an image from its chunks.
+    fn read_image(multiscale: &Multiscale, array: ZarrArray, chunks: &HashMap<String, Vec<u8>>) -> (Vec<Volume>, Affine) {
+        let (mut reader, nifti) = OmeZarrReader::open(multiscale, 0, array, LIMITS).unwrap();
+        let mut volumes = vec![nifti.volume];
+        for timepoint in 0..reader.timepoints() {
+            if timepoint > 0 {
+                volumes.push(volumes[0].zeros_timepoint());
+            }
+
+            while let Some(chunk) = reader.next_chunk(timepoint) {
+                reader.read_chunk(&mut volumes[timepoint], timepoint, &chunk, chunks.get(&chunk.key).cloned()).unwrap();
+            }
+
+            assert!(reader.is_loaded(timepoint));
+        }
+
+        (volumes, nifti.affine)
+    }
+
+    fn gzip(bytes: Vec<u8>) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&bytes).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn parses_v2_and_v3_group_metadata() {
+        let v2 = format!(r#"{{"multiscales": [{}]}}"#, MULTISCALE);
+        let v3 = format!(r#"{{"zarr_format": 3, "node_type": "group", "attributes": {{"ome": {{"version": "0.5", "multiscales": [{}]}}}}}}"#, MULTISCALE);
+        for (format, json) in [(ZarrFormat::V2, v2), (ZarrFormat::V3, v3)] {
+            let multiscale = Multiscale::parse(format, json.as_bytes()).unwrap();
+            assert_eq!(multiscale.name.as_deref(), Some("image"));
+            assert_eq!(multiscale.axes.len(), 5);
+            assert_eq!(multiscale.axes[2].unit.as_deref(), Some("micrometer"));
+            assert_eq!(multiscale.levels.len(), 2);
+            assert_eq!(multiscale.levels[0].scale, [2.0, 1.0, 2.0, 0.5, 0.5]);
+            assert_eq!(multiscale.levels[1].path, "1");
+            assert_eq!(multiscale.levels[1].scale, [2.0, 1.0, 4.0, 1.0, 1.0]);
+            assert_eq!(multiscale.levels[1].translation, [0.0, 0.0, 1.0, 0.25, 0.25]);
+            assert_eq!(multiscale.array_metadata_key(1).unwrap(), format!("1/{}", format.array_metadata_name()));
+            assert!(multiscale.array_metadata_key(2).is_err());
+        }
+    }
+
+    #[test]
+    fn parses_the_axes_of_older_versions() {
+        // Version 0.3 names its axes, and the previous versions have 5 axes.
+        let multiscale = Multiscale::parse(ZarrFormat::V2, br#"{"multiscales": [{"axes": ["c", "y", "x"], "datasets": [{"path": "0"}]}]}"#).unwrap();
+        let names: Vec<&str> = multiscale.axes.iter().map(|axis| axis.name.as_str()).collect();
+        assert_eq!(names, ["c", "y", "x"]);
+        assert_eq!(multiscale.levels[0].scale, [1.0; 3]);
+
+        let multiscale = Multiscale::parse(ZarrFormat::V2, br#"{"multiscales": [{"datasets": [{"path": "0"}]}]}"#).unwrap();
+        let names: Vec<&str> = multiscale.axes.iter().map(|axis| axis.name.as_str()).collect();
+        assert_eq!(names, ["t", "c", "z", "y", "x"]);
+    }
+
+    #[test]
+    fn rejects_invalid_group_metadata() {
+        let invalid = [
+            r#"{}"#,
+            r#"{"multiscales": []}"#,
+            r#"{"multiscales": [{"axes": ["y", "x"], "datasets": []}]}"#,
+            r#"{"multiscales": [{"axes": ["z", "x"], "datasets": [{"path": "0"}]}]}"#,
+            r#"{"multiscales": [{"axes": ["y", "x", "x"], "datasets": [{"path": "0"}]}]}"#,
+            r#"{"multiscales": [{"axes": ["q", "y", "x"], "datasets": [{"path": "0"}]}]}"#,
+            r#"{"multiscales": [{"axes": ["y", "x"], "datasets": [{"path": "0", "coordinateTransformations": [{"type": "scale", "scale": [1, 1, 1]}]}]}]}"#,
+            r#"{"multiscales": "#,
+        ];
+        for json in invalid {
+            assert!(Multiscale::parse(ZarrFormat::V2, json.as_bytes()).is_err(), "parsed {}", json);
+        }
+    }
+
+    #[test]
+    fn parses_array_metadata_and_chunk_keys() {
+        let array = ZarrArray::parse(ZarrFormat::V2, br#"{
+            "zarr_format": 2, "shape": [3, 5], "chunks": [2, 4], "dtype": ">u2", "compressor": {"id": "zlib", "level": 1},
+            "fill_value": "NaN", "order": "F", "filters": null
+        }"#).unwrap();
+        assert_eq!(array.chunk_shape, [2, 4]);
+        assert_eq!(array.value_type.voxel_type(), VoxelType::U16);
+        assert!(array.is_big_endian && array.fortran_order);
+        assert!(matches!(array.codecs[..], [BytesCodec::Zlib]));
+        assert!(array.fill_value.is_nan());
+        assert_eq!(array.chunk_key(&[1, 0]), "1.0");
+
+        let array = ZarrArray::parse(ZarrFormat::V3, br#"{
+            "zarr_format": 3, "node_type": "array", "shape": [3, 5], "data_type": "int16",
+            "chunk_grid": {"name": "regular", "configuration": {"chunk_shape": [2, 4]}},
+            "chunk_key_encoding": {"name": "default", "configuration": {"separator": "."}},
+            "fill_value": -1,
+            "codecs": [
+                {"name": "transpose", "configuration": {"order": [1, 0]}},
+                {"name": "bytes", "configuration": {"endian": "little"}},
+                {"name": "blosc", "configuration": {"cname": "lz4"}},
+                {"name": "crc32c"}
+            ]
+        }"#).unwrap();
+        assert_eq!(array.value_type.voxel_type(), VoxelType::I16);
+        assert!(!array.is_big_endian && array.fortran_order);
+        assert!(matches!(array.codecs[..], [BytesCodec::Blosc, BytesCodec::Crc32c]));
+        assert_eq!(array.fill_value, -1.0);
+        assert_eq!(array.chunk_key(&[1, 0]), "c.1.0");
+
+        let v3_array = |chunk_key_encoding: &str| format!(r#"{{
+            "shape": [3, 5], "data_type": "uint8", "chunk_grid": {{"name": "regular", "configuration": {{"chunk_shape": [2, 4]}}}},
+            {} "codecs": [{{"name": "bytes"}}]
+        }}"#, chunk_key_encoding);
+        let chunk_key = |chunk_key_encoding| ZarrArray::parse(ZarrFormat::V3, v3_array(chunk_key_encoding).as_bytes()).unwrap().chunk_key(&[1, 0]);
+        assert_eq!(chunk_key(""), "c/1/0");
+        assert_eq!(chunk_key(r#""chunk_key_encoding": {"name": "v2"},"#), "1.0");
+        assert_eq!(chunk_key(r#""chunk_key_encoding": {"name": "v2", "configuration": {"separator": "/"}},"#), "1/0");
+    }
+
+    #[test]
+    fn rejects_unsupported_array_metadata() {
+        let v2_array = |fields: &str| format!(r#"{{"shape": [3, 5], "chunks": [2, 4], "dtype": "<u2", {}}}"#, fields);
+        for fields in [r#""filters": [{"id": "delta"}]"#, r#""compressor": {"id": "lzma"}"#, r#""dtype": "<c8""#, r#""chunks": [2, 0]"#, r#""chunks": [2]"#] {
+            assert!(ZarrArray::parse(ZarrFormat::V2, v2_array(fields).as_bytes()).is_err(), "parsed {}", fields);
+        }
+
+        let v3_array = |codecs: &str| format!(r#"{{
+            "shape": [3, 5, 7], "data_type": "uint8", "chunk_grid": {{"name": "regular", "configuration": {{"chunk_shape": [2, 4, 6]}}}},
+            "codecs": [{}]
+        }}"#, codecs);
+        for codecs in [r#"{"name": "sharding_indexed"}"#, r#"{"name": "transpose", "configuration": {"order": [1, 0, 2]}}"#, r#"{"name": "bz2"}"#] {
+            assert!(ZarrArray::parse(ZarrFormat::V3, v3_array(codecs).as_bytes()).is_err(), "parsed {}", codecs);
+        }
+    }
+
+    #[test]
+    fn picks_the_coarsest_level_for_the_display_size() {
+        let multiscale = Multiscale::parse(ZarrFormat::V2, br#"{"multiscales": [{
+            "axes": [{"name": "z", "type": "space"}, {"name": "y", "type": "space"}, {"name": "x", "type": "space"}],
+            "datasets": [
+                {"path": "0", "coordinateTransformations": [{"type": "scale", "scale": [1, 1, 1]}]},
+                {"path": "1", "coordinateTransformations": [{"type": "scale", "scale": [2, 2, 2]}]},
+                {"path": "2", "coordinateTransformations": [{"type": "scale", "scale": [4, 4, 4]}]}
+            ]
+        }]}"#).unwrap();
+
+        // The levels are 128 x 128 x 64, 64 x 64 x 32 and 32 x 32 x 16 voxels.
+        let shape = [64, 128, 128];
+        assert_eq!(multiscale.pick_level(&shape, 100, LIMITS).unwrap(), 0);
+        assert_eq!(multiscale.pick_level(&shape, 60, LIMITS).unwrap(), 1);
+        assert_eq!(multiscale.pick_level(&shape, 10, LIMITS).unwrap(), 2);
+        assert_eq!(multiscale.pick_level(&shape, 1000, LIMITS).unwrap(), 0);
+
+        // The finest levels that exceed the limits are never picked.
+        let limits = ReaderLimits { max_voxels: 64 * 64 * 32 };
+        assert_eq!(multiscale.pick_level(&shape, 1000, limits).unwrap(), 1);
+        assert!(multiscale.pick_level(&shape, 1000, ReaderLimits { max_voxels: 10 }).is_err());
+        assert!(multiscale.pick_level(&[128, 128], 100, LIMITS).is_err());
+    }
+
+    #[test]
+    fn reads_padded_chunks_of_the_first_channel() {
+        let multiscale = Multiscale::parse(ZarrFormat::V2, format!(r#"{{"multiscales": [{}]}}"#, MULTISCALE).as_bytes()).unwrap();
+        let v2_array = br#"{"shape": [2, 2, 3, 3, 5], "chunks": [1, 2, 2, 2, 4], "dtype": "<u2", "compressor": null, "fill_value": 7}"#;
+        let v3_array = br#"{
+            "shape": [2, 2, 3, 3, 5], "data_type": "uint16", "fill_value": 7,
+            "chunk_grid": {"name": "regular", "configuration": {"chunk_shape": [1, 2, 2, 2, 4]}},
+            "codecs": [
+                {"name": "transpose", "configuration": {"order": [4, 3, 2, 1, 0]}},
+                {"name": "bytes", "configuration": {"endian": "big"}},
+                {"name": "gzip", "configuration": {"level": 1}}
+            ]
+        }"#;
+
+        // The chunk of the second timepoint at the end of all the spatial axes is missing.
+        let missing_chunk = [1, 0, 1, 1, 1];
+        let arrays = [
+            (ZarrArray::parse(ZarrFormat::V2, v2_array).unwrap(), None),
+            (ZarrArray::parse(ZarrFormat::V3, v3_array).unwrap(), Some(gzip as fn(Vec<u8>) -> Vec<u8>)),
+        ];
+        for (array, encode) in arrays {
+            let chunks = encode_chunks(&multiscale, &array, &missing_chunk, |bytes| match encode {
+                Some(encode) => encode(bytes),
+                None => bytes,
+            });
+            let (volumes, affine) = read_image(&multiscale, array, &chunks);
+            assert_eq!(volumes.len(), 2);
+            for (t, volume) in volumes.iter().enumerate() {
+                let Volume::U16(voxels) = volume else {
+                    panic!("expected u16 voxels, found {:?}", volume.voxel_type());
+                };
+
+                assert_eq!(voxels.dim(), (5, 3, 3, 1));
+                for ((x, y, z, _), &value) in voxels.indexed_iter() {
+                    let expected = if t == 1 && x >= 4 && y >= 2 && z >= 2 { 7 } else { get_value(&multiscale.axes, &[t, 0, z, y, x]) };
+                    assert_eq!(value, expected, "voxel ({}, {}, {}) of timepoint {}", x, y, z, t);
+                }
+            }
+
+            // The spatial axes are in micrometers.
+            assert_eq!(affine.0[0][0], 0.0005);
+            assert_eq!(affine.0[2][2], 0.002);
+        }
+    }
+
+    #[test]
+    fn reads_images_without_z_and_t_axes() {
+        // The channel axis is last, so that the axes are permuted after it is removed.
+        let multiscale = Multiscale::parse(ZarrFormat::V2, br#"{"multiscales": [{"axes": ["y", "x", "c"], "datasets": [{"path": "0"}]}]}"#).unwrap();
+        let array = ZarrArray::parse(ZarrFormat::V2, br#"{"shape": [3, 5, 2], "chunks": [2, 2, 2], "dtype": "<u2", "compressor": null, "fill_value": 0}"#).unwrap();
+        let chunks = encode_chunks(&multiscale, &array, &[], |bytes| bytes);
+        let (volumes, _) = read_image(&multiscale, array, &chunks);
+        let Volume::U16(voxels) = &volumes[0] else {
+            panic!("expected u16 voxels");
+        };
+
+        assert_eq!(voxels.dim(), (5, 3, 1, 1));
+        for ((x, y, _, _), &value) in voxels.indexed_iter() {
+            assert_eq!(value, get_value(&multiscale.axes, &[y, x, 0]));
+        }
+    }
+
+    #[test]
+    fn rejects_chunks_of_the_wrong_size() {
+        let multiscale = Multiscale::parse(ZarrFormat::V2, br#"{"multiscales": [{"axes": ["y", "x"], "datasets": [{"path": "0"}]}]}"#).unwrap();
+        let array = ZarrArray::parse(ZarrFormat::V2, br#"{"shape": [3, 5], "chunks": [2, 4], "dtype": "<u2", "compressor": {"id": "gzip"}, "fill_value": 0}"#).unwrap();
+        let (mut reader, mut nifti) = OmeZarrReader::open(&multiscale, 0, array, LIMITS).unwrap();
+        let chunk = reader.next_chunk(0).unwrap();
+        assert_eq!(chunk.key, "0/0.0");
+        for size in [15, 17, 1 << 16] {
+            assert!(reader.read_chunk(&mut nifti.volume, 0, &chunk, Some(gzip(vec![0; size]))).is_err(), "read a chunk of {} bytes", size);
+        }
+
+        assert!(reader.read_chunk(&mut nifti.volume, 0, &chunk, Some(vec![0; 16])).is_err());
+        assert!(reader.read_chunk(&mut nifti.volume, 0, &chunk, Some(gzip(vec![0; 16]))).is_ok());
+        assert!(reader.read_chunk(&mut nifti.volume, 0, &chunk, Some(gzip(vec![0; 16]))).is_err());
+    }
+}
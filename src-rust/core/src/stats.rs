@@ -0,0 +1,45 @@
+use ndarray::Array4;
+use serde::{Deserialize, Serialize};
+
+use crate::volume::{Volume, Voxel, with_volume};
+
+/// The intensity statistics of a volume.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct VolumeStats {
+    pub minimum: f64,
+    pub maximum: f64,
+    pub mean: f64,
+    pub standard_deviation: f64,
+}
+
+impl VolumeStats {
+    /// Compute the statistics of all the voxels of a volume. Voxels that are not a number are
+    /// ignored.
+    pub fn compute(volume: &Volume) -> Self {
+        with_volume!(volume, array => compute_typed_stats(array))
+    }
+}
+
+fn compute_typed_stats<T: Voxel>(array: &Array4<T>) -> VolumeStats {
+    let mut minimum = f64::INFINITY;
+    let mut maximum = f64::NEG_INFINITY;
+    let mut count = 0usize;
+    let mut sum = 0.0;
+    let mut squares_sum = 0.0;
+
+    for value in array.iter().map(|&voxel| voxel.to_f64()).filter(|value| !value.is_nan()) {
+        minimum = minimum.min(value);
+        maximum = maximum.max(value);
+        count += 1;
+        sum += value;
+        squares_sum += value * value;
+    }
+
+    if count == 0 {
+        return VolumeStats { minimum: f64::NAN, maximum: f64::NAN, mean: f64::NAN, standard_deviation: f64::NAN };
+    }
+
+    let mean = sum / count as f64;
+    let variance = (squares_sum / count as f64 - mean * mean).max(0.0);
+    VolumeStats { minimum, maximum, mean, standard_deviation: variance.sqrt() }
+}
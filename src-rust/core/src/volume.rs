@@ -0,0 +1,288 @@
+use ndarray::{Array4, ShapeBuilder};
+use serde::{Deserialize, Serialize};
+
+/// A 4D volume stored with the native datatype of its source file.
+pub enum Volume {
+    U8(Array4<u8>),
+    I16(Array4<i16>),
+    U16(Array4<u16>),
+    I32(Array4<i32>),
+    F32(Array4<f32>),
+    F64(Array4<f64>),
+}
+
+/// The datatype of the voxels of a volume.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum VoxelType {
+    U8,
+    I16,
+    U16,
+    I32,
+    F32,
+    F64,
+}
+
+impl VoxelType {
+    /// Get the size of the voxels of this datatype, in bytes.
+    pub fn size(self) -> usize {
+        match self {
+            VoxelType::U8  => 1,
+            VoxelType::I16 => 2,
+            VoxelType::U16 => 2,
+            VoxelType::I32 => 4,
+            VoxelType::F32 => 4,
+            VoxelType::F64 => 8,
+        }
+    }
+}
+
+/// A voxel value that can be stored natively in a volume.
+pub trait Voxel: Copy + Default + PartialOrd + bytemuck::Pod {
+    /// The datatype of this voxel.
+    const TYPE: VoxelType;
+
+    /// Convert this voxel value to a floating point value.
+    fn to_f32(self) -> f32;
+
+    /// Convert this voxel value to a double precision floating point value, without loss.
+    fn to_f64(self) -> f64;
+
+    /// Convert a double precision floating point value to a voxel value, saturating at the bounds
+    /// of the datatype.
+    fn from_f64(value: f64) -> Self;
+}
+
+macro_rules! impl_voxel {
+    ($($scalar:ty => $variant:ident),* $(,)?) => {
+        $(
+            impl Voxel for $scalar {
+                const TYPE: VoxelType = VoxelType::$variant;
+
+                fn to_f32(self) -> f32 {
+                    self as f32
+                }
+
+                fn to_f64(self) -> f64 {
+                    self as f64
+                }
+
+                fn from_f64(value: f64) -> Self {
+                    value as $scalar
+                }
+            }
+        )*
+    };
+}
+
+impl_voxel! {
+    u8  => U8,
+    i16 => I16,
+    u16 => U16,
+    i32 => I32,
+    f32 => F32,
+    f64 => F64,
+}
+
+/// Decode voxels stored with a byte order, whose byte count is a multiple of the voxel size.
+pub(crate) fn decode_voxels<T: Voxel>(mut bytes: Vec<u8>, is_big_endian: bool) -> Vec<T> {
+    if is_big_endian != cfg!(target_endian = "big") {
+        for voxel in bytes.chunks_exact_mut(size_of::<T>()) {
+            voxel.reverse();
+        }
+    }
+
+    bytes.chunks_exact(size_of::<T>()).map(bytemuck::pod_read_unaligned).collect()
+}
+
+/// Get the little-endian bytes of a voxel.
+pub(crate) fn voxel_to_le_bytes<T: Voxel>(voxel: T) -> Vec<u8> {
+    let mut bytes = bytemuck::bytes_of(&voxel).to_vec();
+    if cfg!(target_endian = "big") {
+        bytes.reverse();
+    }
+    bytes
+}
+
+/// The type of the scalar values stored in a file, which may not be a voxel datatype.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum ScalarType {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    I64,
+    U64,
+    F32,
+    F64,
+}
+
+impl ScalarType {
+    /// Get the size of the values of this type, in bytes.
+    pub fn size(self) -> usize {
+        match self {
+            ScalarType::I8  | ScalarType::U8  => 1,
+            ScalarType::I16 | ScalarType::U16 => 2,
+            ScalarType::I32 | ScalarType::U32 | ScalarType::F32 => 4,
+            ScalarType::I64 | ScalarType::U64 | ScalarType::F64 => 8,
+        }
+    }
+
+    /// Get the datatype in which the values of this type can be stored, without loss except for
+    /// 64-bit integers.
+    pub fn voxel_type(self) -> VoxelType {
+        match self {
+            ScalarType::U8  => VoxelType::U8,
+            ScalarType::I8  | ScalarType::I16 => VoxelType::I16,
+            ScalarType::U16 => VoxelType::U16,
+            ScalarType::I32 => VoxelType::I32,
+            ScalarType::F32 => VoxelType::F32,
+            ScalarType::U32 | ScalarType::I64 | ScalarType::U64 | ScalarType::F64 => VoxelType::F64,
+        }
+    }
+
+    /// Decode values of this type stored with a byte order as voxels, whose byte count is a
+    /// multiple of the value size.
+    pub fn decode<T: Voxel>(self, bytes: Vec<u8>, is_big_endian: bool) -> Vec<T> {
+        if self.voxel_type() == T::TYPE && self.size() == size_of::<T>() {
+            return decode_voxels(bytes, is_big_endian);
+        }
+
+        macro_rules! decode {
+            ($scalar:ty) => {
+                bytes.chunks_exact(size_of::<$scalar>())
+                    .map(|value| {
+                        let value = value.try_into().unwrap();
+                        let value = if is_big_endian { <$scalar>::from_be_bytes(value) } else { <$scalar>::from_le_bytes(value) };
+                        T::from_f64(value as f64)
+                    })
+                    .collect()
+            };
+        }
+
+        match self {
+            ScalarType::I8  => decode!(i8),
+            ScalarType::U8  => decode!(u8),
+            ScalarType::I16 => decode!(i16),
+            ScalarType::U16 => decode!(u16),
+            ScalarType::I32 => decode!(i32),
+            ScalarType::U32 => decode!(u32),
+            ScalarType::I64 => decode!(i64),
+            ScalarType::U64 => decode!(u64),
+            ScalarType::F32 => decode!(f32),
+            ScalarType::F64 => decode!(f64),
+        }
+    }
+}
+
+/// Evaluate an expression on the native array of a volume, whatever its datatype.
+macro_rules! with_volume {
+    ($volume:expr, $array:ident => $body:expr) => {
+        match $volume {
+            $crate::volume::Volume::U8($array)  => $body,
+            $crate::volume::Volume::I16($array) => $body,
+            $crate::volume::Volume::U16($array) => $body,
+            $crate::volume::Volume::I32($array) => $body,
+            $crate::volume::Volume::F32($array) => $body,
+            $crate::volume::Volume::F64($array) => $body,
+        }
+    };
+}
+
+pub(crate) use with_volume;
+
+impl Volume {
+    /// Create a volume of a datatype whose voxels are all zero, stored in Fortran order like the
+    /// voxels of NIfTI files.
+    pub fn zeros(voxel_type: VoxelType, dimensions: (usize, usize, usize, usize)) -> Volume {
+        let shape = dimensions.f();
+        match voxel_type {
+            VoxelType::U8  => Volume::U8(Array4::zeros(shape)),
+            VoxelType::I16 => Volume::I16(Array4::zeros(shape)),
+            VoxelType::U16 => Volume::U16(Array4::zeros(shape)),
+            VoxelType::I32 => Volume::I32(Array4::zeros(shape)),
+            VoxelType::F32 => Volume::F32(Array4::zeros(shape)),
+            VoxelType::F64 => Volume::F64(Array4::zeros(shape)),
+        }
+    }
+
+    /// Create a volume of a single timepoint with the datatype and the spatial dimensions of this
+    /// volume, whose voxels are all zero, such as the volume of another timepoint of a streamed
+    /// image.
+    pub fn zeros_timepoint(&self) -> Volume {
+        let (x_size, y_size, z_size, _) = self.dim();
+        Volume::zeros(self.voxel_type(), (x_size, y_size, z_size, 1))
+    }
+
+    /// Get the dimensions of this volume.
+    pub fn dim(&self) -> (usize, usize, usize, usize) {
+        with_volume!(self, array => array.dim())
+    }
+
+    /// Get the voxel datatype of this volume.
+    pub fn voxel_type(&self) -> VoxelType {
+        match self {
+            Volume::U8(_)  => VoxelType::U8,
+            Volume::I16(_) => VoxelType::I16,
+            Volume::U16(_) => VoxelType::U16,
+            Volume::I32(_) => VoxelType::I32,
+            Volume::F32(_) => VoxelType::F32,
+            Volume::F64(_) => VoxelType::F64,
+        }
+    }
+
+    /// Convert this volume to another voxel datatype. Values that do not fit the datatype are
+    /// saturated, and converting floating point values to integers truncates them.
+    pub fn cast(&self, voxel_type: VoxelType) -> Volume {
+        with_volume!(self, array => match voxel_type {
+            VoxelType::U8  => Volume::U8(cast_array(array)),
+            VoxelType::I16 => Volume::I16(cast_array(array)),
+            VoxelType::U16 => Volume::U16(cast_array(array)),
+            VoxelType::I32 => Volume::I32(cast_array(array)),
+            VoxelType::F32 => Volume::F32(cast_array(array)),
+            VoxelType::F64 => Volume::F64(cast_array(array)),
+        })
+    }
+
+    /// Get the minimum intensity of this volume, or zero if it has no voxels.
+    pub fn min(&self) -> f32 {
+        with_volume!(self, array => extreme_voxel(array, |voxel, min| voxel < min).to_f32())
+    }
+
+    /// Get the maximum intensity of this volume, or zero if it has no voxels.
+    pub fn max(&self) -> f32 {
+        with_volume!(self, array => extreme_voxel(array, |voxel, max| voxel > max).to_f32())
+    }
+}
+
+fn cast_array<T: Voxel, U: Voxel>(array: &Array4<T>) -> Array4<U> {
+    array.mapv(|voxel| U::from_f64(voxel.to_f64()))
+}
+
+/// Get the minimum or maximum voxel of an array, which replaces the extreme voxel found so far
+/// when it precedes it, comparing the voxels in their native datatype. NaN voxels are skipped.
+fn extreme_voxel<T: Voxel>(array: &Array4<T>, precedes: impl Fn(T, T) -> bool) -> T {
+    array.iter()
+        .copied()
+        .filter(|voxel| voxel.partial_cmp(voxel).is_some())
+        .reduce(|extreme, voxel| if precedes(voxel, extreme) { voxel } else { extreme })
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_the_intensity_range_of_volumes() {
+        let negative = Volume::I16(Array4::from_shape_vec((2, 1, 1, 1), vec![-7, -3]).unwrap());
+        assert_eq!((negative.min(), negative.max()), (-7.0, -3.0));
+
+        let with_nan = Volume::F32(Array4::from_shape_vec((3, 1, 1, 1), vec![f32::NAN, -2.5, 4.0]).unwrap());
+        assert_eq!((with_nan.min(), with_nan.max()), (-2.5, 4.0));
+
+        let empty = Volume::U8(Array4::zeros((0, 1, 1, 1)));
+        assert_eq!((empty.min(), empty.max()), (0.0, 0.0));
+    }
+}
@@ -0,0 +1,373 @@
+//! A reader and a writer for the subset of ZIP archives used by NumPy `.npz` files: stored and
+//! deflate compressed entries, with the ZIP64 sizes written by NumPy.
+
+use std::io::{Read, Seek, SeekFrom, Write};
+
+use flate2::{Compression, Crc, read::DeflateDecoder, write::DeflateEncoder};
+
+use crate::error::Error;
+
+const LOCAL_HEADER_SIGNATURE: u32 = 0x04034b50;
+const CENTRAL_HEADER_SIGNATURE: u32 = 0x02014b50;
+const END_SIGNATURE: u32 = 0x06054b50;
+const ZIP64_END_SIGNATURE: u32 = 0x06064b50;
+const ZIP64_LOCATOR_SIGNATURE: u32 = 0x07064b50;
+const ZIP64_EXTRA_ID: u16 = 0x0001;
+
+const LOCAL_HEADER_SIZE: usize = 30;
+const CENTRAL_HEADER_SIZE: usize = 46;
+const END_SIZE: usize = 22;
+const ZIP64_LOCATOR_SIZE: usize = 20;
+
+/// The maximum size of the end of central directory record, with its comment.
+const MAX_END_SIZE: u64 = END_SIZE as u64 + u16::MAX as u64;
+
+const METHOD_STORED: u16 = 0;
+const METHOD_DEFLATE: u16 = 8;
+
+/// The DOS date of the entries written, which is 1980-01-01.
+const DOS_DATE: u16 = 0x21;
+
+/// An entry of a ZIP archive.
+pub(crate) struct ZipEntry {
+    pub name: String,
+    method: u16,
+    compressed_size: u64,
+    pub size: u64,
+    header_offset: u64,
+}
+
+/// Read the entries of a ZIP archive from its central directory.
+pub(crate) fn read_entries<R: Read + Seek>(source: &mut R) -> Result<Vec<ZipEntry>, Error> {
+    let source_size = source.seek(SeekFrom::End(0))?;
+    let tail_size = source_size.min(MAX_END_SIZE);
+    let mut tail = vec![0; tail_size as usize];
+    source.seek(SeekFrom::Start(source_size - tail_size))?;
+    source.read_exact(&mut tail)?;
+
+    let end = (0..tail.len().saturating_sub(END_SIZE - 1)).rev()
+        .find(|&offset| read_u32(&tail, offset) == END_SIGNATURE)
+        .ok_or_else(|| Error::InvalidHeader("not a zip archive".to_string()))?;
+    let mut entry_count = read_u16(&tail, end + 10) as u64;
+    let mut directory_size = read_u32(&tail, end + 12) as u64;
+    let mut directory_offset = read_u32(&tail, end + 16) as u64;
+
+    // ZIP64 archives store the directory location in a record found from a locator before the end
+    // record.
+    if let Some(locator) = end.checked_sub(ZIP64_LOCATOR_SIZE).filter(|&locator| read_u32(&tail, locator) == ZIP64_LOCATOR_SIGNATURE) {
+        let mut record = [0; 56];
+        source.seek(SeekFrom::Start(read_u64(&tail, locator + 8)))?;
+        source.read_exact(&mut record)?;
+        if read_u32(&record, 0) != ZIP64_END_SIGNATURE {
+            return Err(Error::InvalidHeader("invalid zip64 end of central directory".to_string()));
+        }
+
+        entry_count = read_u64(&record, 32);
+        directory_size = read_u64(&record, 40);
+        directory_offset = read_u64(&record, 48);
+    }
+
+    if directory_offset.checked_add(directory_size).is_none_or(|end| end > source_size) {
+        return Err(Error::InvalidHeader("the zip central directory is outside of the archive".to_string()));
+    }
+
+    let mut directory = vec![0; directory_size as usize];
+    source.seek(SeekFrom::Start(directory_offset))?;
+    source.read_exact(&mut directory)?;
+
+    let mut entries = Vec::new();
+    let mut offset = 0;
+    for _ in 0..entry_count {
+        if offset + CENTRAL_HEADER_SIZE > directory.len() || read_u32(&directory, offset) != CENTRAL_HEADER_SIGNATURE {
+            return Err(Error::InvalidHeader("invalid zip central directory entry".to_string()));
+        }
+
+        let name_length = read_u16(&directory, offset + 28) as usize;
+        let extra_length = read_u16(&directory, offset + 30) as usize;
+        let comment_length = read_u16(&directory, offset + 32) as usize;
+        let name_start = offset + CENTRAL_HEADER_SIZE;
+        let extra_start = name_start + name_length;
+        let next_offset = extra_start + extra_length + comment_length;
+        if next_offset > directory.len() {
+            return Err(Error::InvalidHeader("truncated zip central directory entry".to_string()));
+        }
+
+        let mut entry = ZipEntry {
+            name: String::from_utf8_lossy(&directory[name_start..extra_start]).into_owned(),
+            method: read_u16(&directory, offset + 10),
+            compressed_size: read_u32(&directory, offset + 20) as u64,
+            size: read_u32(&directory, offset + 24) as u64,
+            header_offset: read_u32(&directory, offset + 42) as u64,
+        };
+
+        read_zip64_extra(&directory[extra_start..extra_start + extra_length], &mut entry);
+        entries.push(entry);
+        offset = next_offset;
+    }
+
+    Ok(entries)
+}
+
+/// Replace the sizes and offset of an entry that do not fit 32 bits by their ZIP64 values, which are
+/// stored in this order in its extra field when they are needed.
+fn read_zip64_extra(extra: &[u8], entry: &mut ZipEntry) {
+    let mut offset = 0;
+    while offset + 4 <= extra.len() {
+        let (id, size) = (read_u16(extra, offset), read_u16(extra, offset + 2) as usize);
+        let data = &extra[(offset + 4).min(extra.len())..(offset + 4 + size).min(extra.len())];
+        if id == ZIP64_EXTRA_ID {
+            let mut values = data.chunks_exact(8).map(|value| read_u64(value, 0));
+            for field in [&mut entry.size, &mut entry.compressed_size, &mut entry.header_offset] {
+                if *field == u32::MAX as u64 {
+                    match values.next() {
+                        Some(value) => *field = value,
+                        None => break,
+                    }
+                }
+            }
+        }
+
+        offset += 4 + size;
+    }
+}
+
+/// Open the data of an entry of a ZIP archive, which is decompressed while it is read.
+pub(crate) fn open_entry<'a, R: Read + Seek + 'a>(mut source: R, entry: &ZipEntry) -> Result<Box<dyn Read + 'a>, Error> {
+    let mut header = [0; LOCAL_HEADER_SIZE];
+    source.seek(SeekFrom::Start(entry.header_offset))?;
+    source.read_exact(&mut header)?;
+    if read_u32(&header, 0) != LOCAL_HEADER_SIGNATURE {
+        return Err(Error::InvalidHeader(format!("invalid zip local header of {}", entry.name)));
+    }
+
+    let data_offset = LOCAL_HEADER_SIZE as i64 + read_u16(&header, 26) as i64 + read_u16(&header, 28) as i64;
+    source.seek(SeekFrom::Start(entry.header_offset + data_offset as u64))?;
+    let data = source.take(entry.compressed_size);
+    match entry.method {
+        METHOD_STORED => Ok(Box::new(data)),
+        METHOD_DEFLATE => Ok(Box::new(DeflateDecoder::new(data).take(entry.size))),
+        method => Err(Error::UnsupportedDatatype(format!("zip compression method {} of {} is not supported", method, entry.name))),
+    }
+}
+
+/// A writer of a ZIP archive, whose entries are written at once.
+pub(crate) struct ZipWriter<W> {
+    writer: W,
+    compress: bool,
+    offset: u64,
+    directory: Vec<u8>,
+    entry_count: u16,
+}
+
+impl<W: Write> ZipWriter<W> {
+    /// Create a writer whose entries are deflate compressed if requested.
+    pub fn new(writer: W, compress: bool) -> Self {
+        Self { writer, compress, offset: 0, directory: Vec::new(), entry_count: 0 }
+    }
+
+    /// Write an entry to the archive, which must be smaller than 4 GiB.
+    pub fn write_entry(&mut self, name: &str, data: &[u8]) -> Result<(), Error> {
+        let mut crc = Crc::new();
+        crc.update(data);
+        let (method, stored) = if self.compress {
+            let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(data)?;
+            (METHOD_DEFLATE, encoder.finish()?)
+        } else {
+            (METHOD_STORED, data.to_vec())
+        };
+
+        let too_large = |size: u64| size >= u32::MAX as u64;
+        if too_large(data.len() as u64) || too_large(stored.len() as u64) || too_large(self.offset) || self.entry_count == u16::MAX {
+            return Err(Error::BadArgument(format!("the zip entry {} is too large", name)));
+        }
+
+        // The fields shared by the local header and the central directory entry, from the version
+        // needed to extract the entry to the length of its name.
+        let mut fields = Vec::with_capacity(26);
+        fields.extend_from_slice(&20u16.to_le_bytes());
+        fields.extend_from_slice(&0u16.to_le_bytes());
+        fields.extend_from_slice(&method.to_le_bytes());
+        fields.extend_from_slice(&0u16.to_le_bytes());
+        fields.extend_from_slice(&DOS_DATE.to_le_bytes());
+        fields.extend_from_slice(&crc.sum().to_le_bytes());
+        fields.extend_from_slice(&(stored.len() as u32).to_le_bytes());
+        fields.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        fields.extend_from_slice(&(name.len() as u16).to_le_bytes());
+
+        self.writer.write_all(&LOCAL_HEADER_SIGNATURE.to_le_bytes())?;
+        self.writer.write_all(&fields)?;
+        self.writer.write_all(&0u16.to_le_bytes())?;
+        self.writer.write_all(name.as_bytes())?;
+        self.writer.write_all(&stored)?;
+
+        self.directory.extend_from_slice(&CENTRAL_HEADER_SIGNATURE.to_le_bytes());
+        self.directory.extend_from_slice(&20u16.to_le_bytes());
+        self.directory.extend_from_slice(&fields);
+        // The lengths of the extra field and comment, the disk number and the attributes.
+        self.directory.extend_from_slice(&[0; 12]);
+        self.directory.extend_from_slice(&(self.offset as u32).to_le_bytes());
+        self.directory.extend_from_slice(name.as_bytes());
+
+        self.offset += (LOCAL_HEADER_SIZE + name.len() + stored.len()) as u64;
+        self.entry_count += 1;
+        Ok(())
+    }
+
+    /// Write the central directory of the archive, and get the writer back.
+    pub fn finish(mut self) -> Result<W, Error> {
+        if self.offset >= u32::MAX as u64 {
+            return Err(Error::BadArgument("the zip archive is too large".to_string()));
+        }
+
+        self.writer.write_all(&self.directory)?;
+        self.writer.write_all(&END_SIGNATURE.to_le_bytes())?;
+        self.writer.write_all(&[0; 4])?;
+        self.writer.write_all(&self.entry_count.to_le_bytes())?;
+        self.writer.write_all(&self.entry_count.to_le_bytes())?;
+        self.writer.write_all(&(self.directory.len() as u32).to_le_bytes())?;
+        self.writer.write_all(&(self.offset as u32).to_le_bytes())?;
+        self.writer.write_all(&0u16.to_le_bytes())?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    /// Write an archive with a stored entry whose sizes and offset are in a ZIP64 extra field, and
+    /// whose central directory is found from a ZIP64 end record, as NumPy writes large arrays.
+    fn write_zip64_archive(name: &str, data: &[u8]) -> Vec<u8> {
+        let mut crc = Crc::new();
+        crc.update(data);
+        let mut fields = Vec::new();
+        fields.extend_from_slice(&45u16.to_le_bytes());
+        fields.extend_from_slice(&0u16.to_le_bytes());
+        fields.extend_from_slice(&METHOD_STORED.to_le_bytes());
+        fields.extend_from_slice(&0u16.to_le_bytes());
+        fields.extend_from_slice(&DOS_DATE.to_le_bytes());
+        fields.extend_from_slice(&crc.sum().to_le_bytes());
+        fields.extend_from_slice(&u32::MAX.to_le_bytes());
+        fields.extend_from_slice(&u32::MAX.to_le_bytes());
+        fields.extend_from_slice(&(name.len() as u16).to_le_bytes());
+
+        let zip64_extra = |values: &[u64]| {
+            let mut extra = ZIP64_EXTRA_ID.to_le_bytes().to_vec();
+            extra.extend_from_slice(&(8 * values.len() as u16).to_le_bytes());
+            extra.extend(values.iter().flat_map(|value| value.to_le_bytes()));
+            extra
+        };
+
+        let mut archive = LOCAL_HEADER_SIGNATURE.to_le_bytes().to_vec();
+        let local_extra = zip64_extra(&[data.len() as u64; 2]);
+        archive.extend_from_slice(&fields);
+        archive.extend_from_slice(&(local_extra.len() as u16).to_le_bytes());
+        archive.extend_from_slice(name.as_bytes());
+        archive.extend_from_slice(&local_extra);
+        archive.extend_from_slice(data);
+
+        // The extra field of the central directory entry follows an unrelated extra field.
+        let directory_offset = archive.len() as u64;
+        let mut central_extra = vec![0x55, 0x54, 1, 0, 0];
+        central_extra.extend(zip64_extra(&[data.len() as u64, data.len() as u64, 0]));
+        archive.extend_from_slice(&CENTRAL_HEADER_SIGNATURE.to_le_bytes());
+        archive.extend_from_slice(&45u16.to_le_bytes());
+        archive.extend_from_slice(&fields);
+        archive.extend_from_slice(&(central_extra.len() as u16).to_le_bytes());
+        archive.extend_from_slice(&[0; 10]);
+        archive.extend_from_slice(&u32::MAX.to_le_bytes());
+        archive.extend_from_slice(name.as_bytes());
+        archive.extend_from_slice(&central_extra);
+
+        let zip64_end_offset = archive.len() as u64;
+        let directory_size = zip64_end_offset - directory_offset;
+        archive.extend_from_slice(&ZIP64_END_SIGNATURE.to_le_bytes());
+        archive.extend_from_slice(&44u64.to_le_bytes());
+        archive.extend_from_slice(&[45, 0, 45, 0]);
+        archive.extend_from_slice(&[0; 8]);
+        for value in [1, 1, directory_size, directory_offset] {
+            archive.extend_from_slice(&value.to_le_bytes());
+        }
+
+        archive.extend_from_slice(&ZIP64_LOCATOR_SIGNATURE.to_le_bytes());
+        archive.extend_from_slice(&0u32.to_le_bytes());
+        archive.extend_from_slice(&zip64_end_offset.to_le_bytes());
+        archive.extend_from_slice(&1u32.to_le_bytes());
+
+        archive.extend_from_slice(&END_SIGNATURE.to_le_bytes());
+        archive.extend_from_slice(&[0; 4]);
+        archive.extend_from_slice(&[0xFF; 12]);
+        archive.extend_from_slice(&0u16.to_le_bytes());
+        archive
+    }
+
+    fn read_entry(archive: &[u8], entry: &ZipEntry) -> Vec<u8> {
+        let mut data = Vec::new();
+        open_entry(Cursor::new(archive), entry).unwrap().read_to_end(&mut data).unwrap();
+        data
+    }
+
+    #[test]
+    fn reads_written_archives() {
+        let entries: [(&str, Vec<u8>); 2] = [("volume.npy", (0..=255).cycle().take(1000).collect()), ("affine.npy", vec![7; 128])];
+        for compress in [false, true] {
+            let mut writer = ZipWriter::new(Vec::new(), compress);
+            for (name, data) in &entries {
+                writer.write_entry(name, data).unwrap();
+            }
+
+            let archive = writer.finish().unwrap();
+            let read_entries = read_entries(&mut Cursor::new(&archive)).unwrap();
+            assert_eq!(read_entries.len(), 2);
+            for ((name, data), entry) in entries.iter().zip(&read_entries) {
+                assert_eq!(&entry.name, name);
+                assert_eq!(entry.size, data.len() as u64);
+                assert_eq!(&read_entry(&archive, entry), data);
+            }
+        }
+    }
+
+    #[test]
+    fn reads_zip64_extra_fields() {
+        let data: Vec<u8> = (0..100).collect();
+        let archive = write_zip64_archive("volume.npy", &data);
+        let entries = read_entries(&mut Cursor::new(&archive)).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!((entries[0].size, entries[0].compressed_size, entries[0].header_offset), (100, 100, 0));
+        assert_eq!(read_entry(&archive, &entries[0]), data);
+    }
+
+    #[test]
+    fn rejects_truncated_and_garbage_archives() {
+        let mut writer = ZipWriter::new(Vec::new(), true);
+        writer.write_entry("volume.npy", &[1; 100]).unwrap();
+        let archive = writer.finish().unwrap();
+        for size in 0..archive.len() {
+            assert!(read_entries(&mut Cursor::new(&archive[..size])).is_err(), "read a zip archive from {} bytes", size);
+        }
+
+        let archive = write_zip64_archive("volume.npy", &[1; 100]);
+        for size in [archive.len() - 30, archive.len() - 1] {
+            assert!(read_entries(&mut Cursor::new(&archive[..size])).is_err(), "read a zip64 archive from {} bytes", size);
+        }
+
+        let garbage: Vec<u8> = (0..=255).cycle().take(1000).collect();
+        assert!(read_entries(&mut Cursor::new(garbage)).is_err());
+    }
+}
@@ -1,3 +1,6 @@
+use std::ops::Range;
+
+use brain_render_core::Error;
 use wasm_bindgen::prelude::*;
 
 #[wasm_bindgen]
@@ -10,27 +13,196 @@ extern "C" {
     #[wasm_bindgen(js_namespace = console, js_name = debug)]
     pub(crate) fn console_debug(s: &str);
 
+    #[wasm_bindgen(js_namespace = console, js_name = warn)]
+    pub(crate) fn console_warn(s: &str);
+
     #[wasm_bindgen(js_namespace = console, js_name = error)]
     pub(crate) fn console_error(s: &str);
 }
 
-#[macro_export]
-macro_rules! log {
-    ($($arg:tt)*) => {
-        crate::browser::console_log(&format!($($arg)*))
-    };
+/// Call a function before the next repaint, or as soon as possible if animation frames are not
+/// available in this context.
+pub(crate) fn request_animation_frame(callback: impl FnOnce() + 'static) {
+    let global = js_sys::global();
+    let callback = Closure::once_into_js(callback);
+    let request = ["requestAnimationFrame", "setTimeout"].into_iter()
+        .filter_map(|name| js_sys::Reflect::get(&global, &name.into()).ok())
+        .find_map(|function| function.dyn_into::<js_sys::Function>().ok());
+
+    match request {
+        Some(request) => {
+            if request.call1(&global, &callback).is_err() {
+                log::error!("could not request an animation frame");
+            }
+        }
+        None => log::error!("animation frames are not supported"),
+    }
+}
+
+/// Get the current time in milliseconds, with the best precision available.
+pub(crate) fn now() -> f64 {
+    js_sys::Reflect::get(&js_sys::global(), &"performance".into())
+        .ok()
+        .and_then(|performance| performance.dyn_into::<web_sys::Performance>().ok())
+        .map_or_else(js_sys::Date::now, |performance| performance.now())
+}
+
+/// Wait for the next task of the event loop, so that the pending messages and events are handled
+/// during a long operation.
+pub(crate) async fn yield_now() {
+    let promise = js_sys::Promise::new(&mut |resolve, _| {
+        let global = js_sys::global();
+        let set_timeout = js_sys::Reflect::get(&global, &"setTimeout".into())
+            .ok()
+            .and_then(|function| function.dyn_into::<js_sys::Function>().ok());
+
+        let result = match set_timeout {
+            Some(set_timeout) => set_timeout.call2(&global, &resolve, &0.into()),
+            None => resolve.call0(&JsValue::NULL),
+        };
+
+        if result.is_err() {
+            log::error!("could not yield to the event loop");
+        }
+    });
+
+    // The promise is resolved with no value, and is never rejected.
+    let _ = wasm_bindgen_futures::JsFuture::from(promise).await;
+}
+
+/// The response to a request of a byte range of a URL.
+pub(crate) enum RangeResponse {
+    /// The bytes of the range, and the size of the whole file if the server sent it.
+    Partial(js_sys::Uint8Array, Option<u64>),
+    /// The whole file, sent by a server that does not support range requests.
+    Full(js_sys::Uint8Array),
+}
+
+/// Fetch a byte range of a URL. The range may be shorter than requested if it goes past the end
+/// of the file.
+pub(crate) async fn fetch_range(url: &str, range: Range<u64>) -> Result<RangeResponse, Error> {
+    let headers = web_sys::Headers::new().map_err(get_network_error)?;
+    headers.set("Range", &format!("bytes={}-{}", range.start, range.end - 1)).map_err(get_network_error)?;
+    let response = fetch(url, Some(&headers)).await?;
+    let bytes = read_response_bytes(&response).await?;
+    match response.status() {
+        206 => {
+            // The size of the file is only exposed to cross-origin requests if the server allows it.
+            let file_size = response.headers().get("Content-Range").ok().flatten()
+                .and_then(|content_range| content_range.rsplit_once('/').and_then(|(_, size)| size.parse().ok()));
+            Ok(RangeResponse::Partial(bytes, file_size))
+        }
+        _ => Ok(RangeResponse::Full(bytes)),
+    }
+}
+
+/// Fetch the whole file of a URL.
+pub(crate) async fn fetch_bytes(url: &str) -> Result<js_sys::Uint8Array, Error> {
+    let response = fetch(url, None).await?;
+    read_response_bytes(&response).await
+}
+
+/// Fetch the whole file of a URL, or get `None` if the server does not have it.
+pub(crate) async fn fetch_optional_bytes(url: &str) -> Result<Option<js_sys::Uint8Array>, Error> {
+    let response = send_request(url, None).await?;
+    if response.status() == 404 {
+        return Ok(None);
+    }
+
+    check_response(url, &response)?;
+    read_response_bytes(&response).await.map(Some)
+}
+
+/// Read a file of a directory handle from its path relative to the directory, or get `None` if
+/// it does not exist.
+pub(crate) async fn read_directory_file(directory: &JsValue, path: &str) -> Result<Option<js_sys::Uint8Array>, Error> {
+    let names: Vec<&str> = path.split('/').filter(|name| !name.is_empty()).collect();
+    let mut handle = directory.clone();
+    for (i, name) in names.iter().enumerate() {
+        let method = if i + 1 == names.len() { "getFileHandle" } else { "getDirectoryHandle" };
+        match call_async_method(&handle, method, &[JsValue::from_str(name)]).await {
+            Ok(child) => handle = child,
+            Err(error) if get_error_name(&error).as_deref() == Some("NotFoundError") => return Ok(None),
+            Err(error) => return Err(get_file_error(path, error)),
+        }
+    }
+
+    let file: web_sys::File = call_async_method(&handle, "getFile", &[]).await
+        .and_then(|file| file.dyn_into())
+        .map_err(|error| get_file_error(path, error))?;
+    let buffer = wasm_bindgen_futures::JsFuture::from(file.array_buffer()).await
+        .map_err(|error| get_file_error(path, error))?;
+    Ok(Some(js_sys::Uint8Array::new(&buffer)))
+}
+
+/// Call a method of a JavaScript object that returns a promise, and wait for its result.
+async fn call_async_method(target: &JsValue, name: &str, arguments: &[JsValue]) -> Result<JsValue, JsValue> {
+    let method: js_sys::Function = js_sys::Reflect::get(target, &name.into())?.dyn_into()?;
+    let promise: js_sys::Promise = method.apply(target, &arguments.iter().collect())?.dyn_into()?;
+    wasm_bindgen_futures::JsFuture::from(promise).await
+}
+
+/// Fetch a URL with the fetch function of this context, and check that the request succeeded.
+async fn fetch(url: &str, headers: Option<&web_sys::Headers>) -> Result<web_sys::Response, Error> {
+    let response = send_request(url, headers).await?;
+    check_response(url, &response)?;
+    Ok(response)
+}
+
+/// Send a request to a URL with the fetch function of this context.
+async fn send_request(url: &str, headers: Option<&web_sys::Headers>) -> Result<web_sys::Response, Error> {
+    let init = web_sys::RequestInit::new();
+    if let Some(headers) = headers {
+        init.set_headers(headers);
+    }
+
+    let request = web_sys::Request::new_with_str_and_init(url, &init).map_err(get_network_error)?;
+    let global = js_sys::global();
+    let fetch = js_sys::Reflect::get(&global, &"fetch".into())
+        .ok()
+        .and_then(|function| function.dyn_into::<js_sys::Function>().ok())
+        .ok_or_else(|| Error::Network("fetch is not supported".to_string()))?;
+
+    let promise = fetch.call1(&global, &request).map_err(get_network_error)?;
+    let response: web_sys::Response = wasm_bindgen_futures::JsFuture::from(js_sys::Promise::from(promise)).await
+        .map_err(get_network_error)?
+        .dyn_into()
+        .map_err(get_network_error)?;
+
+    Ok(response)
+}
+
+fn check_response(url: &str, response: &web_sys::Response) -> Result<(), Error> {
+    if !response.ok() {
+        return Err(Error::Network(format!("could not fetch {}: {} {}", url, response.status(), response.status_text())));
+    }
+
+    Ok(())
+}
+
+async fn read_response_bytes(response: &web_sys::Response) -> Result<js_sys::Uint8Array, Error> {
+    let buffer = wasm_bindgen_futures::JsFuture::from(response.array_buffer().map_err(get_network_error)?).await
+        .map_err(get_network_error)?;
+    Ok(js_sys::Uint8Array::new(&buffer))
+}
+
+/// Convert an error thrown by a JavaScript network API.
+fn get_network_error(error: JsValue) -> Error {
+    Error::Network(get_error_message(&error))
+}
+
+/// Convert an error thrown by a JavaScript file system API while reading a file.
+fn get_file_error(path: &str, error: JsValue) -> Error {
+    Error::Io(std::io::Error::other(format!("{}: {}", path, get_error_message(&error))))
 }
 
-#[macro_export]
-macro_rules! debug {
-    ($($arg:tt)*) => {
-        crate::browser::console_debug(&format!($($arg)*))
-    };
+fn get_error_message(error: &JsValue) -> String {
+    error.dyn_ref::<js_sys::Error>()
+        .map(|error| String::from(error.message()))
+        .unwrap_or_else(|| format!("{:?}", error))
 }
 
-#[macro_export]
-macro_rules! error {
-    ($($arg:tt)*) => {
-        crate::browser::console_error(&format!($($arg)*))
-    };
+/// Get the name of a JavaScript error, such as the name of a `DOMException`.
+fn get_error_name(error: &JsValue) -> Option<String> {
+    js_sys::Reflect::get(error, &"name".into()).ok()?.as_string()
 }
@@ -0,0 +1,51 @@
+use wasm_bindgen::{Clamped, JsCast};
+use web_sys::{ImageData, OffscreenCanvas, OffscreenCanvasRenderingContext2d};
+
+use brain_render_core::{cpu_renderer::render_slice, nifti::SliceView, volume::Volume, Error};
+
+/// A renderer that draws slices rendered on the CPU on a 2D canvas, used when WebGPU is not
+/// available.
+pub struct CanvasRenderer {
+    canvas: OffscreenCanvas,
+    context: OffscreenCanvasRenderingContext2d,
+    pixels: Option<Vec<u8>>,
+}
+
+impl CanvasRenderer {
+    pub fn new(canvas: OffscreenCanvas) -> Result<Self, Error> {
+        let context = canvas
+            .get_context("2d")
+            .map_err(|_| Error::Gpu("failed to get 2d context from canvas".to_string()))?
+            .ok_or_else(|| Error::Gpu("2d context not supported".to_string()))?
+            .dyn_into::<OffscreenCanvasRenderingContext2d>()
+            .map_err(|_| Error::Gpu("failed to convert to offscreencanvasrenderingcontext2d".to_string()))?;
+
+        Ok(Self {
+            canvas,
+            context,
+            pixels: None,
+        })
+    }
+
+    /// Get the size of the canvas of this renderer, in pixels.
+    pub fn size(&self) -> (u32, u32) {
+        (self.canvas.width(), self.canvas.height())
+    }
+
+    pub fn update_nifti_slice(&mut self, volume: &Volume, view: &SliceView) -> Result<(), Error> {
+        self.pixels = Some(render_slice(volume, self.canvas.width(), self.canvas.height(), view)?);
+        Ok(())
+    }
+
+    pub fn render(&mut self) -> Result<(), Error> {
+        let Some(pixels) = &self.pixels else {
+            return Ok(());
+        };
+
+        let image = ImageData::new_with_u8_clamped_array_and_sh(Clamped(pixels), self.canvas.width(), self.canvas.height())
+            .map_err(|_| Error::Gpu("failed to create image data".to_string()))?;
+
+        self.context.put_image_data(&image, 0, 0)
+            .map_err(|_| Error::Gpu("failed to draw image data".to_string()))
+    }
+}
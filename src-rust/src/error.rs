@@ -0,0 +1,45 @@
+use std::future::Future;
+
+use brain_render_core::Error;
+use wasm_bindgen::JsValue;
+use wasm_bindgen_futures::future_to_promise;
+
+/// An error returned to JavaScript by the API.
+pub struct ApiError(JsValue);
+
+pub type ApiResult<T> = Result<T, ApiError>;
+
+/// Convert an error into a JavaScript `Error`, whose `code` property contains the code of the
+/// error.
+impl From<Error> for ApiError {
+    fn from(error: Error) -> Self {
+        let js_error = js_sys::Error::new(&error.to_string());
+        js_error.set_name("BrainRenderError");
+        // Setting a property on a new error object cannot fail.
+        let _ = js_sys::Reflect::set(&js_error, &"code".into(), &error.code().into());
+        Self(js_error.into())
+    }
+}
+
+impl From<JsValue> for ApiError {
+    fn from(error: JsValue) -> Self {
+        Self(error)
+    }
+}
+
+impl From<serde_wasm_bindgen::Error> for ApiError {
+    fn from(error: serde_wasm_bindgen::Error) -> Self {
+        Self(error.into())
+    }
+}
+
+impl From<ApiError> for JsValue {
+    fn from(error: ApiError) -> Self {
+        error.0
+    }
+}
+
+/// Run a future of the API as a JavaScript promise.
+pub fn api_promise(future: impl Future<Output = ApiResult<JsValue>> + 'static) -> js_sys::Promise {
+    future_to_promise(async move { future.await.map_err(JsValue::from) })
+}
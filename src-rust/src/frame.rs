@@ -0,0 +1,56 @@
+use serde::Serialize;
+
+/// The weight of the last frame in the averages of the frame statistics.
+const AVERAGE_WEIGHT: f64 = 0.1;
+
+/// The pacing statistics of the frames drawn by a viewer. Views requested faster than the frame
+/// rate are coalesced, only the last one is drawn.
+#[derive(Clone, Copy, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FrameStats {
+    /// The number of frames drawn.
+    pub frames: u32,
+    /// The number of views requested.
+    pub requested_views: u32,
+    /// The number of views replaced by a newer view before being drawn.
+    pub dropped_views: u32,
+    /// The time taken to draw the last frame, in milliseconds.
+    pub last_draw_time: f64,
+    /// The moving average of the time taken to draw a frame, in milliseconds.
+    pub average_draw_time: f64,
+    /// The moving average of the interval between two frames, in milliseconds.
+    pub average_frame_interval: f64,
+    #[serde(skip)]
+    last_frame_start: Option<f64>,
+}
+
+impl FrameStats {
+    /// Record a requested view, which replaces the pending view if there is one.
+    pub fn record_view(&mut self, replaces_pending_view: bool) {
+        self.requested_views += 1;
+        if replaces_pending_view {
+            self.dropped_views += 1;
+        }
+    }
+
+    /// Record a drawn frame, which started at a given time and took a given time to draw.
+    pub fn record_frame(&mut self, start: f64, draw_time: f64) {
+        if let Some(last_frame_start) = self.last_frame_start {
+            self.average_frame_interval = get_average(self.average_frame_interval, start - last_frame_start, self.frames);
+        }
+
+        self.average_draw_time = get_average(self.average_draw_time, draw_time, self.frames + 1);
+        self.last_draw_time = draw_time;
+        self.last_frame_start = Some(start);
+        self.frames += 1;
+    }
+}
+
+/// Update an exponential moving average, which is the first value if there is no previous value.
+fn get_average(average: f64, value: f64, count: u32) -> f64 {
+    if count <= 1 {
+        value
+    } else {
+        average + AVERAGE_WEIGHT * (value - average)
+    }
+}
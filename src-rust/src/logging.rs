@@ -0,0 +1,173 @@
+use std::cell::RefCell;
+
+use brain_render_core::Error;
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use serde::Serialize;
+use wasm_bindgen::prelude::*;
+
+use crate::{browser, error::ApiResult};
+
+/// The level of the logs that are not matched by a module filter, unless the filter sets another
+/// default level.
+const DEFAULT_LEVEL: LevelFilter = LevelFilter::Info;
+
+/// The levels of the logs to keep, by module.
+struct LogFilter {
+    default_level: LevelFilter,
+    /// The levels of the modules and their submodules, sorted by decreasing module path length so
+    /// that the most specific filter of a module is found first.
+    module_levels: Vec<(String, LevelFilter)>,
+}
+
+impl LogFilter {
+    /// Parse a filter of the form `warn,brain_renderer::renderer=debug`, which is a list of levels
+    /// of modules and a default level.
+    fn parse(filter: &str) -> Result<Self, Error> {
+        let mut default_level = DEFAULT_LEVEL;
+        let mut module_levels = Vec::new();
+        for directive in filter.split(',').map(str::trim).filter(|directive| !directive.is_empty()) {
+            match directive.split_once('=') {
+                Some((module, level)) => module_levels.push((module.trim().to_string(), parse_level(level)?)),
+                None => default_level = parse_level(directive)?,
+            }
+        }
+
+        module_levels.sort_by_key(|(module, _)| std::cmp::Reverse(module.len()));
+        Ok(Self { default_level, module_levels })
+    }
+
+    /// Get the level of the logs to keep for a target, which is the module path of the logs.
+    fn level(&self, target: &str) -> LevelFilter {
+        self.module_levels.iter()
+            .find(|(module, _)| is_in_module(target, module))
+            .map_or(self.default_level, |&(_, level)| level)
+    }
+
+    /// Get the most verbose level of the filter.
+    fn max_level(&self) -> LevelFilter {
+        self.module_levels.iter()
+            .map(|&(_, level)| level)
+            .fold(self.default_level, |max_level, level| max_level.max(level))
+    }
+}
+
+impl Default for LogFilter {
+    fn default() -> Self {
+        Self { default_level: DEFAULT_LEVEL, module_levels: Vec::new() }
+    }
+}
+
+/// A log record sent to JavaScript.
+#[derive(Serialize)]
+struct LogRecord {
+    level: &'static str,
+    target: String,
+    message: String,
+    /// The time of the record, in milliseconds since the Unix epoch.
+    time: f64,
+}
+
+thread_local! {
+    static FILTER: RefCell<LogFilter> = RefCell::new(LogFilter::default());
+    static CALLBACK: RefCell<Option<js_sys::Function>> = const { RefCell::new(None) };
+}
+
+/// A logger that writes the logs to the browser console, and forwards them to a JavaScript callback.
+struct ConsoleLogger;
+
+static LOGGER: ConsoleLogger = ConsoleLogger;
+
+impl Log for ConsoleLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        FILTER.with_borrow(|filter| metadata.level() <= filter.level(metadata.target()))
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let message = record.args().to_string();
+        let line = format!("[{}] {}", record.target(), message);
+        match record.level() {
+            Level::Error => browser::console_error(&line),
+            Level::Warn => browser::console_warn(&line),
+            Level::Info => browser::console_log(&line),
+            Level::Debug | Level::Trace => browser::console_debug(&line),
+        }
+
+        // The callback is cloned out of its cell, so that it can set or remove the callback.
+        let Some(callback) = CALLBACK.with_borrow(Option::clone) else {
+            return;
+        };
+
+        let record = LogRecord {
+            level: level_name(record.level()),
+            target: record.target().to_string(),
+            message,
+            time: js_sys::Date::now(),
+        };
+
+        // The errors of the callback are not logged, which could call it again.
+        let sent = serde_wasm_bindgen::to_value(&record)
+            .ok()
+            .is_some_and(|record| callback.call1(&JsValue::NULL, &record).is_ok());
+        if !sent {
+            browser::console_error("[logging] log callback failed");
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// Install the console logger, if no logger is installed yet.
+pub(crate) fn init_logger() {
+    if log::set_logger(&LOGGER).is_ok() {
+        log::set_max_level(FILTER.with_borrow(LogFilter::max_level));
+    }
+}
+
+/// Set the levels of the logs to keep, with a filter of the form
+/// `warn,brain_renderer::renderer=debug,brain_render_core=trace`. The filter is a list of levels of
+/// modules and their submodules, and of a default level for the other modules, which is `info` if
+/// it is not set. The levels are `off`, `error`, `warn`, `info`, `debug` and `trace`.
+#[wasm_bindgen(js_name = setLogFilter)]
+pub fn set_log_filter(filter: &str) -> ApiResult<()> {
+    apply_log_filter(filter)?;
+    Ok(())
+}
+
+/// Set a function called with each log record kept by the filter, as a
+/// `{level, target, message, time}` object, or remove it.
+#[wasm_bindgen(js_name = onLog)]
+pub fn on_log(callback: Option<js_sys::Function>) {
+    init_logger();
+    CALLBACK.set(callback);
+}
+
+pub(crate) fn apply_log_filter(filter: &str) -> Result<(), Error> {
+    init_logger();
+    let filter = LogFilter::parse(filter)?;
+    log::set_max_level(filter.max_level());
+    FILTER.set(filter);
+    Ok(())
+}
+
+fn parse_level(level: &str) -> Result<LevelFilter, Error> {
+    level.trim().parse().map_err(|_| Error::BadArgument(format!("invalid log level {}", level.trim())))
+}
+
+fn level_name(level: Level) -> &'static str {
+    match level {
+        Level::Error => "error",
+        Level::Warn  => "warn",
+        Level::Info  => "info",
+        Level::Debug => "debug",
+        Level::Trace => "trace",
+    }
+}
+
+/// Check whether a module path is a module or one of its submodules.
+fn is_in_module(path: &str, module: &str) -> bool {
+    path.strip_prefix(module).is_some_and(|rest| rest.is_empty() || rest.starts_with("::"))
+}
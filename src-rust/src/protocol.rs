@@ -0,0 +1,206 @@
+use brain_render_core::{nifti::SliceView, nifti_writer::NiftiWriteOptions, npy::NpyWriteOptions, Error};
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::JsValue;
+use web_sys::{File, OffscreenCanvas};
+
+use crate::{frame::FrameStats, renderer::{RendererBackend, RendererStatus}, viewer::{LoadedVolume, VolumeId}};
+
+/// The version of the message protocol, which is incremented on breaking changes.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// The identifier of a request, chosen by the sender, which is repeated in its response.
+pub type RequestId = u32;
+
+/// A message sent to a viewer, in the form `{version, id, request: {"<action>": {...}}}`.
+#[derive(Deserialize)]
+pub struct RequestMessage {
+    pub version: u32,
+    pub id: RequestId,
+    pub request: Request,
+}
+
+/// A request to a viewer. The requests are externally tagged so that their JavaScript objects, such
+/// as canvases and files, are passed as is.
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Request {
+    InitRenderer {
+        #[serde(with = "serde_wasm_bindgen::preserve")]
+        canvas: OffscreenCanvas,
+    },
+    ReadFile {
+        #[serde(with = "serde_wasm_bindgen::preserve")]
+        file: File,
+    },
+    /// Read a two-file image from its header file and its data file, which are either a `.hdr`
+    /// header and its `.img` voxel file, or a detached `.nhdr` NRRD header and its data file.
+    ReadFilePair {
+        #[serde(with = "serde_wasm_bindgen::preserve")]
+        header: File,
+        #[serde(with = "serde_wasm_bindgen::preserve")]
+        image: File,
+    },
+    /// Read a NumPy `.npy` or `.npz` file, whose affine and description are set by a JSON
+    /// sidecar file.
+    ReadFileWithSidecar {
+        #[serde(with = "serde_wasm_bindgen::preserve")]
+        file: File,
+        #[serde(with = "serde_wasm_bindgen::preserve")]
+        sidecar: File,
+    },
+    /// Read the DICOM series of a set of files, such as the files of a directory.
+    ReadDicomFiles {
+        #[serde(with = "serde_wasm_bindgen::preserve")]
+        files: js_sys::Array,
+    },
+    ReadBytes {
+        #[serde(with = "serde_wasm_bindgen::preserve")]
+        bytes: js_sys::Uint8Array,
+        name: Option<String>,
+    },
+    /// Read an image from a URL. Uncompressed NIfTI files are streamed with range requests, and
+    /// their timepoints are fetched when they are displayed.
+    ReadUrl {
+        url: String,
+    },
+    /// Read an OME-Zarr multiscale image from the URL of its root, at the given level or at the
+    /// coarsest level that fills the canvas of the renderer.
+    ReadZarrUrl {
+        url: String,
+        level: Option<usize>,
+    },
+    /// Read an OME-Zarr multiscale image from a `FileSystemDirectoryHandle` of its root directory,
+    /// like `ReadZarrUrl`.
+    ReadZarrDirectory {
+        #[serde(with = "serde_wasm_bindgen::preserve")]
+        directory: JsValue,
+        level: Option<usize>,
+    },
+    SetMaxVoxelCount {
+        count: usize,
+    },
+    /// Set the size in pixels at which the volumes are displayed, see `setDisplaySize`.
+    SetDisplaySize {
+        size: usize,
+    },
+    RenderSlice {
+        view: SliceView,
+    },
+    RenderSliceImage {
+        width: u32,
+        height: u32,
+        view: SliceView,
+    },
+    GetFrameStats {},
+    /// Export a volume as a single file NIfTI-1 image, see `exportNifti`.
+    ExportNifti {
+        volume: VolumeId,
+        #[serde(default)]
+        options: NiftiWriteOptions,
+    },
+    /// Export a volume as a NumPy array, see `exportNpy`.
+    ExportNpy {
+        volume: VolumeId,
+        #[serde(default)]
+        options: NpyWriteOptions,
+    },
+    /// Close a volume and free its memory, see `closeVolume`.
+    CloseVolume {
+        volume: VolumeId,
+    },
+    /// Set the levels of the logs to keep, see `setLogFilter`.
+    SetLogFilter {
+        filter: String,
+    },
+    /// Abort a pending read request, which then fails with the `aborted` error code.
+    Abort {
+        request: RequestId,
+    },
+}
+
+/// A message sent by a viewer, in the form `{version, event: {type: "<event>", ...}}`.
+#[derive(Serialize)]
+pub struct EventMessage {
+    pub version: u32,
+    pub event: Event,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum Event {
+    /// The successful response to a request.
+    Response {
+        id: RequestId,
+        response: Response,
+    },
+    /// The failed response to a request, or to a message that could not be parsed.
+    Error {
+        id: Option<RequestId>,
+        error: ErrorPayload,
+    },
+    /// The progress of a read request, in slices, or in files for DICOM reads.
+    Progress {
+        id: RequestId,
+        loaded: usize,
+        total: usize,
+    },
+    /// The renderer status changed.
+    RendererStatus {
+        status: RendererStatus,
+    },
+    /// The displayed OME-Zarr image switched to another level, whose dimensions are in its
+    /// properties.
+    VolumeChanged {
+        volume: LoadedVolume,
+    },
+}
+
+#[derive(Serialize)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+pub enum Response {
+    RendererInitialized {
+        backend: RendererBackend,
+    },
+    VolumeLoaded {
+        volume: LoadedVolume,
+    },
+    /// The volumes of the series of a DICOM read, the first of which is displayed.
+    VolumesLoaded {
+        volumes: Vec<LoadedVolume>,
+    },
+    SliceImage {
+        #[serde(with = "serde_wasm_bindgen::preserve")]
+        pixels: js_sys::Uint8Array,
+    },
+    FrameStats {
+        stats: FrameStats,
+    },
+    /// The bytes of an exported NIfTI-1 image.
+    NiftiExported {
+        #[serde(with = "serde_wasm_bindgen::preserve")]
+        bytes: js_sys::Uint8Array,
+    },
+    /// The bytes of an exported NumPy array, and its JSON sidecar.
+    NpyExported {
+        #[serde(with = "serde_wasm_bindgen::preserve")]
+        bytes: js_sys::Uint8Array,
+        sidecar: String,
+    },
+    Done,
+}
+
+/// An error sent to JavaScript, whose code identifies its kind.
+#[derive(Serialize)]
+pub struct ErrorPayload {
+    pub code: &'static str,
+    pub message: String,
+}
+
+impl From<&Error> for ErrorPayload {
+    fn from(error: &Error) -> Self {
+        Self {
+            code: error.code(),
+            message: error.to_string(),
+        }
+    }
+}
@@ -1,39 +1,75 @@
-use wasm_bindgen::JsCast;
+use std::{ops::Range, rc::Rc, sync::{Arc, Mutex, atomic::{AtomicBool, Ordering}}};
+
+use serde::{Deserialize, Serialize};
 use web_sys::OffscreenCanvas;
 
-use crate::{display_window::DisplayWindow, nifti::{AnatomicalAxis, Rotation}, renderer::texture::{create_bind_group_layout, create_texture_from_nifti_slice}};
+use brain_render_core::{display_window::DisplayWindow, nifti::{AnatomicalAxis, Rotation}, volume::{Volume, VoxelType}, Error};
+
+use crate::renderer::{target::{RenderTarget, TARGET_TEXTURE_FORMAT, create_target_texture, read_texture_pixels}, texture::{create_texture_from_nifti_slice, get_sample_type, write_texture_region, VolumeTextures}};
 
 pub mod params;
+pub mod target;
 pub mod texture;
 
+/// The graphics backend used to render the slices.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub enum RendererBackend {
+    WebGpu,
+    WebGl,
+    Cpu,
+    Native,
+}
+
+/// A change of the state of the renderer that is reported to the user interface.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub enum RendererStatus {
+    /// The GPU device was lost, the renderer is being restored.
+    Lost,
+    /// The renderer was restored after a device loss.
+    Restored,
+    /// The renderer failed and cannot display slices anymore.
+    Failed,
+}
+
+/// A handler called on the thread of a renderer when its GPU device is lost.
+pub type DeviceLostHandler = Rc<dyn Fn()>;
+
 pub struct Renderer {
-    surface: wgpu::Surface<'static>,
+    backend: RendererBackend,
+    instance: wgpu::Instance,
+    target: RenderTarget,
+    format: wgpu::TextureFormat,
     device: wgpu::Device,
     queue: wgpu::Queue,
-    config: wgpu::SurfaceConfiguration,
-    render_pipeline: wgpu::RenderPipeline,
-    bind_group_layout: wgpu::BindGroupLayout,
+    device_lost: Arc<AtomicBool>,
+    device_lost_handler: Option<DeviceLostHandler>,
     bind_group: Option<wgpu::BindGroup>,
-    pub texture_views: Option<Vec<wgpu::TextureView>>,
+    pub volume_textures: Option<VolumeTextures>,
 }
 
 impl Renderer {
-    pub async fn new(canvas: OffscreenCanvas) -> Result<Self, String> {
+    /// Create a renderer that draws on a canvas. The device lost handler is called when the GPU
+    /// device is lost, after which the renderer can be restored with `restore`.
+    pub async fn new(canvas: OffscreenCanvas, device_lost_handler: Option<DeviceLostHandler>) -> Result<Self, Error> {
         let x = canvas.width();
         let y = canvas.height();
-        let instance = wgpu::Instance::default();
+        let instance = create_instance().await;
 
-        // Check that the canvas supports WebGPU.
-        canvas
-            .get_context("webgpu")
-            .map_err(|_| "failed to get webgpu context from canvas")?
-            .ok_or("webgpu context not supported")?
-            .dyn_into::<web_sys::GpuCanvasContext>()
-            .map_err(|_| "failed to convert to gpucanvascontext")?;
+        // Check that the canvas supports WebGPU, unless the renderer can fall back to WebGL.
+        #[cfg(not(feature = "webgl"))]
+        {
+            use wasm_bindgen::JsCast;
+            canvas
+                .get_context("webgpu")
+                .map_err(|_| Error::Gpu("failed to get webgpu context from canvas".to_string()))?
+                .ok_or_else(|| Error::Gpu("webgpu context not supported".to_string()))?
+                .dyn_into::<web_sys::GpuCanvasContext>()
+                .map_err(|_| Error::Gpu("failed to convert to gpucanvascontext".to_string()))?;
+        }
 
         let surface = wgpu::SurfaceTarget::OffscreenCanvas(canvas);
         let surface = instance.create_surface(surface)
-            .map_err(|_| "failed to create surface")?;
+            .map_err(|_| Error::Gpu("failed to create surface".to_string()))?;
 
         let adapter = instance
             .request_adapter(&wgpu::RequestAdapterOptions {
@@ -42,64 +78,19 @@ impl Renderer {
                 compatible_surface: Some(&surface),
             })
             .await
-            .map_err(|_| "failed to find an appropriate adapter")?;
-
-        let (device, queue) = adapter
-            .request_device(
-                &wgpu::DeviceDescriptor {
-                    label: None,
-                    required_features: wgpu::Features::empty(),
-                    required_limits: wgpu::Limits::downlevel_webgl2_defaults()
-                        .using_resolution(adapter.limits()),
-                    memory_hints: wgpu::MemoryHints::Performance,
-                    experimental_features: wgpu::ExperimentalFeatures::disabled(),
-                    trace: wgpu::Trace::Off,
-                },
-            )
-            .await
-            .map_err(|_| "failed to create device")?;
+            .map_err(|_| Error::Gpu("failed to find an appropriate adapter".to_string()))?;
 
-        // Create initial empty bind group and layout
-        let bind_group_layout = create_bind_group_layout(&device);
-
-        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
-            label: None,
-            source: wgpu::ShaderSource::Wgsl(include_str!("renderer/shaders/shader.wgsl").into()),
-        });
-
-        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-            label: Some("render_pipeline_layout"),
-            bind_group_layouts: &[&bind_group_layout],
-            push_constant_ranges: &[],
-        });
+        let (device, queue) = request_device(&adapter).await?;
+        let device_lost = watch_device_loss(&device, device_lost_handler.clone());
 
         let swapchain_capabilities = surface.get_capabilities(&adapter);
-        let swapchain_format = swapchain_capabilities.formats[0];
-
-        let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("render_pipeline"),
-            layout: Some(&pipeline_layout),
-            vertex: wgpu::VertexState {
-                module: &shader,
-                entry_point: Some("vs_main"),
-                buffers: &[],
-                compilation_options: Default::default(),
-            },
-            fragment: Some(wgpu::FragmentState {
-                module: &shader,
-                entry_point: Some("fs_main"),
-                targets: &[Some(swapchain_format.into())],
-                compilation_options: Default::default(),
-            }),
-            primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::TriangleStrip,
-                ..Default::default()
-            },
-            depth_stencil: None,
-            multisample: wgpu::MultisampleState::default(),
-            multiview: None,
-            cache: None,
-        });
+
+        // Prefer a linear format so that the grayscale values are displayed as computed, which is
+        // not the default on WebGL.
+        let swapchain_format = swapchain_capabilities.formats.iter()
+            .copied()
+            .find(|format| !format.is_srgb())
+            .unwrap_or(swapchain_capabilities.formats[0]);
 
         let config = wgpu::SurfaceConfiguration {
             usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
@@ -115,29 +106,180 @@ impl Renderer {
         surface.configure(&device, &config);
 
         Ok(Self {
-            surface,
+            backend: get_backend(&adapter),
+            instance,
+            target: RenderTarget::Surface { surface, config },
+            format: swapchain_format,
+            device,
+            queue,
+            device_lost,
+            device_lost_handler,
+            bind_group: None,
+            volume_textures: None,
+        })
+    }
+
+    /// Create a headless renderer that draws into an owned texture, whose pixels can then be
+    /// read back with `read_pixels`.
+    pub async fn new_offscreen(width: u32, height: u32) -> Result<Self, Error> {
+        let instance = create_instance().await;
+
+        let adapter = instance
+            .request_adapter(&wgpu::RequestAdapterOptions {
+                power_preference: wgpu::PowerPreference::default(),
+                force_fallback_adapter: false,
+                compatible_surface: None,
+            })
+            .await
+            .map_err(|_| Error::Gpu("failed to find an appropriate adapter".to_string()))?;
+
+        let (device, queue) = request_device(&adapter).await?;
+        let device_lost = watch_device_loss(&device, None);
+
+        let texture = create_target_texture(&device, width, height);
+
+        Ok(Self {
+            backend: get_backend(&adapter),
+            instance,
+            target: RenderTarget::Texture { texture },
+            format: TARGET_TEXTURE_FORMAT,
             device,
             queue,
-            config,
-            render_pipeline,
-            bind_group_layout,
+            device_lost,
+            device_lost_handler: None,
             bind_group: None,
-            texture_views: None,
+            volume_textures: None,
         })
     }
 
+    /// Get the graphics backend used by this renderer.
+    pub fn backend(&self) -> RendererBackend {
+        self.backend
+    }
+
+    /// Get the size of the render target of this renderer, in pixels.
+    pub fn size(&self) -> (u32, u32) {
+        match &self.target {
+            RenderTarget::Surface { config, .. } => (config.width, config.height),
+            RenderTarget::Texture { texture } => (texture.width(), texture.height()),
+        }
+    }
+
+    /// Release the textures of the current volume, for instance before displaying another volume.
+    pub fn unload_volume(&mut self) {
+        self.bind_group = None;
+        self.volume_textures = None;
+    }
+
+    /// Upload a region of the volume to its textures after it changed, such as a brick that is
+    /// streamed, given by the ranges of its x, y, z and t indices. The region is uploaded with its
+    /// timepoint if the texture of the timepoint is not created yet.
+    pub fn update_volume_region(&mut self, volume: &Volume, region: [Range<usize>; 4]) {
+        let Some(volume_textures) = &self.volume_textures else {
+            return;
+        };
+
+        let [x, y, z, timepoints] = region;
+        for timepoint in timepoints {
+            if let Some(Some(texture)) = volume_textures.textures.get(timepoint) {
+                write_texture_region(&self.queue, &texture.texture, volume, timepoint, [x.clone(), y.clone(), z.clone()]);
+            }
+        }
+    }
+
     // Separate function to update the Nifti slice
-    pub fn update_nifti_slice(&mut self, volume: &ndarray::Array4<f32>, window: DisplayWindow, coordinate: usize, timepoint: usize, axis: AnatomicalAxis, rotation: Rotation) {
+    pub fn update_nifti_slice(&mut self, volume: &Volume, window: DisplayWindow, coordinate: usize, timepoint: usize, axis: AnatomicalAxis, rotation: Rotation) {
         self.bind_group = Some(create_texture_from_nifti_slice(self, volume, window, axis, coordinate as u32, timepoint, rotation));
     }
 
-    pub fn render(&mut self) {
-        let frame = self
-            .surface
-            .get_current_texture()
-            .expect("Failed to acquire next swap chain texture");
-        let view: wgpu::TextureView = frame
-            .texture
+    /// Check whether the GPU device of this renderer was lost.
+    pub fn is_device_lost(&self) -> bool {
+        self.device_lost.load(Ordering::Relaxed)
+    }
+
+    /// Restore this renderer after its GPU device was lost, by creating a new device and
+    /// reconfiguring the render target. The volume textures are uploaded again from the CPU copy
+    /// of the volume on the next slice update.
+    pub async fn restore(&mut self) -> Result<(), Error> {
+        let adapter = {
+            let compatible_surface = match &self.target {
+                RenderTarget::Surface { surface, .. } => Some(surface),
+                RenderTarget::Texture { .. } => None,
+            };
+
+            self.instance
+                .request_adapter(&wgpu::RequestAdapterOptions {
+                    power_preference: wgpu::PowerPreference::default(),
+                    force_fallback_adapter: false,
+                    compatible_surface,
+                })
+                .await
+                .map_err(|_| Error::Gpu("failed to find an appropriate adapter".to_string()))?
+        };
+
+        let (device, queue) = request_device(&adapter).await?;
+
+        match &mut self.target {
+            RenderTarget::Surface { surface, config } => {
+                surface.configure(&device, config);
+            }
+            RenderTarget::Texture { texture } => {
+                *texture = create_target_texture(&device, texture.width(), texture.height());
+            }
+        }
+
+        self.device_lost = watch_device_loss(&device, self.device_lost_handler.clone());
+        self.device = device;
+        self.queue = queue;
+        self.bind_group = None;
+        self.volume_textures = None;
+        Ok(())
+    }
+
+    pub fn render(&mut self) -> Result<(), Error> {
+        match &self.target {
+            RenderTarget::Surface { surface, config } => {
+                let frame = match surface.get_current_texture() {
+                    Ok(frame) => frame,
+                    // The surface needs to be configured again, for instance after the canvas was
+                    // resized or the GPU was reset.
+                    Err(wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated) => {
+                        log::debug!("reconfiguring the surface");
+                        surface.configure(&self.device, config);
+                        surface.get_current_texture()
+                            .map_err(|error| Error::Gpu(format!("failed to acquire surface texture: {}", error)))?
+                    }
+                    Err(wgpu::SurfaceError::Timeout) => {
+                        log::debug!("surface texture timeout, skipping frame");
+                        return Ok(());
+                    }
+                    Err(error) => {
+                        return Err(Error::Gpu(format!("failed to acquire surface texture: {}", error)));
+                    }
+                };
+
+                self.draw(&frame.texture);
+                frame.present();
+            }
+            RenderTarget::Texture { texture } => {
+                self.draw(texture);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Read the RGBA8 pixels of the last render of an offscreen renderer, row by row.
+    pub async fn read_pixels(&self) -> Result<Vec<u8>, Error> {
+        match &self.target {
+            RenderTarget::Texture { texture } => read_texture_pixels(&self.device, &self.queue, texture).await,
+            RenderTarget::Surface { .. } => Err(Error::Gpu("cannot read the pixels of a canvas renderer".to_string())),
+        }
+    }
+
+    /// Draw the current slice into a texture.
+    fn draw(&self, texture: &wgpu::Texture) {
+        let view: wgpu::TextureView = texture
             .create_view(&wgpu::TextureViewDescriptor::default());
         let mut encoder = self
             .device
@@ -158,12 +300,204 @@ impl Renderer {
                 timestamp_writes: None,
                 occlusion_query_set: None,
             });
-            render_pass.set_pipeline(&self.render_pipeline);
-            render_pass.set_bind_group(0, &self.bind_group, &[]);
-            render_pass.draw(0..4, 0..1);
+            if let Some(volume_textures) = &self.volume_textures {
+                render_pass.set_pipeline(&volume_textures.render_pipeline);
+                render_pass.set_bind_group(0, &self.bind_group, &[]);
+                render_pass.draw(0..4, 0..1);
+            }
         }
 
         self.queue.submit(Some(encoder.finish()));
-        frame.present();
+    }
+}
+
+/// Create a wgpu instance. If the `webgl` feature is enabled, the instance uses WebGL when WebGPU
+/// is not supported by the browser.
+async fn create_instance() -> wgpu::Instance {
+    #[cfg(feature = "webgl")]
+    return wgpu::util::new_instance_with_webgpu_detection(&wgpu::InstanceDescriptor::default()).await;
+
+    #[cfg(not(feature = "webgl"))]
+    wgpu::Instance::default()
+}
+
+/// Watch a GPU device for losses, and call the given handler when it is lost. Destroying the device
+/// does not count as a loss. The device lost callback must be sendable to other threads, so it
+/// signals the loss to a task that calls the handler on the thread of the renderer.
+fn watch_device_loss(device: &wgpu::Device, handler: Option<DeviceLostHandler>) -> Arc<AtomicBool> {
+    let device_lost = Arc::new(AtomicBool::new(false));
+    let flag = device_lost.clone();
+    let (sender, receiver) = futures_channel::oneshot::channel();
+    let sender = Mutex::new(Some(sender));
+    device.set_device_lost_callback(move |reason, message| {
+        if let wgpu::DeviceLostReason::Destroyed = reason {
+            return;
+        }
+
+        log::error!("device lost: {}", message);
+        flag.store(true, Ordering::Relaxed);
+        if let Some(sender) = sender.lock().ok().and_then(|mut sender| sender.take()) {
+            let _ = sender.send(());
+        }
+    });
+
+    if let Some(handler) = handler {
+        // The loss is cancelled if the callback is dropped with the device.
+        wasm_bindgen_futures::spawn_local(async move {
+            if receiver.await.is_ok() {
+                handler();
+            }
+        });
+    }
+
+    device_lost
+}
+
+fn get_backend(adapter: &wgpu::Adapter) -> RendererBackend {
+    match adapter.get_info().backend {
+        wgpu::Backend::BrowserWebGpu => RendererBackend::WebGpu,
+        wgpu::Backend::Gl if cfg!(target_arch = "wasm32") => RendererBackend::WebGl,
+        _ => RendererBackend::Native,
+    }
+}
+
+async fn request_device(adapter: &wgpu::Adapter) -> Result<(wgpu::Device, wgpu::Queue), Error> {
+    adapter
+        .request_device(
+            &wgpu::DeviceDescriptor {
+                label: None,
+                required_features: wgpu::Features::empty(),
+                // Use the 3D texture limit of the adapter, which is often larger than the WebGL 2
+                // default, to support large volumes.
+                required_limits: wgpu::Limits {
+                    max_texture_dimension_3d: adapter.limits().max_texture_dimension_3d,
+                    ..wgpu::Limits::downlevel_webgl2_defaults().using_resolution(adapter.limits())
+                },
+                memory_hints: wgpu::MemoryHints::Performance,
+                experimental_features: wgpu::ExperimentalFeatures::disabled(),
+                trace: wgpu::Trace::Off,
+            },
+        )
+        .await
+        .map_err(|_| Error::Gpu("failed to create device".to_string()))
+}
+
+/// Create the render pipeline that displays the slices of a volume of a given voxel datatype.
+pub fn create_render_pipeline(device: &wgpu::Device, format: wgpu::TextureFormat, voxel_type: VoxelType, bind_group_layout: &wgpu::BindGroupLayout) -> wgpu::RenderPipeline {
+    // The shader reads the volume texture with the `VoxelScalar` placeholder, which is replaced by
+    // the scalar of the sample type of the texture, since naga does not resolve aliases there.
+    let scalar = match get_sample_type(voxel_type) {
+        wgpu::TextureSampleType::Uint => "u32",
+        wgpu::TextureSampleType::Sint => "i32",
+        _ => "f32",
+    };
+
+    let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: None,
+        source: wgpu::ShaderSource::Wgsl(include_str!("renderer/shaders/shader.wgsl").replace("VoxelScalar", scalar).into()),
+    });
+
+    let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some("render_pipeline_layout"),
+        bind_group_layouts: &[bind_group_layout],
+        push_constant_ranges: &[],
+    });
+
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("render_pipeline"),
+        layout: Some(&pipeline_layout),
+        vertex: wgpu::VertexState {
+            module: &shader,
+            entry_point: Some("vs_main"),
+            buffers: &[],
+            compilation_options: Default::default(),
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: &shader,
+            entry_point: Some("fs_main"),
+            targets: &[Some(format.into())],
+            compilation_options: Default::default(),
+        }),
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleStrip,
+            ..Default::default()
+        },
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+        cache: None,
+    })
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use std::{pin::pin, task::{Context, Poll, Waker}};
+
+    use brain_render_core::{cpu_renderer, display_window::DisplayPolarity, nifti::SliceView};
+    use ndarray::{Array4, ShapeBuilder};
+
+    use super::*;
+
+    /// The size of the rendered images, in which each voxel of the test volumes covers 4x4 pixels,
+    /// so that no pixel is sampled at the edge between two voxels.
+    const IMAGE_SIZE: u32 = 16;
+
+    /// Run a future to completion on the current thread. The futures of native wgpu complete
+    /// once the device is polled, so they only need to be polled again.
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let mut future = pin!(future);
+        let mut context = Context::from_waker(Waker::noop());
+        loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut context) {
+                return output;
+            }
+
+            std::thread::yield_now();
+        }
+    }
+
+    /// Create volumes of 4x4x4 voxels and 2 timepoints in each uploaded datatype, with signed
+    /// intensities for the signed datatypes.
+    fn create_volumes() -> Vec<Volume> {
+        let shape = (4, 4, 4, 2).f();
+        let intensity = |(x, y, z, t): (usize, usize, usize, usize)| (x + 4 * y + 16 * z + 64 * t) as i32;
+        vec![
+            Volume::U8(Array4::from_shape_fn(shape, |index| intensity(index) as u8)),
+            Volume::I16(Array4::from_shape_fn(shape, |index| (intensity(index) - 64) as i16)),
+            Volume::U16(Array4::from_shape_fn(shape, |index| (intensity(index) * 100) as u16)),
+            Volume::I32(Array4::from_shape_fn(shape, |index| (intensity(index) - 64) * 1000)),
+            Volume::F32(Array4::from_shape_fn(shape, |index| intensity(index) as f32 / 8.0)),
+            Volume::F64(Array4::from_shape_fn(shape, |index| intensity(index) as f64 / 8.0)),
+        ]
+    }
+
+    #[test]
+    #[ignore = "requires a GPU adapter"]
+    fn renders_the_same_slices_as_the_cpu_renderer() {
+        let mut renderer = block_on(Renderer::new_offscreen(IMAGE_SIZE, IMAGE_SIZE)).expect("no gpu adapter is available");
+
+        let axes = [AnatomicalAxis::Axial, AnatomicalAxis::Coronal, AnatomicalAxis::Sagittal];
+        let rotations = [Rotation::Rotate0, Rotation::Rotate90, Rotation::Rotate180, Rotation::Rotate270];
+        for volume in create_volumes() {
+            let maximum = volume.max();
+            renderer.unload_volume();
+            for (axis, rotation) in axes.into_iter().flat_map(|axis| rotations.map(|rotation| (axis, rotation))) {
+                for (coordinate, timepoint, polarity) in [(0, 0, DisplayPolarity::Positive), (3, 1, DisplayPolarity::Negative)] {
+                    let window = DisplayWindow { level: maximum / 2.0, width: maximum, polarity };
+                    let view = SliceView { axis, coordinate, timepoint, window, rotation };
+
+                    renderer.update_nifti_slice(&volume, window, coordinate, timepoint, axis, rotation);
+                    renderer.render().unwrap();
+                    let gpu_pixels = block_on(renderer.read_pixels()).unwrap();
+                    let cpu_pixels = cpu_renderer::render_slice(&volume, IMAGE_SIZE, IMAGE_SIZE, &view).unwrap();
+
+                    // The GPU may round the gray levels differently when it converts them to RGBA8.
+                    let difference = gpu_pixels.iter().zip(&cpu_pixels).map(|(&gpu, &cpu)| gpu.abs_diff(cpu)).max();
+                    assert_eq!(gpu_pixels.len(), cpu_pixels.len());
+                    assert!(difference <= Some(1), "{:?} slice {} of timepoint {} with {:?} of {:?} voxels differs by {:?}",
+                        axis as u32, coordinate, timepoint, rotation as u32, volume.voxel_type(), difference);
+                }
+            }
+        }
     }
 }
@@ -1,4 +1,4 @@
-use crate::{display_window::DisplayWindow, nifti::{AnatomicalAxis, Rotation}};
+use brain_render_core::{cpu_renderer::normalize_slice_index, display_window::DisplayWindow, nifti::{AnatomicalAxis, Rotation}};
 
 #[repr(C)]
 #[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
@@ -26,7 +26,7 @@ impl FragmentParams {
             AnatomicalAxis::Sagittal => volume_dimensions[0],
         };
 
-        let normalized_slice_index = slice_index as f32 / (dimension_length - 1) as f32;
+        let normalized_slice_index = normalize_slice_index(slice_index, dimension_length);
 
         Self {
             volume_dims: [
@@ -34,11 +34,10 @@ struct FragmentParams {
     rotation: u32,
 }
 
+// `VoxelScalar` is replaced by the renderer with the scalar type of the volume texture.
 @group(0) @binding(0)
-var volume_texture: texture_3d<f32>;
+var volume_texture: texture_3d<VoxelScalar>;
 @group(0) @binding(1)
-var volume_sampler: sampler;
-@group(0) @binding(2)
 var<uniform> params: FragmentParams;
 
 @fragment
@@ -46,8 +45,12 @@ fn fs_main(input: VertexOutput) -> @location(0) vec4<f32> {
     // Get the voxel coordinates with rotation applied
     let voxel_coords = get_voxel_coords(input.tex_coords, params);
 
+    // Get the nearest voxel, clamped to the edges of the volume.
+    let volume_dims = vec3<i32>(textureDimensions(volume_texture));
+    let voxel = clamp(vec3<i32>(floor(voxel_coords * vec3<f32>(volume_dims))), vec3<i32>(0), volume_dims - 1);
+
     // Get the raw intensity from the volume.
-    let raw_value = textureSample(volume_texture, volume_sampler, voxel_coords).r;
+    let raw_value = f32(textureLoad(volume_texture, voxel, 0).r);
 
     // Normalize the value based on window parameters.
     let normalized_value = (raw_value - params.window.x) / (params.window.y - params.window.x);
@@ -0,0 +1,98 @@
+use brain_render_core::Error;
+
+/// The texture format of the offscreen render targets.
+pub const TARGET_TEXTURE_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8Unorm;
+
+/// The destination of the frames drawn by a renderer.
+pub enum RenderTarget {
+    /// A canvas surface, whose frames are presented on the page.
+    Surface {
+        surface: wgpu::Surface<'static>,
+        config: wgpu::SurfaceConfiguration,
+    },
+    /// An owned texture, whose pixels can be read back.
+    Texture {
+        texture: wgpu::Texture,
+    },
+}
+
+pub fn create_target_texture(device: &wgpu::Device, width: u32, height: u32) -> wgpu::Texture {
+    device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("target_texture"),
+        size: wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: TARGET_TEXTURE_FORMAT,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+        view_formats: &[],
+    })
+}
+
+/// Copy the pixels of a render target texture to the CPU, as tightly packed RGBA8 rows.
+pub async fn read_texture_pixels(device: &wgpu::Device, queue: &wgpu::Queue, texture: &wgpu::Texture) -> Result<Vec<u8>, Error> {
+    let width = texture.width();
+    let height = texture.height();
+
+    // Buffer copies require the rows to be aligned, so they are padded and unpadded afterwards.
+    let unpadded_bytes_per_row = 4 * width;
+    let padded_bytes_per_row = unpadded_bytes_per_row.next_multiple_of(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT);
+
+    let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("readback_buffer"),
+        size: padded_bytes_per_row as u64 * height as u64,
+        usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+        mapped_at_creation: false,
+    });
+
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+    encoder.copy_texture_to_buffer(
+        wgpu::TexelCopyTextureInfo {
+            texture,
+            mip_level: 0,
+            origin: wgpu::Origin3d::ZERO,
+            aspect: wgpu::TextureAspect::All,
+        },
+        wgpu::TexelCopyBufferInfo {
+            buffer: &buffer,
+            layout: wgpu::TexelCopyBufferLayout {
+                offset: 0,
+                bytes_per_row: Some(padded_bytes_per_row),
+                rows_per_image: Some(height),
+            },
+        },
+        texture.size(),
+    );
+
+    queue.submit(Some(encoder.finish()));
+
+    // Wait for the buffer to be mapped. On the web, the mapping callback is called by the browser
+    // event loop, on native platforms, it is called by polling the device.
+    let slice = buffer.slice(..);
+    let (sender, receiver) = futures_channel::oneshot::channel();
+    slice.map_async(wgpu::MapMode::Read, move |result| {
+        let _ = sender.send(result);
+    });
+
+    device.poll(wgpu::PollType::wait_indefinitely())
+        .map_err(|_| Error::Gpu("failed to poll device".to_string()))?;
+
+    receiver.await
+        .map_err(|_| Error::Gpu("readback buffer mapping was cancelled".to_string()))?
+        .map_err(|_| Error::Gpu("failed to map readback buffer".to_string()))?;
+
+    let mut pixels = Vec::with_capacity((unpadded_bytes_per_row * height) as usize);
+    {
+        let data = slice.get_mapped_range();
+        for row in data.chunks(padded_bytes_per_row as usize) {
+            pixels.extend_from_slice(&row[..unpadded_bytes_per_row as usize]);
+        }
+    }
+
+    buffer.unmap();
+    Ok(pixels)
+}
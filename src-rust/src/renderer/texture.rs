@@ -1,33 +1,50 @@
+use std::ops::Range;
+
 use wgpu::util::DeviceExt;
 
-use crate::{display_window::DisplayWindow, nifti::{AnatomicalAxis, Rotation}, renderer::{Renderer, params::FragmentParams}};
+use brain_render_core::{display_window::DisplayWindow, nifti::{AnatomicalAxis, Rotation}, volume::{Volume, Voxel, VoxelType}};
+
+use crate::renderer::{Renderer, create_render_pipeline, params::FragmentParams};
+
+/// The GPU resources of a volume uploaded to the renderer.
+pub struct VolumeTextures {
+    /// The textures of the timepoints of the volume, which are created and uploaded when their
+    /// timepoint is first displayed.
+    pub textures: Vec<Option<TimepointTexture>>,
+    pub bind_group_layout: wgpu::BindGroupLayout,
+    pub render_pipeline: wgpu::RenderPipeline,
+}
+
+/// The texture of a timepoint of a volume.
+pub struct TimepointTexture {
+    pub texture: wgpu::Texture,
+    pub view: wgpu::TextureView,
+}
 
 pub fn create_texture_from_nifti_slice(
     renderer: &mut Renderer,
-    volume: &ndarray::Array4<f32>,
+    volume: &Volume,
     window: DisplayWindow,
     axis: AnatomicalAxis,
     index: u32,
     timepoint: usize,
     rotation: Rotation,
 ) -> wgpu::BindGroup {
-    if renderer.texture_views.is_none() {
-        let textures = create_textures(&renderer.device, &renderer.queue, volume);
-        let texture_views = create_texture_views(textures);
-        renderer.texture_views = Some(texture_views);
+    if renderer.volume_textures.is_none() {
+        let bind_group_layout = create_bind_group_layout(&renderer.device, volume.voxel_type());
+        let render_pipeline = create_render_pipeline(&renderer.device, renderer.format, volume.voxel_type(), &bind_group_layout);
+        renderer.volume_textures = Some(VolumeTextures {
+            textures: (0..volume.dim().3).map(|_| None).collect(),
+            bind_group_layout,
+            render_pipeline,
+        });
     };
 
-    let dims: [usize; 4] = volume.dim().into();
+    let volume_textures = renderer.volume_textures.as_mut().expect("volume textures not initialized");
+    let texture = volume_textures.textures[timepoint]
+        .get_or_insert_with(|| create_timepoint_texture(&renderer.device, &renderer.queue, volume, timepoint));
 
-    let sampler = renderer.device.create_sampler(&wgpu::SamplerDescriptor {
-        address_mode_u: wgpu::AddressMode::ClampToEdge,
-        address_mode_v: wgpu::AddressMode::ClampToEdge,
-        address_mode_w: wgpu::AddressMode::ClampToEdge,
-        mag_filter: wgpu::FilterMode::Nearest,
-        min_filter: wgpu::FilterMode::Nearest,
-        mipmap_filter: wgpu::FilterMode::Nearest,
-        ..Default::default()
-    });
+    let dims: [usize; 4] = volume.dim().into();
 
     // Create slice parameters buffer
     let slice_params = FragmentParams::new(dims, axis, index as usize, window, rotation);
@@ -40,76 +57,114 @@ pub fn create_texture_from_nifti_slice(
 
     renderer.device.create_bind_group(&wgpu::BindGroupDescriptor {
         label: Some("nifti_bind_group"),
-        layout: &renderer.bind_group_layout,
+        layout: &volume_textures.bind_group_layout,
         entries: &[
             wgpu::BindGroupEntry {
                 binding: 0,
-                resource: wgpu::BindingResource::TextureView(&renderer.texture_views.as_ref().expect("texture view not initialized")[timepoint]),
+                resource: wgpu::BindingResource::TextureView(&texture.view),
             },
             wgpu::BindGroupEntry {
                 binding: 1,
-                resource: wgpu::BindingResource::Sampler(&sampler),
-            },
-            wgpu::BindGroupEntry {
-                binding: 2,
                 resource: slice_buffer.as_entire_binding(),
             },
         ],
     })
 }
 
-pub fn create_textures(device: &wgpu::Device, queue: &wgpu::Queue, volume: &ndarray::Array4<f32>) -> Vec<wgpu::Texture> {
-    let dims: [usize; 4] = volume.dim().into();
-    let [x_size, y_size, z_size, t_size] = dims.map(|x| x as u32);
-
-    let size =  wgpu::Extent3d {
-        width: x_size,
-        height: y_size,
-        depth_or_array_layers: z_size,
-    };
+/// Get the texture format in which the voxels of a given datatype are uploaded. The voxels are
+/// uploaded in their native datatype, except for `f64` voxels, which are converted to `f32` since
+/// textures do not support double precision.
+pub fn get_texture_format(voxel_type: VoxelType) -> wgpu::TextureFormat {
+    match voxel_type {
+        VoxelType::U8  => wgpu::TextureFormat::R8Uint,
+        VoxelType::I16 => wgpu::TextureFormat::R16Sint,
+        VoxelType::U16 => wgpu::TextureFormat::R16Uint,
+        VoxelType::I32 => wgpu::TextureFormat::R32Sint,
+        VoxelType::F32 => wgpu::TextureFormat::R32Float,
+        VoxelType::F64 => wgpu::TextureFormat::R32Float,
+    }
+}
 
-    // Create one texture per timepoint
-    let textures: Vec<wgpu::Texture> = (0..t_size).map(|_| {
-        device.create_texture(&wgpu::TextureDescriptor {
-            label: Some("nifti_texture"),
-            size,
-            mip_level_count: 1,
-            sample_count: 1,
-            dimension: wgpu::TextureDimension::D3,
-            format: wgpu::TextureFormat::R32Float,
-            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
-            view_formats: &[],
-        })
-    }).collect();
-
-    for (i, texture) in textures.iter().enumerate() {
-        queue.write_texture(
-            wgpu::TexelCopyTextureInfo {
-                texture: texture,
-                mip_level: 0,
-                origin: wgpu::Origin3d::ZERO,
-                aspect: wgpu::TextureAspect::All,
-            },
-            bytemuck::cast_slice(volume.slice(ndarray::s![.., .., .., i]).as_slice_memory_order().expect("Could not slice data")),
-            wgpu::TexelCopyBufferLayout {
-                offset: 0,
-                bytes_per_row: Some(4 * dims[0] as u32), // 4 bytes per f32
-                rows_per_image: Some(dims[1] as u32),
-            },
-            size,
-        );
+/// Get the sample type of the textures of the voxels of a given datatype.
+pub fn get_sample_type(voxel_type: VoxelType) -> wgpu::TextureSampleType {
+    match voxel_type {
+        VoxelType::U8 | VoxelType::U16 => wgpu::TextureSampleType::Uint,
+        VoxelType::I16 | VoxelType::I32 => wgpu::TextureSampleType::Sint,
+        VoxelType::F32 | VoxelType::F64 => wgpu::TextureSampleType::Float { filterable: false },
     }
+}
 
-    textures
+/// Create the texture of a timepoint of a volume, and upload the voxels of the timepoint.
+pub fn create_timepoint_texture(device: &wgpu::Device, queue: &wgpu::Queue, volume: &Volume, timepoint: usize) -> TimepointTexture {
+    let (x_size, y_size, z_size, _) = volume.dim();
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("nifti_texture"),
+        size: wgpu::Extent3d {
+            width: x_size as u32,
+            height: y_size as u32,
+            depth_or_array_layers: z_size as u32,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D3,
+        format: get_texture_format(volume.voxel_type()),
+        usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+        view_formats: &[],
+    });
+
+    write_texture_region(queue, &texture, volume, timepoint, [0..x_size, 0..y_size, 0..z_size]);
+    let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+    TimepointTexture { texture, view }
 }
 
-pub fn create_texture_views(textures: Vec<wgpu::Texture>) -> Vec<wgpu::TextureView> {
-    textures.into_iter()
-        .map(|texture| texture.create_view(&wgpu::TextureViewDescriptor::default()))
-        .collect()
+/// Upload a region of a timepoint of a volume to the texture of the timepoint, given by the ranges
+/// of its x, y and z indices, such as a brick of a volume that is streamed.
+pub fn write_texture_region(queue: &wgpu::Queue, texture: &wgpu::Texture, volume: &Volume, timepoint: usize, region: [Range<usize>; 3]) {
+    let origin = region.clone().map(|range| range.start);
+    let [x, y, z] = region;
+    let index = ndarray::s![x, y, z, timepoint];
+    match volume {
+        Volume::U8(array)  => write_typed_region(queue, texture, array.slice(index), origin),
+        Volume::I16(array) => write_typed_region(queue, texture, array.slice(index), origin),
+        Volume::U16(array) => write_typed_region(queue, texture, array.slice(index), origin),
+        Volume::I32(array) => write_typed_region(queue, texture, array.slice(index), origin),
+        Volume::F32(array) => write_typed_region(queue, texture, array.slice(index), origin),
+        Volume::F64(array) => write_typed_region(queue, texture, array.slice(index).mapv(|voxel| voxel as f32).view(), origin),
+    }
 }
 
-pub fn create_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+/// Upload a region of a timepoint to its texture, at the origin of the region in the volume.
+fn write_typed_region<T: Voxel>(queue: &wgpu::Queue, texture: &wgpu::Texture, region: ndarray::ArrayView3<T>, origin: [usize; 3]) {
+    let (x_size, y_size, z_size) = region.dim();
+    // Regions narrower than the volume are not contiguous, and are copied in Fortran order.
+    let region = region.reversed_axes();
+    let voxels = match region.as_slice() {
+        Some(voxels) => std::borrow::Cow::Borrowed(voxels),
+        None => std::borrow::Cow::Owned(region.iter().copied().collect()),
+    };
+
+    queue.write_texture(
+        wgpu::TexelCopyTextureInfo {
+            texture,
+            mip_level: 0,
+            origin: wgpu::Origin3d { x: origin[0] as u32, y: origin[1] as u32, z: origin[2] as u32 },
+            aspect: wgpu::TextureAspect::All,
+        },
+        bytemuck::cast_slice(&voxels),
+        wgpu::TexelCopyBufferLayout {
+            offset: 0,
+            bytes_per_row: Some(size_of::<T>() as u32 * x_size as u32),
+            rows_per_image: Some(y_size as u32),
+        },
+        wgpu::Extent3d {
+            width: x_size as u32,
+            height: y_size as u32,
+            depth_or_array_layers: z_size as u32,
+        },
+    );
+}
+
+pub fn create_bind_group_layout(device: &wgpu::Device, voxel_type: VoxelType) -> wgpu::BindGroupLayout {
     device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
         label: Some("texture_bind_group_layout"),
         entries: &[
@@ -119,19 +174,13 @@ pub fn create_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout
                 ty: wgpu::BindingType::Texture {
                     multisampled: false,
                     view_dimension: wgpu::TextureViewDimension::D3,
-                    sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                    sample_type: get_sample_type(voxel_type),
                 },
                 count: None,
             },
             wgpu::BindGroupLayoutEntry {
                 binding: 1,
                 visibility: wgpu::ShaderStages::FRAGMENT,
-                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::NonFiltering),
-                count: None,
-            },
-            wgpu::BindGroupLayoutEntry {
-                binding: 2,
-                visibility: wgpu::ShaderStages::FRAGMENT,
                 ty: wgpu::BindingType::Buffer {
                     ty: wgpu::BufferBindingType::Uniform,
                     has_dynamic_offset: false,
@@ -0,0 +1,1426 @@
+use std::{cell::{Cell, RefCell}, collections::{BTreeMap, HashMap}, io::{Cursor, Read}, ops::Range, rc::{Rc, Weak}};
+
+use brain_render_core::{cpu_renderer, dicom_reader::{self, DicomInstance}, nifti::{Nifti, NiftiProperies, SliceView}, image_reader::{self, ReadProgress, ReaderLimits, VolumeReader}, nifti_reader::NiftiRangeReader, nifti_writer::{self, NiftiWriteOptions}, npy::{self, NpySidecar, NpyWriteOptions}, ome_zarr::{Multiscale, OmeZarrReader, ZarrArray, ZarrChunk, ZarrFormat}, volume::Volume, Error};
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::prelude::*;
+use wasm_bindgen_file_reader::WebSysFile;
+use web_sys::{File, OffscreenCanvas};
+
+use crate::{browser::{self, RangeResponse}, canvas_renderer::CanvasRenderer, error::{api_promise, ApiResult}, frame::FrameStats, logging, protocol::{Event, EventMessage, Request, RequestId, RequestMessage, Response, PROTOCOL_VERSION}, renderer::{Renderer, RendererBackend, RendererStatus}, utils};
+
+/// The renderer used to display the slices, depending on the capabilities of the browser.
+enum SliceRenderer {
+    Gpu(Box<Renderer>),
+    Cpu(CanvasRenderer),
+}
+
+/// The identifier of a volume loaded in a viewer.
+pub type VolumeId = u32;
+
+/// A volume loaded in a viewer.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct LoadedVolume {
+    pub id: VolumeId,
+    pub properties: NiftiProperies,
+}
+
+/// A volume streamed from a URL or a directory, whose timepoints are fetched when they are
+/// displayed.
+struct RemoteVolume {
+    reader: RemoteReader,
+    /// Whether the displayed timepoint is being fetched.
+    is_fetching: bool,
+    /// The timepoint held by the volume of the image, which is the displayed timepoint.
+    timepoint: usize,
+    /// The volumes of the other timepoints, which are allocated when their timepoint is first
+    /// fetched.
+    timepoints: Vec<Option<Volume>>,
+}
+
+impl RemoteVolume {
+    fn new(reader: RemoteReader) -> Self {
+        let timepoints = (0..reader.timepoints()).map(|_| None).collect();
+        Self { reader, is_fetching: false, timepoint: 0, timepoints }
+    }
+}
+
+/// The reader of a streamed volume, with the location of its data.
+enum RemoteReader {
+    /// An uncompressed NIfTI file, read by byte ranges of its URL.
+    Nifti { url: String, reader: NiftiRangeReader },
+    /// A level of an OME-Zarr image, read by chunks from its store, with the minimum and maximum
+    /// intensities estimated from its coarsest level, and the levels of the image if the level is
+    /// picked from the display size.
+    Zarr { store: ZarrStore, reader: Box<OmeZarrReader>, intensities: (f32, f32), levels: Option<ZarrLevels> },
+}
+
+/// The levels of an OME-Zarr image whose displayed level is picked from the display size.
+struct ZarrLevels {
+    multiscale: Multiscale,
+    /// The shape of the array of the finest level, from which the shapes of the other levels are
+    /// estimated.
+    finest_shape: Vec<usize>,
+    /// The displayed level.
+    level: usize,
+}
+
+impl RemoteReader {
+    fn timepoints(&self) -> usize {
+        match self {
+            RemoteReader::Nifti { reader, .. } => reader.timepoints(),
+            RemoteReader::Zarr { reader, .. } => reader.timepoints(),
+        }
+    }
+
+    fn progress(&self, timepoint: usize) -> ReadProgress {
+        match self {
+            RemoteReader::Nifti { reader, .. } => reader.progress(timepoint),
+            RemoteReader::Zarr { reader, .. } => reader.progress(timepoint),
+        }
+    }
+
+    fn is_loaded(&self, timepoint: usize) -> bool {
+        match self {
+            RemoteReader::Nifti { reader, .. } => reader.is_loaded(timepoint),
+            RemoteReader::Zarr { reader, .. } => reader.is_loaded(timepoint),
+        }
+    }
+
+    /// Get the next request to fetch to read a timepoint, or `None` if the timepoint is read.
+    fn next_request(&self, timepoint: usize) -> Option<RemoteRequest> {
+        match self {
+            RemoteReader::Nifti { url, reader } => reader.next_range(timepoint, RANGE_CHUNK_SIZE)
+                .map(|range| RemoteRequest::Range(url.clone(), range)),
+            RemoteReader::Zarr { store, reader, .. } => reader.next_chunk(timepoint)
+                .map(|chunk| RemoteRequest::Chunk(store.clone(), chunk)),
+        }
+    }
+
+    /// Check whether a request is the next request of a timepoint, which it is not anymore if it
+    /// was made for another level of an OME-Zarr image.
+    fn is_next_request(&self, timepoint: usize, request: &RemoteRequest) -> bool {
+        match (self.next_request(timepoint), request) {
+            (Some(RemoteRequest::Range(_, next)), RemoteRequest::Range(_, range)) => next == *range,
+            (Some(RemoteRequest::Chunk(_, next)), RemoteRequest::Chunk(_, chunk)) => next.key == chunk.key,
+            _ => false,
+        }
+    }
+
+    /// Read the fetched data of a request of a timepoint into the volume of the timepoint, and get
+    /// the region of the volume read, as the ranges of its x, y, z and t indices.
+    fn read(&mut self, volume: &mut Volume, timepoint: usize, request: &RemoteRequest, bytes: Option<Vec<u8>>) -> Result<[Range<usize>; 4], Error> {
+        match (self, request) {
+            (RemoteReader::Nifti { reader, .. }, RemoteRequest::Range(..)) => {
+                let slices = reader.read_range(volume, timepoint, bytes.unwrap_or_default())?;
+                let (x, y, _, _) = volume.dim();
+                Ok([0..x, 0..y, slices, 0..1])
+            }
+            (RemoteReader::Zarr { reader, .. }, RemoteRequest::Chunk(_, chunk)) => reader.read_chunk(volume, timepoint, chunk, bytes),
+            _ => Err(Error::BadArgument("the request does not match the streamed volume".to_string())),
+        }
+    }
+}
+
+/// A request for the data of a streamed volume.
+enum RemoteRequest {
+    /// A byte range of the file of a URL.
+    Range(String, Range<u64>),
+    /// A chunk of an OME-Zarr image.
+    Chunk(ZarrStore, ZarrChunk),
+}
+
+impl RemoteRequest {
+    /// Fetch the data of this request, or get `None` if the chunk is missing from its store.
+    async fn fetch(&self) -> Result<Option<Vec<u8>>, Error> {
+        match self {
+            RemoteRequest::Range(url, range) => fetch_remote_range(url, range.clone()).await.map(Some),
+            RemoteRequest::Chunk(store, chunk) => store.get(&chunk.key).await,
+        }
+    }
+}
+
+/// The store of an OME-Zarr image, from which its metadata and chunks are read by key.
+#[derive(Clone)]
+enum ZarrStore {
+    /// A directory handle of the File System Access API.
+    Directory(JsValue),
+    /// The URL of the root of the image on an HTTP server.
+    Url(String),
+}
+
+impl ZarrStore {
+    /// Read the value of a key of this store, or get `None` if it does not exist.
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, Error> {
+        let bytes = match self {
+            ZarrStore::Directory(directory) => browser::read_directory_file(directory, key).await?,
+            ZarrStore::Url(url) => browser::fetch_optional_bytes(&format!("{}/{}", url.trim_end_matches('/'), key)).await?,
+        };
+
+        Ok(bytes.map(|bytes| bytes.to_vec()))
+    }
+}
+
+/// The state of a viewer, which is shared with its asynchronous operations.
+struct ViewerState {
+    renderer: Option<SliceRenderer>,
+    /// The headless renderer of the last slice image, which is kept for the next images of the
+    /// same size, without volume textures.
+    offscreen_renderer: Option<Renderer>,
+    volumes: BTreeMap<VolumeId, Nifti>,
+    /// The volumes streamed from URLs and directories, by volume identifier.
+    remote_volumes: HashMap<VolumeId, RemoteVolume>,
+    next_volume_id: VolumeId,
+    displayed_volume: Option<VolumeId>,
+    view: Option<SliceView>,
+    status_callback: Option<js_sys::Function>,
+    message_callback: Option<js_sys::Function>,
+    limits: ReaderLimits,
+    /// The size in pixels at which the volumes are displayed, if it was set instead of following
+    /// the size of the canvas, such as when the view is zoomed.
+    display_size: Option<usize>,
+    /// The abort flags of the reads requested through messages, by request identifier.
+    active_reads: HashMap<RequestId, Rc<Cell<bool>>>,
+    /// Whether the view changed since the last drawn frame.
+    view_pending: bool,
+    frame_requested: bool,
+    frame_stats: FrameStats,
+}
+
+/// A slice viewer, which owns its renderer, its volumes and its view state. Several independent
+/// viewers can coexist on the same page or in the same worker.
+#[wasm_bindgen]
+pub struct Viewer {
+    state: Rc<RefCell<ViewerState>>,
+}
+
+#[wasm_bindgen]
+impl Viewer {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Self {
+        utils::set_panic_hook();
+        logging::init_logger();
+        let state = Rc::new(RefCell::new(ViewerState {
+            renderer: None,
+            offscreen_renderer: None,
+            volumes: BTreeMap::new(),
+            remote_volumes: HashMap::new(),
+            next_volume_id: 0,
+            displayed_volume: None,
+            view: None,
+            status_callback: None,
+            message_callback: None,
+            limits: ReaderLimits::default(),
+            display_size: None,
+            active_reads: HashMap::new(),
+            view_pending: false,
+            frame_requested: false,
+            frame_stats: FrameStats::default(),
+        }));
+        Self { state }
+    }
+
+    /// Read an image file, whose format is found from its name, display it, and return its volume
+    /// identifier and properties. Files are read synchronously, so this must be called from a web
+    /// worker. The progress callback is called with the number of slices read and the number of
+    /// slices of the volume, and the read stops if the abort signal is aborted.
+    #[wasm_bindgen(js_name = readFile)]
+    pub fn read_file(&self, file: File, on_progress: Option<js_sys::Function>, signal: Option<web_sys::AbortSignal>) -> js_sys::Promise {
+        self.read_source(VolumeSource::File(file), on_progress, signal)
+    }
+
+    /// Read a two-file image from its header file and its data file, display it, and return its
+    /// volume identifier and properties. The header is either a `.hdr` Analyze 7.5 or NIfTI-1 header
+    /// of a `.img` file, or a detached `.nhdr` NRRD header of the data file it names, and the files
+    /// are read like in `readFile`.
+    #[wasm_bindgen(js_name = readFilePair)]
+    pub fn read_file_pair(&self, header: File, image: File, on_progress: Option<js_sys::Function>, signal: Option<web_sys::AbortSignal>) -> js_sys::Promise {
+        self.read_source(VolumeSource::FilePair(header, image), on_progress, signal)
+    }
+
+    /// Read a NumPy `.npy` or `.npz` file with a JSON sidecar file, which sets the `affine` and
+    /// `description` of the image, display it, and return its volume identifier and properties.
+    /// The files are read like in `readFile`.
+    #[wasm_bindgen(js_name = readFileWithSidecar)]
+    pub fn read_file_with_sidecar(&self, file: File, sidecar: File, on_progress: Option<js_sys::Function>, signal: Option<web_sys::AbortSignal>) -> js_sys::Promise {
+        self.read_source(VolumeSource::FileWithSidecar(file, sidecar), on_progress, signal)
+    }
+
+    /// Read an image from a URL, display it, and return its volume identifier and properties.
+    /// Uncompressed NIfTI files are streamed with range requests if the server supports them:
+    /// the first timepoint is read before the promise resolves, and the other timepoints are
+    /// fetched when they are displayed. The other files are fetched whole and read like in
+    /// `readBytes`.
+    #[wasm_bindgen(js_name = readUrl)]
+    pub fn read_url(&self, url: String, on_progress: Option<js_sys::Function>, signal: Option<web_sys::AbortSignal>) -> js_sys::Promise {
+        self.read_source(VolumeSource::Url(url), on_progress, signal)
+    }
+
+    /// Read the metadata of an OME-Zarr multiscale image from the URL of its root, display it, and
+    /// return its volume identifier and properties. The level read is the given one, or the
+    /// coarsest level that fills the display size, which is picked again when the display size
+    /// changes. The promise resolves before the voxels are read: the chunks of the displayed
+    /// timepoint are fetched once a slice is rendered, and drawn as they arrive.
+    #[wasm_bindgen(js_name = readZarrUrl)]
+    pub fn read_zarr_url(&self, url: String, level: Option<usize>, on_progress: Option<js_sys::Function>, signal: Option<web_sys::AbortSignal>) -> js_sys::Promise {
+        self.read_source(VolumeSource::Zarr(ZarrStore::Url(url), level), on_progress, signal)
+    }
+
+    /// Read an OME-Zarr multiscale image from a `FileSystemDirectoryHandle` of its root directory,
+    /// like in `readZarrUrl`.
+    #[wasm_bindgen(js_name = readZarrDirectory)]
+    pub fn read_zarr_directory(&self, directory: JsValue, level: Option<usize>, on_progress: Option<js_sys::Function>, signal: Option<web_sys::AbortSignal>) -> js_sys::Promise {
+        self.read_source(VolumeSource::Zarr(ZarrStore::Directory(directory), level), on_progress, signal)
+    }
+
+    /// Read the DICOM series of a set of files, such as the files of a directory, display the
+    /// first series, and return the volume identifiers and properties of all the series. The files
+    /// that are not DICOM images are skipped, and the progress is reported in files.
+    #[wasm_bindgen(js_name = readDicomFiles)]
+    pub fn read_dicom_files(&self, files: Vec<File>, on_progress: Option<js_sys::Function>, signal: Option<web_sys::AbortSignal>) -> js_sys::Promise {
+        let state = self.state.clone();
+        api_promise(async move {
+            let volumes = read_dicom(&state, files, get_progress_reporter(on_progress), get_abort_check(signal)).await?;
+            Ok(to_js_value(&volumes)?)
+        })
+    }
+
+    /// Read an image from bytes, which may be gzip compressed, display it, and return its volume
+    /// identifier and properties. The name hint is the file name of the image, if any, from which
+    /// its format is found.
+    #[wasm_bindgen(js_name = readBytes)]
+    pub fn read_bytes(&self, bytes: js_sys::Uint8Array, name_hint: Option<String>, on_progress: Option<js_sys::Function>, signal: Option<web_sys::AbortSignal>) -> js_sys::Promise {
+        self.read_source(VolumeSource::Bytes(bytes, name_hint), on_progress, signal)
+    }
+
+    /// Export a volume of this viewer as a single file NIfTI-1 image, and return its bytes. The
+    /// options set the compression, affine, datatype, scaling and description of the file, which
+    /// are otherwise taken from the volume.
+    #[wasm_bindgen(js_name = exportNifti)]
+    pub fn export_nifti(&self, volume_id: VolumeId, js_options: JsValue) -> ApiResult<js_sys::Uint8Array> {
+        let options = parse_write_options(js_options, "nifti")?;
+        let bytes = self.state.borrow().export_nifti(volume_id, &options)?;
+        Ok(js_sys::Uint8Array::from(bytes.as_slice()))
+    }
+
+    /// Export a volume of this viewer as a NumPy `.npy` array, or as a `.npz` archive with its
+    /// affine, and return its bytes. The options set the order, datatype and archive compression
+    /// of the array. The affine of `.npy` arrays is exported with `exportNpySidecar`.
+    #[wasm_bindgen(js_name = exportNpy)]
+    pub fn export_npy(&self, volume_id: VolumeId, js_options: JsValue) -> ApiResult<js_sys::Uint8Array> {
+        let options = parse_write_options(js_options, "npy")?;
+        let bytes = self.state.borrow().export_npy(volume_id, &options)?;
+        Ok(js_sys::Uint8Array::from(bytes.as_slice()))
+    }
+
+    /// Get the JSON sidecar of a volume of this viewer exported as a NumPy array, which carries
+    /// its affine and description.
+    #[wasm_bindgen(js_name = exportNpySidecar)]
+    pub fn export_npy_sidecar(&self, volume_id: VolumeId) -> ApiResult<String> {
+        Ok(self.state.borrow().export_npy_sidecar(volume_id)?)
+    }
+
+    /// Close a volume of this viewer and free its memory, such as the previous volume once
+    /// another one is read. Nothing is displayed until another volume is read if the volume is
+    /// displayed.
+    #[wasm_bindgen(js_name = closeVolume)]
+    pub fn close_volume(&self, volume_id: VolumeId) -> ApiResult<()> {
+        Ok(self.state.borrow_mut().close_volume(volume_id)?)
+    }
+
+    /// Set the maximum number of voxels of the volumes read by this viewer. Files whose header
+    /// declares a larger volume are rejected before it is allocated.
+    #[wasm_bindgen(js_name = setMaxVoxelCount)]
+    pub fn set_max_voxel_count(&self, count: usize) {
+        self.state.borrow_mut().limits.max_voxels = count;
+    }
+
+    /// Set the size in pixels at which the volumes are displayed, such as the largest dimension of
+    /// the canvas times the zoom factor of the view, instead of the size of the canvas. The
+    /// displayed OME-Zarr image switches to the level picked for this size, unless its level was
+    /// given when it was read, and a `volume-changed` event is sent with its new dimensions.
+    #[wasm_bindgen(js_name = setDisplaySize)]
+    pub fn set_display_size(&self, size: usize) {
+        set_display_size(&self.state, size);
+    }
+
+    /// Initiate the renderer, falling back to rendering on the CPU if neither WebGPU nor WebGL are
+    /// available, and return the backend used by the renderer.
+    #[wasm_bindgen(js_name = initRenderer)]
+    pub fn init_renderer(&self, canvas: OffscreenCanvas) -> js_sys::Promise {
+        let state = self.state.clone();
+        api_promise(async move {
+            let backend = init_renderer(&state, canvas).await?;
+            Ok(serde_wasm_bindgen::to_value(&backend)?)
+        })
+    }
+
+    /// Render a slice of the displayed volume on the next animation frame. Only the last slice
+    /// requested before a frame is rendered.
+    #[wasm_bindgen(js_name = renderSlice)]
+    pub fn render_slice(&self, js_view: JsValue) -> ApiResult<()> {
+        let view = parse_slice_view(js_view)?;
+        set_view(&self.state, view)?;
+        Ok(())
+    }
+
+    /// Get the pacing statistics of the frames drawn by this viewer.
+    #[wasm_bindgen(js_name = frameStats)]
+    pub fn frame_stats(&self) -> ApiResult<JsValue> {
+        Ok(serde_wasm_bindgen::to_value(&self.state.borrow().frame_stats)?)
+    }
+
+    /// Render a slice of the displayed volume into an image of the given size, without a canvas,
+    /// and return its RGBA8 pixels. The slice is rendered on the CPU if WebGPU is not available.
+    #[wasm_bindgen(js_name = renderSliceImage)]
+    pub fn render_slice_image(&self, width: u32, height: u32, js_view: JsValue) -> js_sys::Promise {
+        let state = self.state.clone();
+        api_promise(async move {
+            let view = parse_slice_view(js_view)?;
+            let pixels = render_slice_image(&state, width, height, view).await?;
+            Ok(js_sys::Uint8Array::from(pixels.as_slice()).into())
+        })
+    }
+
+    /// Register a callback that is called with the new status of the renderer when it changes.
+    #[wasm_bindgen(js_name = onRendererStatus)]
+    pub fn on_renderer_status(&self, callback: js_sys::Function) {
+        self.state.borrow_mut().status_callback = Some(callback);
+    }
+
+    /// Register a callback that is called with the event messages of this viewer, which are the
+    /// responses to the request messages and the renderer status changes.
+    #[wasm_bindgen(js_name = onMessage)]
+    pub fn on_message(&self, callback: js_sys::Function) {
+        self.state.borrow_mut().message_callback = Some(callback);
+    }
+
+    /// Handle a request message of the worker protocol. The response is sent to the message
+    /// callback once the request is completed.
+    #[wasm_bindgen(js_name = handleMessage)]
+    pub fn handle_message(&self, message: JsValue) {
+        let message: RequestMessage = match serde_wasm_bindgen::from_value(message) {
+            Ok(message) => message,
+            Err(error) => {
+                let error = Error::BadArgument(format!("invalid message: {}", error));
+                post_event(&self.state, Event::Error { id: None, error: (&error).into() });
+                return;
+            }
+        };
+
+        let id = message.id;
+        if message.version != PROTOCOL_VERSION {
+            let error = Error::BadArgument(format!("unsupported protocol version {}, expected {}", message.version, PROTOCOL_VERSION));
+            post_event(&self.state, Event::Error { id: Some(id), error: (&error).into() });
+            return;
+        }
+
+        let state = self.state.clone();
+        wasm_bindgen_futures::spawn_local(async move {
+            let result = match message.request {
+                Request::InitRenderer { canvas } => init_renderer(&state, canvas).await
+                    .map(|backend| Response::RendererInitialized { backend }),
+                Request::ReadFile { file } => read_request_volume(&state, id, VolumeSource::File(file)).await
+                    .map(|volume| Response::VolumeLoaded { volume }),
+                Request::ReadFilePair { header, image } => read_request_volume(&state, id, VolumeSource::FilePair(header, image)).await
+                    .map(|volume| Response::VolumeLoaded { volume }),
+                Request::ReadFileWithSidecar { file, sidecar } => read_request_volume(&state, id, VolumeSource::FileWithSidecar(file, sidecar)).await
+                    .map(|volume| Response::VolumeLoaded { volume }),
+                Request::ReadDicomFiles { files } => read_request_dicom(&state, id, files).await
+                    .map(|volumes| Response::VolumesLoaded { volumes }),
+                Request::ReadBytes { bytes, name } => read_request_volume(&state, id, VolumeSource::Bytes(bytes, name)).await
+                    .map(|volume| Response::VolumeLoaded { volume }),
+                Request::ReadUrl { url } => read_request_volume(&state, id, VolumeSource::Url(url)).await
+                    .map(|volume| Response::VolumeLoaded { volume }),
+                Request::ReadZarrUrl { url, level } => read_request_volume(&state, id, VolumeSource::Zarr(ZarrStore::Url(url), level)).await
+                    .map(|volume| Response::VolumeLoaded { volume }),
+                Request::ReadZarrDirectory { directory, level } => read_request_volume(&state, id, VolumeSource::Zarr(ZarrStore::Directory(directory), level)).await
+                    .map(|volume| Response::VolumeLoaded { volume }),
+                Request::Abort { request } => {
+                    if let Some(aborted) = state.borrow().active_reads.get(&request) {
+                        aborted.set(true);
+                    }
+                    Ok(Response::Done)
+                }
+                Request::SetMaxVoxelCount { count } => {
+                    state.borrow_mut().limits.max_voxels = count;
+                    Ok(Response::Done)
+                }
+                Request::SetDisplaySize { size } => {
+                    set_display_size(&state, size);
+                    Ok(Response::Done)
+                }
+                Request::RenderSlice { view } => set_view(&state, view)
+                    .map(|()| Response::Done),
+                Request::ExportNifti { volume, options } => state.borrow().export_nifti(volume, &options)
+                    .map(|bytes| Response::NiftiExported { bytes: js_sys::Uint8Array::from(bytes.as_slice()) }),
+                Request::ExportNpy { volume, options } => {
+                    let state = state.borrow();
+                    state.export_npy(volume, &options)
+                        .and_then(|bytes| Ok(Response::NpyExported { bytes: js_sys::Uint8Array::from(bytes.as_slice()), sidecar: state.export_npy_sidecar(volume)? }))
+                }
+                Request::CloseVolume { volume } => state.borrow_mut().close_volume(volume)
+                    .map(|()| Response::Done),
+                Request::SetLogFilter { filter } => logging::apply_log_filter(&filter).map(|_| Response::Done),
+                Request::GetFrameStats {} => Ok(Response::FrameStats { stats: state.borrow().frame_stats }),
+                Request::RenderSliceImage { width, height, view } => render_slice_image(&state, width, height, view).await
+                    .map(|pixels| Response::SliceImage { pixels: js_sys::Uint8Array::from(pixels.as_slice()) }),
+            };
+
+            let event = match result {
+                Ok(response) => Event::Response { id, response },
+                Err(error) => Event::Error { id: Some(id), error: (&error).into() },
+            };
+
+            post_event(&state, event);
+        });
+    }
+}
+
+impl Viewer {
+    fn read_source(&self, source: VolumeSource, on_progress: Option<js_sys::Function>, signal: Option<web_sys::AbortSignal>) -> js_sys::Promise {
+        let state = self.state.clone();
+        api_promise(async move {
+            let volume = read_volume(&state, source, get_progress_reporter(on_progress), get_abort_check(signal)).await?;
+            Ok(to_js_value(&volume)?)
+        })
+    }
+}
+
+impl Default for Viewer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ViewerState {
+    /// Add a volume to this viewer and display it.
+    fn add_volume(&mut self, nifti: Nifti) -> LoadedVolume {
+        let id = self.next_volume_id;
+        self.next_volume_id += 1;
+        let properties = nifti.get_properties();
+        self.volumes.insert(id, nifti);
+        self.displayed_volume = Some(id);
+
+        // The textures of the previously displayed volume are not needed anymore.
+        if let Some(SliceRenderer::Gpu(renderer)) = &mut self.renderer {
+            renderer.unload_volume();
+        }
+
+        LoadedVolume { id, properties }
+    }
+
+    /// Add a streamed volume to this viewer and display it.
+    fn add_remote_volume(&mut self, reader: RemoteReader, nifti: Nifti) -> LoadedVolume {
+        let mut volume = self.add_volume(nifti);
+        // The volume of the image only holds its displayed timepoint.
+        volume.properties.dimensions.timepoints = reader.timepoints();
+        if let RemoteReader::Zarr { intensities: (minimum, maximum), .. } = &reader {
+            (volume.properties.minimum, volume.properties.maximum) = (*minimum, *maximum);
+        }
+
+        self.remote_volumes.insert(volume.id, RemoteVolume::new(reader));
+        volume
+    }
+
+    /// Remove a volume from this viewer, with its streamed timepoints and the textures of the
+    /// volume if it is displayed. The streaming of the volume stops before its next request.
+    fn close_volume(&mut self, id: VolumeId) -> Result<(), Error> {
+        self.volumes.remove(&id).ok_or_else(|| Error::BadArgument(format!("unknown volume {}", id)))?;
+        self.remote_volumes.remove(&id);
+        if self.displayed_volume == Some(id) {
+            self.displayed_volume = None;
+            self.view = None;
+            self.view_pending = false;
+            if let Some(SliceRenderer::Gpu(renderer)) = &mut self.renderer {
+                renderer.unload_volume();
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Set the slice view of the displayed volume, which is drawn on the next frame.
+    fn set_view(&mut self, view: SliceView) -> Result<(), Error> {
+        self.validate_view(&view)?;
+        if let Some(id) = self.displayed_volume {
+            self.hold_timepoint(id, view.timepoint);
+        }
+
+        self.view = Some(view);
+        self.frame_stats.record_view(self.view_pending);
+        self.view_pending = true;
+        Ok(())
+    }
+
+    /// Draw the pending view, if any.
+    fn draw_frame(&mut self) -> Result<(), Error> {
+        self.frame_requested = false;
+        if !self.view_pending {
+            return Ok(());
+        }
+
+        self.view_pending = false;
+        let start = browser::now();
+        let result = self.draw_slice();
+        self.frame_stats.record_frame(start, browser::now() - start);
+        result
+    }
+
+    /// Check that a view is within the bounds of the displayed volume, whose timepoints are not
+    /// all held by its volume if it is streamed.
+    fn validate_view(&self, view: &SliceView) -> Result<(), Error> {
+        let (x, y, z, timepoints) = self.displayed_nifti()?.volume.dim();
+        let timepoints = self.displayed_volume
+            .and_then(|id| self.remote_volumes.get(&id))
+            .map_or(timepoints, |remote| remote.reader.timepoints());
+        view.validate_dimensions((x, y, z, timepoints))
+    }
+
+    /// Hold a timepoint of a streamed volume in the volume of its image, so that it is displayed,
+    /// and keep the volume of the previously held timepoint.
+    fn hold_timepoint(&mut self, id: VolumeId, timepoint: usize) {
+        let (Some(remote), Some(nifti)) = (self.remote_volumes.get_mut(&id), self.volumes.get_mut(&id)) else {
+            return;
+        };
+
+        if remote.timepoint == timepoint {
+            return;
+        }
+
+        let volume = remote.timepoints[timepoint].take().unwrap_or_else(|| nifti.volume.zeros_timepoint());
+        remote.timepoints[remote.timepoint] = Some(std::mem::replace(&mut nifti.volume, volume));
+        remote.timepoint = timepoint;
+
+        // The textures of the volume hold the previous timepoint.
+        if let Some(SliceRenderer::Gpu(renderer)) = &mut self.renderer {
+            renderer.unload_volume();
+        }
+    }
+
+    /// Decode the fetched data of a request of a timepoint of a streamed volume, and upload the
+    /// region read if the timepoint is displayed.
+    fn read_remote(&mut self, id: VolumeId, timepoint: usize, request: &RemoteRequest, bytes: Option<Vec<u8>>) -> Result<(), Error> {
+        let (Some(remote), Some(nifti)) = (self.remote_volumes.get_mut(&id), self.volumes.get_mut(&id)) else {
+            return Ok(());
+        };
+
+        // The requests made for the previous level of an OME-Zarr image are dropped.
+        if !remote.reader.is_next_request(timepoint, request) {
+            return Ok(());
+        }
+
+        // The timepoint may not be displayed anymore once its data is fetched.
+        let is_held = remote.timepoint == timepoint;
+        let volume = if is_held {
+            &mut nifti.volume
+        } else {
+            remote.timepoints.get_mut(timepoint)
+                .ok_or_else(|| Error::BadArgument(format!("invalid timepoint {}", timepoint)))?
+                .get_or_insert_with(|| nifti.volume.zeros_timepoint())
+        };
+
+        let region = remote.reader.read(volume, timepoint, request, bytes)?;
+        if is_held && self.displayed_volume == Some(id) {
+            if let Some(SliceRenderer::Gpu(renderer)) = &mut self.renderer {
+                renderer.update_volume_region(&nifti.volume, region);
+            }
+
+            self.view_pending = true;
+        }
+
+        Ok(())
+    }
+
+    /// Pick the level of a streamed OME-Zarr image for the display size, and get it if it is not
+    /// the displayed level. The level is only picked if it was not given when the image was read.
+    fn pick_zarr_level(&self, id: VolumeId) -> Result<Option<usize>, Error> {
+        let Some(RemoteVolume { reader: RemoteReader::Zarr { levels: Some(levels), .. }, .. }) = self.remote_volumes.get(&id) else {
+            return Ok(None);
+        };
+
+        let level = levels.multiscale.pick_level(&levels.finest_shape, self.display_size(), self.limits)?;
+        Ok((level != levels.level).then_some(level))
+    }
+
+    /// Replace the displayed level of a streamed OME-Zarr image by another level, whose chunks are
+    /// fetched like the chunks of a new image, and move the view to the same position in the new
+    /// level. Get the volume with the dimensions of the new level.
+    fn replace_zarr_level(&mut self, id: VolumeId, level: usize, reader: OmeZarrReader, nifti: Nifti) -> Result<LoadedVolume, Error> {
+        let Some(RemoteVolume { reader: RemoteReader::Zarr { reader: level_reader, intensities, levels: Some(levels), .. }, timepoint, timepoints, .. }) = self.remote_volumes.get_mut(&id) else {
+            return Err(Error::BadArgument(format!("volume {} is not a streamed ome-zarr image", id)));
+        };
+
+        // The timepoints read from the previous level are dropped, and the fetch of the displayed
+        // timepoint, if any, continues with the new level.
+        **level_reader = reader;
+        levels.level = level;
+        *timepoint = 0;
+        *timepoints = (0..level_reader.timepoints()).map(|_| None).collect();
+        let (timepoint_count, (minimum, maximum)) = (timepoints.len(), *intensities);
+
+        let previous = std::mem::replace(self.volumes.get_mut(&id).ok_or_else(|| Error::BadArgument(format!("unknown volume {}", id)))?, nifti);
+        if self.displayed_volume == Some(id) {
+            if let Some(SliceRenderer::Gpu(renderer)) = &mut self.renderer {
+                renderer.unload_volume();
+            }
+
+            if let Some(view) = &mut self.view {
+                let dimensions = self.volumes[&id].volume.dim();
+                let (previous_count, count) = (view.slice_count(previous.volume.dim()), view.slice_count(dimensions));
+                view.coordinate = (view.coordinate * count / previous_count).min(count - 1);
+                let view = *view;
+                self.hold_timepoint(id, view.timepoint);
+            }
+
+            self.view_pending = true;
+        }
+
+        let mut properties = self.volumes[&id].get_properties();
+        properties.dimensions.timepoints = timepoint_count;
+        (properties.minimum, properties.maximum) = (minimum, maximum);
+        Ok(LoadedVolume { id, properties })
+    }
+
+    /// Encode a volume of this viewer as a single file NIfTI-1 image.
+    fn export_nifti(&self, id: VolumeId, options: &NiftiWriteOptions) -> Result<Vec<u8>, Error> {
+        log::debug!("exporting volume {} as nifti", id);
+        nifti_writer::encode_nifti(self.exported_nifti(id)?, options)
+    }
+
+    /// Encode a volume of this viewer as a NumPy array.
+    fn export_npy(&self, id: VolumeId, options: &NpyWriteOptions) -> Result<Vec<u8>, Error> {
+        log::debug!("exporting volume {} as npy", id);
+        npy::encode_npy(self.exported_nifti(id)?, options)
+    }
+
+    /// Get the JSON sidecar of a volume of this viewer exported as a NumPy array.
+    fn export_npy_sidecar(&self, id: VolumeId) -> Result<String, Error> {
+        Ok(NpySidecar::from_nifti(self.nifti(id)?).to_json())
+    }
+
+    /// Get the size in pixels at which the volumes are displayed, which is the size set by the
+    /// application, or the largest dimension of the canvas of the renderer.
+    fn display_size(&self) -> usize {
+        if let Some(size) = self.display_size {
+            return size;
+        }
+
+        let (width, height) = match &self.renderer {
+            Some(SliceRenderer::Gpu(renderer)) => renderer.size(),
+            Some(SliceRenderer::Cpu(renderer)) => renderer.size(),
+            None => return DEFAULT_DISPLAY_SIZE,
+        };
+
+        width.max(height) as usize
+    }
+
+    fn nifti(&self, id: VolumeId) -> Result<&Nifti, Error> {
+        self.volumes.get(&id).ok_or_else(|| Error::BadArgument(format!("unknown volume {}", id)))
+    }
+
+    /// Get a volume of this viewer to export, whose volume must hold all its timepoints.
+    fn exported_nifti(&self, id: VolumeId) -> Result<&Nifti, Error> {
+        if let Some(remote) = self.remote_volumes.get(&id) && remote.reader.timepoints() > 1 {
+            return Err(Error::BadArgument(format!("volume {} is streamed, and its timepoints cannot be exported", id)));
+        }
+
+        self.nifti(id)
+    }
+
+    fn displayed_nifti(&self) -> Result<&Nifti, Error> {
+        self.displayed_volume
+            .and_then(|id| self.volumes.get(&id))
+            .ok_or_else(|| Error::BadArgument("no volume is loaded".to_string()))
+    }
+
+    /// Get the volume that holds the timepoint of a view of the displayed volume, with the view
+    /// of the slice in this volume.
+    fn displayed_view_volume(&self, view: SliceView) -> Result<(&Volume, SliceView), Error> {
+        let remote = self.displayed_volume.and_then(|id| self.remote_volumes.get(&id));
+        get_view_volume(self.displayed_nifti()?, remote, view)
+    }
+
+    /// Draw the current slice view of the displayed volume, and get the error of the renderer if
+    /// it failed.
+    fn draw_slice(&mut self) -> Result<(), Error> {
+        let Some(view) = self.view else {
+            return Ok(());
+        };
+
+        let Some(renderer) = &mut self.renderer else {
+            log::warn!("renderer not initialized yet");
+            return Ok(());
+        };
+
+        let Some(id) = self.displayed_volume else {
+            return Ok(());
+        };
+
+        let Some(nifti) = self.volumes.get(&id) else {
+            return Ok(());
+        };
+
+        let (volume, view) = get_view_volume(nifti, self.remote_volumes.get(&id), view)?;
+        match renderer {
+            SliceRenderer::Gpu(renderer) => {
+                // The slice is drawn once the renderer is restored.
+                if renderer.is_device_lost() {
+                    return Ok(());
+                }
+
+                renderer.update_nifti_slice(volume, view.window, view.coordinate, view.timepoint, view.axis, view.rotation);
+                renderer.render()
+            }
+            SliceRenderer::Cpu(renderer) => {
+                renderer.update_nifti_slice(volume, &view)?;
+                renderer.render()
+            }
+        }
+    }
+}
+
+/// Get the volume that holds the timepoint of a view of an image, with the view of the slice in
+/// this volume. The timepoints of a streamed image are held by volumes of a single timepoint, and
+/// the timepoints that were never fetched have none.
+fn get_view_volume<'a>(nifti: &'a Nifti, remote: Option<&'a RemoteVolume>, view: SliceView) -> Result<(&'a Volume, SliceView), Error> {
+    let Some(remote) = remote else {
+        return Ok((&nifti.volume, view));
+    };
+
+    let volume = if remote.timepoint == view.timepoint {
+        Some(&nifti.volume)
+    } else {
+        remote.timepoints.get(view.timepoint).and_then(Option::as_ref)
+    };
+
+    let volume = volume.ok_or_else(|| Error::BadArgument(format!("timepoint {} of the streamed volume is not read", view.timepoint)))?;
+    Ok((volume, SliceView { timepoint: 0, ..view }))
+}
+
+/// Report the status of the renderer of a viewer to its status callback and message callback, if
+/// any. The callbacks are called without borrowing the state, so that they can call the viewer.
+fn report_renderer_status(state: &RefCell<ViewerState>, status: RendererStatus) {
+    log::debug!("renderer status: {:?}", status);
+    post_event(state, Event::RendererStatus { status });
+    let Some(callback) = state.borrow().status_callback.clone() else {
+        return;
+    };
+
+    let Ok(status) = serde_wasm_bindgen::to_value(&status) else {
+        return;
+    };
+    if callback.call1(&JsValue::NULL, &status).is_err() {
+        log::error!("renderer status callback failed");
+    }
+}
+
+/// Report the failure of the renderer of a viewer if drawing a slice failed.
+fn report_draw_result(state: &RefCell<ViewerState>, result: Result<(), Error>) {
+    if let Err(error) = result {
+        log::error!("{}", error);
+        report_renderer_status(state, RendererStatus::Failed);
+    }
+}
+
+/// Send an event message to the message callback of a viewer, if any. The callback is called
+/// without borrowing the state, so that it can call the viewer.
+fn post_event(state: &RefCell<ViewerState>, event: Event) {
+    let Some(callback) = state.borrow().message_callback.clone() else {
+        return;
+    };
+
+    let message = EventMessage { version: PROTOCOL_VERSION, event };
+    match to_js_value(&message) {
+        Ok(message) => {
+            if callback.call1(&JsValue::NULL, &message).is_err() {
+                log::error!("message callback failed");
+            }
+        }
+        Err(error) => log::error!("could not serialize message: {}", error),
+    }
+}
+
+/// Set the slice view of a viewer, start fetching its timepoint if the volume is streamed, and
+/// request a frame to draw it if none is requested yet.
+fn set_view(state: &Rc<RefCell<ViewerState>>, view: SliceView) -> Result<(), Error> {
+    state.borrow_mut().set_view(view)?;
+    fetch_displayed_timepoint(state);
+    request_frame(state);
+    Ok(())
+}
+
+/// Set the display size of a viewer, and switch the displayed OME-Zarr image to the level picked
+/// for this size if its level is picked from the display size.
+fn set_display_size(state: &Rc<RefCell<ViewerState>>, size: usize) {
+    let mut state_ref = state.borrow_mut();
+    state_ref.display_size = Some(size);
+    let Some(id) = state_ref.displayed_volume else {
+        return;
+    };
+
+    match state_ref.pick_zarr_level(id) {
+        Ok(Some(level)) => wasm_bindgen_futures::spawn_local(change_zarr_level(state.clone(), id, level)),
+        Ok(None) => {}
+        Err(error) => log::warn!("could not pick the level of volume {}: {}", id, error),
+    }
+}
+
+/// Switch a streamed OME-Zarr image to another level once the metadata of the level is read, send
+/// the new dimensions of the volume, and fetch the displayed timepoint from the new level.
+async fn change_zarr_level(state: Rc<RefCell<ViewerState>>, id: VolumeId, level: usize) {
+    match read_zarr_level(&state, id, level).await {
+        Ok(Some(volume)) => {
+            post_event(&state, Event::VolumeChanged { volume });
+            fetch_displayed_timepoint(&state);
+            request_frame(&state);
+        }
+        Ok(None) => {}
+        Err(error) => {
+            log::error!("could not read level {} of volume {}: {}", level, id, error);
+            post_event(&state, Event::Error { id: None, error: (&error).into() });
+        }
+    }
+}
+
+/// Read the metadata of a level of a streamed OME-Zarr image and display this level, unless the
+/// display size changed in the meantime and another level is picked, in which case `None` is
+/// returned.
+async fn read_zarr_level(state: &RefCell<ViewerState>, id: VolumeId, level: usize) -> Result<Option<LoadedVolume>, Error> {
+    let (store, multiscale) = match state.borrow().remote_volumes.get(&id) {
+        Some(RemoteVolume { reader: RemoteReader::Zarr { store, levels: Some(levels), .. }, .. }) => (store.clone(), levels.multiscale.clone()),
+        _ => return Ok(None),
+    };
+
+    let array = read_zarr_array(&store, &multiscale, level).await?;
+    let mut state = state.borrow_mut();
+    if state.pick_zarr_level(id)? != Some(level) {
+        return Ok(None);
+    }
+
+    log::info!("switching to level {} of {} of the ome-zarr image for a display size of {}", level, multiscale.levels.len(), state.display_size());
+    let (reader, nifti) = OmeZarrReader::open(&multiscale, level, array, state.limits)?;
+    state.replace_zarr_level(id, level, reader, nifti).map(Some)
+}
+
+/// Request a frame to draw the pending view of a viewer, if none is requested yet.
+fn request_frame(state: &Rc<RefCell<ViewerState>>) {
+    let mut state_ref = state.borrow_mut();
+    if state_ref.frame_requested {
+        return;
+    }
+
+    state_ref.frame_requested = true;
+    let state = Rc::downgrade(state);
+    browser::request_animation_frame(move || {
+        if let Some(state) = state.upgrade() {
+            let result = state.borrow_mut().draw_frame();
+            report_draw_result(&state, result);
+        }
+    });
+}
+
+/// Start fetching the displayed timepoint of the displayed volume of a viewer, if the volume is
+/// streamed and the timepoint is not read yet.
+fn fetch_displayed_timepoint(state: &Rc<RefCell<ViewerState>>) {
+    let mut state_ref = state.borrow_mut();
+    let Some(id) = state_ref.displayed_volume else {
+        return;
+    };
+
+    let timepoint = state_ref.view.map_or(0, |view| view.timepoint);
+    if let Some(remote) = state_ref.remote_volumes.get_mut(&id)
+        && !remote.is_fetching
+        && !remote.reader.is_loaded(timepoint)
+    {
+        remote.is_fetching = true;
+        wasm_bindgen_futures::spawn_local(stream_displayed_timepoints(state.clone(), id));
+    }
+}
+
+/// Fetch the displayed timepoints of a streamed volume request by request while the volume is
+/// displayed, and draw the view again after each request. The timepoint is looked up before each
+/// request, so that the fetch follows the view when it moves to another timepoint.
+async fn stream_displayed_timepoints(state: Rc<RefCell<ViewerState>>, id: VolumeId) {
+    loop {
+        let next = {
+            let state = state.borrow();
+            let timepoint = state.view.map_or(0, |view| view.timepoint);
+            state.remote_volumes.get(&id)
+                .filter(|_| state.displayed_volume == Some(id))
+                .and_then(|remote| Some((timepoint, remote.reader.next_request(timepoint)?)))
+        };
+
+        let Some((timepoint, request)) = next else {
+            break;
+        };
+
+        let result = match request.fetch().await {
+            Ok(bytes) => state.borrow_mut().read_remote(id, timepoint, &request, bytes),
+            Err(error) => Err(error),
+        };
+
+        if let Err(error) = result {
+            log::error!("could not stream volume {}: {}", id, error);
+            post_event(&state, Event::Error { id: None, error: (&error).into() });
+            break;
+        }
+
+        request_frame(&state);
+    }
+
+    if let Some(remote) = state.borrow_mut().remote_volumes.get_mut(&id) {
+        remote.is_fetching = false;
+    }
+}
+
+/// The minimum time between two progress reports of a read, in milliseconds.
+const PROGRESS_INTERVAL: f64 = 50.0;
+
+/// The maximum size of the ranges fetched from the file of a streamed volume.
+const RANGE_CHUNK_SIZE: u64 = 4 << 20;
+
+/// The size in pixels at which the volumes are displayed when the viewer has no renderer.
+const DEFAULT_DISPLAY_SIZE: usize = 512;
+
+/// The source of a volume read by a viewer.
+enum VolumeSource {
+    File(File),
+    /// The header file and the data file of a two-file image.
+    FilePair(File, File),
+    /// A NumPy file and its JSON sidecar file.
+    FileWithSidecar(File, File),
+    Bytes(js_sys::Uint8Array, Option<String>),
+    Url(String),
+    /// An OME-Zarr image and the level to read, if it is not picked from the display size.
+    Zarr(ZarrStore, Option<usize>),
+}
+
+/// Read a volume, display it, and report the progress of the read. The read yields to the event
+/// loop between progress reports, and stops if it is aborted in the meantime.
+async fn read_volume(state: &RefCell<ViewerState>, source: VolumeSource, mut on_progress: impl FnMut(ReadProgress), is_aborted: impl Fn() -> bool) -> Result<LoadedVolume, Error> {
+    let limits = state.borrow().limits;
+    let mut sidecar = None;
+    let mut reader: Box<dyn VolumeReader> = match source {
+        VolumeSource::File(file) => {
+            let name = file.name();
+            image_reader::open_image(WebSysFile::new(file), &name, limits)
+        }
+        VolumeSource::FileWithSidecar(file, sidecar_file) => {
+            let name = file.name();
+            read_sidecar(sidecar_file)
+                .and_then(|read_sidecar| {
+                    sidecar = Some(read_sidecar);
+                    image_reader::open_image(WebSysFile::new(file), &name, limits)
+                })
+        }
+        VolumeSource::FilePair(header, image) => {
+            let name = header.name();
+            image_reader::open_image_pair(WebSysFile::new(header), WebSysFile::new(image), &name, limits)
+        }
+        VolumeSource::Bytes(bytes, name_hint) => {
+            let source = Cursor::new(bytes.to_vec());
+            image_reader::open_image(source, name_hint.as_deref().unwrap_or("<bytes>"), limits)
+        }
+        VolumeSource::Url(url) => {
+            let name = get_url_name(&url).to_string();
+            // Only uncompressed NIfTI files can be read by ranges, the other files are fetched whole.
+            let response = if name.to_ascii_lowercase().ends_with(".nii") {
+                browser::fetch_range(&url, 0..NiftiRangeReader::HEADER_SIZE).await
+            } else {
+                browser::fetch_bytes(&url).await.map(RangeResponse::Full)
+            }
+            .inspect_err(|error| log::error!("{}", error))?;
+
+            match response {
+                RangeResponse::Partial(header, file_size) => {
+                    return read_url_ranges(state, url, &name, &header.to_vec(), file_size, on_progress, is_aborted).await;
+                }
+                RangeResponse::Full(bytes) => image_reader::open_image(Cursor::new(bytes.to_vec()), &name, limits),
+            }
+        }
+        VolumeSource::Zarr(store, level) => {
+            return read_zarr(state, store, level).await
+                .inspect_err(|error| log::error!("{}", error));
+        }
+    }
+    .inspect_err(|error| log::error!("{}", error))?;
+
+    on_progress(reader.progress());
+    let mut last_report = browser::now();
+    while !reader.is_done() {
+        reader.read_slice().inspect_err(|error| log::error!("{}", error))?;
+        if browser::now() - last_report >= PROGRESS_INTERVAL {
+            on_progress(reader.progress());
+            browser::yield_now().await;
+            // The partially read volume is freed with the reader.
+            if is_aborted() {
+                log::info!("read aborted");
+                return Err(Error::Aborted);
+            }
+
+            last_report = browser::now();
+        }
+    }
+
+    on_progress(reader.progress());
+    let mut nifti = reader.finish().inspect_err(|error| log::error!("{}", error))?;
+    if let Some(sidecar) = sidecar {
+        sidecar.apply(&mut nifti);
+    }
+
+    Ok(state.borrow_mut().add_volume(nifti))
+}
+
+/// Read the first timepoint of an uncompressed NIfTI file from byte ranges of its URL, display the
+/// volume, and report the progress of the read. The other timepoints are fetched when they are
+/// displayed.
+async fn read_url_ranges(state: &RefCell<ViewerState>, url: String, name: &str, header: &[u8], file_size: Option<u64>, on_progress: impl FnMut(ReadProgress), is_aborted: impl Fn() -> bool) -> Result<LoadedVolume, Error> {
+    let limits = state.borrow().limits;
+    let (reader, mut nifti) = NiftiRangeReader::open(header, file_size, name, limits)
+        .inspect_err(|error| log::error!("{}", error))?;
+    if let Some(range) = reader.extension_range() {
+        let bytes = fetch_remote_range(&url, range).await?;
+        reader.read_extensions(&mut nifti, &bytes);
+    }
+
+    read_remote_volume(state, RemoteReader::Nifti { url, reader }, nifti, on_progress, is_aborted).await
+}
+
+/// Read the metadata of a level of an OME-Zarr image from its store, and display the volume before
+/// its voxels are read. The chunks of the displayed timepoint are fetched once its view is set,
+/// and uploaded as they arrive. The level is picked from the display size if it is not given.
+async fn read_zarr(state: &RefCell<ViewerState>, store: ZarrStore, level: Option<usize>) -> Result<LoadedVolume, Error> {
+    let (limits, display_size) = {
+        let state = state.borrow();
+        (state.limits, state.display_size())
+    };
+
+    let multiscale = read_multiscale(&store).await?;
+    let (level, array, finest_shape) = match level {
+        Some(level) => (level, read_zarr_array(&store, &multiscale, level).await?, None),
+        None => {
+            // The shapes of the other levels are estimated from the shape of the finest level.
+            let finest = read_zarr_array(&store, &multiscale, 0).await?;
+            let level = multiscale.pick_level(&finest.shape, display_size, limits)?;
+            let finest_shape = finest.shape.clone();
+            let array = if level == 0 { finest } else { read_zarr_array(&store, &multiscale, level).await? };
+            (level, array, Some(finest_shape))
+        }
+    };
+
+    log::info!("reading level {} of {} of the ome-zarr image for a display size of {}", level, multiscale.levels.len(), display_size);
+    let (reader, nifti) = OmeZarrReader::open(&multiscale, level, array, limits)?;
+    let intensities = read_zarr_intensities(&store, &multiscale, limits).await?;
+    let levels = finest_shape.map(|finest_shape| ZarrLevels { multiscale, finest_shape, level });
+    Ok(state.borrow_mut().add_remote_volume(RemoteReader::Zarr { store, reader: Box::new(reader), intensities, levels }, nifti))
+}
+
+/// Estimate the minimum and maximum intensities of an OME-Zarr image from the first timepoint of
+/// its coarsest level, which usually fits in a single chunk, so that its display window is known
+/// before its displayed level is read.
+async fn read_zarr_intensities(store: &ZarrStore, multiscale: &Multiscale, limits: ReaderLimits) -> Result<(f32, f32), Error> {
+    let level = multiscale.levels.len() - 1;
+    let array = read_zarr_array(store, multiscale, level).await?;
+    let (mut reader, mut nifti) = OmeZarrReader::open(multiscale, level, array, limits)?;
+    while let Some(chunk) = reader.next_chunk(0) {
+        let bytes = store.get(&chunk.key).await?;
+        reader.read_chunk(&mut nifti.volume, 0, &chunk, bytes)?;
+    }
+
+    Ok((nifti.get_min_intensity(), nifti.get_max_intensity()))
+}
+
+/// Read the multiscale metadata of the group of an OME-Zarr image, in the first zarr format whose
+/// group metadata is in its store.
+async fn read_multiscale(store: &ZarrStore) -> Result<Multiscale, Error> {
+    for format in ZarrFormat::ALL {
+        if let Some(json) = store.get(format.group_metadata_key()).await? {
+            return Multiscale::parse(format, &json);
+        }
+    }
+
+    Err(Error::InvalidHeader("the ome-zarr image has no zarr.json or .zattrs group metadata".to_string()))
+}
+
+/// Read the metadata of the array of a level of an OME-Zarr image.
+async fn read_zarr_array(store: &ZarrStore, multiscale: &Multiscale, level: usize) -> Result<ZarrArray, Error> {
+    let key = multiscale.array_metadata_key(level)?;
+    let json = store.get(&key).await?
+        .ok_or_else(|| Error::InvalidHeader(format!("the ome-zarr image has no array metadata {}", key)))?;
+    ZarrArray::parse(multiscale.format, &json)
+}
+
+/// Read the first timepoint of a streamed volume, display the volume, and report the progress of
+/// the read. The read stops if it is aborted between two requests.
+async fn read_remote_volume(state: &RefCell<ViewerState>, mut reader: RemoteReader, mut nifti: Nifti, mut on_progress: impl FnMut(ReadProgress), is_aborted: impl Fn() -> bool) -> Result<LoadedVolume, Error> {
+    on_progress(reader.progress(0));
+    let mut last_report = browser::now();
+    while let Some(request) = reader.next_request(0) {
+        let bytes = request.fetch().await?;
+        reader.read(&mut nifti.volume, 0, &request, bytes).inspect_err(|error| log::error!("{}", error))?;
+        if is_aborted() {
+            log::info!("read aborted");
+            return Err(Error::Aborted);
+        }
+
+        if browser::now() - last_report >= PROGRESS_INTERVAL {
+            on_progress(reader.progress(0));
+            last_report = browser::now();
+        }
+    }
+
+    on_progress(reader.progress(0));
+    Ok(state.borrow_mut().add_remote_volume(reader, nifti))
+}
+
+/// Fetch a byte range of the file of a streamed volume, which must be served whole.
+async fn fetch_remote_range(url: &str, range: Range<u64>) -> Result<Vec<u8>, Error> {
+    let size = range.end - range.start;
+    match browser::fetch_range(url, range).await? {
+        RangeResponse::Partial(bytes, _) if bytes.length() as u64 == size => Ok(bytes.to_vec()),
+        RangeResponse::Partial(bytes, _) => Err(Error::Network(format!("expected {} bytes from {}, received {}", size, url, bytes.length()))),
+        RangeResponse::Full(_) => Err(Error::Network(format!("the server of {} stopped supporting range requests", url))),
+    }
+}
+
+/// Get the file name of a URL, without its query and fragment, from which the format of its image
+/// is found.
+fn get_url_name(url: &str) -> &str {
+    let path = url.split(['?', '#']).next().unwrap_or(url);
+    path.rsplit('/').next().unwrap_or(path)
+}
+
+/// Read a volume requested by a message, which reports its progress with events and can be
+/// aborted by another message.
+async fn read_request_volume(state: &RefCell<ViewerState>, id: RequestId, source: VolumeSource) -> Result<LoadedVolume, Error> {
+    let aborted = Rc::new(Cell::new(false));
+    state.borrow_mut().active_reads.insert(id, aborted.clone());
+    let report_progress = |progress: ReadProgress| {
+        post_event(state, Event::Progress { id, loaded: progress.slices_read, total: progress.slice_count });
+    };
+
+    let result = read_volume(state, source, report_progress, || aborted.get()).await;
+    state.borrow_mut().active_reads.remove(&id);
+    result
+}
+
+/// Read the DICOM series of a set of files, display the first series, and report the progress of
+/// the read in files. The read yields to the event loop between progress reports, and stops if it
+/// is aborted in the meantime.
+async fn read_dicom(state: &RefCell<ViewerState>, files: Vec<File>, mut on_progress: impl FnMut(ReadProgress), is_aborted: impl Fn() -> bool) -> Result<Vec<LoadedVolume>, Error> {
+    let limits = state.borrow().limits;
+    let file_count = files.len();
+    let mut instances = Vec::new();
+    let mut last_error = None;
+    on_progress(ReadProgress { slices_read: 0, slice_count: file_count });
+    let mut last_report = browser::now();
+    for (i, file) in files.into_iter().enumerate() {
+        let name = file.name();
+        let mut bytes = Vec::new();
+        WebSysFile::new(file).read_to_end(&mut bytes)?;
+        // Directories usually contain other files than images, such as DICOMDIR indexes.
+        match DicomInstance::parse(&bytes, limits) {
+            Ok(instance) => instances.push(instance),
+            Err(error) => {
+                log::warn!("skipping {}: {}", name, error);
+                last_error = Some(error);
+            }
+        }
+
+        if browser::now() - last_report >= PROGRESS_INTERVAL {
+            on_progress(ReadProgress { slices_read: i + 1, slice_count: file_count });
+            browser::yield_now().await;
+            if is_aborted() {
+                log::info!("read aborted");
+                return Err(Error::Aborted);
+            }
+
+            last_report = browser::now();
+        }
+    }
+
+    if instances.is_empty() {
+        return Err(last_error.unwrap_or_else(|| Error::BadArgument("no files to read".to_string())));
+    }
+
+    let series = dicom_reader::read_dicom_series(instances, limits).inspect_err(|error| log::error!("{}", error))?;
+    on_progress(ReadProgress { slices_read: file_count, slice_count: file_count });
+    let mut state = state.borrow_mut();
+    let volumes: Vec<LoadedVolume> = series.into_iter().map(|series| state.add_volume(series.nifti)).collect();
+    state.displayed_volume = volumes.first().map(|volume| volume.id);
+    Ok(volumes)
+}
+
+/// Read the DICOM series of a set of files requested by a message, which reports its progress
+/// with events and can be aborted by another message.
+async fn read_request_dicom(state: &RefCell<ViewerState>, id: RequestId, files: js_sys::Array) -> Result<Vec<LoadedVolume>, Error> {
+    let files = files.iter()
+        .map(|file| file.dyn_into::<File>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| Error::BadArgument("expected an array of files".to_string()))?;
+
+    let aborted = Rc::new(Cell::new(false));
+    state.borrow_mut().active_reads.insert(id, aborted.clone());
+    let report_progress = |progress: ReadProgress| {
+        post_event(state, Event::Progress { id, loaded: progress.slices_read, total: progress.slice_count });
+    };
+
+    let result = read_dicom(state, files, report_progress, || aborted.get()).await;
+    state.borrow_mut().active_reads.remove(&id);
+    result
+}
+
+/// Get a function that reports the progress of a read to an optional JavaScript callback, which is
+/// called with the number of slices read and the number of slices.
+fn get_progress_reporter(on_progress: Option<js_sys::Function>) -> impl FnMut(ReadProgress) {
+    move |progress: ReadProgress| {
+        let Some(on_progress) = &on_progress else {
+            return;
+        };
+
+        if on_progress.call2(&JsValue::NULL, &progress.slices_read.into(), &progress.slice_count.into()).is_err() {
+            log::error!("progress callback failed");
+        }
+    }
+}
+
+/// Get a function that checks whether an optional abort signal is aborted.
+fn get_abort_check(signal: Option<web_sys::AbortSignal>) -> impl Fn() -> bool {
+    move || signal.as_ref().is_some_and(|signal| signal.aborted())
+}
+
+/// Initiate the renderer of a viewer, falling back to rendering on the CPU if neither WebGPU nor
+/// WebGL are available.
+async fn init_renderer(state: &Rc<RefCell<ViewerState>>, canvas: OffscreenCanvas) -> Result<RendererBackend, Error> {
+    let viewer = Rc::downgrade(state);
+    let device_lost_handler = Rc::new(move || handle_device_lost(&viewer));
+    let renderer = match Renderer::new(canvas.clone(), Some(device_lost_handler)).await {
+        Ok(renderer) => SliceRenderer::Gpu(Box::new(renderer)),
+        Err(error) => {
+            log::error!("{}", error);
+            log::info!("falling back to cpu rendering");
+            let renderer = CanvasRenderer::new(canvas)
+                .inspect_err(|error| log::error!("{}", error))?;
+            SliceRenderer::Cpu(renderer)
+        }
+    };
+
+    let backend = match &renderer {
+        SliceRenderer::Gpu(renderer) => renderer.backend(),
+        SliceRenderer::Cpu(_) => RendererBackend::Cpu,
+    };
+
+    log::debug!("using the {:?} backend", backend);
+    state.borrow_mut().renderer = Some(renderer);
+    Ok(backend)
+}
+
+/// Render a slice of the displayed volume of a viewer into an image of the given size, and return
+/// its RGBA8 pixels.
+async fn render_slice_image(state: &RefCell<ViewerState>, width: u32, height: u32, view: SliceView) -> Result<Vec<u8>, Error> {
+    if width == 0 || height == 0 {
+        return Err(Error::BadArgument(format!("invalid image size {}x{}", width, height)));
+    }
+    state.borrow().validate_view(&view)?;
+
+    // A new adapter and device are only requested when the size of the images changes.
+    let cached_renderer = state.borrow_mut().offscreen_renderer.take()
+        .filter(|renderer| renderer.size() == (width, height) && !renderer.is_device_lost());
+    let mut renderer = match cached_renderer {
+        Some(renderer) => renderer,
+        None => match Renderer::new_offscreen(width, height).await {
+            Ok(renderer) => renderer,
+            Err(error) => {
+                log::error!("{}", error);
+                log::info!("falling back to cpu rendering");
+                let state = state.borrow();
+                let (volume, view) = state.displayed_view_volume(view)?;
+                return cpu_renderer::render_slice(volume, width, height, &view);
+            }
+        },
+    };
+
+    let pixels = async {
+        {
+            let state = state.borrow();
+            let (volume, view) = state.displayed_view_volume(view)?;
+            renderer.update_nifti_slice(volume, view.window, view.coordinate, view.timepoint, view.axis, view.rotation);
+        }
+
+        renderer.render()?;
+        renderer.read_pixels().await
+    }.await;
+
+    // The volume may change or be closed before the next image, so its textures are not kept.
+    renderer.unload_volume();
+    state.borrow_mut().offscreen_renderer = Some(renderer);
+    pixels
+}
+
+/// Read the JSON sidecar file of a NumPy array.
+fn read_sidecar(file: File) -> Result<NpySidecar, Error> {
+    let mut json = String::new();
+    WebSysFile::new(file).read_to_string(&mut json)?;
+    NpySidecar::parse(&json)
+}
+
+/// Parse the options of an exported image in a format, which are all optional.
+fn parse_write_options<T: Default + serde::de::DeserializeOwned>(js_options: JsValue, format: &str) -> Result<T, Error> {
+    if js_options.is_undefined() || js_options.is_null() {
+        return Ok(T::default());
+    }
+
+    serde_wasm_bindgen::from_value(js_options)
+        .map_err(|error| Error::BadArgument(format!("invalid {} options: {}", format, error)))
+}
+
+/// Convert a value returned by the API or sent in a message to JavaScript, with its maps, such as
+/// the decoded JSON extensions of the volumes, converted to plain objects.
+fn to_js_value<T: Serialize>(value: &T) -> Result<JsValue, serde_wasm_bindgen::Error> {
+    value.serialize(&serde_wasm_bindgen::Serializer::new().serialize_maps_as_objects(true))
+}
+
+/// Parse a slice view passed to the API.
+fn parse_slice_view(js_view: JsValue) -> Result<SliceView, Error> {
+    serde_wasm_bindgen::from_value(js_view)
+        .map_err(|error| Error::BadArgument(format!("invalid slice view: {}", error)))
+}
+
+/// Handle the loss of the GPU device of the renderer of a viewer, which can happen if the GPU is
+/// reset. Nothing is done if the viewer was dropped.
+fn handle_device_lost(viewer: &Weak<RefCell<ViewerState>>) {
+    let Some(state) = viewer.upgrade() else {
+        return;
+    };
+
+    report_renderer_status(&state, RendererStatus::Lost);
+    wasm_bindgen_futures::spawn_local(restore_renderer(state));
+}
+
+/// Restore the renderer of a viewer after its GPU device was lost, and draw the current slice
+/// again.
+async fn restore_renderer(state: Rc<RefCell<ViewerState>>) {
+    // Take the renderer out of the viewer while it is being restored, slices requested in the
+    // meantime are not rendered.
+    let Some(mut renderer) = state.borrow_mut().renderer.take() else {
+        return;
+    };
+
+    let result = match &mut renderer {
+        SliceRenderer::Gpu(renderer) => renderer.restore().await,
+        SliceRenderer::Cpu(_) => Ok(()),
+    };
+
+    state.borrow_mut().renderer = Some(renderer);
+    match result {
+        Ok(()) => {
+            report_renderer_status(&state, RendererStatus::Restored);
+            let result = state.borrow_mut().draw_slice();
+            report_draw_result(&state, result);
+        }
+        Err(error) => {
+            log::error!("could not restore renderer: {}", error);
+            report_renderer_status(&state, RendererStatus::Failed);
+        }
+    }
+}
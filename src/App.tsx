@@ -2,20 +2,60 @@ import "./App.css";
 import { useEffect, useRef, useState } from "react";
 import NiftiFileWorker from './worker?worker';
 import Controls from "./Controls";
-import { createViewerState, getCoordinate, NiftiProperties, ViewerState } from "./types";
+import { createViewerState, getCoordinate, RendererStatus, scalePoint, ViewerState } from "./types";
+import { createRequest, EventMessage, LogRecord, Request } from "./protocol";
 import Pane from "./Pane";
-import FileLoader from "./FileLoader";
-
-type WorkerMessage =
-  | {action: 'init-renderer', result: string | null}
-  | {action: 'read-file', properties: NiftiProperties}
+import FileLoader, { ImageFiles } from "./FileLoader";
 
 /** Web worker that handles the loading and reading of NIfTI files. */
 export const worker = new NiftiFileWorker();
 
+/** Actions of the requests sent to the worker that are not answered yet, by request identifier. */
+const pendingActions = new Map<number, string>();
+
+/** Maximum number of log records of the viewer kept for bug reports. */
+const MAX_LOG_RECORDS = 1000;
+
+/** Last log records of the viewer, from the oldest to the newest. */
+const logRecords: LogRecord[] = [];
+
+/** Get the last log records of the viewer, to attach them to a bug report. */
+export function getLogRecords(): readonly LogRecord[] {
+  return logRecords;
+}
+
+/** Send a request to the viewer of the worker, and return its identifier. */
+export function sendRequest(request: Request, transfer: Transferable[] = []): number {
+  const message = createRequest(request);
+  pendingActions.set(message.id, Object.keys(request)[0]);
+  worker.postMessage(message, transfer);
+  return message.id;
+}
+
+/** The progress of the file being read, in slices. */
+type LoadProgress = {
+  id: number,
+  loaded: number,
+  total: number,
+}
+
+/** Download bytes as a file. */
+function downloadBytes(bytes: Uint8Array, name: string) {
+  const url = URL.createObjectURL(new Blob([bytes], {type: 'application/octet-stream'}));
+  const link = document.createElement('a');
+  link.href = url;
+  link.download = name;
+  link.click();
+  URL.revokeObjectURL(url);
+}
+
 export default function App() {
   let [state, setState] = useState<ViewerState | null>(null);
+  let [load, setLoad] = useState<LoadProgress | null>(null);
+  let [volumeId, setVolumeId] = useState<number | null>(null);
   const stateRef = useRef(state);
+  // The volumes of the last read, which are closed once another read succeeds.
+  const volumeIdsRef = useRef<number[]>([]);
 
   // Keep a reference to the state to use in the worker message reception closure.
   useEffect(() => {
@@ -23,30 +63,108 @@ export default function App() {
   });
 
   useEffect(() => {
-    worker.onmessage = (event: MessageEvent<WorkerMessage>) => {
-      switch (event.data.action) {
-        case 'read-file':
-          setState(createViewerState(event.data.properties));
+    /** Close the volumes of the previous read, and keep the volumes of the last one. */
+    function replaceVolumes(volumeIds: number[]) {
+      for (const volume of volumeIdsRef.current) {
+        sendRequest({'close-volume': {volume}});
+      }
+      volumeIdsRef.current = volumeIds;
+    }
+
+    worker.onmessage = ({data: {event}}: MessageEvent<EventMessage>) => {
+      switch (event.type) {
+        case 'response':
+          pendingActions.delete(event.id);
+          switch (event.response.kind) {
+            case 'volume-loaded':
+              setLoad(null);
+              replaceVolumes([event.response.volume.id]);
+              setVolumeId(event.response.volume.id);
+              setState(createViewerState(event.response.volume.properties));
+              break;
+            case 'volumes-loaded':
+              // The first series of a DICOM read is displayed.
+              setLoad(null);
+              replaceVolumes(event.response.volumes.map(volume => volume.id));
+              setVolumeId(event.response.volumes[0].id);
+              setState(createViewerState(event.response.volumes[0].properties));
+              break;
+            case 'nifti-exported':
+              downloadBytes(event.response.bytes, 'volume.nii.gz');
+              break;
+            case 'npy-exported':
+              downloadBytes(event.response.bytes, 'volume.npz');
+              break;
+            case 'renderer-initialized':
+              if (stateRef.current === null) {
+                return;
+              }
+
+              console.debug(`[renderer] using the ${event.response.backend} backend`);
+              setState({...stateRef.current, rendererInitialied: true, rendererBackend: event.response.backend});
+              break;
+          }
           break;
-        case 'init-renderer':
+        case 'error': {
+          const action = event.id !== null ? pendingActions.get(event.id) : undefined;
+          if (event.id !== null) {
+            pendingActions.delete(event.id);
+          }
+
+          switch (action) {
+            case 'read-file':
+            case 'read-file-pair':
+            case 'read-file-with-sidecar':
+            case 'read-dicom-files':
+            case 'read-url':
+            case 'read-zarr-url':
+            case 'read-zarr-directory':
+              setLoad(null);
+              if (event.error.code === 'aborted') {
+                break;
+              }
+
+              alert(`The file could not be read: ${event.error.message}`);
+              break;
+            case 'init-renderer':
+              alert(`The renderer could not be initialized: ${event.error.message}`);
+              break;
+            case 'export-nifti':
+            case 'export-npy':
+              alert(`The volume could not be exported: ${event.error.message}`);
+              break;
+            default:
+              console.error(`[viewer] ${event.error.code}: ${event.error.message}`);
+          }
+          break;
+        }
+        case 'progress':
+          setLoad({id: event.id, loaded: event.loaded, total: event.total});
+          break;
+        case 'log':
+          logRecords.push(event.record);
+          if (logRecords.length > MAX_LOG_RECORDS) {
+            logRecords.shift();
+          }
+          break;
+        case 'renderer-status':
           if (stateRef.current === null) {
             return;
           }
 
-          if (event.data.result !== null) {
-            alert(
-              "WebGPU is required to run this application but does not seem to be supported by "
-              + "your web browser yet. WebGPU should be enabled by default on Chromium-based "
-              + "browsers (Google Chrome, Microsoft Edge...) on all operating systems, and  "
-              + "Mozilla Firefox on Windows. For other browsers or operating systems, WebGPU "
-              + "should be enable-able as an experimental feature in the browser configuration."
-            );
-
+          setState({...stateRef.current, rendererStatus: event.status});
+          break;
+        case 'volume-changed': {
+          // The displayed OME-Zarr image switched to the level picked for the display size.
+          if (stateRef.current === null) {
             return;
           }
 
-          setState({...stateRef.current, rendererInitialied: true});
+          const {dimensions} = event.volume.properties;
+          const focalPoint = scalePoint(stateRef.current.focalPoint, stateRef.current.dimensions, dimensions);
+          setState({...stateRef.current, dimensions, focalPoint});
           break;
+        }
       }
     }
   }, [])
@@ -56,25 +174,62 @@ export default function App() {
       return;
     }
 
-    worker.postMessage({
-      action: 'render-slice',
+    sendRequest({'render-slice': {view: {
       axis: state.axis,
       coordinate: getCoordinate(state.focalPoint, state.axis),
       timepoint: state.focalPoint.t,
       window: state.window,
       rotation: state.rotation,
-    });
+    }}});
 
   }, [state]);
 
-  function handleFileLoaded(file: File) {
-    worker.postMessage({ action: 'read-file', file });
+  function handleFileLoaded(files: ImageFiles) {
+    const request: Request = 'sidecar' in files ? {'read-file-with-sidecar': files}
+      : 'file' in files ? {'read-file': {file: files.file}}
+      : 'dicom' in files ? {'read-dicom-files': {files: files.dicom}}
+      : 'url' in files ? {'read-url': {url: files.url}}
+      : 'zarrUrl' in files ? {'read-zarr-url': {url: files.zarrUrl}}
+      : 'zarr' in files ? {'read-zarr-directory': {directory: files.zarr}}
+      : {'read-file-pair': files};
+    const id = sendRequest(request);
+    setLoad({id, loaded: 0, total: 0});
+  }
+
+  function handleExport() {
+    if (volumeId !== null) {
+      sendRequest({'export-nifti': {volume: volumeId, options: {compress: true}}});
+    }
+  }
+
+  function handleExportNpy() {
+    if (volumeId !== null) {
+      sendRequest({'export-npy': {volume: volumeId, options: {archive: true, compress: true}}});
+    }
+  }
+
+  function handleLoadCanceled() {
+    if (load !== null) {
+      sendRequest({'abort': {request: load.id}});
+    }
   }
 
   return (
     <div id="app">
       <header id="header">
         <h1 className="app-title">Brain Render</h1>
+        {volumeId !== null && (
+          <div className="export-buttons">
+            <button onClick={handleExport}>Export NIfTI</button>
+            <button onClick={handleExportNpy}>Export NumPy</button>
+          </div>
+        )}
+        {state?.rendererStatus === RendererStatus.Lost && (
+          <p className="renderer-status">The GPU was reset, restoring the renderer...</p>
+        )}
+        {state?.rendererStatus === RendererStatus.Failed && (
+          <p className="renderer-status">The renderer failed, please reload the page.</p>
+        )}
       </header>
       {state !== null ? (
         <main className="viewer">
@@ -83,7 +238,14 @@ export default function App() {
         </main>
        ) : (
         <main className="loader">
-          <FileLoader onFileLoaded={handleFileLoaded} />
+          {load !== null ? (
+            <div className="load-progress">
+              <progress value={load.loaded} max={load.total || 1} />
+              <button onClick={handleLoadCanceled}>Cancel</button>
+            </div>
+          ) : (
+            <FileLoader onFileLoaded={handleFileLoaded} />
+          )}
         </main>
       )}
     </div>
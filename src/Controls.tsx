@@ -16,6 +16,7 @@ export default function Controls({state, setState}: {
         id="window-level-slider"
         name="Window level (brightness)"
         value={state.window.level}
+        min={state.window.minimum}
         max={state.window.maximum}
         update={(level) => setState({...state, window: {...state.window, level }})}
       />
@@ -23,7 +24,7 @@ export default function Controls({state, setState}: {
         id="window-width-slider"
         name="Window width (contrast)"
         value={state.window.width}
-        max={state.window.maximum}
+        max={state.window.maximum - state.window.minimum}
         update={(width) => setState({...state, window: {...state.window, width }})}
       />
       <div className={styles.axisButtons}>
@@ -168,10 +169,11 @@ function AxisSlider({axis, state, setState}: {
   );
 }
 
-function Slider({id, name, value, max, update}: {
+function Slider({id, name, value, min = 0, max, update}: {
   id: string,
   name: string,
   value: number,
+  min?: number,
   max: number,
   update: (value: number) => void,
 }) {
@@ -189,7 +191,7 @@ function Slider({id, name, value, max, update}: {
       const delta = Math.sign(event.deltaY); // -1 for scroll up, 1 for scroll down
       const newValue = value - delta; // Invert so scroll up increases, scroll down decreases
 
-      const clampedValue = clamp(0, max, newValue);
+      const clampedValue = clamp(min, max, newValue);
 
       // Only update if the value actually changed
       if (clampedValue !== value) {
@@ -202,7 +204,7 @@ function Slider({id, name, value, max, update}: {
     return () => {
       input.removeEventListener('wheel', handleWheel);
     };
-  }, [value, max, update]);
+  }, [value, min, max, update]);
 
   function handleChange(event: React.ChangeEvent<HTMLInputElement>) {
     update(parseInt(event.target.value));
@@ -215,7 +217,7 @@ function Slider({id, name, value, max, update}: {
         ref={inputRef}
         id={id}
         type="range"
-        min={0}
+        min={min}
         max={max}
         value={value}
         onChange={handleChange}
@@ -1,4 +1,4 @@
-import { ChangeEvent, useState } from "react";
+import { ChangeEvent, FormEvent, useState } from "react";
 import { formatFileSize } from "./util";
 
 type DemoFile = {
@@ -8,16 +8,125 @@ type DemoFile = {
 
 declare const DEMO_FILES: DemoFile[];
 
-export default function FileLoader({onFileLoaded}: {onFileLoaded: (file: File) => void}) {
+declare global {
+  interface Window {
+    /** The directory picker of the File System Access API, which not all browsers support. */
+    showDirectoryPicker?: () => Promise<FileSystemDirectoryHandle>;
+  }
+}
+
+/** Image selected by the user, which is either a single file, a NumPy file and its JSON sidecar,
+ * a `.hdr`/`.img` pair of files, a detached `.nhdr` NRRD header and its data file, the files of a
+ * DICOM series, the URL of a file, or an OME-Zarr image from its URL or its directory. */
+export type ImageFiles =
+  | {file: File}
+  | {file: File, sidecar: File}
+  | {header: File, image: File}
+  | {dicom: File[]}
+  | {url: string}
+  | {zarrUrl: string}
+  | {zarr: FileSystemDirectoryHandle}
+
+/** Get the name of a file without its `.hdr` or `.img` extension, if it is part of a file pair. */
+function getPairStem(name: string): string | null {
+  const match = name.match(/^(.*)\.(hdr|img)(\.gz)?$/);
+  return match !== null ? match[1] : null;
+}
+
+/** Find the data file of a detached NRRD header among the selected files, from the name in its
+ * header. */
+async function findNrrdFiles(header: File, files: File[]): Promise<ImageFiles | string> {
+  const dataFile = (await header.text()).match(/^data ?file: *(.*?)\s*$/m)?.[1];
+  if (dataFile === undefined) {
+    return {file: header};
+  }
+
+  const name = dataFile.split('/').pop();
+  const image = files.find(file => file.name === name);
+  if (image === undefined) {
+    return `Please select both ${header.name} and its data file ${dataFile}.`;
+  }
+
+  return {header, image};
+}
+
+/** Find the image of the selected files, pairing a `.hdr` file with its `.img` file, a `.nhdr`
+ * file with its data file, and a `.npy`/`.npz` file with the `.json` sidecar of the same name. */
+async function findImageFiles(files: File[]): Promise<ImageFiles | string> {
+  const npyFile = files.find(file => file.name.match(/\.np[yz]$/));
+  if (npyFile !== undefined) {
+    const sidecarName = npyFile.name.replace(/\.np[yz]$/, '.json');
+    const sidecar = files.find(file => file.name === sidecarName);
+    return sidecar !== undefined ? {file: npyFile, sidecar} : {file: npyFile};
+  }
+
+  const nrrdHeader = files.find(file => file.name.endsWith('.nhdr'));
+  if (nrrdHeader !== undefined) {
+    return findNrrdFiles(nrrdHeader, files);
+  }
+
+  const stem = files.map(file => getPairStem(file.name)).find(stem => stem !== null);
+  if (stem === undefined) {
+    // DICOM files often have no extension, so any other set of files is read as DICOM.
+    return files.length > 1 || files[0].name.endsWith('.dcm') ? {dicom: files} : {file: files[0]};
+  }
+
+  const header = files.find(file => file.name.match(/\.hdr(\.gz)?$/) && getPairStem(file.name) === stem);
+  const image = files.find(file => file.name.match(/\.img(\.gz)?$/) && getPairStem(file.name) === stem);
+  if (header === undefined || image === undefined) {
+    return `Please select both the .hdr and the .img files of ${stem}.`;
+  }
+
+  return {header, image};
+}
+
+export default function FileLoader({onFileLoaded}: {onFileLoaded: (files: ImageFiles) => void}) {
   const [isLoading, setIsLoading] = useState(false);
   const [downloadProgress, setDownloadProgress] = useState(0);
+  const [url, setUrl] = useState('');
 
   async function handleFileChange(e: ChangeEvent<HTMLInputElement>) {
-    if (e.target.files === null) {
+    if (e.target.files === null || e.target.files.length === 0) {
+      return;
+    }
+
+    const files = await findImageFiles(Array.from(e.target.files));
+    if (typeof files === 'string') {
+      alert(files);
       return;
     }
 
-    onFileLoaded(e.target.files[0]);
+    onFileLoaded(files);
+  }
+
+  function handleDirectoryChange(e: ChangeEvent<HTMLInputElement>) {
+    if (e.target.files === null || e.target.files.length === 0) {
+      return;
+    }
+
+    onFileLoaded({dicom: Array.from(e.target.files)});
+  }
+
+  function handleUrlSubmit(e: FormEvent<HTMLFormElement>) {
+    e.preventDefault();
+    const trimmedUrl = url.trim();
+    if (trimmedUrl === '') {
+      return;
+    }
+
+    // OME-Zarr images are directories, which are only recognized from their extension.
+    onFileLoaded(trimmedUrl.match(/\.zarr\/?$/) ? {zarrUrl: trimmedUrl} : {url: trimmedUrl});
+  }
+
+  async function handleZarrDirectoryClick() {
+    try {
+      onFileLoaded({zarr: await window.showDirectoryPicker!()});
+    } catch (error) {
+      // The picker is rejected with an AbortError when the user cancels it.
+      if (!(error instanceof DOMException && error.name === 'AbortError')) {
+        console.error('Failed to open directory:', error);
+      }
+    }
   }
 
   async function handleLoadDemoFile(file: DemoFile) {
@@ -53,7 +162,7 @@ export default function FileLoader({onFileLoaded}: {onFileLoaded: (file: File) =
       const blob = new Blob(chunks, { type: 'application/octet-stream' });
       const demoFile = new File([blob], file.name, { type: 'application/octet-stream' });
 
-      onFileLoaded(demoFile);
+      onFileLoaded({file: demoFile});
     } catch (error) {
       console.error('Failed to load demo file:', error);
     } finally {
@@ -70,10 +179,39 @@ export default function FileLoader({onFileLoaded}: {onFileLoaded: (file: File) =
           <input
             type="file"
             disabled={isLoading}
+            multiple
             onChange={handleFileChange}
-            accept=".nii,.nii.gz"
+            accept=".nii,.nii.gz,.hdr,.img,.hdr.gz,.img.gz,.dcm,.mgh,.mgz,.mnc,.nrrd,.nhdr,.raw,.raw.gz,.npy,.npz,.json"
+          />
+        </div>
+        <h3>Use DICOM directory</h3>
+        <div className="custom-file">
+          <input
+            type="file"
+            disabled={isLoading}
+            onChange={handleDirectoryChange}
+            {...{webkitdirectory: ''}}
           />
         </div>
+        <h3>Use URL</h3>
+        <form className="custom-file" onSubmit={handleUrlSubmit}>
+          <input
+            type="url"
+            disabled={isLoading}
+            placeholder="http://localhost:8000/image.nii or image.zarr"
+            value={url}
+            onChange={e => setUrl(e.target.value)}
+          />
+          <button type="submit" disabled={isLoading || url.trim() === ''}>Load</button>
+        </form>
+        {window.showDirectoryPicker !== undefined && (
+          <>
+            <h3>Use OME-Zarr directory</h3>
+            <div className="custom-file">
+              <button type="button" disabled={isLoading} onClick={handleZarrDirectoryClick}>Open directory</button>
+            </div>
+          </>
+        )}
       </div>
       <div className="demo-file-loader">
         <h3>Use demonstration files</h3>
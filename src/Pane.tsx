@@ -1,5 +1,5 @@
 import { getCoordinate, getDimension, setCoordinate, ViewerState } from "./types";
-import { worker } from "./App";
+import { sendRequest } from "./App";
 import { useCallback, useEffect, useRef } from "react";
 import { clamp } from "./util";
 
@@ -15,10 +15,7 @@ export default function Pane({state, setState}: {
     }
 
     const offscreen = canvasRef.transferControlToOffscreen();
-    worker.postMessage({
-      action: 'init-renderer',
-      canvas: offscreen
-    }, [offscreen]);
+    sendRequest({'init-renderer': {canvas: offscreen}}, [offscreen]);
   }, []);
 
   useEffect(() => {
@@ -0,0 +1,110 @@
+import { DisplayWindow, AnatomicalAxis, LoadedVolume, RendererBackend, RendererStatus, Rotation, ViewerError, VoxelType } from "./types";
+
+/** Version of the message protocol of the viewer, which must match the version of the WebAssembly module. */
+export const PROTOCOL_VERSION = 1;
+
+export type SliceView = {
+  axis: AnatomicalAxis,
+  coordinate: number,
+  timepoint: number,
+  window: DisplayWindow,
+  rotation: Rotation,
+}
+
+/** Options of an exported NIfTI-1 image, whose fields default to the properties of the volume. The affine is the rows of a 4x4 matrix. */
+export type NiftiWriteOptions = {
+  compress?: boolean,
+  affine?: number[][],
+  datatype?: VoxelType,
+  scaling?: {Linear: {slope: number, intercept: number}} | 'Fit',
+  description?: string,
+}
+
+/** Options of an exported NumPy array. Arrays are stored in C order unless `fortran_order` is set, and `archive` exports a `.npz` archive with the affine of the volume. */
+export type NpyWriteOptions = {
+  fortran_order?: boolean,
+  datatype?: VoxelType,
+  archive?: boolean,
+  compress?: boolean,
+}
+
+/** Requests to the viewer, which are tagged by their action. */
+export type Request =
+  | {'init-renderer': {canvas: OffscreenCanvas}}
+  | {'read-file': {file: File}}
+  | {'read-file-pair': {header: File, image: File}}
+  | {'read-file-with-sidecar': {file: File, sidecar: File}}
+  | {'read-dicom-files': {files: File[]}}
+  | {'read-bytes': {bytes: Uint8Array, name: string | null}}
+  | {'read-url': {url: string}}
+  | {'read-zarr-url': {url: string, level?: number}}
+  | {'read-zarr-directory': {directory: FileSystemDirectoryHandle, level?: number}}
+  | {'set-max-voxel-count': {count: number}}
+  | {'set-display-size': {size: number}}
+  | {'render-slice': {view: SliceView}}
+  | {'render-slice-image': {width: number, height: number, view: SliceView}}
+  | {'get-frame-stats': {}}
+  | {'export-nifti': {volume: number, options?: NiftiWriteOptions}}
+  | {'export-npy': {volume: number, options?: NpyWriteOptions}}
+  | {'close-volume': {volume: number}}
+  | {'abort': {request: number}}
+  | {'set-log-filter': {filter: string}}
+
+/** Pacing statistics of the frames drawn by the viewer, with durations in milliseconds. */
+export type FrameStats = {
+  frames: number,
+  requestedViews: number,
+  droppedViews: number,
+  lastDrawTime: number,
+  averageDrawTime: number,
+  averageFrameInterval: number,
+}
+
+/** Log record of the viewer, with its time in milliseconds since the Unix epoch. */
+export type LogRecord = {
+  level: 'error' | 'warn' | 'info' | 'debug' | 'trace',
+  target: string,
+  message: string,
+  time: number,
+}
+
+export type RequestMessage = {
+  version: number,
+  id: number,
+  request: Request,
+}
+
+export type Response =
+  | {kind: 'renderer-initialized', backend: RendererBackend}
+  | {kind: 'volume-loaded', volume: LoadedVolume}
+  | {kind: 'volumes-loaded', volumes: LoadedVolume[]}
+  | {kind: 'slice-image', pixels: Uint8Array}
+  | {kind: 'frame-stats', stats: FrameStats}
+  | {kind: 'nifti-exported', bytes: Uint8Array}
+  | {kind: 'npy-exported', bytes: Uint8Array, sidecar: string}
+  | {kind: 'done'}
+
+/** Events sent by the viewer, which are the responses to the requests, the renderer status changes and the log records. */
+export type Event =
+  | {type: 'response', id: number, response: Response}
+  | {type: 'error', id: number | null, error: ViewerError}
+  | {type: 'progress', id: number, loaded: number, total: number}
+  | {type: 'renderer-status', status: RendererStatus}
+  | {type: 'volume-changed', volume: LoadedVolume}
+  | {type: 'log', record: LogRecord}
+
+export type EventMessage = {
+  version: number,
+  event: Event,
+}
+
+let nextRequestId = 0;
+
+/** Create a request message with a new request identifier. */
+export function createRequest(request: Request): RequestMessage {
+  return {
+    version: PROTOCOL_VERSION,
+    id: nextRequestId++,
+    request,
+  };
+}
@@ -1,10 +1,57 @@
+/** A decoded header extension of a NIfTI image. AFNI attributes are unquoted strings, or their
+ * whitespace separated values. */
+export type NiftiExtension =
+  | {kind: 'json', code: number, value: unknown}
+  | {kind: 'afni', attributes: Record<string, string>}
+  | {kind: 'text', code: number, text: string}
+  | {kind: 'binary', code: number, size: number}
+
 export type NiftiProperties = {
   dimensions: ImageDimensions,
+  datatype: VoxelType,
+  minimum: number,
   maximum: number,
+  /** The header extensions of NIfTI images, which are empty for the other formats. */
+  extensions: NiftiExtension[],
+}
+
+export type LoadedVolume = {
+  id: number,
+  properties: NiftiProperties,
+}
+
+export enum VoxelType {
+  U8  = 'U8',
+  I16 = 'I16',
+  U16 = 'U16',
+  I32 = 'I32',
+  F32 = 'F32',
+  F64 = 'F64',
+}
+
+export enum RendererBackend {
+  WebGpu = 'WebGpu',
+  WebGl  = 'WebGl',
+  Cpu    = 'Cpu',
+  Native = 'Native',
+}
+
+export enum RendererStatus {
+  Lost     = 'Lost',
+  Restored = 'Restored',
+  Failed   = 'Failed',
+}
+
+/** An error raised by the viewer, whose code identifies its kind. */
+export type ViewerError = {
+  code: 'io' | 'unsupported-datatype' | 'invalid-header' | 'gpu' | 'bad-argument' | 'network' | 'aborted' | 'unknown',
+  message: string,
 }
 
 export type ViewerState = {
   rendererInitialied: boolean,
+  rendererBackend: RendererBackend | null,
+  rendererStatus: RendererStatus | null,
   dimensions: ImageDimensions,
   focalPoint: ImagePoint,
   axis: AnatomicalAxis,
@@ -37,6 +84,7 @@ export enum AnatomicalAxis {
 }
 
 export type DisplayWindow = {
+  minimum: number,
   maximum: number,
   level: number,
   width: number,
@@ -81,9 +129,14 @@ export function decrementRotation(rotation: Rotation): Rotation {
   }
 }
 
-export function createViewerState({dimensions, maximum}: NiftiProperties): ViewerState {
+export function createViewerState({dimensions, minimum, maximum}: NiftiProperties): ViewerState {
+  // The window of images without negative intensities starts from zero.
+  const low = Math.min(minimum, 0);
+  const range = maximum - low;
   return {
     rendererInitialied: false,
+    rendererBackend: null,
+    rendererStatus: null,
     dimensions,
     axis: AnatomicalAxis.Axial,
     focalPoint: {
@@ -93,9 +146,10 @@ export function createViewerState({dimensions, maximum}: NiftiProperties): Viewe
       t: 0,
     },
     window: {
+      minimum: low,
       maximum,
-      level: Math.round(maximum * 0.25),
-      width: Math.round(maximum * 0.5),
+      level: Math.round(low + range * 0.25),
+      width: Math.round(range * 0.5),
       polarity: DisplayPolarity.Positive,
     },
     rotation: Rotation.Rotate0,
@@ -132,6 +186,18 @@ export function getCoordinate(point: ImagePoint, axis: AnatomicalAxis): number {
   return point[getVoxelAxis(axis)];
 }
 
+/** Scale a point to the dimensions of another resolution of the same image, such as another level of an OME-Zarr image. */
+export function scalePoint(point: ImagePoint, from: ImageDimensions, to: ImageDimensions): ImagePoint {
+  const scale = (coordinate: number, length: number, scaledLength: number) =>
+    Math.min(Math.floor(coordinate * scaledLength / length), scaledLength - 1);
+  return {
+    x: scale(point.x, from.rows,    to.rows),
+    y: scale(point.y, from.columns, to.columns),
+    z: scale(point.z, from.slices,  to.slices),
+    t: point.t,
+  };
+}
+
 export function setCoordinate(point: ImagePoint, coordinate: number, axis: AnatomicalAxis): ImagePoint {
   return {...point, [getVoxelAxis(axis)]: coordinate}
 }
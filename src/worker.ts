@@ -1,34 +1,17 @@
-import wasm, {initRenderer, readFile, renderSlice} from "../src-rust/pkg/brain_renderer";
-import { AnatomicalAxis, NiftiProperties, DisplayWindow, Rotation } from "./types";
+import wasm, { onLog, Viewer } from "../src-rust/pkg/brain_renderer";
+import { EventMessage, LogRecord, PROTOCOL_VERSION, RequestMessage } from "./protocol";
 
-type WorkerMessage =
-  | {action: 'init-renderer', canvas: OffscreenCanvas}
-  | {action: 'read-file', file: File}
-  | {action: 'render-slice', window: DisplayWindow, axis: AnatomicalAxis, coordinate: number, timepoint: number, rotation: Rotation}
+/** Viewer of this worker, created once the WebAssembly module is initialized. */
+const viewerPromise: Promise<Viewer> = wasm().then(() => {
+  const viewer = new Viewer();
+  onLog((record: LogRecord) => postMessage({version: PROTOCOL_VERSION, event: {type: 'log', record}}));
+  viewer.onMessage((message: EventMessage) => postMessage(message));
+  return viewer;
+});
 
-onmessage = async (event: MessageEvent<WorkerMessage>) => {
-  await wasm();
-  switch (event.data.action) {
-    case 'init-renderer':
-      console.debug("[web-worker] initialize renderer");
-      let result = await initRenderer(event.data.canvas);
-      postMessage({
-        action: 'init-renderer',
-        result,
-      });
-      break;
-    case 'read-file':
-      console.debug("[web-worker] read nifti file");
-      let properties: NiftiProperties = await readFile(event.data.file);
-      postMessage({
-        action: 'read-file',
-        properties,
-      });
-      break;
-    case 'render-slice':
-      console.debug("[web-worker] render slice");
-      renderSlice(event.data.axis, event.data.coordinate, event.data.timepoint, event.data.window, event.data.rotation);
-  }
+onmessage = async (event: MessageEvent<RequestMessage>) => {
+  const viewer = await viewerPromise;
+  viewer.handleMessage(event.data);
 }
 
 export {};